@@ -0,0 +1,124 @@
+//! Benchmarks of the IMT operations and of the mutations verification.
+//!
+//! Run with `cargo bench --features test-support,sha2`. Every benchmark is parameterized over the
+//! hasher and the IMT size, e.g. `insert_node/keccak/100000`.
+//!
+//! The memory used by the nodes of each benchmarked IMT is printed along with the memory the
+//! same nodes would use stored in a map by key, and so are the hashes performed by an insertion
+//! and an update, which the cost of proving them in a circuit follows.
+
+use std::mem::size_of;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use imt::circuits::{
+    mutate::IMTMutate,
+    node::{Hashor, IMTNode},
+    sha256::Sha256,
+    test_support::{build_imt, key, value},
+};
+use tiny_keccak::Keccak;
+
+/// Sizes of the benchmarked IMTs.
+const SIZES: [u64; 3] = [1_000, 100_000, 1_000_000];
+
+/// Number of nodes appended one after the other by the incremental append benchmark.
+const APPENDS: u64 = 100;
+
+fn bench_hasher<H: Hashor + Clone>(c: &mut Criterion, name: &str, hasher_factory: fn() -> H) {
+    for size in SIZES {
+        let imt = build_imt(hasher_factory, size - 1);
+        let id = BenchmarkId::new(name, size);
+
+        let stats = imt.stats();
+        println!(
+            "memory/{name}/{size}: nodes use {} bytes, {} bytes as a map of nodes",
+            stats.nodes_heap_bytes,
+            node_map_heap_bytes(stats.node_count)
+        );
+
+        // Keys absent from the IMT and keys present in it.
+        let new_key = key(size);
+        let existing_key = key(size / 2);
+
+        let mut group = c.benchmark_group("insert_node");
+        group.sample_size(10);
+        group.bench_function(id.clone(), |b| {
+            b.iter_batched_ref(
+                || imt.clone(),
+                |imt| imt.insert_node(new_key, value(size)),
+                BatchSize::LargeInput,
+            )
+        });
+        group.finish();
+
+        // Nodes appended one after the other, each insertion climbing the tree it grew.
+        let mut group = c.benchmark_group("append_nodes");
+        group.sample_size(10);
+        group.bench_function(id.clone(), |b| {
+            b.iter_batched_ref(
+                || imt.clone(),
+                |imt| {
+                    for i in size..size + APPENDS {
+                        imt.insert_node(key(i), value(i));
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+        group.finish();
+
+        let mut group = c.benchmark_group("update_node");
+        group.sample_size(10);
+        group.bench_function(id.clone(), |b| {
+            b.iter_batched_ref(
+                || imt.clone(),
+                |imt| imt.update_node(existing_key, value(0)),
+                BatchSize::LargeInput,
+            )
+        });
+        group.finish();
+
+        c.benchmark_group("siblings")
+            .bench_function(id.clone(), |b| b.iter(|| imt.siblings(&existing_key)));
+
+        let mut mutated = imt.clone();
+        let insert = mutated.insert_node(new_key, value(size));
+        let insert_hashes = mutated.last_op_hash_count();
+        let update = mutated.update_node(existing_key, value(0));
+        println!(
+            "hashes/{name}/{size}: insert_node performs {insert_hashes} hashes, update_node {}",
+            mutated.last_op_hash_count()
+        );
+        let (insert, update) = match (insert, update) {
+            (IMTMutate::Insert(insert), IMTMutate::Update(update)) => (insert, update),
+            _ => unreachable!(),
+        };
+
+        c.benchmark_group("verify_insert")
+            .bench_function(id.clone(), |b| {
+                b.iter(|| insert.verify(hasher_factory, insert.old_root).unwrap())
+            });
+
+        c.benchmark_group("verify_update").bench_function(id, |b| {
+            b.iter(|| update.verify(hasher_factory, update.old_root).unwrap())
+        });
+    }
+}
+
+/// Estimates the heap memory used by `count` nodes stored in a `HashMap` by key, accounting for
+/// one control byte per bucket and the map load factor.
+fn node_map_heap_bytes(count: u64) -> u64 {
+    let buckets = (count * 8 / 7).next_power_of_two();
+    buckets * (size_of::<([u8; 32], IMTNode<[u8; 32], [u8; 32]>)>() as u64 + 1)
+}
+
+fn bench_keccak(c: &mut Criterion) {
+    bench_hasher(c, "keccak", Keccak::v256);
+}
+
+fn bench_sha256(c: &mut Criterion) {
+    bench_hasher(c, "sha256", Sha256::new);
+}
+
+criterion_group!(benches, bench_keccak, bench_sha256);
+criterion_main!(benches);
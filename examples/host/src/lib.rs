@@ -0,0 +1,155 @@
+//! Host side driver for the example guests.
+//!
+//! Builds an `Imt`, produces a batch of mutations and runs the guest program on it, either
+//! locally through `zkvm::run_guest` or in the SP1 / RISC Zero provers.
+
+use std::io::Cursor;
+
+use anyhow::Result;
+use imt::circuits::{
+    imt::Imt,
+    mutate::IMTMutate,
+    node::Hashor,
+    zkvm::{self, CommitSink, Journal, Read},
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// A batch of mutations, along with the roots before and after applying it.
+pub struct Batch {
+    pub old_root: [u8; 32],
+    pub mutations: Vec<IMTMutate<[u8; 32], [u8; 32]>>,
+    pub new_root: [u8; 32],
+}
+
+/// Builds an IMT and returns the batch of mutations mixing inserts and updates applied to it.
+pub fn build_batch<H: Hashor>(hasher_factory: fn() -> H) -> Batch {
+    let mut imt = Imt::new(hasher_factory);
+    imt.insert_node([1; 32], [42; 32]);
+
+    let old_root = imt.root;
+    let mut mutations = Vec::new();
+    for i in 2..=8 {
+        mutations.push(imt.insert_node([i; 32], [42; 32]));
+    }
+    mutations.push(imt.update_node([1; 32], [43; 32]));
+    mutations.push(imt.update_node([5; 32], [43; 32]));
+
+    Batch {
+        old_root,
+        mutations,
+        new_root: imt.root,
+    }
+}
+
+/// In memory guest input, reading bincode encoded values.
+struct MemoryInput(Cursor<Vec<u8>>);
+
+impl Read for MemoryInput {
+    fn read<T: DeserializeOwned>(&mut self) -> Result<T> {
+        Ok(bincode::deserialize_from(&mut self.0)?)
+    }
+}
+
+/// In memory guest output, writing bincode encoded values.
+#[derive(Default)]
+struct MemoryOutput(Vec<u8>);
+
+impl CommitSink for MemoryOutput {
+    fn commit<T: Serialize>(&mut self, value: &T) -> Result<()> {
+        Ok(bincode::serialize_into(&mut self.0, value)?)
+    }
+
+    fn commit_slice(&mut self, bytes: &[u8]) -> Result<()> {
+        self.0.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// Runs the guest program on the host and returns the committed journal.
+pub fn run_locally<H: Hashor>(hasher_factory: fn() -> H, batch: &Batch) -> Result<Journal> {
+    let mut input = MemoryInput(Cursor::new(bincode::serialize(&(
+        batch.old_root,
+        &batch.mutations,
+    ))?));
+    let mut output = MemoryOutput::default();
+
+    zkvm::run_guest::<_, [u8; 32], [u8; 32], _, _>(hasher_factory, &mut input, &mut output)?;
+
+    Journal::from_bytes(&output.0)
+}
+
+/// Proves the SP1 guest `elf` on the given `batch` and returns the verified journal.
+#[cfg(feature = "prove-sp1")]
+pub fn prove_sp1(elf: &[u8], batch: &Batch) -> Result<Journal> {
+    use sp1_sdk::{ProverClient, SP1Stdin};
+
+    let mut stdin = SP1Stdin::new();
+    stdin.write(&(batch.old_root, &batch.mutations));
+
+    let client = ProverClient::from_env();
+    let (pk, vk) = client.setup(elf);
+    let proof = client.prove(&pk, &stdin).run()?;
+    client.verify(&proof, &vk)?;
+
+    Journal::from_bytes(proof.public_values.as_slice())
+}
+
+/// Proves the RISC Zero guest `elf` on the given `batch` and returns the verified journal.
+#[cfg(feature = "prove-risc0")]
+pub fn prove_risc0(elf: &[u8], batch: &Batch) -> Result<Journal> {
+    use risc0_zkvm::{compute_image_id, default_prover, ExecutorEnv};
+
+    let env = ExecutorEnv::builder()
+        .write(&(batch.old_root, &batch.mutations))?
+        .build()?;
+
+    let receipt = default_prover().prove(env, elf)?.receipt;
+    receipt.verify(compute_image_id(elf)?)?;
+
+    Journal::from_bytes(&receipt.journal.bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use imt::circuits::sha256::Sha256;
+    use tiny_keccak::Keccak;
+
+    use super::*;
+
+    fn check_run_locally<H: Hashor>(hasher_factory: fn() -> H) {
+        let batch = build_batch(hasher_factory);
+        let journal = run_locally(hasher_factory, &batch).unwrap();
+
+        assert_eq!(journal.old_root, batch.old_root);
+        assert_eq!(journal.new_root, batch.new_root);
+        assert_eq!(journal.mutation_count, batch.mutations.len() as u64);
+    }
+
+    #[test]
+    fn test_run_locally() {
+        check_run_locally(Keccak::v256);
+        check_run_locally(Sha256::new);
+    }
+
+    #[test]
+    #[ignore = "requires the SP1 guest ELF in IMT_SP1_GUEST_ELF"]
+    #[cfg(feature = "prove-sp1")]
+    fn test_prove_sp1() {
+        let elf = std::fs::read(std::env::var("IMT_SP1_GUEST_ELF").unwrap()).unwrap();
+        let batch = build_batch(Keccak::v256);
+
+        let journal = prove_sp1(&elf, &batch).unwrap();
+        assert_eq!(journal, run_locally(Keccak::v256, &batch).unwrap());
+    }
+
+    #[test]
+    #[ignore = "requires the RISC Zero guest ELF in IMT_RISC0_GUEST_ELF"]
+    #[cfg(feature = "prove-risc0")]
+    fn test_prove_risc0() {
+        let elf = std::fs::read(std::env::var("IMT_RISC0_GUEST_ELF").unwrap()).unwrap();
+        let batch = build_batch(Sha256::new);
+
+        let journal = prove_risc0(&elf, &batch).unwrap();
+        assert_eq!(journal, run_locally(Sha256::new, &batch).unwrap());
+    }
+}
@@ -0,0 +1,40 @@
+//! Builds example batches and runs the guest program on them.
+//!
+//! The guest is run locally by default. With the `prove-sp1` (resp. `prove-risc0`) feature and
+//! the `IMT_SP1_GUEST_ELF` (resp. `IMT_RISC0_GUEST_ELF`) environment variable pointing to the
+//! guest ELF, the batch is also proven.
+
+use anyhow::Result;
+use imt::circuits::sha256::Sha256;
+use imt_host::{build_batch, run_locally};
+use tiny_keccak::Keccak;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn main() -> Result<()> {
+    let batch = build_batch(Keccak::v256);
+    let journal = run_locally(Keccak::v256, &batch)?;
+    println!("keccak journal: 0x{}", hex(&journal.to_bytes()));
+
+    #[cfg(feature = "prove-sp1")]
+    if let Ok(path) = std::env::var("IMT_SP1_GUEST_ELF") {
+        let proven = imt_host::prove_sp1(&std::fs::read(path)?, &batch)?;
+        anyhow::ensure!(proven == journal, "SP1 journal mismatch");
+        println!("SP1 proof verified");
+    }
+
+    let batch = build_batch(Sha256::new);
+    let journal = run_locally(Sha256::new, &batch)?;
+    println!("sha256 journal: 0x{}", hex(&journal.to_bytes()));
+
+    #[cfg(feature = "prove-risc0")]
+    if let Ok(path) = std::env::var("IMT_RISC0_GUEST_ELF") {
+        let proven = imt_host::prove_risc0(&std::fs::read(path)?, &batch)?;
+        anyhow::ensure!(proven == journal, "RISC Zero journal mismatch");
+        println!("RISC Zero proof verified");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,37 @@
+//! RISC Zero guest verifying a batch of `IMTMutate` with the accelerated SHA-256 hashor.
+//!
+//! The host generates the mutations with `Imt::new(Sha256::new)` (`sha2` feature) and writes the
+//! `(old_root, mutations)` tuple to the guest input. The guest commits the canonical encoding of
+//! the resulting `zkvm::Journal`.
+
+use imt::circuits::zkvm::{self, risc0::Risc0Sha256, CommitSink, Read};
+use risc0_zkvm::guest::env;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// The RISC Zero guest environment.
+struct Env;
+
+impl Read for Env {
+    fn read<T: DeserializeOwned>(&mut self) -> anyhow::Result<T> {
+        Ok(env::read())
+    }
+}
+
+impl CommitSink for Env {
+    fn commit<T: Serialize>(&mut self, value: &T) -> anyhow::Result<()> {
+        env::commit(value);
+        Ok(())
+    }
+
+    fn commit_slice(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        env::commit_slice(bytes);
+        Ok(())
+    }
+}
+
+/// Runs the guest program: verifies the batch read from the guest input and commits the
+/// resulting journal.
+pub fn run() {
+    zkvm::run_guest::<_, [u8; 32], [u8; 32], _, _>(Risc0Sha256::new, &mut Env, &mut Env)
+        .expect("invalid IMT mutations");
+}
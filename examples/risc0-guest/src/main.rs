@@ -0,0 +1,9 @@
+//! zkVM entrypoint of the guest program, see the library for the guest logic.
+
+#![no_main]
+
+risc0_zkvm::guest::entry!(main);
+
+fn main() {
+    imt_risc0_guest::run();
+}
@@ -0,0 +1,36 @@
+//! SP1 guest verifying a batch of `IMTMutate` with the precompile backed Keccak256 hashor.
+//!
+//! The host generates the mutations with `Imt::new(Keccak::v256)` and writes the
+//! `(old_root, mutations)` tuple to the guest stdin. The guest commits the canonical encoding of
+//! the resulting `zkvm::Journal` as its public values.
+
+use imt::circuits::zkvm::{self, sp1::Sp1Keccak, CommitSink, Read};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// The SP1 guest environment.
+struct Env;
+
+impl Read for Env {
+    fn read<T: DeserializeOwned>(&mut self) -> anyhow::Result<T> {
+        Ok(sp1_zkvm::io::read())
+    }
+}
+
+impl CommitSink for Env {
+    fn commit<T: Serialize>(&mut self, value: &T) -> anyhow::Result<()> {
+        sp1_zkvm::io::commit(value);
+        Ok(())
+    }
+
+    fn commit_slice(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        sp1_zkvm::io::commit_slice(bytes);
+        Ok(())
+    }
+}
+
+/// Runs the guest program: verifies the batch read from the guest input and commits the
+/// resulting journal.
+pub fn run() {
+    zkvm::run_guest::<_, [u8; 32], [u8; 32], _, _>(Sp1Keccak::v256, &mut Env, &mut Env)
+        .expect("invalid IMT mutations");
+}
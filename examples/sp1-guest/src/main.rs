@@ -0,0 +1,9 @@
+//! zkVM entrypoint of the guest program, see the library for the guest logic.
+
+#![no_main]
+
+sp1_zkvm::entrypoint!(main);
+
+fn main() {
+    imt_sp1_guest::run();
+}
@@ -0,0 +1,53 @@
+//! Applies byte level mutations to a valid proof generated from a seeded IMT before verifying
+//! it.
+
+#![no_main]
+
+use imt::circuits::{
+    mutate::IMTMutate,
+    test_support::{build_imt, key, value},
+};
+use libfuzzer_sys::{arbitrary::Arbitrary, fuzz_target};
+use tiny_keccak::Keccak;
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    /// Number of keys in the seeded IMT.
+    size: u8,
+    /// Whether the proof is an update of an existing key rather than an insert.
+    update: bool,
+    /// `(position, mask)` pairs xored into the serialized proof.
+    flips: Vec<(u16, u8)>,
+}
+
+fuzz_target!(|input: Input| {
+    let size = input.size as u64;
+    let mut imt = build_imt(Keccak::v256, size);
+
+    let old_root = imt.root;
+    let mutate = if input.update && size > 0 {
+        imt.update_node(key(size / 2), value(size))
+    } else {
+        imt.insert_node(key(size), value(size))
+    };
+
+    let bytes = bincode::serialize(&mutate).unwrap();
+    let mut tampered = bytes.clone();
+    for (position, mask) in input.flips {
+        let len = tampered.len();
+        tampered[position as usize % len] ^= mask;
+    }
+
+    let Ok(tampered_mutate) = bincode::deserialize::<IMTMutate<[u8; 32], [u8; 32]>>(&tampered)
+    else {
+        return;
+    };
+
+    // A tampered proof must never verify to the honest root.
+    if let Ok(root) = tampered_mutate.verify(Keccak::v256, old_root) {
+        assert!(
+            tampered == bytes || root != imt.root,
+            "tampered proof verified to the honest root"
+        );
+    }
+});
@@ -0,0 +1,176 @@
+//! Proof that two keys directly follow each other in an IMT, e.g. for ordered set arguments.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Hash;
+
+use super::{
+    node::{Hashor, IMTNode, Key, LeafLayout, NodeHasher, Value},
+    node_exists,
+    siblings::Siblings,
+    CommitmentScheme,
+};
+
+/// Proof that the key of `node` (`a`) and `b` are both in the IMT with no key strictly between
+/// them.
+///
+/// As `node` links to `b`, `b` is in the IMT as well. A zero `b` instead shows that `a` is the
+/// largest key of the IMT.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct IMTAdjacency<K: Key, V: Value, S = Vec<Option<Hash>>> {
+    pub node: IMTNode<K, V>,
+    pub node_siblings: S,
+    pub b: K,
+}
+
+/// Error returned by `IMTAdjacency::verify`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdjacencyError {
+    /// The node of `a` is not in the IMT.
+    NotAMember,
+    /// `b` is not the key following `a`.
+    NotAdjacent,
+}
+
+impl fmt::Display for AdjacencyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdjacencyError::NotAMember => write!(f, "IMTAdjacency.node is not in the IMT"),
+            AdjacencyError::NotAdjacent => {
+                write!(f, "IMTAdjacency.b does not follow IMTAdjacency.node")
+            }
+        }
+    }
+}
+
+impl std::error::Error for AdjacencyError {}
+
+impl<K: Key, V: Value, S: Siblings> IMTAdjacency<K, V, S> {
+    /// Returns the key preceding `b`.
+    pub fn a(&self) -> &K {
+        &self.node.key
+    }
+
+    /// Verifies that `a` and `b` directly follow each other in the IMT of `size` nodes commited
+    /// to in `root`.
+    pub fn verify<H: Hashor>(
+        &self,
+        hasher_factory: fn() -> H,
+        root: Hash,
+        size: u64,
+    ) -> Result<(), AdjacencyError> {
+        self.verify_with_layout(
+            hasher_factory,
+            CommitmentScheme::default(),
+            LeafLayout::default(),
+            root,
+            size,
+        )
+    }
+
+    /// Same as `verify` under the given commitment `scheme`, the leaves being hashed with the
+    /// given `layout`.
+    pub fn verify_with_layout<H: Hashor, N: NodeHasher<K, V>>(
+        &self,
+        hasher_factory: fn() -> H,
+        scheme: CommitmentScheme,
+        layout: N,
+        root: Hash,
+        size: u64,
+    ) -> Result<(), AdjacencyError> {
+        // A vacant slot holds no key.
+        if self.node.is_vacant()
+            || self.node.index >= size
+            || !node_exists(
+                hasher_factory,
+                scheme,
+                layout,
+                &root,
+                size,
+                &self.node,
+                &self.node_siblings,
+            )
+        {
+            return Err(AdjacencyError::NotAMember);
+        }
+
+        if self.node.next_key != self.b {
+            return Err(AdjacencyError::NotAdjacent);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use tiny_keccak::Keccak;
+
+    use super::*;
+    use crate::circuits::imt::Imt;
+
+    #[test]
+    fn test_adjacency() {
+        let mut imt = Imt::new(Keccak::v256);
+        for key in [[5; 32], [2; 32], [9; 32], [7; 32]] {
+            imt.insert_node(key, [42; 32]);
+        }
+
+        // Adjacent pairs, from the zero node to the largest key wrapping to the zero sentinel.
+        for (a, b) in [(0, 2), (2, 5), (5, 7), (7, 9), (9, 0)] {
+            let adjacency = imt.prove_adjacent(&[a; 32], &[b; 32]);
+            assert_eq!(*adjacency.a(), [a; 32]);
+            adjacency.verify(Keccak::v256, imt.root, imt.size).unwrap();
+        }
+
+        // Non adjacent pairs.
+        for (a, b) in [(2, 7), (5, 2), (9, 2), (7, 0), (2, 3)] {
+            let adjacency = imt.prove_adjacent(&[a; 32], &[b; 32]);
+            let res = adjacency.verify(Keccak::v256, imt.root, imt.size);
+            assert_eq!(res, Err(AdjacencyError::NotAdjacent));
+        }
+    }
+
+    #[test]
+    fn test_adjacency_not_a_member() {
+        let mut imt = Imt::new(Keccak::v256);
+        for key in [[1; 32], [2; 32], [3; 32]] {
+            imt.insert_node(key, [42; 32]);
+        }
+        let adjacency = imt.prove_adjacent(&[1; 32], &[2; 32]);
+
+        // A node that is not in the IMT, even though it links to `b`.
+        let forged = IMTAdjacency {
+            node: IMTNode {
+                value: [43; 32],
+                ..adjacency.node
+            },
+            ..adjacency.clone()
+        };
+        let res = forged.verify(Keccak::v256, imt.root, imt.size);
+        assert_eq!(res, Err(AdjacencyError::NotAMember));
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "IMTAdjacency.node is not in the IMT"
+        );
+
+        // Nor does it hold against another root.
+        let old_root = imt.root;
+        imt.insert_node([4; 32], [42; 32]);
+        let res = adjacency.verify(Keccak::v256, imt.root, imt.size);
+        assert_eq!(res, Err(AdjacencyError::NotAMember));
+        adjacency.verify(Keccak::v256, old_root, 4).unwrap();
+
+        // A vacant slot links to the zero key but holds no key.
+        imt.remove_node(&[3; 32]);
+        let forged = IMTAdjacency {
+            node: IMTNode::<[u8; 32], [u8; 32]>::vacant(3),
+            node_siblings: imt.siblings_cached(3),
+            b: [0; 32],
+        };
+        let res = forged.verify(Keccak::v256, imt.root, imt.size);
+        assert_eq!(res, Err(AdjacencyError::NotAMember));
+    }
+}
@@ -0,0 +1,179 @@
+//! Support of the `alloy_primitives` types.
+//!
+//! `B256` already satisfies the `Key` and `Value` bounds, and orders as the big endian integer
+//! it holds. `Address` does too, on 20 bytes: `PaddedAddress` widens it to 32 bytes for IMTs
+//! whose keys must all be 32 bytes wide. `keys::AddressKey` is serialized as an EIP-55
+//! checksummed address.
+
+use alloy_primitives::{Address, B256};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{inclusion::IMTInclusion, keys::AddressKey, mutate::IMTMutate};
+
+/// Mutation of an IMT keyed by `B256` and holding `B256` values.
+pub type B256Mutate = IMTMutate<B256, B256>;
+
+/// Inclusion proof in an IMT keyed by `B256` and holding `B256` values.
+pub type B256Inclusion = IMTInclusion<B256, B256>;
+
+/// `Address` left padded with zeros to 32 bytes, as in the ABI encoding.
+///
+/// The padding keeps the order of the addresses, which sort as the 160 bits integers they are.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize,
+)]
+pub struct PaddedAddress(B256);
+
+impl PaddedAddress {
+    /// Returns the padded address.
+    pub fn address(&self) -> Address {
+        Address::from_word(self.0)
+    }
+}
+
+impl AsRef<[u8]> for PaddedAddress {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+
+impl From<Address> for PaddedAddress {
+    fn from(address: Address) -> Self {
+        Self(address.into_word())
+    }
+}
+
+impl From<PaddedAddress> for Address {
+    fn from(address: PaddedAddress) -> Self {
+        address.address()
+    }
+}
+
+impl From<PaddedAddress> for B256 {
+    fn from(address: PaddedAddress) -> Self {
+        address.0
+    }
+}
+
+/// Human readable formats hold the checksummed hex address, others its padded 32 bytes.
+impl Serialize for AddressKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&Address::from(self.address()).to_checksum(None))
+        } else {
+            let mut bytes = [0; 32];
+            bytes.copy_from_slice(self.as_ref());
+            bytes.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for AddressKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let address = String::deserialize(deserializer)?;
+            Address::parse_checksummed(&address, None)
+                .map(|address| Self::from(address.0 .0))
+                .map_err(de::Error::custom)
+        } else {
+            let bytes = <[u8; 32]>::deserialize(deserializer)?;
+            Self::try_from(&bytes[..]).map_err(de::Error::custom)
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use alloy_primitives::{address, b256};
+    use tiny_keccak::Keccak;
+
+    use super::*;
+    use crate::circuits::imt::Imt;
+
+    #[test]
+    fn test_b256_imt() {
+        let mut imt = Imt::<_, B256, B256>::new(Keccak::v256);
+
+        for i in [3_u64, 1, 2, 256] {
+            let old_root = imt.root;
+            let mutate: B256Mutate = imt.insert_node(B256::from(U256(i)), B256::repeat_byte(42));
+            assert_eq!(mutate.verify(Keccak::v256, old_root).unwrap(), imt.root);
+        }
+
+        let old_root = imt.root;
+        let key = B256::from(U256(2));
+        let mutate = imt.update_node(key, B256::repeat_byte(43));
+        assert_eq!(mutate.verify(Keccak::v256, old_root).unwrap(), imt.root);
+        imt.validate_invariants().unwrap();
+
+        // The keys order numerically.
+        assert_eq!(
+            imt.low_nullifier(&B256::from(U256(255))).key,
+            B256::from(U256(3))
+        );
+
+        // Proofs are serialized with the alloy hex encoding.
+        let proof: B256Inclusion = imt.prove_membership(&key).unwrap();
+        let json = serde_json::to_value(&proof).unwrap();
+        assert_eq!(json["node"]["key"], key.to_string());
+        assert_eq!(json["node"]["value"], B256::repeat_byte(43).to_string());
+        let proof: B256Inclusion = serde_json::from_value(json).unwrap();
+        proof.verify(Keccak::v256, imt.root).unwrap();
+
+        let json = serde_json::to_value(&mutate).unwrap();
+        assert_eq!(
+            json["Update"]["new_value"],
+            format!("0x{}", "2b".repeat(32))
+        );
+    }
+
+    #[test]
+    fn test_padded_address() {
+        let a = address!("00000000000000000000000000000000000000ff");
+        let b = address!("0000000000000000000000000000000000000100");
+
+        let padded = PaddedAddress::from(a);
+        assert_eq!(
+            B256::from(padded),
+            b256!("00000000000000000000000000000000000000000000000000000000000000ff")
+        );
+        assert_eq!(padded.address(), a);
+        assert!(padded < PaddedAddress::from(b));
+
+        let mut imt = Imt::new(Keccak::v256);
+        for address in [b, a] {
+            imt.insert_node(PaddedAddress::from(address), B256::ZERO);
+        }
+        imt.validate_invariants().unwrap();
+        assert_eq!(imt.first_key().map(Address::from), Some(a));
+        assert_eq!(imt.last_key().map(Address::from), Some(b));
+    }
+
+    #[test]
+    fn test_address_key_serde() {
+        let a = address!("5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed");
+        let key = AddressKey::from(a.0 .0);
+
+        let json = serde_json::to_value(key).unwrap();
+        assert_eq!(json, "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed");
+        assert_eq!(serde_json::from_value::<AddressKey>(json).unwrap(), key);
+
+        // A wrong checksum is rejected.
+        let json = serde_json::json!("0x5aaeb6053F3E94C9b9A09f33669435E7Ef1BeAed");
+        assert!(serde_json::from_value::<AddressKey>(json).is_err());
+
+        let bytes = bincode::serialize(&key).unwrap();
+        assert_eq!(bytes, key.as_ref());
+        assert_eq!(bincode::deserialize::<AddressKey>(&bytes).unwrap(), key);
+        assert!(bincode::deserialize::<AddressKey>(&[1; 32]).is_err());
+    }
+
+    /// Big endian 32 bytes encoding of the given integer.
+    struct U256(u64);
+
+    impl From<U256> for B256 {
+        fn from(value: U256) -> Self {
+            B256::left_padding_from(&value.0.to_be_bytes())
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! `proptest` strategies for keys, values, nodes and sequences of IMT operations.
+
+use std::collections::HashSet;
+
+use proptest::{collection::vec, prelude::*, sample::Index};
+
+use super::{
+    imt::Imt,
+    mutate::IMTMutate,
+    node::{Hashor, IMTNode},
+};
+
+/// Maximum number of inserts (resp. updates) in a generated sequence of operations.
+const MAX_OPERATIONS: usize = 32;
+
+/// Returns a strategy generating non zero keys.
+///
+/// Only the last two bytes of the keys are used, so that they shrink towards small readable
+/// keys and that generated keys often fall next to each other.
+pub fn key() -> impl Strategy<Value = [u8; 32]> {
+    (1..=u16::MAX).prop_map(|i| {
+        let mut key = [0; 32];
+        key[30..].copy_from_slice(&i.to_be_bytes());
+        key
+    })
+}
+
+/// Returns a strategy generating values, shrinking towards the zero value.
+pub fn value() -> impl Strategy<Value = [u8; 32]> {
+    any::<[u8; 32]>()
+}
+
+/// Returns a strategy generating (key; value) pairs with unique keys.
+pub fn entries() -> impl Strategy<Value = Vec<([u8; 32], [u8; 32])>> {
+    vec((key(), value()), 1..=MAX_OPERATIONS).prop_map(|entries| {
+        let mut keys = HashSet::with_capacity(entries.len());
+        entries
+            .into_iter()
+            .filter(|(key, _)| keys.insert(*key))
+            .collect()
+    })
+}
+
+impl Arbitrary for IMTNode<[u8; 32], [u8; 32]> {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        (any::<u64>(), key(), value(), key())
+            .prop_map(|(index, key, value, next_key)| IMTNode {
+                index,
+                key,
+                value,
+                next_key,
+            })
+            .boxed()
+    }
+}
+
+/// Operation applied to an IMT.
+#[derive(Debug, Clone, Copy)]
+pub enum Operation {
+    Insert([u8; 32], [u8; 32]),
+    Update([u8; 32], [u8; 32]),
+}
+
+impl Operation {
+    /// Applies the operation to `imt` and returns the resulting mutation.
+    pub fn apply<H: Hashor>(
+        &self,
+        imt: &mut Imt<H, [u8; 32], [u8; 32]>,
+    ) -> IMTMutate<[u8; 32], [u8; 32]> {
+        match *self {
+            Operation::Insert(key, value) => imt.insert_node(key, value),
+            Operation::Update(key, value) => imt.update_node(key, value),
+        }
+    }
+}
+
+/// Sequence of operations that can be applied to an empty IMT: the inserted keys are unique and
+/// each key is only updated after being inserted.
+#[derive(Debug, Clone)]
+pub struct Operations(pub Vec<Operation>);
+
+impl Arbitrary for Operations {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        let inserts = entries();
+        let updates = vec(
+            (any::<Index>(), any::<Index>(), value()),
+            0..=MAX_OPERATIONS,
+        );
+
+        (inserts, updates)
+            .prop_map(|(inserts, updates)| {
+                // Schedule each update after the insert of the key it updates.
+                let mut scheduled = vec![Vec::new(); inserts.len()];
+                for (after, key, value) in updates {
+                    let after = after.index(inserts.len());
+                    let (key, _) = inserts[key.index(after + 1)];
+                    scheduled[after].push(Operation::Update(key, value));
+                }
+
+                Operations(
+                    inserts
+                        .into_iter()
+                        .zip(scheduled)
+                        .flat_map(|((key, value), updates)| {
+                            std::iter::once(Operation::Insert(key, value)).chain(updates)
+                        })
+                        .collect(),
+                )
+            })
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tiny_keccak::Keccak;
+
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn prop_verify_chains(operations: Operations) {
+            let mut imt = Imt::new(Keccak::v256);
+
+            let mut root = imt.root;
+            for operation in &operations.0 {
+                let mutate = operation.apply(&mut imt);
+                root = mutate.verify(Keccak::v256, root).unwrap();
+                prop_assert_eq!(root, imt.root);
+            }
+        }
+
+        #[test]
+        fn prop_recompute_root(operations: Operations) {
+            let mut imt = Imt::new(Keccak::v256);
+
+            for operation in &operations.0 {
+                operation.apply(&mut imt);
+                prop_assert_eq!(imt.recompute_root(), imt.root);
+            }
+        }
+
+        #[test]
+        fn prop_flipped_byte_is_rejected(operations: Operations, mask in 1..=u8::MAX) {
+            let mut imt = Imt::new(Keccak::v256);
+
+            let mut old_root = imt.root;
+            let mut mutate = None;
+            for operation in &operations.0 {
+                old_root = imt.root;
+                mutate = Some(operation.apply(&mut imt));
+            }
+
+            // Flip each byte of the last mutation in turn, it must not verify to the same root.
+            let bytes = bincode::serialize(&mutate.unwrap()).unwrap();
+            for i in 0..bytes.len() {
+                let mut tampered = bytes.clone();
+                tampered[i] ^= mask;
+
+                let tampered: bincode::Result<IMTMutate<[u8; 32], [u8; 32]>> =
+                    bincode::deserialize(&tampered);
+                let Ok(tampered) = tampered else {
+                    continue;
+                };
+                let res = tampered.verify(Keccak::v256, old_root);
+                prop_assert!(
+                    !res.is_ok_and(|root| root == imt.root),
+                    "flipping byte {} is not detected",
+                    i
+                );
+            }
+        }
+
+        #[test]
+        fn prop_order_independent(
+            (entries, shuffled) in entries().prop_flat_map(|entries| {
+                (Just(entries.clone()), Just(entries).prop_shuffle())
+            }),
+        ) {
+            let mut imt = Imt::new(Keccak::v256);
+            for (key, value) in entries {
+                imt.insert_node(key, value);
+            }
+
+            let mut shuffled_imt = Imt::new(Keccak::v256);
+            for (key, value) in shuffled {
+                shuffled_imt.insert_node(key, value);
+            }
+
+            prop_assert!(imt.same_entries(&shuffled_imt));
+        }
+
+        #[test]
+        fn prop_predecessor_successor(
+            entries in entries(),
+            removed in any::<Index>(),
+            probes in vec(key(), 1..=MAX_OPERATIONS),
+        ) {
+            let mut imt = Imt::new(Keccak::v256);
+            for (key, value) in &entries {
+                imt.insert_node(*key, *value);
+            }
+            let removed = entries[removed.index(entries.len())].0;
+            imt.remove_node(&removed);
+
+            let mut sorted: Vec<_> = entries.iter().map(|(key, _)| *key).filter(|key| *key != removed).collect();
+            sorted.sort_unstable();
+
+            // Probe the keys of the IMT, the removed one and arbitrary keys.
+            for probe in sorted.iter().chain([&removed]).chain(&probes) {
+                let predecessor = sorted.iter().rev().find(|key| *key < probe);
+                let successor = sorted.iter().find(|key| *key > probe);
+                prop_assert_eq!(imt.predecessor(probe).map(|node| node.key), predecessor.copied());
+                prop_assert_eq!(imt.successor(probe).map(|node| node.key), successor.copied());
+            }
+        }
+
+        #[test]
+        fn prop_arbitrary_node_is_not_in_imt(
+            operations: Operations,
+            node: IMTNode<[u8; 32], [u8; 32]>,
+        ) {
+            let mut imt = Imt::new(Keccak::v256);
+            for operation in &operations.0 {
+                operation.apply(&mut imt);
+            }
+
+            // Updating an arbitrary node with the siblings of another node is rejected.
+            let Operation::Insert(key, _) = operations.0[0] else {
+                unreachable!("the first operation is an insert");
+            };
+            prop_assume!(node.key != key);
+
+            let siblings = imt.siblings(&key);
+            let mutate = IMTMutate::update(imt.root, imt.size, node, siblings, node.value);
+            prop_assert!(mutate.verify(Keccak::v256, imt.root).is_err());
+        }
+    }
+}
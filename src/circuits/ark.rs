@@ -0,0 +1,191 @@
+//! Support of BN254 scalar field elements, for IMTs hashed with Poseidon.
+//!
+//! Poseidon absorbs field elements rather than bytes: `FrKey` holds the canonical big endian
+//! bytes of an `Fr`, so that its byte order matches the integer order of the element, and
+//! `Poseidon` absorbs each input as one field element.
+
+use std::mem::size_of;
+
+use anyhow::{ensure, Error, Result};
+use ark_bn254::Fr;
+use ark_ff::{BigInteger, PrimeField};
+use light_poseidon::{Poseidon as PoseidonHasher, PoseidonBytesHasher};
+use serde::{Deserialize, Serialize};
+use tiny_keccak::Hasher;
+
+use crate::{circuits::node::Hashor, Hash};
+
+/// BN254 scalar field element, stored as its canonical big endian bytes.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize,
+)]
+#[serde(try_from = "[u8; 32]")]
+pub struct FrKey([u8; 32]);
+
+/// `FrKey` used as a value.
+pub type FrValue = FrKey;
+
+impl FrKey {
+    /// Creates an `FrKey` from the big endian `bytes` of a field element, which must be lower
+    /// than the modulus.
+    pub fn from_be_bytes(bytes: [u8; 32]) -> Result<Self> {
+        let element = Fr::from_be_bytes_mod_order(&bytes);
+        ensure!(
+            element.into_bigint().to_bytes_be() == bytes,
+            "bytes are not a canonical field element"
+        );
+
+        Ok(Self(bytes))
+    }
+
+    /// Returns the field element.
+    pub fn get(&self) -> Fr {
+        Fr::from_be_bytes_mod_order(&self.0)
+    }
+}
+
+impl AsRef<[u8]> for FrKey {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Fr> for FrKey {
+    fn from(element: Fr) -> Self {
+        let mut bytes = [0; 32];
+        bytes.copy_from_slice(&element.into_bigint().to_bytes_be());
+        Self(bytes)
+    }
+}
+
+impl From<FrKey> for Fr {
+    fn from(key: FrKey) -> Self {
+        key.get()
+    }
+}
+
+impl TryFrom<[u8; 32]> for FrKey {
+    type Error = Error;
+
+    fn try_from(bytes: [u8; 32]) -> Result<Self> {
+        Self::from_be_bytes(bytes)
+    }
+}
+
+/// Circom compatible Poseidon hashor over BN254, usable as an IMT hasher factory with
+/// `Poseidon::new`.
+///
+/// Each non empty `update` is absorbed as one field element, hence must be the big endian
+/// bytes of a canonical element: keys, values and hashes are, as well as the IMT size and node
+/// indices. At most 12 elements can be absorbed, and `finalize` panics otherwise.
+#[derive(Debug, Clone, Default)]
+pub struct Poseidon(Vec<Vec<u8>>);
+
+impl Poseidon {
+    /// Creates a new Poseidon hashor.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Hashor for Poseidon {
+    const OUTPUT_LEN: usize = 32;
+
+    fn finalize_reset(&mut self, output: &mut [u8]) {
+        let inputs: Vec<_> = self.0.iter().map(Vec::as_slice).collect();
+        let hash = PoseidonHasher::<Fr>::new_circom(inputs.len())
+            .and_then(|mut poseidon| poseidon.hash_bytes_be(&inputs))
+            .expect("Poseidon inputs are canonical field elements");
+
+        output.copy_from_slice(&hash[..output.len()]);
+        self.0.clear();
+    }
+}
+
+const _: () = assert!(<Poseidon as Hashor>::OUTPUT_LEN == size_of::<Hash>());
+
+impl Hasher for Poseidon {
+    fn update(&mut self, input: &[u8]) {
+        // Empty leaf prefixes absorb nothing.
+        if !input.is_empty() {
+            self.0.push(input.to_vec());
+        }
+    }
+
+    fn finalize(mut self, output: &mut [u8]) {
+        self.finalize_reset(output);
+    }
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use super::*;
+    use crate::circuits::imt::Imt;
+
+    /// Returns the big endian bytes of the BN254 scalar field modulus.
+    fn modulus() -> [u8; 32] {
+        let mut bytes = [0; 32];
+        bytes.copy_from_slice(&Fr::MODULUS.to_bytes_be());
+        bytes
+    }
+
+    #[test]
+    fn test_modulus_boundary() {
+        let max = FrKey::from(-Fr::from(1_u64));
+        let mut bytes = modulus();
+        bytes[31] -= 1;
+        assert_eq!(max.as_ref(), bytes);
+        assert_eq!(FrKey::from_be_bytes(bytes).unwrap(), max);
+        assert_eq!(max.get(), -Fr::from(1_u64));
+
+        for bytes in [modulus(), [0xff; 32], {
+            let mut bytes = modulus();
+            bytes[31] += 1;
+            bytes
+        }] {
+            let res = FrKey::from_be_bytes(bytes);
+            assert!(
+                matches!(res, Err(e) if e.to_string() == "bytes are not a canonical field element")
+            );
+            assert!(FrKey::try_from(bytes).is_err());
+            assert!(bincode::deserialize::<FrKey>(&bincode::serialize(&bytes).unwrap()).is_err());
+        }
+
+        // The byte order matches the integer order of the elements.
+        assert!(FrKey::from(Fr::from(255_u64)) < FrKey::from(Fr::from(256_u64)));
+        assert!(FrKey::from(Fr::from(u64::MAX)) < max);
+        assert_eq!(FrKey::default().get(), Fr::from(0_u64));
+    }
+
+    #[test]
+    fn test_poseidon_imt() {
+        let mut imt = Imt::new(Poseidon::new);
+
+        let max = FrKey::from(-Fr::from(1_u64));
+        let keys = [
+            FrKey::from(Fr::from(256_u64)),
+            max,
+            FrKey::from(Fr::from(1_u64)),
+            FrKey::from(-Fr::from(2_u64)),
+        ];
+        for key in keys {
+            let old_root = imt.root;
+            let mutate = imt.insert_node(key, max);
+            assert_eq!(mutate.verify(Poseidon::new, old_root).unwrap(), imt.root);
+        }
+        imt.validate_invariants().unwrap();
+
+        let old_root = imt.root;
+        let mutate = imt.update_node(keys[0], FrKey::from(Fr::from(42_u64)));
+        assert_eq!(mutate.verify(Poseidon::new, old_root).unwrap(), imt.root);
+
+        // Low nullifiers follow the integer order, up to the modulus.
+        let ln = imt.low_nullifier(&FrKey::from(Fr::from(300_u64)));
+        assert_eq!(ln.key, keys[0]);
+        assert_eq!(ln.next_key, keys[3]);
+        assert_eq!(imt.last_key(), Some(max));
+
+        let proof = imt.prove_membership(&max).unwrap();
+        proof.verify(Poseidon::new, imt.root).unwrap();
+    }
+}
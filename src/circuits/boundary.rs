@@ -0,0 +1,231 @@
+//! Proofs that a node holds the smallest or the largest key of an IMT, e.g. for range-set
+//! membership arguments.
+
+use anyhow::{ensure, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::Hash;
+
+use super::{
+    node::{Hashor, IMTNode, Key, LeafLayout, NodeHasher, Value},
+    node_exists,
+    siblings::Siblings,
+    CommitmentScheme,
+};
+
+/// Proof that `node` holds the smallest key of the IMT: the zero node links to it.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MinProof<K: Key, V: Value, S = Vec<Option<Hash>>> {
+    pub root: Hash,
+    pub size: u64,
+    pub zero_node: IMTNode<K, V>,
+    pub zero_siblings: S,
+
+    pub node: IMTNode<K, V>,
+    pub node_siblings: S,
+}
+
+impl<K: Key, V: Value, S: Siblings> MinProof<K, V, S> {
+    /// Verifies that `self.node` holds the smallest key of the IMT commited to in `root`.
+    pub fn verify<H: Hashor>(&self, hasher_factory: fn() -> H, root: Hash) -> Result<()> {
+        self.verify_with_layout(
+            hasher_factory,
+            CommitmentScheme::default(),
+            LeafLayout::default(),
+            root,
+        )
+    }
+
+    /// Same as `verify` under the given commitment `scheme`, the leaves being hashed with the
+    /// given `layout`.
+    pub fn verify_with_layout<H: Hashor, N: NodeHasher<K, V>>(
+        &self,
+        hasher_factory: fn() -> H,
+        scheme: CommitmentScheme,
+        layout: N,
+        root: Hash,
+    ) -> Result<()> {
+        ensure!(root == self.root, "MinProof.root is stale");
+
+        // The zero node is always at index 0.
+        ensure!(
+            self.zero_node.index == 0
+                && *self.zero_node.key.as_ref() == *K::default().as_ref()
+                && node_exists(
+                    hasher_factory,
+                    scheme,
+                    layout,
+                    &self.root,
+                    self.size,
+                    &self.zero_node,
+                    &self.zero_siblings
+                ),
+            "MinProof.zero_node is invalid"
+        );
+
+        // Keys are linked in increasing order from the zero node.
+        ensure!(
+            *self.node.key.as_ref() != *K::default().as_ref()
+                && self.zero_node.next_key == self.node.key,
+            "MinProof.node is not the min node"
+        );
+        ensure!(
+            self.node.index < self.size
+                && node_exists(
+                    hasher_factory,
+                    scheme,
+                    layout,
+                    &self.root,
+                    self.size,
+                    &self.node,
+                    &self.node_siblings
+                ),
+            "MinProof.node is not in the IMT"
+        );
+
+        Ok(())
+    }
+}
+
+/// Proof that `node` holds the largest key of the IMT: it links to no other node.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MaxProof<K: Key, V: Value, S = Vec<Option<Hash>>> {
+    pub root: Hash,
+    pub size: u64,
+    pub node: IMTNode<K, V>,
+    pub node_siblings: S,
+}
+
+impl<K: Key, V: Value, S: Siblings> MaxProof<K, V, S> {
+    /// Verifies that `self.node` holds the largest key of the IMT commited to in `root`.
+    pub fn verify<H: Hashor>(&self, hasher_factory: fn() -> H, root: Hash) -> Result<()> {
+        self.verify_with_layout(
+            hasher_factory,
+            CommitmentScheme::default(),
+            LeafLayout::default(),
+            root,
+        )
+    }
+
+    /// Same as `verify` under the given commitment `scheme`, the leaves being hashed with the
+    /// given `layout`.
+    pub fn verify_with_layout<H: Hashor, N: NodeHasher<K, V>>(
+        &self,
+        hasher_factory: fn() -> H,
+        scheme: CommitmentScheme,
+        layout: N,
+        root: Hash,
+    ) -> Result<()> {
+        ensure!(root == self.root, "MaxProof.root is stale");
+
+        // Neither the zero node nor a vacant node hold a key, while both link to no node.
+        ensure!(
+            *self.node.key.as_ref() != *K::default().as_ref()
+                && *self.node.next_key.as_ref() == *K::default().as_ref(),
+            "MaxProof.node is not the max node"
+        );
+        ensure!(
+            self.node.index < self.size
+                && node_exists(
+                    hasher_factory,
+                    scheme,
+                    layout,
+                    &self.root,
+                    self.size,
+                    &self.node,
+                    &self.node_siblings
+                ),
+            "MaxProof.node is not in the IMT"
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use tiny_keccak::Keccak;
+
+    use super::*;
+    use crate::circuits::imt::Imt;
+
+    #[test]
+    fn test_boundaries() {
+        let mut imt = Imt::new(Keccak::v256);
+        assert!(imt.min_proof().is_none());
+        assert!(imt.max_proof().is_none());
+
+        for key in [[5; 32], [2; 32], [9; 32], [7; 32]] {
+            imt.insert_node(key, [42; 32]);
+        }
+
+        let min = imt.min_proof().unwrap();
+        assert_eq!(min.node.key, [2; 32]);
+        min.verify(Keccak::v256, imt.root).unwrap();
+
+        let max = imt.max_proof().unwrap();
+        assert_eq!(max.node.key, [9; 32]);
+        max.verify(Keccak::v256, imt.root).unwrap();
+
+        // Proofs only hold for the root they were generated at.
+        let old_root = imt.root;
+        imt.insert_node([1; 32], [42; 32]);
+        let res = min.verify(Keccak::v256, imt.root);
+        assert!(matches!(res, Err(e) if e.to_string() == "MinProof.root is stale"));
+        assert_eq!(imt.min_proof().unwrap().node.key, [1; 32]);
+        assert_eq!(imt.max_proof().unwrap().node.key, [9; 32]);
+
+        // Another node is neither the min nor the max node.
+        let (node, node_siblings) = (imt.low_nullifier(&[6; 32]), imt.siblings(&[5; 32]));
+        let forged = MinProof {
+            node,
+            node_siblings: node_siblings.clone(),
+            ..imt.min_proof().unwrap()
+        };
+        let res = forged.verify(Keccak::v256, imt.root);
+        assert!(matches!(res, Err(e) if e.to_string() == "MinProof.node is not the min node"));
+
+        let forged = MaxProof {
+            root: imt.root,
+            size: imt.size,
+            node,
+            node_siblings,
+        };
+        let res = forged.verify(Keccak::v256, imt.root);
+        assert!(matches!(res, Err(e) if e.to_string() == "MaxProof.node is not the max node"));
+
+        // A max node that is not in the IMT.
+        let forged = MaxProof {
+            node: IMTNode {
+                value: [43; 32],
+                ..max.node
+            },
+            ..max
+        };
+        let res = forged.verify(Keccak::v256, old_root);
+        assert!(matches!(res, Err(e) if e.to_string() == "MaxProof.node is not in the IMT"));
+    }
+
+    #[test]
+    fn test_boundaries_vacant_slot() {
+        let mut imt = Imt::new(Keccak::v256);
+        for key in [[1; 32], [2; 32], [3; 32]] {
+            imt.insert_node(key, [42; 32]);
+        }
+        imt.remove_node(&[3; 32]);
+
+        // A vacant node links to no node but holds no key.
+        let forged = MaxProof {
+            root: imt.root,
+            size: imt.size,
+            node: IMTNode::<[u8; 32], [u8; 32]>::vacant(3),
+            node_siblings: imt.siblings_cached(3),
+        };
+        let res = forged.verify(Keccak::v256, imt.root);
+        assert!(matches!(res, Err(e) if e.to_string() == "MaxProof.node is not the max node"));
+
+        let max = imt.max_proof().unwrap();
+        assert_eq!(max.node.key, [2; 32]);
+        max.verify(Keccak::v256, imt.root).unwrap();
+    }
+}
@@ -0,0 +1,174 @@
+//! `Imt` shared between a single writer and concurrent readers.
+
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use anyhow::Result;
+
+use crate::Hash;
+
+use super::{
+    imt::Imt,
+    inclusion::IMTInclusion,
+    mutate::IMTMutate,
+    node::{Hashor, IMTNode, Key, Value},
+    root::Root,
+};
+
+/// `Imt` behind a `RwLock`: readers generate proofs concurrently while mutations take exclusive
+/// access, so that a reader never observes a half applied mutation, e.g. an ln node relinked to
+/// a node that is not inserted yet.
+///
+/// Readers return owned data, each call being consistent with the root at the time it was made.
+/// Use `read` to make several calls against the same root.
+///
+/// A mutation panicking, e.g. on a key conflict, poisons the lock: any later call panics too.
+#[derive(Debug)]
+pub struct ConcurrentImt<H: Hashor, K: Key, V: Value> {
+    imt: RwLock<Imt<H, K, V>>,
+}
+
+impl<H: Hashor, K: Key, V: Value> ConcurrentImt<H, K, V> {
+    /// Shares the given `imt`.
+    pub fn new(imt: Imt<H, K, V>) -> Self {
+        Self {
+            imt: RwLock::new(imt),
+        }
+    }
+
+    /// Returns the shared IMT.
+    pub fn into_inner(self) -> Imt<H, K, V> {
+        self.imt
+            .into_inner()
+            .expect("ConcurrentImt lock is poisoned")
+    }
+
+    /// Calls `f` with the IMT, no mutation being applied until it returns.
+    pub fn read<R>(&self, f: impl FnOnce(&Imt<H, K, V>) -> R) -> R {
+        f(&self.read_guard())
+    }
+
+    /// Returns the current root along with the IMT size.
+    pub fn root(&self) -> Root {
+        self.read_guard().root()
+    }
+
+    /// Returns the current root hash.
+    pub fn root_hash(&self) -> Hash {
+        self.read_guard().root
+    }
+
+    /// See `Imt::get_value`.
+    pub fn get_value(&self, key: &K) -> Option<V> {
+        self.read_guard().get_value(key)
+    }
+
+    /// See `Imt::low_nullifier`.
+    pub fn low_nullifier(&self, key: &K) -> IMTNode<K, V> {
+        self.read_guard().low_nullifier(key)
+    }
+
+    /// See `Imt::siblings`.
+    pub fn siblings(&self, key: &K) -> Vec<Option<Hash>> {
+        self.read_guard().siblings(key)
+    }
+
+    /// See `Imt::prove_membership`, the proof holding the root it is valid for.
+    pub fn prove_membership(&self, key: &K) -> Result<IMTInclusion<K, V>> {
+        self.read_guard().prove_membership(key)
+    }
+
+    /// See `Imt::insert_node`.
+    pub fn insert_node(&self, key: K, value: V) -> IMTMutate<K, V> {
+        self.write_guard().insert_node(key, value)
+    }
+
+    /// See `Imt::try_insert_node`.
+    pub fn try_insert_node(&self, key: K, value: V) -> Result<IMTMutate<K, V>> {
+        self.write_guard().try_insert_node(key, value)
+    }
+
+    /// See `Imt::update_node`.
+    pub fn update_node(&self, key: K, value: V) -> IMTMutate<K, V> {
+        self.write_guard().update_node(key, value)
+    }
+
+    /// See `Imt::set`.
+    pub fn set(&self, key: K, value: V) -> IMTMutate<K, V> {
+        self.write_guard().set(key, value)
+    }
+
+    fn read_guard(&self) -> RwLockReadGuard<'_, Imt<H, K, V>> {
+        self.imt.read().expect("ConcurrentImt lock is poisoned")
+    }
+
+    fn write_guard(&self) -> RwLockWriteGuard<'_, Imt<H, K, V>> {
+        self.imt.write().expect("ConcurrentImt lock is poisoned")
+    }
+}
+
+impl<H: Hashor, K: Key, V: Value> From<Imt<H, K, V>> for ConcurrentImt<H, K, V> {
+    fn from(imt: Imt<H, K, V>) -> Self {
+        Self::new(imt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use tiny_keccak::Keccak;
+
+    use super::*;
+
+    #[test]
+    fn test_concurrent_readers() {
+        let imt = Arc::new(ConcurrentImt::new(Imt::new(Keccak::v256)));
+        imt.insert_node([1; 32], [42; 32]);
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let imt = Arc::clone(&imt);
+                thread::spawn(move || {
+                    let mut reads = 0;
+                    loop {
+                        let done = imt.get_value(&[255; 32]).is_some();
+
+                        // Every proof verifies against the root read along with it, whatever the
+                        // insertions in progress.
+                        let (proof, root) =
+                            imt.read(|imt| (imt.prove_membership(&[1; 32]).unwrap(), imt.root));
+                        proof.verify(Keccak::v256, root).unwrap();
+
+                        // The last inserted node is always linked from its ln node.
+                        let (last_key, ln_node) = imt.read(|imt| {
+                            let last_key = imt.last_key().unwrap();
+                            (last_key, imt.predecessor(&last_key).unwrap())
+                        });
+                        assert_eq!(ln_node.next_key, last_key);
+
+                        reads += 1;
+                        if done {
+                            return reads;
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        // Insert keys while the readers prove, each mutation verifying from the previous root.
+        let mut root = imt.root_hash();
+        for i in 2..=255 {
+            let mutate = imt.insert_node([i; 32], [42; 32]);
+            root = mutate.verify(Keccak::v256, root).unwrap();
+            assert_eq!(root, imt.root_hash());
+        }
+
+        for reader in readers {
+            assert!(reader.join().unwrap() > 0);
+        }
+
+        let imt = Arc::into_inner(imt).unwrap().into_inner();
+        imt.validate_invariants().unwrap();
+        assert_eq!(imt.root, root);
+    }
+}
@@ -0,0 +1,262 @@
+//! Verification of IMT proofs as Solidity verifiers do, hashing with `keccak256` over the
+//! `abi.encodePacked` encoding of each step.
+//!
+//! With Keccak256, the `LeafLayout::NATIVE` leaves and the `ETH_SCHEME` commitment scheme, every
+//! hash of the crate is the `keccak256` of the packed encoding of its inputs, in this order:
+//!
+//! - leaf: `abi.encodePacked(bytes32 key, bytes32 value, bytes32 nextKey)`
+//! - parent: `abi.encodePacked(bytes32 left, bytes32 right)`
+//! - lone left child, the last node of a level of odd width: `abi.encodePacked(bytes32 left)`
+//! - root: `abi.encodePacked(bytes32 top, uint256 size)`
+//!
+//! So that a proof generated with `Imt::with_scheme(Keccak::v256, ETH_SCHEME)` verifies
+//! byte-for-byte in the following verifier, given the siblings of the proof that are not `None`:
+//!
+//! ```solidity
+//! function imtRoot(
+//!     bytes32 key,
+//!     bytes32 value,
+//!     bytes32 nextKey,
+//!     uint256 index,
+//!     uint256 size,
+//!     bytes32[] calldata siblings
+//! ) pure returns (bytes32) {
+//!     require(index < size);
+//!     bytes32 hash = keccak256(abi.encodePacked(key, value, nextKey));
+//!     uint256 width = size;
+//!     uint256 s = 0;
+//!     while (width > 1) {
+//!         if (index % 2 == 1) {
+//!             hash = keccak256(abi.encodePacked(siblings[s++], hash));
+//!         } else if (index + 1 < width) {
+//!             hash = keccak256(abi.encodePacked(hash, siblings[s++]));
+//!         } else {
+//!             hash = keccak256(abi.encodePacked(hash));
+//!         }
+//!         index /= 2;
+//!         width = (width + 1) / 2;
+//!     }
+//!     require(s == siblings.length);
+//!     return keccak256(abi.encodePacked(hash, size));
+//! }
+//! ```
+//!
+//! `eth_root` is its Rust transcription. The `bind-index`, `level-tagging` and
+//! `balanced-hashing` features change the hashed bytes and are not supported by it.
+
+use anyhow::{ensure, Context, Result};
+use tiny_keccak::{Hasher, Keccak};
+
+use crate::Hash;
+
+use super::{
+    inclusion::IMTInclusion,
+    mutate::IMTMutate,
+    node::{IMTNode, LeafLayout},
+    CommitmentScheme, SizeEncoding,
+};
+
+/// Commitment scheme of Solidity verifiers, folding the size into the root as a `uint256`.
+pub const ETH_SCHEME: CommitmentScheme = CommitmentScheme::SizeInRoot(SizeEncoding::U256BigEndian);
+
+/// Computes the root of the IMT of `size` nodes holding `node`, as the Solidity `imtRoot`
+/// verifier does, from the `siblings` of the node that are not `None`.
+pub fn eth_root(node: &IMTNode<Hash, Hash>, size: u64, siblings: &[Hash]) -> Result<Hash> {
+    // Out of bounds indices would alias the leaves they match once halved.
+    ensure!(node.index < size, "node.index is invalid");
+
+    let mut hash = keccak256(&[&node.key, &node.value, &node.next_key]);
+
+    let mut siblings = siblings.iter();
+    let (mut index, mut width) = (node.index, size);
+    while width > 1 {
+        hash = if !index.is_multiple_of(2) {
+            let sibling = siblings.next().context("siblings are invalid")?;
+            keccak256(&[sibling, &hash])
+        } else if index + 1 < width {
+            let sibling = siblings.next().context("siblings are invalid")?;
+            keccak256(&[&hash, sibling])
+        } else {
+            keccak256(&[&hash])
+        };
+
+        index /= 2;
+        width = width.div_ceil(2);
+    }
+    ensure!(siblings.next().is_none(), "siblings are invalid");
+
+    let mut size_word = [0; 32];
+    size_word[24..].copy_from_slice(&size.to_be_bytes());
+    Ok(keccak256(&[&hash, &size_word]))
+}
+
+/// Hashes the packed encoding of `words`.
+fn keccak256(words: &[&Hash]) -> Hash {
+    let mut hash = [0; 32];
+
+    let mut hasher = Keccak::v256();
+    for word in words {
+        hasher.update(*word);
+    }
+    hasher.finalize(&mut hash);
+
+    hash
+}
+
+impl IMTInclusion<Hash, Hash> {
+    /// Verifies that `self.node` is in the IMT commited to in `root`, hashed with Keccak256
+    /// under the `ETH_SCHEME`, as a Solidity verifier would.
+    pub fn verify_eth(&self, root: Hash) -> Result<()> {
+        self.verify_with_layout(Keccak::v256, ETH_SCHEME, LeafLayout::NATIVE, root)
+    }
+}
+
+impl IMTMutate<Hash, Hash> {
+    /// Verifies the IMT mutation hashed with Keccak256 under the `ETH_SCHEME`, as a Solidity
+    /// verifier would, and returns the new updated root.
+    pub fn verify_eth(&self, old_root: Hash) -> Result<Hash> {
+        self.verify_with_scheme(Keccak::v256, ETH_SCHEME, old_root, self.old_size())
+    }
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use super::*;
+    use crate::circuits::{hex::Hex, imt::Imt};
+
+    /// IMT holding the keys `[1; 32]..=[n; 32]` with the value `[42; 32]`.
+    fn eth_imt(n: u8) -> Imt<Keccak, Hash, Hash> {
+        let mut imt = Imt::with_scheme(Keccak::v256, ETH_SCHEME);
+        for i in 1..=n {
+            imt.insert_node([i; 32], [42; 32]);
+        }
+        imt
+    }
+
+    #[test]
+    fn test_verify_eth() {
+        let mut imt = eth_imt(0);
+        for i in 1..=20 {
+            let old_root = imt.root;
+            let insert = imt.insert_node([i; 32], [42; 32]);
+            assert_eq!(insert.verify_eth(old_root).unwrap(), imt.root);
+
+            let old_root = imt.root;
+            let update = imt.update_node([i / 2; 32], [i; 32]);
+            assert_eq!(update.verify_eth(old_root).unwrap(), imt.root);
+
+            let inclusion = imt.prove_membership(&[i; 32]).unwrap();
+            inclusion.verify_eth(imt.root).unwrap();
+        }
+
+        let res = imt
+            .prove_membership(&[1; 32])
+            .unwrap()
+            .verify_eth(eth_imt(4).root);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTInclusion.root is stale"));
+
+        // Proofs of IMTs folding the size on 8 bytes, as the default scheme does, do not verify.
+        let mut imt = Imt::new(Keccak::v256);
+        let old_root = imt.root;
+        let insert = imt.insert_node([1; 32], [42; 32]);
+        assert!(insert.verify_eth(old_root).is_err());
+    }
+
+    #[test]
+    #[cfg(not(any(
+        feature = "bind-index",
+        feature = "balanced-hashing",
+        feature = "level-tagging"
+    )))]
+    fn test_eth_root() {
+        // The Solidity reference agrees with the IMT for every node and size.
+        for n in 0..=20 {
+            let imt = eth_imt(n);
+            for i in 0..=n {
+                let inclusion = imt.prove_membership(&[i; 32]).unwrap();
+                let siblings: Vec<_> = inclusion.node_siblings.iter().flatten().copied().collect();
+                let root = eth_root(&inclusion.node, imt.size, &siblings).unwrap();
+                assert_eq!(root, imt.root);
+
+                // Every sibling is consumed.
+                let mut extra = siblings.clone();
+                extra.push([0; 32]);
+                let res = eth_root(&inclusion.node, imt.size, &extra);
+                assert!(matches!(res, Err(e) if e.to_string() == "siblings are invalid"));
+                if let Some((_, missing)) = siblings.split_last() {
+                    let res = eth_root(&inclusion.node, imt.size, missing);
+                    assert!(matches!(res, Err(e) if e.to_string() == "siblings are invalid"));
+                }
+            }
+        }
+
+        // The last node of a size 3 IMT relabelled at index 6 would climb the same path.
+        let imt = eth_imt(2);
+        let inclusion = imt.prove_membership(&[2; 32]).unwrap();
+        let siblings: Vec<_> = inclusion.node_siblings.iter().flatten().copied().collect();
+        let aliased = IMTNode {
+            index: 6,
+            ..inclusion.node
+        };
+        let res = eth_root(&aliased, imt.size, &siblings);
+        assert!(matches!(res, Err(e) if e.to_string() == "node.index is invalid"));
+    }
+
+    /// Vector to check a Solidity verifier against, e.g. in a Foundry test: the IMT holds the
+    /// keys `0x0101..01` to `0x0404..04` with the value `0x2a2a..2a`, its size is 5.
+    #[test]
+    #[cfg(not(any(
+        feature = "bind-index",
+        feature = "balanced-hashing",
+        feature = "level-tagging"
+    )))]
+    fn test_eth_vector() {
+        let imt = eth_imt(4);
+        assert_eq!(imt.size, 5);
+        assert_eq!(
+            Hex(&imt.root).to_string(),
+            "0xfe00a70df95a9295a51750ae19b2e3d2bc546c3c27f3d83775b83cf660a6aee0"
+        );
+
+        // Inclusion of the key `0x0202..02`, at index 2, linking to `0x0303..03`.
+        let inclusion = imt.prove_membership(&[2; 32]).unwrap();
+        assert_eq!(
+            (inclusion.node.index, inclusion.node.next_key),
+            (2, [3; 32])
+        );
+        let siblings: Vec<_> = inclusion
+            .node_siblings
+            .iter()
+            .map(|sibling| Hex(&sibling.unwrap()).to_string())
+            .collect();
+        assert_eq!(
+            siblings,
+            [
+                "0x479c594544ee2ca3a447fa08e40cbb7c58575ea8661078bd2a36c7ae44b27767",
+                "0x9eb942806417f5cfe8708bfa79e645ed446c67767d0cdc6e5cdbab948ddb43f6",
+                "0x6ef56bdbd29cae91d3206df6023137af31dd61428d4f87a7d407dcbcdc98f2bc",
+            ]
+        );
+
+        // Inclusion of the key `0x0404..04`, at index 4, the last node: it is a lone left child
+        // in the first two levels.
+        let inclusion = imt.prove_membership(&[4; 32]).unwrap();
+        assert_eq!(
+            (inclusion.node.index, inclusion.node.next_key),
+            (4, [0; 32])
+        );
+        let siblings: Vec<_> = inclusion
+            .node_siblings
+            .iter()
+            .map(|sibling| sibling.map(|sibling| Hex(&sibling).to_string()))
+            .collect();
+        assert_eq!(
+            siblings,
+            [
+                None,
+                None,
+                Some("0xa6735500fce21edcc33963541f1a9c0cdd150013e0db56671e527345fadc4f36".into()),
+            ]
+        );
+    }
+}
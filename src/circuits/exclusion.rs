@@ -0,0 +1,178 @@
+//! Proof that none of a batch of keys is in an IMT, e.g. to check nullifiers.
+
+use anyhow::{ensure, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::Hash;
+
+use super::{
+    multiproof::IMTMultiProof,
+    node::{Hashor, Key, LeafLayout, NodeHasher, Value},
+    CommitmentScheme,
+};
+
+/// Proof that none of `keys` is in the IMT, each of them falling in the gap of a low nullifier
+/// node of `proof`.
+///
+/// Keys falling in the same gap share their low nullifier node, and the low nullifier nodes
+/// share the hashes their sibling paths have in common.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct IMTBatchExclusion<K: Key, V: Value> {
+    pub keys: Vec<K>,
+    pub proof: IMTMultiProof<K, V>,
+}
+
+impl<K: Key, V: Value> IMTBatchExclusion<K, V> {
+    /// Verifies that none of `self.keys` is in the IMT of `size` nodes commited to in `root`.
+    pub fn verify<H: Hashor>(
+        &self,
+        hasher_factory: fn() -> H,
+        root: Hash,
+        size: u64,
+    ) -> Result<()> {
+        self.verify_with_layout(
+            hasher_factory,
+            CommitmentScheme::default(),
+            LeafLayout::default(),
+            root,
+            size,
+        )
+    }
+
+    /// Same as `verify` under the given commitment `scheme`, the leaves being hashed with the
+    /// given `layout`.
+    pub fn verify_with_layout<H: Hashor, N: NodeHasher<K, V>>(
+        &self,
+        hasher_factory: fn() -> H,
+        scheme: CommitmentScheme,
+        layout: N,
+        root: Hash,
+        size: u64,
+    ) -> Result<()> {
+        ensure!(!self.keys.is_empty(), "IMTBatchExclusion.keys is empty");
+
+        self.proof
+            .verify_with_layout(hasher_factory, scheme, layout, root, size)?;
+
+        // The low nullifier of a key is the node holding the largest smaller key.
+        let mut lns: Vec<_> = self.proof.nodes.iter().collect();
+        lns.sort_unstable_by(|a, b| a.key.as_ref().cmp(b.key.as_ref()));
+
+        for (i, key) in self.keys.iter().enumerate() {
+            let position = lns.partition_point(|ln| ln.key.as_ref() < key.as_ref());
+            ensure!(
+                position > 0 && lns[position - 1].is_ln_of(key),
+                "IMTBatchExclusion.keys[{i}] is not excluded"
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use tiny_keccak::Keccak;
+
+    use super::*;
+    use crate::circuits::imt::Imt;
+
+    /// Returns an IMT holding the keys multiple of 10 up to 200.
+    fn imt() -> Imt<Keccak, [u8; 32], [u8; 32]> {
+        let mut imt = Imt::new(Keccak::v256);
+        for i in 1..=20 {
+            imt.insert_node([i * 10; 32], [42; 32]);
+        }
+        imt
+    }
+
+    #[test]
+    fn test_batch_exclusion() {
+        let imt = imt();
+
+        // Keys sharing a gap share their low nullifier node.
+        let keys: Vec<_> = [11, 12, 13, 55, 56, 201, 255, 1, 12]
+            .map(|i| [i; 32])
+            .to_vec();
+        let exclusion = imt.prove_non_membership_many(&keys);
+        assert_eq!(exclusion.proof.nodes.len(), 4);
+        exclusion.verify(Keccak::v256, imt.root, imt.size).unwrap();
+
+        // Proofs only hold for the root they were generated at.
+        let mut other = imt.clone();
+        other.insert_node([250; 32], [42; 32]);
+        let res = exclusion.verify(Keccak::v256, other.root, other.size);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_batch_exclusion_is_smaller() {
+        let imt = imt();
+
+        let keys: Vec<_> = (100..200)
+            .filter(|i| i % 10 != 0)
+            .map(|i| [i; 32])
+            .collect();
+        let exclusion = imt.prove_non_membership_many(&keys);
+        exclusion.verify(Keccak::v256, imt.root, imt.size).unwrap();
+
+        let batch_len = bincode::serialize(&exclusion).unwrap().len();
+        let single_len: usize = keys
+            .iter()
+            .map(|key| {
+                let proof = (key, imt.low_nullifier_proof(key));
+                bincode::serialize(&proof).unwrap().len()
+            })
+            .sum();
+        assert!(batch_len * 4 < single_len);
+    }
+
+    #[test]
+    fn test_batch_exclusion_existing_key() {
+        let imt = imt();
+
+        let mut exclusion = imt.prove_non_membership_many(&[[11; 32], [35; 32], [42; 32]]);
+        exclusion.verify(Keccak::v256, imt.root, imt.size).unwrap();
+
+        // The key 30 is in the IMT, no node of the proof excludes it.
+        exclusion.keys.insert(1, [30; 32]);
+        let res = exclusion.verify(Keccak::v256, imt.root, imt.size);
+        assert!(
+            matches!(res, Err(e) if e.to_string() == "IMTBatchExclusion.keys[1] is not excluded")
+        );
+
+        // The key 60 has no low nullifier in the proof.
+        exclusion.keys[1] = [60; 32];
+        let res = exclusion.verify(Keccak::v256, imt.root, imt.size);
+        assert!(
+            matches!(res, Err(e) if e.to_string() == "IMTBatchExclusion.keys[1] is not excluded")
+        );
+
+        // Nor has the zero key.
+        exclusion.keys[1] = [0; 32];
+        let res = exclusion.verify(Keccak::v256, imt.root, imt.size);
+        assert!(
+            matches!(res, Err(e) if e.to_string() == "IMTBatchExclusion.keys[1] is not excluded")
+        );
+    }
+
+    #[test]
+    fn test_batch_exclusion_empty_imt() {
+        let imt = Imt::<_, [u8; 32], [u8; 32]>::new(Keccak::v256);
+
+        // Every key falls in the gap of the zero node.
+        let keys: Vec<_> = (1..=255).map(|i| [i; 32]).collect();
+        let exclusion = imt.prove_non_membership_many(&keys);
+        assert_eq!(exclusion.proof.nodes.len(), 1);
+        assert!(exclusion.proof.decommitments.is_empty());
+        exclusion.verify(Keccak::v256, imt.root, imt.size).unwrap();
+
+        // A batch of no key proves nothing.
+        let exclusion = IMTBatchExclusion {
+            keys: vec![],
+            proof: exclusion.proof,
+        };
+        let res = exclusion.verify(Keccak::v256, imt.root, imt.size);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTBatchExclusion.keys is empty"));
+    }
+}
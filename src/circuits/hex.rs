@@ -0,0 +1,67 @@
+//! Hex formatting of the bytes (keys, values, hashes) held by the IMT types, for their `Debug`
+//! output and the `tracing` events, and parsing of hex hashes.
+
+use std::{fmt, mem::size_of};
+
+use crate::Hash;
+
+use super::siblings::Siblings;
+
+/// Displays bytes (keys, hashes) as a `0x` prefixed hex string without allocating.
+pub(crate) struct Hex<'a>(pub &'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("0x")?;
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Parses a `0x` prefixed hex string into a hash, the inverse of `Hex`.
+pub(crate) fn parse_hash(hex: &str) -> Option<Hash> {
+    let hex = hex.strip_prefix("0x")?;
+    if hex.len() != 2 * size_of::<Hash>() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let mut hash = Hash::default();
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+    }
+
+    Some(hash)
+}
+
+/// Debug formats a sibling path as a list of hex hashes, the levels without sibling showing as
+/// `None`.
+pub(crate) struct HexSiblings<'a, S: ?Sized>(pub &'a S);
+
+impl<S: Siblings + ?Sized> fmt::Debug for HexSiblings<'_, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.levels().map(HexSibling))
+            .finish()
+    }
+}
+
+/// Debug formats a sibling as a hex hash, or `None`.
+struct HexSibling<'a>(Option<&'a Hash>);
+
+impl fmt::Debug for HexSibling<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(hash) => fmt::Display::fmt(&Hex(hash), f),
+            None => f.write_str("None"),
+        }
+    }
+}
@@ -1,36 +1,276 @@
-use std::collections::HashMap;
-use tiny_keccak::{Hasher, Keccak};
+use std::{
+    borrow::Borrow,
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt,
+    mem::size_of,
+    num::NonZeroU64,
+    ops::{Bound, RangeBounds},
+};
+
+use anyhow::{bail, ensure, Result};
+use serde::Serialize;
 
+#[cfg(feature = "oplog")]
+use std::io::Write;
+
+#[cfg(feature = "oplog")]
+use crate::circuits::oplog::OpLog;
 use crate::{
     circuits::{
+        adjacency::IMTAdjacency,
+        boundary::{MaxProof, MinProof},
+        depth_for_size,
+        exclusion::IMTBatchExclusion,
+        hash_level,
+        hex::Hex,
+        inclusion::IMTInclusion,
+        level_width,
+        multiproof::IMTMultiProof,
         mutate::IMTMutate,
-        node::{Hashor, IMTNode, Key, Value},
+        node::{Hashor, IMTNode, Key, LeafLayout, NodeHasher, Value},
+        node_exists,
+        observer::{ImtObserver, Observer},
+        paged::{PagedConfig, PagedHashes},
+        range::IMTRangeProof,
+        root::Root,
+        siblings::SiblingsVec,
+        storage::HashStorage,
+        CommitmentScheme, ImtError,
     },
     Hash,
 };
 
-#[derive(Debug)]
-pub struct Imt<H: Hashor, K: Key, V: Value> {
+/// Domain separation tag of the subset commitments, see `Imt::subset_commitment`.
+const SUBSET_TAG: &[u8] = b"imt.subset";
+
+/// Indexed Merkle Tree.
+///
+/// Each inserted node is appended at index `size`, so the `root` commits to the insertion order:
+/// inserting the same (key; value) pairs in different orders leads to different roots. This is
+/// what allows an insertion to be proven with a single new leaf, order independence is thus not
+/// guaranteed by the root. Use `canonical_root` for a commitment to the entries alone.
+///
+/// Removing a node leaves a vacant slot behind, which the next insertion fills instead of
+/// appending a new leaf. The `size` thus counts every slot, vacant ones included. Removals are
+/// not provable, and neither are the insertions filling their vacant slots: the verifiers only
+/// accept appended nodes.
+///
+/// The IMT is `Send` and `Sync` whenever its keys, values and node hasher are, e.g. to be moved
+/// into a spawned task: the hasher factory is a plain `fn`, and the observer and oplog sink are
+/// required to be `Send + Sync` too.
+#[derive(Clone)]
+pub struct Imt<H: Hashor, K: Key, V: Value, N: NodeHasher<K, V> = LeafLayout> {
     pub root: Hash,
+    /// Number of slots of the IMT, vacant ones included, see `node_count` for the number of
+    /// occupied ones.
     pub size: u64,
     pub depth: u8,
 
     hasher_factory: fn() -> H,
-    nodes: HashMap<K, IMTNode<K, V>>,
-    hashes: HashMap<u8, HashMap<u64, Hash>>,
+    scheme: CommitmentScheme,
+    layout: N,
+    /// Nodes laid out contiguously, the node at index `i` being stored at `nodes[i]`. Vacant
+    /// slots hold a vacant node.
+    nodes: Vec<IMTNode<K, V>>,
+    /// Index of every node, by key.
+    indices: HashMap<K, u64>,
+    /// Index of every node but the zero node, in increasing key order.
+    sorted: BTreeMap<SortedKey<K>, u64>,
+    hashes: HashStorage,
+    vacant_indices: BTreeSet<u64>,
+    /// Key of the node linking to no other node, the zero key if the IMT holds no key.
+    last_key: K,
+    /// Previous values of every node, oldest first, if the IMT is versioned.
+    history: Option<HashMap<K, Vec<V>>>,
+    /// Depth the IMT can not grow beyond, if capped.
+    max_depth: Option<u8>,
+
+    inserts: u64,
+    updates: u64,
+
+    auto_prune: Option<NonZeroU64>,
+    mutations_since_prune: u64,
+
+    /// Number of hashes computed by the most recent insertion, update or removal.
+    last_op_hash_count: usize,
+
+    observer: Observer<K, V>,
+
+    #[cfg(feature = "oplog")]
+    oplog: OpLog<K, V>,
+}
+
+/// Key ordered by its bytes, e.g. to sort the keys of an IMT without copying them.
+#[derive(Clone, Copy)]
+struct SortedKey<K>(K);
+
+impl<K: AsRef<[u8]>> PartialEq for SortedKey<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_ref() == other.0.as_ref()
+    }
+}
+
+impl<K: AsRef<[u8]>> Eq for SortedKey<K> {}
+
+impl<K: AsRef<[u8]>> PartialOrd for SortedKey<K> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: AsRef<[u8]>> Ord for SortedKey<K> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.as_ref().cmp(other.0.as_ref())
+    }
+}
+
+/// Allows looking the keys up by their bytes, which are ordered alike.
+impl<K: AsRef<[u8]>> Borrow<[u8]> for SortedKey<K> {
+    fn borrow(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+
+/// Only the root, the size, the depth, the configuration and the nodes are shown, the cached
+/// hashes being left out.
+impl<H: Hashor, K: Key, V: Value, N: NodeHasher<K, V>> fmt::Debug for Imt<H, K, V, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Imt")
+            .field("root", &Hex(&self.root))
+            .field("size", &self.size)
+            .field("depth", &self.depth)
+            .field("scheme", &self.scheme)
+            .field("layout", &self.layout)
+            .field("nodes", &self.nodes)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Statistics about an `Imt`, e.g. for capacity planning.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ImtStats {
+    /// Number of nodes, the zero node included.
+    pub size: u64,
+    /// Current depth of the tree.
+    pub depth: u8,
+    /// Number of nodes held in memory.
+    pub node_count: u64,
+    /// Number of cached hashes in each level, from the leaves up to the top level.
+    pub hashes_per_level: Vec<u64>,
+    /// Estimate of the heap memory used by the nodes and their indices by key, in bytes.
+    pub nodes_heap_bytes: u64,
+    /// Estimate of the heap memory used by the nodes and the cached hashes, in bytes.
+    pub estimated_heap_bytes: u64,
+    /// Number of insertions performed since the IMT was instanciated.
+    pub inserts: u64,
+    /// Number of updates performed since the IMT was instanciated.
+    pub updates: u64,
+}
+
+/// Outcome of an insertion in an `Imt`.
+#[derive(Debug, Clone)]
+pub struct InsertOutcome<K: Key, V: Value> {
+    /// Inserted node, holding the index it was assigned.
+    pub node: IMTNode<K, V>,
+    /// IMT root after the insertion.
+    pub new_root: Hash,
+    /// IMT size after the insertion.
+    pub new_size: u64,
+    /// Insertion to use for zkVM verification.
+    pub mutate: IMTMutate<K, V>,
 }
 
 impl<H: Hashor, K: Key, V: Value> Imt<H, K, V> {
     /// Insanciate a new IMT with the zero node.
     pub fn new(hasher_factory: fn() -> H) -> Self {
+        Self::with_scheme(hasher_factory, Default::default())
+    }
+
+    /// Insanciate a new IMT with the zero node, committing to its size using `scheme`.
+    pub fn with_scheme(hasher_factory: fn() -> H, scheme: CommitmentScheme) -> Self {
+        Self::init(
+            hasher_factory,
+            scheme,
+            HashStorage::Sparse(Default::default()),
+        )
+    }
+
+    /// Insanciate a new IMT with the zero node, storing its hashes contiguously per level.
+    ///
+    /// The dense storage avoids the per entry overhead of the default sparse storage and
+    /// improves locality when climbing the tree.
+    pub fn new_dense(hasher_factory: fn() -> H) -> Self {
+        Self::dense_with_scheme(hasher_factory, Default::default())
+    }
+
+    /// Insanciate a new IMT with the zero node, storing its hashes contiguously per level and
+    /// committing to its size using `scheme`.
+    pub fn dense_with_scheme(hasher_factory: fn() -> H, scheme: CommitmentScheme) -> Self {
+        Self::init(
+            hasher_factory,
+            scheme,
+            HashStorage::Dense(Default::default()),
+        )
+    }
+
+    /// Insanciate a new IMT with the zero node, spilling its hashes to the files of a directory
+    /// and keeping at most a RAM budget of them in memory, as set by the given `config`.
+    ///
+    /// Meant for IMTs whose hashes do not fit in memory: the lower and larger levels are mostly
+    /// read from disk while the top levels stay cached.
+    pub fn new_paged(hasher_factory: fn() -> H, config: PagedConfig) -> Result<Self> {
+        Self::paged_with_scheme(hasher_factory, Default::default(), config)
+    }
+
+    /// Insanciate a new IMT with the zero node, spilling its hashes to disk as set by the given
+    /// `config` and committing to its size using `scheme`.
+    pub fn paged_with_scheme(
+        hasher_factory: fn() -> H,
+        scheme: CommitmentScheme,
+        config: PagedConfig,
+    ) -> Result<Self> {
+        let hashes = HashStorage::Paged(PagedHashes::create(config)?);
+        Ok(Self::init(hasher_factory, scheme, hashes))
+    }
+
+    /// Insanciate a new IMT with the zero node, using the given hashes `storage`.
+    fn init(hasher_factory: fn() -> H, scheme: CommitmentScheme, hashes: HashStorage) -> Self {
+        assert_eq!(
+            H::OUTPUT_LEN,
+            size_of::<Hash>(),
+            "hasher output width does not match the hash width"
+        );
+
         let mut imt = Self {
             root: Default::default(),
             size: 1,
             depth: Default::default(),
 
             hasher_factory,
+            scheme,
+            layout: Default::default(),
             nodes: Default::default(),
-            hashes: Default::default(),
+            indices: Default::default(),
+            sorted: Default::default(),
+            hashes,
+            vacant_indices: Default::default(),
+            last_key: Default::default(),
+            history: None,
+            max_depth: None,
+
+            inserts: 0,
+            updates: 0,
+
+            auto_prune: None,
+            mutations_since_prune: 0,
+
+            last_op_hash_count: 0,
+
+            observer: Default::default(),
+
+            #[cfg(feature = "oplog")]
+            oplog: Default::default(),
         };
 
         let init_node_key = K::default();
@@ -40,52 +280,458 @@ impl<H: Hashor, K: Key, V: Value> Imt<H, K, V> {
             value: Default::default(),
             next_key: Default::default(),
         };
-        imt.nodes.insert(init_node_key, init_node);
+        imt.set_node(init_node);
         imt.refresh_tree(&init_node_key);
+        imt.last_op_hash_count = 0;
+
+        imt
+    }
+
+    /// Insanciate a new IMT holding the given `entries`, hashing each level once instead of
+    /// climbing the tree for every insertion.
+    ///
+    /// The keys must be unique and non zero, nodes are indexed in increasing key order.
+    #[cfg(feature = "test-support")]
+    pub(crate) fn from_entries(hasher_factory: fn() -> H, mut entries: Vec<(K, V)>) -> Self {
+        entries.sort_unstable_by(|(a, _), (b, _)| a.as_ref().cmp(b.as_ref()));
+
+        let mut imt = Self::new(hasher_factory);
+        let mut prev_key = K::default();
+        for (i, (key, value)) in entries.into_iter().enumerate() {
+            assert!(
+                key.as_ref() > prev_key.as_ref(),
+                "keys must be unique and non zero"
+            );
+
+            imt.node_mut(&prev_key)
+                .expect("failed to get node")
+                .next_key = key;
+            imt.set_node(IMTNode {
+                index: i as u64 + 1,
+                key,
+                value,
+                next_key: Default::default(),
+            });
+            prev_key = key;
+        }
 
+        imt.last_key = prev_key;
+        imt.size = imt.nodes.len() as _;
+        imt.refresh_depth();
+        imt.rebuild();
+
+        imt
+    }
+}
+
+impl<H: Hashor, K: Key, V: Value, N: NodeHasher<K, V>> Imt<H, K, V, N> {
+    /// Sets the `observer` notified of every mutation applied to the IMT from now on, replacing
+    /// the previous one if any.
+    pub fn set_observer(&mut self, observer: Box<dyn ImtObserver<K, V>>) {
+        self.observer = Observer::new(observer);
+    }
+
+    /// Sets the `layout` the leaves are hashed with, either a `LeafLayout` or any other
+    /// `NodeHasher`, e.g. to match the roots of an existing deployment, and rehashes the whole
+    /// IMT.
+    ///
+    /// Mutations must then be verified with the same layout, using `verify_with_layout`.
+    pub fn with_layout<L: NodeHasher<K, V>>(self, layout: L) -> Imt<H, K, V, L> {
+        let mut imt = Imt {
+            root: self.root,
+            size: self.size,
+            depth: self.depth,
+
+            hasher_factory: self.hasher_factory,
+            scheme: self.scheme,
+            layout,
+            nodes: self.nodes,
+            indices: self.indices,
+            sorted: self.sorted,
+            hashes: self.hashes,
+            vacant_indices: self.vacant_indices,
+            last_key: self.last_key,
+            history: self.history,
+            max_depth: self.max_depth,
+
+            inserts: self.inserts,
+            updates: self.updates,
+
+            auto_prune: self.auto_prune,
+            mutations_since_prune: self.mutations_since_prune,
+
+            last_op_hash_count: self.last_op_hash_count,
+
+            observer: self.observer,
+
+            #[cfg(feature = "oplog")]
+            oplog: self.oplog,
+        };
+        imt.rebuild();
         imt
     }
 
+    /// Makes the IMT versioned and rehashes the whole IMT: updates then keep the previous values
+    /// of the nodes, and every leaf hash absorbs the number of updates of its node along with its
+    /// current value (see `IMTNode::hash_versioned`).
+    ///
+    /// The `IMTMutate` verifiers do not absorb versions: like removals, the mutations of a
+    /// versioned IMT are not provable.
+    pub fn versioned(mut self) -> Self {
+        self.history = Some(Default::default());
+        self.rebuild();
+        self
+    }
+
+    /// Caps the depth of the IMT to `max_depth`, i.e. its size to `2^max_depth` nodes, to bound
+    /// the memory it uses.
+    ///
+    /// Insertions that would grow the IMT beyond it then fail with `ImtError::CapacityExceeded`,
+    /// filling a vacant slot still being possible.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the IMT is already deeper than `max_depth`.
+    pub fn with_max_depth(mut self, max_depth: u8) -> Self {
+        assert!(self.depth <= max_depth, "IMT is deeper than max_depth");
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Returns the previous values of the given `key`, oldest first, or `None` if the IMT is not
+    /// versioned or does not hold `key`.
+    pub fn value_history(&self, key: &K) -> Option<&[V]> {
+        let history = self.history.as_ref()?;
+        self.indices.get(key)?;
+        Some(history.get(key).map_or(&[], Vec::as_slice))
+    }
+
+    /// Returns the commitment scheme used for the IMT size.
+    pub fn scheme(&self) -> CommitmentScheme {
+        self.scheme
+    }
+
+    /// Returns the layout the leaves are hashed with.
+    pub fn layout(&self) -> N {
+        self.layout
+    }
+
+    /// Returns the root of the IMT, along with its size.
+    pub fn root(&self) -> Root {
+        Root::with_size(self.root, self.size)
+    }
+
+    /// Returns the index the next inserted node will be assigned: the lowest vacant index if
+    /// any, `size` otherwise.
+    pub fn next_index(&self) -> u64 {
+        self.vacant_indices.first().copied().unwrap_or(self.size)
+    }
+
+    /// Returns the depth of the IMT, derived from its `size`.
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+
+    /// Returns the number of levels of the sibling paths of the IMT, e.g. to pre-size buffers.
+    pub fn expected_siblings_len(&self) -> usize {
+        self.depth as _
+    }
+
+    /// Returns the number of nodes in the IMT, the zero node included and the vacant slots
+    /// excluded, i.e. the number of occupied slots out of `size`.
+    pub fn node_count(&self) -> u64 {
+        self.indices.len() as _
+    }
+
+    /// Returns statistics about the IMT size, memory usage and the mutations it went through.
+    ///
+    /// The heap usage is an estimate: maps are accounted for one control byte per bucket on top
+    /// of their entries, ignoring the allocator overhead.
+    pub fn stats(&self) -> ImtStats {
+        let nodes_heap_size = self.nodes.capacity() * size_of::<IMTNode<K, V>>()
+            + self.indices.capacity() * (size_of::<(K, u64)>() + 1)
+            + self.sorted.len() * size_of::<(SortedKey<K>, u64)>();
+
+        ImtStats {
+            size: self.size,
+            depth: self.depth,
+            node_count: self.indices.len() as _,
+            hashes_per_level: self.hashes.level_lens(),
+            nodes_heap_bytes: nodes_heap_size as _,
+            estimated_heap_bytes: (nodes_heap_size + self.hashes.heap_size()) as _,
+            inserts: self.inserts,
+            updates: self.updates,
+        }
+    }
+
+    /// Returns the number of hash invocations performed by the most recent insertion, update or
+    /// removal: the leaf, parent and root hashes of every refreshed path, along with the empty
+    /// hash of each missing sibling with the `balanced-hashing` feature. It grows with the depth
+    /// as the cost of proving the mutation in a circuit does, e.g. to choose the IMT parameters.
+    ///
+    /// Whole tree rehashes, e.g. by `merge` or `rebuild`, are not counted.
+    pub fn last_op_hash_count(&self) -> usize {
+        self.last_op_hash_count
+    }
+
+    /// Merges `other`, whose keys must all be after the keys of `self`, e.g. to combine IMTs built
+    /// in parallel over disjoint key ranges.
+    ///
+    /// The nodes of `self` keep their index while the nodes of `other` fill the vacant slots of
+    /// `self`, lowest first, then are appended after its nodes, in index order, the vacant slots
+    /// of `other` being left out. The merged IMT thus matches an IMT to which the keys of `self`
+    /// then the keys of `other` were inserted.
+    ///
+    /// Fails if the zero node of `other` holds a value, which would be lost, or if `self` has an
+    /// oplog, as the merged nodes come with no mutation to log.
+    pub fn merge(mut self, other: Self) -> Result<Self> {
+        ensure!(
+            self.scheme == other.scheme && self.layout == other.layout,
+            "the IMTs do not commit to their nodes alike"
+        );
+        ensure!(
+            self.history.is_some() == other.history.is_some(),
+            "only one of the IMTs is versioned"
+        );
+        ensure!(
+            *other.nodes[0].value.as_ref() == *V::default().as_ref(),
+            "the zero node of the merged IMT holds a value"
+        );
+        #[cfg(feature = "oplog")]
+        ensure!(
+            !self.oplog.is_enabled(),
+            "merged nodes can not be written to the oplog"
+        );
+        if let (Some(last_key), Some(first_key)) = (self.last_key(), other.first_key()) {
+            ensure!(
+                last_key.as_ref() < first_key.as_ref(),
+                "the key ranges of the IMTs overlap"
+            );
+        }
+        let appended = (other.node_count() - 1).saturating_sub(self.vacant_indices.len() as u64);
+        self.ensure_capacity(self.size + appended)?;
+
+        // Stitch the linked lists, the last node of `self` linking to the first node of `other`.
+        if let Some(first_key) = other.first_key() {
+            let last_key = self.last_key;
+            self.node_mut(&last_key)
+                .expect("failed to get node")
+                .next_key = first_key;
+            self.last_key = other.last_key;
+        }
+
+        let old_root = self.root;
+        let mut indices = vec![];
+        for node in other
+            .nodes
+            .iter()
+            .filter(|node| node.index != 0 && !node.is_vacant())
+        {
+            let index = self.next_index();
+            self.vacant_indices.remove(&index);
+            self.size = self.size.max(index + 1);
+            self.set_node(IMTNode { index, ..*node });
+            indices.push(index);
+        }
+        // The zero node of `other` is left out, and so is its history.
+        if let (Some(history), Some(other_history)) = (&mut self.history, other.history) {
+            history.extend(
+                other_history
+                    .into_iter()
+                    .filter(|(key, _)| *key.as_ref() != *K::default().as_ref()),
+            );
+        }
+
+        self.inserts += other.inserts;
+        self.updates += other.updates;
+
+        self.refresh_depth();
+        self.rebuild();
+
+        self.observer.merged(
+            indices.iter().map(|&index| &self.nodes[index as usize]),
+            old_root,
+            self.root,
+        );
+
+        Ok(self)
+    }
+
+    /// Clears the IMT back to the zero node alone, dropping every other node and every cached
+    /// hash, including the ones written to disk by a paged storage.
+    ///
+    /// The hasher, commitment scheme, layout and hashes storage are kept: the root is then the
+    /// one of a new IMT with the same configuration. So is the oplog, if any, to which the
+    /// clearing is appended for `replay` to clear the IMT it rebuilds alike.
+    pub fn clear(&mut self) {
+        let old_root = self.root;
+        self.nodes.clear();
+        self.indices.clear();
+        self.sorted.clear();
+        self.vacant_indices.clear();
+        self.last_key = Default::default();
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+
+        self.inserts = 0;
+        self.updates = 0;
+        self.mutations_since_prune = 0;
+        self.last_op_hash_count = 0;
+
+        self.size = 1;
+        self.refresh_depth();
+        self.set_node(IMTNode::default());
+        self.rebuild();
+
+        #[cfg(feature = "oplog")]
+        self.oplog.clear();
+
+        self.observer.root_changed(old_root, self.root);
+    }
+
     /// Inserts a new (key; value) in the IMT.
     ///
-    /// Returns the corresponding `IMTInsert` to use for zkVM verification.
+    /// Returns the corresponding `IMTInsert` to use for zkVM verification, which only verifies
+    /// if the node is appended rather than filling a vacant slot.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key already exists or if the IMT is full at its maximum depth, see
+    /// `try_insert_node`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(key = %Hex(key.as_ref())))
+    )]
     pub fn insert_node(&mut self, key: K, value: V) -> IMTMutate<K, V> {
-        // Ensure key does not already exist in the tree.
-        assert!(!self.nodes.contains_key(&key), "key conflict");
+        self.try_insert_node(key, value)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
 
-        let old_root = self.root;
-        let old_size = self.size;
+    /// Same as `insert_node` but fails with `ImtError::CapacityExceeded` instead of panicking if
+    /// the IMT is full at its maximum depth (see `with_max_depth`).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key already exists.
+    pub fn try_insert_node(&mut self, key: K, value: V) -> Result<IMTMutate<K, V>> {
+        // Ensure key does not already exist in the tree.
+        assert!(!self.indices.contains_key(&key), "key conflict");
+        self.ensure_capacity(self.next_index() + 1)?;
 
         // Get the ln node.
         let ln_node = self.low_nullifier(&key);
         let ln_siblings = self.siblings(&ln_node.key);
 
+        Ok(self.insert_after_ln(key, value, ln_node, ln_siblings))
+    }
+
+    /// Same as `insert_node` but also returns the inserted node along with the IMT root and size
+    /// after the insertion.
+    pub fn insert(&mut self, key: K, value: V) -> InsertOutcome<K, V> {
+        let mutate = self.insert_node(key, value);
+        let node = *mutate.inserted_node().expect("mutate is an insertion");
+
+        InsertOutcome {
+            node,
+            new_root: self.root,
+            new_size: self.size,
+            mutate,
+        }
+    }
+
+    /// Inserts a new (key; value) in the IMT using the supplied low nullifier proof.
+    ///
+    /// The `ln_node` and its `ln_siblings` (e.g. provided by a remote prover) are checked against
+    /// the current root instead of searching the nodes for the low nullifier.
+    ///
+    /// Returns the corresponding `IMTInsert` to use for zkVM verification.
+    pub fn insert_with_ln(
+        &mut self,
+        key: K,
+        value: V,
+        ln_node: IMTNode<K, V>,
+        ln_siblings: Vec<Option<Hash>>,
+    ) -> Result<IMTMutate<K, V>> {
+        ensure!(
+            ln_node.is_ln_of(&key) && !ln_node.is_vacant(),
+            "ln_node is not the ln of key"
+        );
+        ensure!(
+            node_exists(
+                self.hasher_factory,
+                self.scheme,
+                self.layout,
+                &self.root,
+                self.size,
+                &ln_node,
+                &ln_siblings
+            ),
+            "ln_node is not in the IMT"
+        );
+        self.ensure_capacity(self.next_index() + 1)?;
+
+        Ok(self.insert_after_ln(key, value, ln_node, ln_siblings))
+    }
+
+    /// Inserts a new (key; value) in the IMT right after its `ln_node`.
+    fn insert_after_ln(
+        &mut self,
+        key: K,
+        value: V,
+        ln_node: IMTNode<K, V>,
+        ln_siblings: Vec<Option<Hash>>,
+    ) -> IMTMutate<K, V> {
+        let old_root = self.root;
+        let old_size = self.size;
+        self.last_op_hash_count = 0;
+
         // Update the ln node and refresh the tree.
-        self.nodes
-            .get_mut(&ln_node.key)
+        self.node_mut(&ln_node.key)
             .expect("failed to get node")
             .next_key = key;
         self.refresh_tree(&ln_node.key);
 
-        self.size += 1;
-        self.refresh_depth();
+        // Fill the lowest vacant slot, if any, or append the node after the last one.
+        let index = self.next_index();
+        self.vacant_indices.remove(&index);
+        if index == old_size {
+            self.size += 1;
+            self.refresh_depth();
+        }
+        self.inserts += 1;
 
         // Create the new node.
         let node = IMTNode {
-            index: old_size,
+            index,
             key,
             value,
             next_key: ln_node.next_key,
         };
+        if *node.next_key.as_ref() == *K::default().as_ref() {
+            self.last_key = key;
+        }
 
         // Insert the new node and refresh the tree.
-        self.nodes.insert(node.key, node);
-        let node_siblings = self.refresh_tree(&key);
+        self.set_node(node);
+        let node_siblings = self.refresh_tree(&key).into_vec();
 
         let updated_ln_siblings = self.siblings(&ln_node.key);
+        self.record_mutation();
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            index = node.index,
+            ln_index = ln_node.index,
+            old_root = %Hex(&old_root),
+            new_root = %Hex(&self.root),
+            depth = self.depth,
+            siblings = node_siblings.len(),
+            "node inserted"
+        );
 
         // Return the IMTMutate insertion to use for proving.
-        IMTMutate::insert(
+        let mutate = IMTMutate::insert(
             old_root,
             old_size,
             ln_node,
@@ -93,130 +739,2492 @@ impl<H: Hashor, K: Key, V: Value> Imt<H, K, V> {
             node,
             node_siblings,
             updated_ln_siblings,
-        )
+        );
+
+        #[cfg(feature = "oplog")]
+        self.oplog.append(&mutate);
+        self.observer.inserted(&node, old_root, self.root);
+
+        mutate
+    }
+
+    /// Removes the given `key` from the IMT and returns its node.
+    ///
+    /// The ln node is linked to the `next_key` of the removed node, whose slot is filled with a
+    /// vacant node until an insertion reuses it, the lowest vacant index being reused first.
+    ///
+    /// Removals are not provable: no `IMTMutate` is returned and the new root has to be shared
+    /// with the verifiers through other means. Neither is the insertion filling the vacant slot,
+    /// which the verifiers reject until removals are provable.
+    pub fn remove_node(&mut self, key: &K) -> IMTNode<K, V> {
+        assert!(
+            *key.as_ref() != *K::default().as_ref(),
+            "the zero node can not be removed"
+        );
+        let old_root = self.root;
+        self.last_op_hash_count = 0;
+        let index = self.indices.remove(key).expect("node does not exist");
+        self.sorted.remove(key.as_ref());
+        let node = std::mem::replace(&mut self.nodes[index as usize], IMTNode::vacant(index));
+
+        // Link the ln node to the next node and refresh the tree.
+        let ln_key = self
+            .predecessor(key)
+            .map_or(K::default(), |ln_node| ln_node.key);
+        self.node_mut(&ln_key)
+            .expect("failed to found ln node")
+            .next_key = node.next_key;
+        self.refresh_tree(&ln_key);
+        if *key == self.last_key {
+            self.last_key = ln_key;
+        }
+        if let Some(history) = &mut self.history {
+            history.remove(key);
+        }
+
+        // Leave a vacant node in the slot of the removed node.
+        self.refresh_leaf(&IMTNode::vacant(node.index));
+        self.vacant_indices.insert(node.index);
+        self.record_mutation();
+        self.observer.root_changed(old_root, self.root);
+
+        node
     }
 
     /// Updates the given `key` to `value` in the IMT.
     ///
+    /// The zero node can be updated like any other node: its value is free, while its key and
+    /// next key, which anchor the linked list, are only changed by insertions and removals.
+    ///
     /// Returns the corresponding `IMTUpdate` to use for zkVM verification.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(key = %Hex(key.as_ref())))
+    )]
     pub fn update_node(&mut self, key: K, value: V) -> IMTMutate<K, V> {
         let old_root = self.root;
+        self.last_op_hash_count = 0;
 
-        let node = self.nodes.get_mut(&key).expect("node does not exist");
+        let node = self.node_mut(&key).expect("node does not exist");
         let old_node = *node;
 
         node.value = value;
-        let node_siblings = self.refresh_tree(&key);
+        if let Some(history) = &mut self.history {
+            history.entry(key).or_default().push(old_node.value);
+        }
+        let node_siblings = self.refresh_tree(&key).into_vec();
+        self.updates += 1;
+        self.record_mutation();
 
-        IMTMutate::update(old_root, self.size, old_node, node_siblings, value)
-    }
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            index = old_node.index,
+            old_root = %Hex(&old_root),
+            new_root = %Hex(&self.root),
+            depth = self.depth,
+            siblings = node_siblings.len(),
+            "node updated"
+        );
 
-    /// Finds the Low Nulifier node for the given `node_key`.
-    pub fn low_nullifier(&self, node_key: &K) -> IMTNode<K, V> {
-        let ln = self
-            .nodes
-            .values()
-            .find(|node| node.is_ln_of(node_key))
-            .expect("failed to found ln node");
+        let mutate = IMTMutate::update(old_root, self.size, old_node, node_siblings, value);
 
-        *ln
+        #[cfg(feature = "oplog")]
+        self.oplog.append(&mutate);
+        self.observer
+            .updated(&old_node, &value, old_root, self.root);
+
+        mutate
     }
 
-    /// Returns the list of siblings for the given `node_key`.
-    pub fn siblings(&self, node_key: &K) -> Vec<Option<Hash>> {
-        let node = self.nodes.get(node_key).expect("node does not exist");
+    /// Same as `update_node`, returning `None` without touching the IMT if `key` already holds
+    /// `value`, so that no proof is spent on a no-op update.
+    pub fn update_node_if_changed(&mut self, key: K, value: V) -> Option<IMTMutate<K, V>> {
+        let node = self.node(&key).expect("node does not exist");
+        if node.value.as_ref() == value.as_ref() {
+            return None;
+        }
 
-        let mut siblings = Vec::with_capacity(self.depth.into());
-        let mut index = node.index;
+        Some(self.update_node(key, value))
+    }
 
-        for level in 0..self.depth {
-            let sibling_index = if index % 2 == 0 { index + 1 } else { index - 1 };
-            let sibling_hash = self
-                .hashes
-                .get(&level)
-                .and_then(|m| m.get(&sibling_index).cloned());
+    /// Deletes the given `key` by updating its value to the tombstone value `V::default()`.
+    ///
+    /// Unlike `remove_node`, the deletion is an update that any verifier accepts, but the key
+    /// stays in the IMT cryptographically: it can not be inserted again, only updated to a live
+    /// value, and its non membership can not be proven.
+    ///
+    /// Returns the corresponding `IMTUpdate` to use for zkVM verification.
+    pub fn delete_node(&mut self, key: K) -> IMTMutate<K, V> {
+        self.update_node(key, V::default())
+    }
 
-            siblings.push(sibling_hash);
-            index /= 2;
+    /// Inserts the (key; value) in the IMT if `key` is absent from it, updates `key` to `value`
+    /// otherwise.
+    ///
+    /// Returns the corresponding `IMTInsert` or `IMTUpdate` to use for zkVM verification.
+    pub fn set(&mut self, key: K, value: V) -> IMTMutate<K, V> {
+        if self.indices.contains_key(&key) {
+            self.update_node(key, value)
+        } else {
+            self.insert_node(key, value)
         }
+    }
 
-        siblings
+    /// Computes the root the IMT would have after updating the given `key` to `value`, along with
+    /// the corresponding `IMTUpdate`, without mutating the IMT.
+    ///
+    /// Both are identical to what a subsequent `update_node` with the same arguments returns.
+    pub fn preview_update(&self, key: &K, value: V) -> Result<(Hash, IMTMutate<K, V>)> {
+        let Some(node) = self.node(key) else {
+            bail!("node does not exist");
+        };
+
+        let node_siblings = self.siblings_cached(node.index);
+        let update = IMTMutate::update(self.root, self.size, *node, node_siblings, value);
+        let new_root = update.verify_with_layout(
+            self.hasher_factory,
+            self.scheme,
+            self.layout,
+            self.root,
+            self.size,
+        )?;
+
+        Ok((new_root, update))
     }
 
-    /// Refreshes the list of hashes based on the provided `node_key` and registers the new root.
-    /// Also returns the updated list of siblings for the given `node_key`.
-    fn refresh_tree(&mut self, node_key: &K) -> Vec<Option<Hash>> {
-        let node = self.nodes.get(node_key).expect("failed to get node");
-        let mut index = node.index;
+    /// Returns the `(index, node)` pairs inserting `key` with `value` would write, as listed by
+    /// `IMTMutate::node_delta`, without mutating the IMT.
+    ///
+    /// Fails where `try_insert_node` fails or panics, so that the insertion can not fail once
+    /// the nodes are persisted.
+    #[cfg(feature = "async")]
+    pub(crate) fn insert_delta(&self, key: &K, value: V) -> Result<Vec<(u64, IMTNode<K, V>)>> {
+        ensure!(!self.indices.contains_key(key), "key conflict");
+        self.ensure_capacity(self.next_index() + 1)?;
 
-        let hasher_factory = self.hasher_factory;
+        let ln_node = self.low_nullifier(key);
+        let node = IMTNode {
+            index: self.next_index(),
+            key: *key,
+            value,
+            next_key: ln_node.next_key,
+        };
+        let updated_ln = IMTNode {
+            next_key: *key,
+            ..ln_node
+        };
 
-        // Recompute and cache the node hash.
-        let mut hash = node.hash(hasher_factory());
-        self.hashes.entry(0).or_default().insert(index, hash);
+        Ok(vec![(node.index, node), (updated_ln.index, updated_ln)])
+    }
 
-        // Climb up the tree and refresh the hashes.
-        let mut siblings = Vec::with_capacity(self.depth as _);
-        for level in 0..self.depth {
-            let sibling_index = if index % 2 == 0 { index + 1 } else { index - 1 };
-            let sibling_hash = self
-                .hashes
-                .entry(level)
-                .or_default()
-                .get(&sibling_index)
-                .cloned();
+    /// Finds the Low Nulifier node for the given `node_key`.
+    pub fn low_nullifier(&self, node_key: &K) -> IMTNode<K, V> {
+        let ln = self
+            .predecessor(node_key)
+            .unwrap_or_else(|| *self.node(&K::default()).expect("failed to get node"));
+        assert!(ln.is_ln_of(node_key), "failed to found ln node");
 
-            siblings.push(sibling_hash);
+        ln
+    }
 
-            let (left, right) = if index % 2 == 0 {
-                (Some(hash), sibling_hash)
-            } else {
-                (sibling_hash, Some(hash))
-            };
+    /// Returns the nodes whose key falls in the given `bounds`, in increasing key order.
+    ///
+    /// The zero node is a sentinel rather than an inserted key: it is never returned, even if
+    /// the zero key is within the `bounds`.
+    pub fn range(&self, bounds: impl RangeBounds<K>) -> impl Iterator<Item = &IMTNode<K, V>> {
+        let bounds = (
+            bounds.start_bound().map(AsRef::<[u8]>::as_ref),
+            bounds.end_bound().map(AsRef::<[u8]>::as_ref),
+        );
 
-            let mut hasher = hasher_factory();
-            match (left, right) {
-                (None, None) => unreachable!(),
-                (None, Some(right)) => hasher.update(&right),
-                (Some(left), None) => hasher.update(&left),
-                (Some(left), Some(right)) => {
-                    hasher.update(&left);
-                    hasher.update(&right);
-                }
-            };
+        // `BTreeMap::range` panics on decreasing bounds, which yield no node here.
+        let is_empty = match &bounds {
+            (Bound::Included(start), Bound::Included(end)) => start > end,
+            (Bound::Included(start) | Bound::Excluded(start), Bound::Excluded(end))
+            | (Bound::Excluded(start), Bound::Included(end)) => start >= end,
+            _ => false,
+        };
 
-            hasher.finalize(&mut hash);
+        (!is_empty)
+            .then(|| self.sorted.range::<[u8], _>(bounds))
+            .into_iter()
+            .flatten()
+            .map(|(_, &index)| &self.nodes[index as usize])
+    }
 
-            index /= 2;
+    /// Returns the node holding the largest key strictly smaller than `key`, whether `key` is in
+    /// the IMT or not, or `None` if there is none. The zero node is never returned.
+    pub fn predecessor(&self, key: &K) -> Option<IMTNode<K, V>> {
+        let (_, &index) = self
+            .sorted
+            .range::<[u8], _>((Bound::Unbounded, Bound::Excluded(key.as_ref())))
+            .next_back()?;
+        Some(self.nodes[index as usize])
+    }
 
-            self.hashes
-                .entry(level + 1)
-                .or_default()
-                .insert(index, hash);
-        }
+    /// Returns the node holding the smallest key strictly larger than `key`, whether `key` is in
+    /// the IMT or not, or `None` if there is none.
+    pub fn successor(&self, key: &K) -> Option<IMTNode<K, V>> {
+        let (_, &index) = self
+            .sorted
+            .range::<[u8], _>((Bound::Excluded(key.as_ref()), Bound::Unbounded))
+            .next()?;
+        Some(self.nodes[index as usize])
+    }
 
-        // Refresh the root hash.
-        self.root = {
-            let mut root_hash = [0; 32];
+    /// Finds the Low Nulifier node for the given `node_key` and returns it along with its
+    /// siblings, both taken from the current IMT state.
+    ///
+    /// This is the witness expected by `insert_with_ln`, and proves the non membership of
+    /// `node_key`. A key deleted with `delete_node` is still in the IMT: it has no low nullifier
+    /// and its deletion can only be shown by proving its tombstone value.
+    pub fn low_nullifier_proof(&self, node_key: &K) -> (IMTNode<K, V>, Vec<Option<Hash>>) {
+        let ln = self.low_nullifier(node_key);
+        let siblings = self.siblings(&ln.key);
 
-            let mut k = Keccak::v256();
-            k.update(&hash);
-            k.update(&self.size.to_be_bytes());
-            k.finalize(&mut root_hash);
+        (ln, siblings)
+    }
 
-            root_hash
-        };
+    /// Returns the node at the given `index`, or `None` if the index is out of the IMT or is a
+    /// vacant slot.
+    pub fn node_by_index(&self, index: u64) -> Option<&IMTNode<K, V>> {
+        let node = self.nodes.get(usize::try_from(index).ok()?)?;
+        (!node.is_vacant()).then_some(node)
+    }
 
-        siblings
+    /// Returns the largest index held by a node, vacant slots excluded.
+    pub fn max_index(&self) -> u64 {
+        self.nodes
+            .iter()
+            .rposition(|node| !node.is_vacant())
+            .expect("failed to get the zero node") as _
     }
 
-    /// Refreshes the IMT depth to be able to store `self.size` nodes.
-    fn refresh_depth(&mut self) {
-        let depth = (u64::BITS - self.size.leading_zeros() - 1) as u8;
-        self.depth = if self.size == (1_u64 << depth) {
-            depth
-        } else {
-            depth + 1
-        }
+    /// Returns the value of the given `key`, if it is in the IMT.
+    pub fn get_value(&self, key: &K) -> Option<V> {
+        self.node(key).map(|node| node.value)
+    }
+
+    /// Same as `get_value` but treats the keys deleted with `delete_node` as absent.
+    pub fn get_live_value(&self, key: &K) -> Option<V> {
+        self.get_value(key).filter(|value| !is_tombstone(value))
+    }
+
+    /// Returns `true` if the given `key` is in the IMT and holds the tombstone value.
+    pub fn is_deleted(&self, key: &K) -> bool {
+        self.get_value(key)
+            .is_some_and(|value| is_tombstone(&value))
+    }
+
+    /// Returns the proof that the node linked from the zero node holds the smallest key of the
+    /// IMT, or `None` if the IMT holds no key.
+    pub fn min_proof(&self) -> Option<MinProof<K, V>> {
+        let zero_node = *self.node(&K::default()).expect("failed to get node");
+        let node = *self.node(&zero_node.next_key)?;
+        if node.index == 0 {
+            return None;
+        }
+
+        Some(MinProof {
+            root: self.root,
+            size: self.size,
+            zero_node,
+            zero_siblings: self.siblings_cached(0),
+            node,
+            node_siblings: self.siblings_cached(node.index),
+        })
+    }
+
+    /// Returns the smallest key of the IMT, or `None` if it holds no key.
+    pub fn first_key(&self) -> Option<K> {
+        let key = self
+            .node(&K::default())
+            .expect("failed to get node")
+            .next_key;
+        (*key.as_ref() != *K::default().as_ref()).then_some(key)
+    }
+
+    /// Returns the largest key of the IMT, or `None` if it holds no key.
+    pub fn last_key(&self) -> Option<K> {
+        (*self.last_key.as_ref() != *K::default().as_ref()).then_some(self.last_key)
+    }
+
+    /// Returns the node holding the smallest key of the IMT, or `None` if it holds no key.
+    pub fn min_node(&self) -> Option<&IMTNode<K, V>> {
+        self.node(&self.first_key()?)
+    }
+
+    /// Returns the node holding the largest key of the IMT, or `None` if it holds no key.
+    pub fn max_node(&self) -> Option<&IMTNode<K, V>> {
+        self.node(&self.last_key()?)
+    }
+
+    /// Returns the proof that the node linking to no other node holds the largest key of the
+    /// IMT, or `None` if the IMT holds no key.
+    pub fn max_proof(&self) -> Option<MaxProof<K, V>> {
+        let node = *self.max_node()?;
+
+        Some(MaxProof {
+            root: self.root,
+            size: self.size,
+            node,
+            node_siblings: self.siblings_cached(node.index),
+        })
+    }
+
+    /// Returns the proof that all the given `keys` are in the IMT, the hashes their sibling paths
+    /// have in common being included only once. Duplicated keys are proven once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if one of the `keys` is not in the IMT.
+    pub fn prove_membership_many(&self, keys: &[K]) -> IMTMultiProof<K, V> {
+        let mut nodes: Vec<_> = keys
+            .iter()
+            .map(|key| *self.node(key).expect("node does not exist"))
+            .collect();
+        nodes.sort_unstable_by_key(|node| node.index);
+        nodes.dedup_by_key(|node| node.index);
+
+        // Walk up the tree in the order the verifier does, collecting the siblings it can not
+        // compute from the nodes.
+        let mut indices: Vec<_> = nodes.iter().map(|node| node.index).collect();
+        let mut decommitments = vec![];
+        for level in 0..self.depth {
+            let width = level_width(self.size, level);
+
+            for (i, &index) in indices.iter().enumerate() {
+                let known = if index.is_multiple_of(2) {
+                    indices.get(i + 1) == Some(&(index + 1))
+                } else {
+                    i > 0 && indices[i - 1] == index - 1
+                };
+
+                let sibling_index = index ^ 1;
+                if !known && sibling_index < width {
+                    let sibling = self.hashes.get(level, sibling_index);
+                    decommitments.push(sibling.expect("failed to get hash"));
+                }
+            }
+
+            indices.iter_mut().for_each(|index| *index /= 2);
+            indices.dedup();
+        }
+
+        IMTMultiProof {
+            nodes,
+            decommitments,
+        }
+    }
+
+    /// Returns the proof that the node of the given `key` is in the IMT.
+    pub fn prove_membership(&self, key: &K) -> Result<IMTInclusion<K, V>> {
+        let Some(node) = self.node(key) else {
+            bail!("node does not exist");
+        };
+
+        self.prove_membership_by_index(node.index)
+    }
+
+    /// Returns the proof that the node at the given `index` is in the IMT, identical to the one
+    /// generated from its key.
+    ///
+    /// Fails if the index is out of the IMT or is a vacant slot.
+    pub fn prove_membership_by_index(&self, index: u64) -> Result<IMTInclusion<K, V>> {
+        let node_siblings = self.siblings_by_index(index)?;
+        let Some(node) = self.node_by_index(index) else {
+            bail!("index is vacant");
+        };
+
+        Ok(IMTInclusion {
+            root: self.root,
+            size: self.size,
+            node: *node,
+            node_siblings,
+        })
+    }
+
+    /// Returns the list of siblings of the slot at the given `index`, vacant slots included.
+    ///
+    /// Fails if the index is out of the IMT.
+    pub fn siblings_by_index(&self, index: u64) -> Result<Vec<Option<Hash>>> {
+        ensure!(index < self.size, "index is out of the IMT");
+        Ok(self.siblings_cached(index))
+    }
+
+    /// Returns the proof that `a` and `b` directly follow each other in the IMT, made of the node
+    /// of `a` and its siblings. A zero `b` stands for `a` being the largest key.
+    ///
+    /// The proof only verifies if `b` actually follows `a`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `a` is not in the IMT.
+    pub fn prove_adjacent(&self, a: &K, b: &K) -> IMTAdjacency<K, V> {
+        let node = *self.node(a).expect("node does not exist");
+
+        IMTAdjacency {
+            node,
+            node_siblings: self.siblings_cached(node.index),
+            b: *b,
+        }
+    }
+
+    /// Returns the proof that none of the given `keys` is in the IMT, the keys falling in the
+    /// same gap sharing their low nullifier node.
+    ///
+    /// # Panics
+    ///
+    /// Panics if one of the `keys` is in the IMT.
+    pub fn prove_non_membership_many(&self, keys: &[K]) -> IMTBatchExclusion<K, V> {
+        let lns: Vec<_> = keys.iter().map(|key| self.low_nullifier(key).key).collect();
+
+        IMTBatchExclusion {
+            keys: keys.to_vec(),
+            proof: self.prove_membership_many(&lns),
+        }
+    }
+
+    /// Returns the proof that the nodes whose key lies in `[start, end]` are exactly the ones it
+    /// holds, chained from the node preceding `start`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `start` is after `end`.
+    pub fn prove_range(&self, start: &K, end: &K) -> IMTRangeProof<K, V> {
+        assert!(start.as_ref() <= end.as_ref(), "start is after end");
+
+        let is_last = |node: &IMTNode<K, V>| *node.next_key.as_ref() == *K::default().as_ref();
+
+        // Walk the keys from the predecessor of `start`, or the zero node if there is none.
+        let mut node = self
+            .predecessor(start)
+            .map_or_else(|| self.node(&K::default()), |node| self.node(&node.key))
+            .expect("failed to get node");
+
+        let mut keys = vec![node.key];
+        while !is_last(node) && node.next_key.as_ref() <= end.as_ref() {
+            node = self.node(&node.next_key).expect("failed to get node");
+            keys.push(node.key);
+        }
+
+        IMTRangeProof {
+            start: *start,
+            end: *end,
+            proof: self.prove_membership_many(&keys),
+        }
+    }
+
+    /// Returns the list of siblings for the given `node_key`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "trace",
+            skip_all,
+            fields(key = %Hex(node_key.as_ref()), depth = self.depth)
+        )
+    )]
+    pub fn siblings(&self, node_key: &K) -> Vec<Option<Hash>> {
+        let node = self.node(node_key).expect("node does not exist");
+        self.siblings_cached(node.index)
+    }
+
+    /// Returns the list of siblings of the node at `index`, read directly from the cached hashes
+    /// without looking up the node.
+    pub fn siblings_cached(&self, index: u64) -> Vec<Option<Hash>> {
+        self.cached_hashes().siblings(index)
+    }
+
+    /// Returns a read only view of the cached level hashes, to serve many proofs against the
+    /// current root without recomputing anything.
+    ///
+    /// The cache is invalidated by any mutation of the IMT (insertion, update, rebuild): hashes
+    /// and siblings read from the view only hold for the `root` it was taken at.
+    pub fn cached_hashes(&self) -> CachedHashes<'_> {
+        CachedHashes {
+            root: self.root,
+            depth: self.depth,
+            hashes: &self.hashes,
+        }
+    }
+
+    /// Returns the hash of every leaf along with its index, in index order, e.g. to bootstrap an
+    /// on-chain mirror of the IMT.
+    ///
+    /// Vacant slots are committed to as well, their leaves are thus exported too.
+    pub fn leaf_hashes(&self) -> Vec<(u64, Hash)> {
+        (0..self.size)
+            .map(|index| {
+                let hash = self.hashes.get(0, index).expect("failed to get hash");
+                (index, hash)
+            })
+            .collect()
+    }
+
+    /// Validates the consistency of the whole IMT.
+    ///
+    /// Checks that the `next_key` chain starting from the zero node visits every node in strictly
+    /// increasing key order and ends on the default key, that node and vacant indices are unique
+    /// and within `[0, size)`, and that the root recomputed from scratch matches `self.root`.
+    pub fn validate_invariants(&self) -> Result<()> {
+        ensure!(
+            self.nodes.len() as u64 == self.size
+                && (self.indices.len() + self.vacant_indices.len()) as u64 == self.size,
+            "IMT size does not match its number of nodes and vacant slots"
+        );
+
+        ensure!(
+            self.depth == depth_for_size(self.size),
+            "IMT depth does not match its size"
+        );
+
+        // Check the node indices.
+        let mut indices = HashSet::with_capacity(self.indices.len());
+        for (slot, node) in self.nodes.iter().enumerate() {
+            if node.is_vacant() {
+                continue;
+            }
+
+            ensure!(
+                node.index < self.size,
+                "node at index {} is out of bounds",
+                node.index
+            );
+            ensure!(
+                indices.insert(node.index),
+                "node index {} is duplicated",
+                node.index
+            );
+            ensure!(
+                !self.vacant_indices.contains(&node.index),
+                "node index {} is vacant",
+                node.index
+            );
+            ensure!(
+                node.index == slot as u64,
+                "node at index {} is stored in slot {slot}",
+                node.index
+            );
+            ensure!(
+                self.indices.get(&node.key) == Some(&node.index),
+                "node at index {} is not indexed by its key",
+                node.index
+            );
+        }
+        for &index in &self.vacant_indices {
+            ensure!(
+                index != 0 && index < self.size,
+                "vacant index {index} is out of bounds"
+            );
+        }
+
+        // Walk the linked list from the zero node.
+        let Some(mut node) = self.node(&K::default()) else {
+            bail!("IMT is missing the zero node");
+        };
+
+        let mut visited = 1;
+        while *node.next_key.as_ref() != *K::default().as_ref() {
+            let Some(next) = self.node(&node.next_key) else {
+                bail!(
+                    "node at index {} has a next_key that is not in the IMT",
+                    node.index
+                );
+            };
+
+            ensure!(
+                node.key.as_ref() < next.key.as_ref(),
+                "node at index {} is not sorted before its next_key",
+                node.index
+            );
+
+            node = next;
+            visited += 1;
+        }
+
+        ensure!(
+            visited == self.indices.len(),
+            "next_key chain does not reach every node"
+        );
+        ensure!(
+            node.key == self.last_key,
+            "last key does not end the next_key chain"
+        );
+
+        ensure!(
+            self.recompute_root() == self.root,
+            "IMT root does not match its nodes"
+        );
+
+        Ok(())
+    }
+
+    /// Recomputes the IMT root from scratch, from the nodes alone.
+    pub fn recompute_root(&self) -> Hash {
+        let hashes = self.compute_hashes();
+        self.scheme
+            .root(self.hasher_factory, &hashes[&self.depth][&0], self.size)
+    }
+
+    /// Computes the root of the IMT holding the same nodes laid out in increasing key order.
+    ///
+    /// Unlike `root`, which commits to the insertion order, the canonical root only depends on
+    /// the (key; value) pairs held by the IMT.
+    pub fn canonical_root(&self) -> Hash {
+        let mut nodes: Vec<_> = self
+            .nodes
+            .iter()
+            .filter(|node| !node.is_vacant())
+            .copied()
+            .collect();
+        nodes.sort_unstable_by(|a, b| a.key.as_ref().cmp(b.key.as_ref()));
+
+        // Vacant slots are left out, the nodes being laid out contiguously.
+        let size = nodes.len() as u64;
+        let depth = depth_for_size(size);
+        let nodes = nodes.into_iter().enumerate().map(|(index, node)| IMTNode {
+            index: index as _,
+            ..node
+        });
+        let hashes = self.hash_levels(nodes, depth);
+
+        self.scheme
+            .root(self.hasher_factory, &hashes[&depth][&0], size)
+    }
+
+    /// Commits to the nodes matching `predicate`, e.g. to an application defined view of the
+    /// IMT, by hashing `SUBSET_TAG` and their count, then their length prefixed (key; value)
+    /// pairs in increasing key order.
+    ///
+    /// The commitment only depends on the matching (key; value) pairs, independently of the IMT
+    /// root and of the neighbouring keys.
+    pub fn subset_commitment(&self, predicate: impl Fn(&IMTNode<K, V>) -> bool) -> Hash {
+        let mut nodes: Vec<_> = self
+            .nodes
+            .iter()
+            .filter(|node| !node.is_vacant() && predicate(node))
+            .collect();
+        nodes.sort_unstable_by(|a, b| a.key.as_ref().cmp(b.key.as_ref()));
+
+        let mut hasher = (self.hasher_factory)();
+        hasher.update(SUBSET_TAG);
+        hasher.update(&(nodes.len() as u64).to_be_bytes());
+        for node in nodes {
+            for field in [node.key.as_ref(), node.value.as_ref()] {
+                hasher.update(&(field.len() as u32).to_be_bytes());
+                hasher.update(field);
+            }
+        }
+
+        let mut commitment = [0; 32];
+        hasher.finalize(&mut commitment);
+        commitment
+    }
+
+    /// Returns whether `self` and `other` hold the same (key; value) pairs, whatever the order
+    /// they were inserted in, by comparing their canonical roots.
+    pub fn same_entries(&self, other: &Self) -> bool {
+        self.canonical_root() == other.canonical_root()
+    }
+
+    /// Drops the cached hashes that are no longer part of the tree, i.e. above its top level or
+    /// past the last leaf, and returns the number of dropped hashes.
+    ///
+    /// Roots, siblings and proofs are left unchanged.
+    pub fn prune(&mut self) -> usize {
+        self.mutations_since_prune = 0;
+        self.hashes.prune(self.depth, self.size)
+    }
+
+    /// Prunes the cached hashes every `every` mutations (insertions, updates and removals), or
+    /// never if `None` (the default).
+    pub fn set_auto_prune(&mut self, every: Option<NonZeroU64>) {
+        self.auto_prune = every;
+    }
+
+    /// Recomputes every cached hash from the nodes and registers the new root.
+    ///
+    /// Each level is hashed once, which is cheaper than climbing the tree for every node when
+    /// the nodes were loaded in bulk.
+    pub fn rebuild(&mut self) {
+        let hashes = self.compute_hashes();
+        self.replace_hashes(hashes);
+    }
+
+    /// Same as `rebuild` but processes the hashes by chunks of `chunk_size`, yielding to the
+    /// executor between chunks so that rebuilding a large IMT does not block it.
+    #[cfg(feature = "async")]
+    pub async fn rebuild_async(&mut self, chunk_size: usize) {
+        assert!(chunk_size > 0, "chunk_size must not be zero");
+
+        let hasher_factory = self.hasher_factory;
+
+        let nodes: Vec<_> = self.leaves().collect();
+        let mut level = HashMap::with_capacity(nodes.len());
+        for chunk in nodes.chunks(chunk_size) {
+            level.extend(chunk.iter().map(|node| (node.index, self.leaf_hash(node))));
+            tokio::task::yield_now().await;
+        }
+
+        let mut hashes = HashMap::with_capacity(self.depth as usize + 1);
+        for depth in 0..self.depth {
+            let indices = parent_indices(&level);
+            let mut parents = HashMap::with_capacity(indices.len());
+            for chunk in indices.chunks(chunk_size) {
+                parents.extend(
+                    chunk
+                        .iter()
+                        .map(|&index| (index, hash_parent(hasher_factory, depth, &level, index))),
+                );
+                tokio::task::yield_now().await;
+            }
+
+            hashes.insert(depth, std::mem::replace(&mut level, parents));
+        }
+        hashes.insert(self.depth, level);
+
+        self.replace_hashes(hashes);
+    }
+
+    /// Replaces all the cached hashes with the given `levels` and registers the new root.
+    fn replace_hashes(&mut self, levels: HashMap<u8, HashMap<u64, Hash>>) {
+        self.root = self
+            .scheme
+            .root(self.hasher_factory, &levels[&self.depth][&0], self.size);
+        self.hashes.replace(levels);
+    }
+
+    /// Computes every level hashes from the nodes, from the leaves up to the top level.
+    fn compute_hashes(&self) -> HashMap<u8, HashMap<u64, Hash>> {
+        self.hash_levels(self.leaves(), self.depth)
+    }
+
+    /// Returns the leaves of the IMT: its nodes and the vacant nodes filling the vacant slots.
+    fn leaves(&self) -> impl Iterator<Item = IMTNode<K, V>> + '_ {
+        self.nodes.iter().copied()
+    }
+
+    /// Hashes the given `nodes` as the leaves of an IMT of the given `depth`, from the leaves up
+    /// to the top level.
+    fn hash_levels(
+        &self,
+        nodes: impl Iterator<Item = IMTNode<K, V>>,
+        depth: u8,
+    ) -> HashMap<u8, HashMap<u64, Hash>> {
+        let hasher_factory = self.hasher_factory;
+
+        let mut level: HashMap<u64, Hash> = nodes
+            .map(|node| (node.index, self.leaf_hash(&node)))
+            .collect();
+
+        let mut hashes = HashMap::with_capacity(depth as usize + 1);
+        for level_depth in 0..depth {
+            let parents = parent_indices(&level)
+                .into_iter()
+                .map(|index| {
+                    (
+                        index,
+                        hash_parent(hasher_factory, level_depth, &level, index),
+                    )
+                })
+                .collect();
+
+            hashes.insert(level_depth, std::mem::replace(&mut level, parents));
+        }
+        hashes.insert(depth, level);
+
+        hashes
+    }
+
+    /// Computes the leaf hash of the given `node`, absorbing its version if the IMT is versioned.
+    fn leaf_hash(&self, node: &IMTNode<K, V>) -> Hash {
+        let hasher = (self.hasher_factory)();
+        match &self.history {
+            // Vacant nodes share the zero key but not its history.
+            Some(history) if !node.is_vacant() => {
+                let version = history.get(&node.key).map_or(0, Vec::len);
+                node.hash_versioned(hasher, self.layout, version as u64)
+            }
+            _ => node.hash_with_layout(hasher, self.layout),
+        }
+    }
+
+    /// Refreshes the list of hashes based on the provided `node_key` and registers the new root.
+    /// Also returns the updated list of siblings for the given `node_key`, which only allocates
+    /// for IMTs deeper than 32 levels.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "trace",
+            skip_all,
+            fields(key = %Hex(node_key.as_ref()), depth = self.depth)
+        )
+    )]
+    fn refresh_tree(&mut self, node_key: &K) -> SiblingsVec {
+        let node = *self.node(node_key).expect("failed to get node");
+        self.refresh_leaf(&node)
+    }
+
+    /// Refreshes the list of hashes based on the provided leaf `node` and registers the new root.
+    /// Also returns the updated list of siblings for the given leaf `node`.
+    fn refresh_leaf(&mut self, node: &IMTNode<K, V>) -> SiblingsVec {
+        let mut index = node.index;
+
+        let hasher_factory = self.hasher_factory;
+
+        // Recompute and cache the node hash.
+        let mut hash = self.leaf_hash(node);
+        self.hashes.set(0, index, hash);
+
+        // Climb up the tree and refresh the hashes.
+        let mut siblings = SiblingsVec::with_capacity(self.depth as _);
+        for level in 0..self.depth {
+            let sibling_index = if index.is_multiple_of(2) {
+                index + 1
+            } else {
+                index - 1
+            };
+            let sibling_hash = self.hashes.get(level, sibling_index);
+
+            // A missing sibling is hashed as the empty hash.
+            #[cfg(feature = "balanced-hashing")]
+            if sibling_hash.is_none() {
+                self.last_op_hash_count += 1;
+            }
+
+            siblings.push(sibling_hash);
+
+            let (left, right) = if index.is_multiple_of(2) {
+                (Some(&hash), sibling_hash.as_ref())
+            } else {
+                (sibling_hash.as_ref(), Some(&hash))
+            };
+
+            hash = hash_level(hasher_factory, level, left, right);
+
+            index /= 2;
+
+            self.hashes.set(level + 1, index, hash);
+        }
+
+        // Refresh the root hash.
+        self.root = self.scheme.root(hasher_factory, &hash, self.size);
+
+        // The leaf hash, one parent hash per level and the size folding, if any.
+        self.last_op_hash_count += 1 + self.depth as usize;
+        if let CommitmentScheme::SizeInRoot(_) = self.scheme {
+            self.last_op_hash_count += 1;
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::trace!(
+            index = node.index,
+            root = %Hex(&self.root),
+            siblings = siblings.len(),
+            "tree refreshed"
+        );
+
+        siblings
+    }
+
+    /// Returns the node of the given `key`, if any.
+    pub(crate) fn node(&self, key: &K) -> Option<&IMTNode<K, V>> {
+        let index = *self.indices.get(key)?;
+        Some(&self.nodes[index as usize])
+    }
+
+    /// Returns the nodes by index, vacant slots included.
+    #[cfg(feature = "mmr")]
+    pub(crate) fn nodes(&self) -> &[IMTNode<K, V>] {
+        &self.nodes
+    }
+
+    /// Returns the factory of the hasher the IMT is hashed with.
+    #[cfg(feature = "mmr")]
+    pub(crate) fn hasher_factory(&self) -> fn() -> H {
+        self.hasher_factory
+    }
+
+    /// Returns the node of the given `key` mutably, if any.
+    fn node_mut(&mut self, key: &K) -> Option<&mut IMTNode<K, V>> {
+        let index = *self.indices.get(key)?;
+        Some(&mut self.nodes[index as usize])
+    }
+
+    /// Stores `node` in its slot, appending it if its index is the next one, and indexes it by
+    /// key.
+    fn set_node(&mut self, node: IMTNode<K, V>) {
+        let index = node.index as usize;
+        if index == self.nodes.len() {
+            self.nodes.push(node);
+        } else {
+            self.nodes[index] = node;
+        }
+        self.indices.insert(node.key, node.index);
+        if *node.key.as_ref() != *K::default().as_ref() {
+            self.sorted.insert(SortedKey(node.key), node.index);
+        }
+    }
+
+    /// Counts a mutation of the IMT, pruning the cached hashes if auto pruning is due.
+    fn record_mutation(&mut self) {
+        self.mutations_since_prune += 1;
+
+        if let Some(every) = self.auto_prune {
+            if self.mutations_since_prune >= every.get() {
+                self.prune();
+            }
+        }
+    }
+
+    /// Refreshes the IMT depth to be able to store `self.size` nodes.
+    fn refresh_depth(&mut self) {
+        self.depth = depth_for_size(self.size);
+    }
+
+    /// Makes sure `size` nodes fit within the maximum depth of the IMT, if capped.
+    fn ensure_capacity(&self, size: u64) -> Result<()> {
+        match self.max_depth {
+            Some(max_depth) if depth_for_size(size) > max_depth => {
+                Err(ImtError::CapacityExceeded { max_depth }.into())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "oplog")]
+impl<
+        H: Hashor,
+        K: Key + Serialize + 'static,
+        V: Value + Serialize + 'static,
+        N: NodeHasher<K, V>,
+    > Imt<H, K, V, N>
+{
+    /// Appends every mutation applied to the IMT from now on to `sink`, along with its clearings,
+    /// bincode serialized and flushed one after the other, for `replay` to rebuild the IMT. Meant
+    /// to be set on a new IMT, as the replay starts from an empty one.
+    ///
+    /// Removals are not provable and thus not logged: the replay of a log spanning a removal
+    /// fails at the next mutation.
+    ///
+    /// Applying a mutation panics if it can not be written to `sink`.
+    pub fn with_oplog<W: Write + Send + Sync + 'static>(mut self, sink: W) -> Self {
+        self.oplog = OpLog::new(sink);
+        self
+    }
+}
+
+/// Read only view of the level hashes cached by an `Imt`, valid for its `root`.
+#[derive(Debug, Clone, Copy)]
+pub struct CachedHashes<'a> {
+    root: Hash,
+    depth: u8,
+    hashes: &'a HashStorage,
+}
+
+impl CachedHashes<'_> {
+    /// Returns the IMT root the cached hashes commit to.
+    pub fn root(&self) -> Hash {
+        self.root
+    }
+
+    /// Returns the IMT depth, the top level hash being stored at level `depth`.
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+
+    /// Returns the hash at `index` in the given `level` (the leaves being level 0), if any.
+    pub fn get(&self, level: u8, index: u64) -> Option<Hash> {
+        self.hashes.get(level, index)
+    }
+
+    /// Returns the list of siblings of the node at `index`.
+    pub fn siblings(&self, mut index: u64) -> Vec<Option<Hash>> {
+        let mut siblings = Vec::with_capacity(self.depth.into());
+
+        for level in 0..self.depth {
+            let sibling_index = if index.is_multiple_of(2) {
+                index + 1
+            } else {
+                index - 1
+            };
+            siblings.push(self.get(level, sibling_index));
+            index /= 2;
+        }
+
+        siblings
+    }
+}
+
+/// Returns `true` if `value` is the tombstone value `V::default()` of deleted keys.
+fn is_tombstone<V: Value>(value: &V) -> bool {
+    *value.as_ref() == *V::default().as_ref()
+}
+
+/// Returns the sorted indices of the parents of the given `level` hashes.
+fn parent_indices(level: &HashMap<u64, Hash>) -> Vec<u64> {
+    let mut indices: Vec<_> = level.keys().map(|index| index / 2).collect();
+    indices.sort_unstable();
+    indices.dedup();
+    indices
+}
+
+/// Hashes the children of the parent at `index` from the hashes of the given `level`, at depth
+/// `level_depth`.
+fn hash_parent<H: Hashor>(
+    hasher_factory: fn() -> H,
+    level_depth: u8,
+    level: &HashMap<u64, Hash>,
+    index: u64,
+) -> Hash {
+    hash_level(
+        hasher_factory,
+        level_depth,
+        level.get(&(index * 2)),
+        level.get(&(index * 2 + 1)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use tiny_keccak::{Hasher, Keccak};
+
+    use super::*;
+    use crate::circuits::{
+        node::{IndexPrefixed, LengthPrefixed},
+        verify_leaf_hash,
+    };
+
+    #[test]
+    fn test_validate_invariants() {
+        let mut imt = Imt::new(Keccak::v256);
+        imt.validate_invariants().unwrap();
+
+        for key in [[5; 32], [1; 32], [20; 32], [3; 32], [10; 32]] {
+            imt.insert_node(key, [42; 32]);
+            imt.validate_invariants().unwrap();
+        }
+
+        imt.update_node([3; 32], [43; 32]);
+        imt.validate_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_remove_node() {
+        let mut imt = Imt::new(Keccak::v256);
+        for i in 1..=4 {
+            imt.insert_node([i; 32], [42; 32]);
+        }
+
+        let removed = imt.remove_node(&[2; 32]);
+        assert_eq!(removed.index, 2);
+        assert_eq!(imt.size, 5);
+        assert_eq!(imt.node_count(), 4);
+        assert_eq!(imt.low_nullifier(&[2; 32]).next_key, [3; 32]);
+        imt.validate_invariants().unwrap();
+
+        // The vacant slot is reused by the next insertion, without growing the IMT. Like the
+        // removal, the insertion is not provable.
+        let old_root = imt.root;
+        let insert = imt.insert_node([10; 32], [42; 32]);
+        let IMTMutate::Insert(insert) = insert else {
+            unreachable!()
+        };
+        assert_eq!(insert.node.index, 2);
+        assert_eq!(imt.size, 5);
+        assert_eq!(insert.new_root(Keccak::v256), imt.root);
+        assert!(insert.verify(Keccak::v256, old_root).is_err());
+        imt.validate_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_remove_and_reinsert() {
+        let mut imt = Imt::new(Keccak::v256);
+        let mut keys = Vec::new();
+
+        // Xorshift generator, to draw reproducible random operations.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for step in 0..100 {
+            let old_root = imt.root;
+
+            if keys.is_empty() || next() % 3 != 0 {
+                let key = [(next() % 255) as u8 + 1; 32];
+                if keys.contains(&key) {
+                    continue;
+                }
+                keys.push(key);
+
+                // The lowest vacant slot is filled first.
+                let expected_index = imt.vacant_indices.first().copied().unwrap_or(imt.size);
+                let insert = imt.insert_node(key, [step; 32]);
+                let IMTMutate::Insert(insert) = &insert else {
+                    unreachable!()
+                };
+                assert_eq!(insert.node.index, expected_index);
+                assert_eq!(insert.new_root(Keccak::v256), imt.root);
+
+                // Only the insertions appending a node are provable.
+                let res = insert.verify(Keccak::v256, old_root);
+                if insert.node.index == insert.old_size {
+                    assert_eq!(res.unwrap(), imt.root);
+                } else {
+                    assert!(res.is_err());
+                }
+            } else {
+                let key = keys.swap_remove((next() % keys.len() as u64) as usize);
+                imt.remove_node(&key);
+            }
+
+            assert_eq!(imt.node_count(), keys.len() as u64 + 1);
+            imt.validate_invariants().unwrap();
+
+            // Proofs of the remaining nodes still hold.
+            for key in &keys {
+                let old_root = imt.root;
+                let update = imt.update_node(*key, [step; 32]);
+                assert_eq!(update.verify(Keccak::v256, old_root).unwrap(), imt.root);
+            }
+        }
+    }
+
+    #[test]
+    fn test_prune() {
+        for mut imt in [Imt::new(Keccak::v256), Imt::new_dense(Keccak::v256)] {
+            for i in 1..=10 {
+                imt.insert_node([i; 32], [42; 32]);
+            }
+            assert_eq!(imt.prune(), 0);
+
+            // Cache hashes out of the tree, past the last leaf and above the top level.
+            imt.hashes.set(0, imt.size, [0xff; 32]);
+            imt.hashes.set(imt.depth + 5, 0, [0xff; 32]);
+
+            let root = imt.root;
+            let siblings = imt.siblings(&[5; 32]);
+            assert_eq!(imt.prune(), 2);
+            assert_eq!(imt.root, root);
+            assert_eq!(imt.siblings(&[5; 32]), siblings);
+            assert_eq!(imt.stats().hashes_per_level.len(), imt.depth as usize + 1);
+            imt.validate_invariants().unwrap();
+
+            // The pruned IMT keeps working.
+            for i in 11..=20 {
+                let old_root = imt.root;
+                let insert = imt.insert_node([i; 32], [42; 32]);
+                assert_eq!(insert.verify(Keccak::v256, old_root).unwrap(), imt.root);
+
+                let old_root = imt.root;
+                let update = imt.update_node([i - 10; 32], [43; 32]);
+                assert_eq!(update.verify(Keccak::v256, old_root).unwrap(), imt.root);
+            }
+            imt.validate_invariants().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_auto_prune() {
+        let mut imt = Imt::new(Keccak::v256);
+        imt.set_auto_prune(NonZeroU64::new(3));
+
+        for i in 1..=4 {
+            imt.insert_node([i; 32], [42; 32]);
+        }
+        imt.hashes.set(imt.depth + 5, 0, [0xff; 32]);
+
+        // The garbage is dropped on the third mutation following the last prune.
+        imt.update_node([1; 32], [43; 32]);
+        assert_eq!(imt.stats().hashes_per_level.len(), imt.depth as usize + 6);
+        imt.remove_node(&[2; 32]);
+        assert_eq!(imt.stats().hashes_per_level.len(), imt.depth as usize + 1);
+
+        let insert = imt.insert_node([5; 32], [42; 32]);
+        assert_eq!(insert.new_root(Keccak::v256), imt.root);
+        imt.validate_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_cached_hashes() {
+        for mut imt in [Imt::new(Keccak::v256), Imt::new_dense(Keccak::v256)] {
+            for i in 1..=20 {
+                imt.insert_node([i; 32], [42; 32]);
+            }
+            imt.update_node([7; 32], [43; 32]);
+
+            // The cached siblings agree with siblings computed from scratch.
+            let fresh = imt.compute_hashes();
+            let cached = imt.cached_hashes();
+            assert_eq!(cached.root(), imt.root);
+            assert_eq!(cached.depth(), imt.depth);
+
+            for index in 0..imt.size {
+                let mut fresh_siblings = Vec::new();
+                let mut sibling_index = index ^ 1;
+                for level in 0..imt.depth {
+                    fresh_siblings.push(fresh[&level].get(&sibling_index).copied());
+                    sibling_index = (sibling_index / 2) ^ 1;
+                }
+
+                assert_eq!(cached.siblings(index), fresh_siblings);
+                assert_eq!(imt.siblings_cached(index), fresh_siblings);
+            }
+            assert_eq!(cached.get(imt.depth, 0), Some(fresh[&imt.depth][&0]));
+        }
+    }
+
+    #[test]
+    #[cfg(not(any(feature = "balanced-hashing", feature = "level-tagging")))]
+    fn test_leaf_hashes() {
+        let mut imt = Imt::new(Keccak::v256);
+        for i in 1..=20 {
+            imt.insert_node([i; 32], [42; 32]);
+        }
+        imt.remove_node(&[7; 32]);
+
+        let leaf_hashes = imt.leaf_hashes();
+        assert_eq!(leaf_hashes.len() as u64, imt.size);
+        assert!(leaf_hashes
+            .iter()
+            .enumerate()
+            .all(|(i, (index, _))| i as u64 == *index));
+
+        // Hash the leaves pairwise up to the top level, a lone child being hashed alone.
+        let mut level: Vec<_> = leaf_hashes.into_iter().map(|(_, hash)| hash).collect();
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| {
+                    let mut hash = [0; 32];
+                    let mut hasher = Keccak::v256();
+                    pair.iter().for_each(|child| hasher.update(child));
+                    hasher.finalize(&mut hash);
+                    hash
+                })
+                .collect();
+        }
+
+        // Fold the size in.
+        let mut root = [0; 32];
+        let mut hasher = Keccak::v256();
+        hasher.update(&level[0]);
+        hasher.update(&imt.size.to_be_bytes());
+        hasher.finalize(&mut root);
+        assert_eq!(root, imt.root);
+    }
+
+    #[test]
+    fn test_node_by_index() {
+        let mut imt = Imt::new(Keccak::v256);
+        assert_eq!(imt.max_index(), 0);
+
+        let keys = [[5; 32], [2; 32], [9; 32], [7; 32]];
+        for key in keys {
+            imt.insert_node(key, [42; 32]);
+        }
+        assert_eq!(imt.max_index(), 4);
+
+        // Nodes are indexed in insertion order, the zero node being at index 0.
+        assert_eq!(imt.node_by_index(0).unwrap().key, [0; 32]);
+        assert_eq!(imt.node_by_index(0).unwrap().next_key, [2; 32]);
+        for (i, key) in keys.iter().enumerate() {
+            let node = imt.node_by_index(i as u64 + 1).unwrap();
+            assert_eq!(node.key, *key);
+            assert_eq!(node.index, i as u64 + 1);
+        }
+        assert!(imt.node_by_index(5).is_none());
+        assert!(imt.node_by_index(u64::MAX).is_none());
+
+        // Removed nodes leave a vacant slot behind, until it is filled again.
+        imt.remove_node(&[7; 32]);
+        assert!(imt.node_by_index(4).is_none());
+        assert_eq!(imt.max_index(), 3);
+
+        imt.remove_node(&[2; 32]);
+        assert!(imt.node_by_index(2).is_none());
+        assert_eq!(imt.max_index(), 3);
+
+        imt.insert_node([1; 32], [42; 32]);
+        assert_eq!(imt.node_by_index(2).unwrap().key, [1; 32]);
+        assert_eq!(imt.node_by_index(0).unwrap().next_key, [1; 32]);
+    }
+
+    #[test]
+    fn test_first_and_last_key() {
+        let mut imt = Imt::<_, [u8; 32], [u8; 32]>::new(Keccak::v256);
+        assert_eq!(imt.first_key(), None);
+        assert_eq!(imt.last_key(), None);
+        assert!(imt.min_node().is_none());
+        assert!(imt.max_node().is_none());
+
+        imt.insert_node([5; 32], [42; 32]);
+        assert_eq!(imt.first_key(), Some([5; 32]));
+        assert_eq!(imt.last_key(), Some([5; 32]));
+        assert_eq!(imt.min_node().unwrap().index, 1);
+        assert_eq!(imt.max_node().unwrap().index, 1);
+
+        imt.insert_node([2; 32], [42; 32]);
+        imt.insert_node([7; 32], [42; 32]);
+        assert_eq!(imt.first_key(), Some([2; 32]));
+        assert_eq!(imt.last_key(), Some([7; 32]));
+
+        // Updating the largest key keeps it the largest one.
+        imt.update_node([7; 32], [43; 32]);
+        assert_eq!(imt.last_key(), Some([7; 32]));
+        assert_eq!(imt.max_node().unwrap().value, [43; 32]);
+
+        // Inserting a new maximum.
+        imt.insert_node([9; 32], [44; 32]);
+        assert_eq!(imt.last_key(), Some([9; 32]));
+        let max = imt.max_node().unwrap();
+        assert_eq!((max.index, max.value), (4, [44; 32]));
+        assert_eq!(imt.min_node().unwrap().key, [2; 32]);
+
+        // Removing the extremities falls back to their neighbours.
+        imt.remove_node(&[9; 32]);
+        imt.remove_node(&[2; 32]);
+        assert_eq!(imt.first_key(), Some([5; 32]));
+        assert_eq!(imt.last_key(), Some([7; 32]));
+        imt.validate_invariants().unwrap();
+
+        imt.remove_node(&[5; 32]);
+        imt.remove_node(&[7; 32]);
+        assert_eq!(imt.first_key(), None);
+        assert_eq!(imt.last_key(), None);
+    }
+
+    #[test]
+    fn test_versioned() {
+        let mut imt = Imt::new(Keccak::v256).versioned();
+        imt.insert_node([1; 32], [42; 32]);
+        assert_eq!(imt.value_history(&[1; 32]), Some(&[][..]));
+        assert_eq!(imt.value_history(&[2; 32]), None);
+
+        let root = imt.root;
+        imt.update_node([1; 32], [43; 32]);
+        let updated_root = imt.root;
+        assert_ne!(updated_root, root);
+
+        imt.update_node([1; 32], [44; 32]);
+        assert_ne!(imt.root, updated_root);
+        assert_eq!(imt.value_history(&[1; 32]), Some(&[[42; 32], [43; 32]][..]));
+        assert_eq!(imt.node(&[1; 32]).unwrap().value, [44; 32]);
+
+        // The leaf commits to the current value along with its version.
+        let node = *imt.node(&[1; 32]).unwrap();
+        assert_eq!(
+            imt.leaf_hashes()[1].1,
+            node.hash_versioned(Keccak::v256(), LeafLayout::NATIVE, 2)
+        );
+        imt.validate_invariants().unwrap();
+
+        // Setting back a previous value still moves the root.
+        let mut reverted = imt.clone();
+        reverted.update_node([1; 32], [43; 32]);
+        assert_ne!(reverted.root, updated_root);
+
+        // Unversioned IMTs keep no history.
+        let mut imt = Imt::new(Keccak::v256);
+        imt.insert_node([1; 32], [42; 32]);
+        imt.update_node([1; 32], [43; 32]);
+        assert_eq!(imt.value_history(&[1; 32]), None);
+    }
+
+    #[test]
+    fn test_predecessor_successor() {
+        let mut imt = Imt::new(Keccak::v256);
+        assert!(imt.predecessor(&[5; 32]).is_none());
+        assert!(imt.successor(&[0; 32]).is_none());
+
+        for key in [[5; 32], [2; 32], [9; 32], [7; 32]] {
+            imt.insert_node(key, [42; 32]);
+        }
+
+        // Existing keys are excluded, the zero node is never returned.
+        for (key, predecessor, successor) in [
+            (0, None, Some(2)),
+            (1, None, Some(2)),
+            (2, None, Some(5)),
+            (6, Some(5), Some(7)),
+            (7, Some(5), Some(9)),
+            (9, Some(7), None),
+            (255, Some(9), None),
+        ] {
+            let key = [key; 32];
+            assert_eq!(imt.predecessor(&key).map(|node| node.key[0]), predecessor);
+            assert_eq!(imt.successor(&key).map(|node| node.key[0]), successor);
+        }
+
+        imt.remove_node(&[7; 32]);
+        assert_eq!(imt.predecessor(&[9; 32]).unwrap().key, [5; 32]);
+        assert_eq!(imt.successor(&[5; 32]).unwrap().key, [9; 32]);
+    }
+
+    #[test]
+    fn test_range() {
+        let mut imt = Imt::new(Keccak::v256);
+        for key in [[5; 32], [2; 32], [9; 32], [7; 32], [4; 32]] {
+            imt.insert_node(key, [42; 32]);
+        }
+        let keys = |nodes: Vec<&IMTNode<[u8; 32], [u8; 32]>>| -> Vec<u8> {
+            nodes.iter().map(|node| node.key[0]).collect()
+        };
+
+        assert_eq!(keys(imt.range(..).collect()), [2, 4, 5, 7, 9]);
+        assert_eq!(keys(imt.range([4; 32]..[7; 32]).collect()), [4, 5]);
+        assert_eq!(keys(imt.range([4; 32]..=[7; 32]).collect()), [4, 5, 7]);
+        assert_eq!(keys(imt.range([3; 32]..).collect()), [4, 5, 7, 9]);
+        assert_eq!(keys(imt.range(..=[5; 32]).collect()), [2, 4, 5]);
+        assert_eq!(
+            keys(
+                imt.range((Bound::Excluded([4; 32]), Bound::Excluded([9; 32])))
+                    .collect()
+            ),
+            [5, 7]
+        );
+
+        // The zero node is never returned.
+        assert_eq!(keys(imt.range([0; 32]..=[2; 32]).collect()), [2]);
+
+        // Empty ranges, including decreasing ones.
+        assert!(imt.range([10; 32]..).next().is_none());
+        assert!(imt.range([5; 32]..[5; 32]).next().is_none());
+        assert!(imt.range([7; 32]..=[5; 32]).next().is_none());
+        assert!(imt
+            .range((Bound::Excluded([5; 32]), Bound::Included([5; 32])))
+            .next()
+            .is_none());
+
+        // Removed keys are not returned.
+        imt.remove_node(&[5; 32]);
+        assert_eq!(keys(imt.range([4; 32]..=[7; 32]).collect()), [4, 7]);
+    }
+
+    #[test]
+    fn test_next_index() {
+        let mut imt = Imt::new(Keccak::v256);
+        assert_eq!(imt.next_index(), 1);
+
+        for key in [[5; 32], [2; 32], [9; 32], [7; 32]] {
+            let index = imt.next_index();
+            let mutate = imt.insert_node(key, [42; 32]);
+            assert_eq!(mutate.inserted_node().unwrap().index, index);
+        }
+        assert_eq!(imt.next_index(), 5);
+
+        // Vacant slots are filled first, lowest index first.
+        imt.remove_node(&[9; 32]);
+        imt.remove_node(&[2; 32]);
+        for (key, expected) in [([1; 32], 2), ([3; 32], 3), ([4; 32], 5)] {
+            assert_eq!(imt.next_index(), expected);
+            let mutate = imt.insert_node(key, [42; 32]);
+            assert_eq!(mutate.inserted_node().unwrap().index, expected);
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        for mut imt in [Imt::new(Keccak::v256), Imt::new_dense(Keccak::v256)] {
+            let stats = imt.stats();
+            assert_eq!(stats.size, 1);
+            assert_eq!(stats.depth, 0);
+            assert_eq!(stats.node_count, 1);
+            assert_eq!(stats.hashes_per_level, [1]);
+            assert_eq!((stats.inserts, stats.updates), (0, 0));
+
+            for i in 1..=4 {
+                imt.insert_node([i; 32], [42; 32]);
+            }
+            let stats = imt.stats();
+            assert_eq!(stats.size, 5);
+            assert_eq!(stats.depth, 3);
+            assert_eq!(stats.node_count, 5);
+            assert_eq!(stats.hashes_per_level, [5, 3, 2, 1]);
+            assert_eq!((stats.inserts, stats.updates), (4, 0));
+
+            // Updates do not grow the tree.
+            let heap_bytes = stats.estimated_heap_bytes;
+            imt.update_node([1; 32], [43; 32]);
+            imt.update_node([1; 32], [44; 32]);
+            let stats = imt.stats();
+            assert_eq!(stats.size, 5);
+            assert_eq!(stats.hashes_per_level, [5, 3, 2, 1]);
+            assert_eq!((stats.inserts, stats.updates), (4, 2));
+            assert_eq!(stats.estimated_heap_bytes, heap_bytes);
+
+            for i in 5..=100 {
+                imt.insert_node([i; 32], [42; 32]);
+            }
+            let stats = imt.stats();
+            assert_eq!(stats.node_count, 101);
+            assert_eq!(stats.inserts, 100);
+            assert!(stats.estimated_heap_bytes > heap_bytes);
+            assert!(
+                stats.nodes_heap_bytes >= 101 * size_of::<IMTNode<[u8; 32], [u8; 32]>>() as u64
+            );
+            assert!(stats.nodes_heap_bytes < stats.estimated_heap_bytes);
+
+            // Rebuilding the cached hashes is not a mutation.
+            imt.rebuild();
+            assert_eq!(imt.stats().hashes_per_level, stats.hashes_per_level);
+            assert_eq!((imt.stats().inserts, imt.stats().updates), (100, 2));
+        }
+
+        // The stats can be exported as JSON.
+        let json =
+            serde_json::to_value(Imt::<_, [u8; 32], [u8; 32]>::new(Keccak::v256).stats()).unwrap();
+        assert_eq!(json["node_count"], 1);
+        assert_eq!(json["hashes_per_level"], serde_json::json!([1]));
+    }
+
+    #[test]
+    #[cfg(not(feature = "balanced-hashing"))]
+    fn test_last_op_hash_count() {
+        let key = |i: u32| {
+            let mut key = [0; 32];
+            key[..4].copy_from_slice(&i.to_be_bytes());
+            key
+        };
+
+        let mut imt = Imt::new(Keccak::v256);
+        assert_eq!(imt.last_op_hash_count(), 0);
+
+        // Each refreshed path hashes its leaf, one parent per level and the size into the root.
+        for i in 1..=1000_u32 {
+            let old_depth = imt.depth as usize;
+            imt.insert_node(key(i), [42; 32]);
+            let depth = imt.depth as usize;
+            assert_eq!(imt.last_op_hash_count(), (old_depth + 2) + (depth + 2));
+
+            imt.update_node(key(i / 2), [43; 32]);
+            assert_eq!(imt.last_op_hash_count(), depth + 2);
+        }
+        assert_eq!(imt.depth, 10);
+
+        // Removing refreshes the ln node and the vacant slot, reusing it does the same.
+        imt.remove_node(&key(500));
+        assert_eq!(imt.last_op_hash_count(), 2 * (10 + 2));
+        imt.insert_node(key(500), [42; 32]);
+        assert_eq!(imt.last_op_hash_count(), 2 * (10 + 2));
+
+        // Without the size folded into the root, one hash less per path.
+        let mut imt = Imt::with_scheme(Keccak::v256, CommitmentScheme::SizeSeparate);
+        for i in 1..=100_u32 {
+            imt.insert_node(key(i), [42; 32]);
+        }
+        imt.update_node(key(1), [43; 32]);
+        assert_eq!(imt.last_op_hash_count(), imt.depth as usize + 1);
+
+        // Rehashing the whole tree is not counted.
+        imt.rebuild();
+        assert_eq!(imt.last_op_hash_count(), imt.depth as usize + 1);
+        imt.clear();
+        assert_eq!(imt.last_op_hash_count(), 0);
+    }
+
+    thread_local! {
+        static HASHERS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// Keccak256 hashor counting the instances created by the current thread.
+    struct CountedKeccak(Keccak);
+
+    impl CountedKeccak {
+        fn new() -> Self {
+            HASHERS.with(|count| count.set(count.get() + 1));
+            Self(Keccak::v256())
+        }
+    }
+
+    impl Hashor for CountedKeccak {
+        const OUTPUT_LEN: usize = 32;
+
+        fn finalize_reset(&mut self, output: &mut [u8]) {
+            self.0.finalize_reset(output);
+        }
+    }
+
+    impl Hasher for CountedKeccak {
+        fn update(&mut self, input: &[u8]) {
+            self.0.update(input);
+        }
+
+        fn finalize(self, output: &mut [u8]) {
+            self.0.finalize(output);
+        }
+    }
+
+    #[test]
+    fn test_last_op_hash_count_matches_hashers() {
+        // Whatever the hashing features, e.g. the empty hashes of missing siblings.
+        let mut imt = Imt::new(CountedKeccak::new);
+        for i in 1..=100_u8 {
+            let start = HASHERS.with(Cell::get);
+            imt.insert_node([i; 32], [42; 32]);
+            let hashers = HASHERS.with(Cell::get) - start;
+            assert_eq!(imt.last_op_hash_count(), hashers);
+
+            let start = HASHERS.with(Cell::get);
+            imt.update_node([i / 2; 32], [43; 32]);
+            let hashers = HASHERS.with(Cell::get) - start;
+            assert_eq!(imt.last_op_hash_count(), hashers);
+        }
+    }
+
+    #[test]
+    fn test_insert_with_ln() {
+        let mut imt = Imt::new(Keccak::v256);
+        for key in [[1; 32], [5; 32], [10; 32]] {
+            imt.insert_node(key, [42; 32]);
+        }
+
+        // A wrong ln, even though it is in the IMT, is rejected.
+        let wrong_ln = imt.low_nullifier(&[2; 32]);
+        let wrong_siblings = imt.siblings(&wrong_ln.key);
+        let res = imt.insert_with_ln([6; 32], [42; 32], wrong_ln, wrong_siblings);
+        assert!(matches!(res, Err(e) if e.to_string() == "ln_node is not the ln of key"));
+
+        // The right ln with stale siblings is rejected.
+        let ln_node = imt.low_nullifier(&[6; 32]);
+        let mut ln_siblings = imt.siblings(&ln_node.key);
+        ln_siblings[0] = Some([0xff; 32]);
+        let res = imt.insert_with_ln([6; 32], [42; 32], ln_node, ln_siblings);
+        assert!(matches!(res, Err(e) if e.to_string() == "ln_node is not in the IMT"));
+
+        // A forged ln that is not in the IMT is rejected.
+        let forged = IMTNode {
+            next_key: [7; 32],
+            ..ln_node
+        };
+        let res = imt.insert_with_ln([6; 32], [42; 32], forged, imt.siblings(&ln_node.key));
+        assert!(matches!(res, Err(e) if e.to_string() == "ln_node is not in the IMT"));
+
+        // The right ln is accepted and agrees with a regular insert.
+        let mut expected = imt.clone();
+        let expected_insert = expected.insert_node([6; 32], [42; 32]);
+
+        let old_root = imt.root;
+        let ln_siblings = imt.siblings(&ln_node.key);
+        let insert = imt
+            .insert_with_ln([6; 32], [42; 32], ln_node, ln_siblings)
+            .unwrap();
+        assert_eq!(imt.root, expected.root);
+        assert_eq!(
+            bincode::serialize(&insert).unwrap(),
+            bincode::serialize(&expected_insert).unwrap()
+        );
+        assert_eq!(insert.verify(Keccak::v256, old_root).unwrap(), imt.root);
+        imt.validate_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_low_nullifier_proof() {
+        let mut imt = Imt::new(Keccak::v256);
+        for key in [[1; 32], [5; 32], [10; 32]] {
+            imt.insert_node(key, [42; 32]);
+        }
+
+        for key in [[3; 32], [7; 32], [11; 32]] {
+            let (ln_node, ln_siblings) = imt.low_nullifier_proof(&key);
+            assert!(ln_node.is_ln_of(&key));
+            assert!(node_exists(
+                Keccak::v256,
+                imt.scheme(),
+                imt.layout(),
+                &imt.root,
+                imt.size,
+                &ln_node,
+                &ln_siblings
+            ));
+        }
+
+        // The proof is the witness expected by `insert_with_ln`.
+        let (ln_node, ln_siblings) = imt.low_nullifier_proof(&[7; 32]);
+        imt.insert_with_ln([7; 32], [42; 32], ln_node, ln_siblings)
+            .unwrap();
+        imt.validate_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_order_independence() {
+        let keys = [[5; 32], [1; 32], [20; 32], [3; 32], [10; 32]];
+
+        let mut imt = Imt::new(Keccak::v256);
+        for key in keys {
+            imt.insert_node(key, [42; 32]);
+        }
+
+        let mut reversed = Imt::new(Keccak::v256);
+        for key in keys.into_iter().rev() {
+            reversed.insert_node(key, [42; 32]);
+        }
+
+        // The roots commit to the insertion order, the canonical roots do not.
+        assert_ne!(imt.root, reversed.root);
+        assert!(imt.same_entries(&reversed));
+
+        // Inserting in increasing key order lays the nodes out canonically.
+        let mut sorted_keys = keys;
+        sorted_keys.sort();
+        let mut sorted = Imt::new(Keccak::v256);
+        for key in sorted_keys {
+            sorted.insert_node(key, [42; 32]);
+        }
+        assert_eq!(sorted.root, sorted.canonical_root());
+        assert_eq!(sorted.root, imt.canonical_root());
+    }
+
+    #[test]
+    fn test_subset_commitment() {
+        let mut imt = Imt::new(Keccak::v256);
+        for i in 1..=10 {
+            imt.insert_node([i; 32], [i % 3; 32]);
+        }
+
+        // Predicates selecting the same nodes commit alike.
+        let by_value = imt.subset_commitment(|node| node.value == [1; 32]);
+        let by_key = imt.subset_commitment(|node| [1, 4, 7, 10].contains(&node.key[0]));
+        assert_eq!(by_value, by_key);
+
+        // The tag and count come first, then the pairs in increasing key order.
+        let mut hasher = Keccak::v256();
+        hasher.update(SUBSET_TAG);
+        hasher.update(&4_u64.to_be_bytes());
+        for key in [[1; 32], [4; 32], [7; 32], [10; 32]] {
+            let node = imt.prove_membership(&key).unwrap().node;
+            for field in [node.key, node.value] {
+                hasher.update(&32_u32.to_be_bytes());
+                hasher.update(&field);
+            }
+        }
+        let mut expected = [0; 32];
+        hasher.finalize(&mut expected);
+        assert_eq!(by_value, expected);
+
+        // Inserting a non-matching key between matching ones leaves the commitment unchanged.
+        let mut key = [4; 32];
+        key[31] = 5;
+        imt.insert_node(key, [0; 32]);
+        assert_eq!(
+            by_value,
+            imt.subset_commitment(|node| node.value == [1; 32])
+        );
+
+        // Other subsets commit differently.
+        assert_ne!(
+            by_value,
+            imt.subset_commitment(|node| node.value == [2; 32])
+        );
+        assert_ne!(by_value, imt.root);
+
+        // So does the same subset once a node in it is updated, or moved out of it.
+        imt.update_node([7; 32], [9; 32]);
+        let updated = imt.subset_commitment(|node| [1, 4, 7, 10].contains(&node.key[0]));
+        assert_ne!(by_key, updated);
+        imt.update_node([4; 32], [2; 32]);
+        let moved = imt.subset_commitment(|node| node.value == [1; 32]);
+        assert_ne!(by_value, moved);
+        assert_ne!(updated, moved);
+    }
+
+    #[test]
+    fn test_order_independence_different_values() {
+        let mut imt = Imt::new(Keccak::v256);
+        let mut other = Imt::new(Keccak::v256);
+        for key in [[1; 32], [2; 32]] {
+            imt.insert_node(key, [42; 32]);
+            other.insert_node(key, [42; 32]);
+        }
+
+        other.update_node([2; 32], [43; 32]);
+        assert!(!imt.same_entries(&other));
+    }
+
+    #[test]
+    fn test_rebuild() {
+        let mut imt = Imt::new(Keccak::v256);
+        for i in 1..=100 {
+            imt.insert_node([i; 32], [42; 32]);
+        }
+
+        // Rebuilding from the nodes alone gives back the same root and siblings.
+        let mut rebuilt = imt.clone();
+        rebuilt.hashes.replace(HashMap::new());
+        rebuilt.root = Default::default();
+        rebuilt.rebuild();
+
+        assert_eq!(rebuilt.root, imt.root);
+        for i in 1..=100 {
+            assert_eq!(rebuilt.siblings(&[i; 32]), imt.siblings(&[i; 32]));
+        }
+        rebuilt.validate_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_node_hasher() {
+        let scheme = CommitmentScheme::default();
+        let (mut native, mut prefixed) = (
+            Imt::new(Keccak::v256),
+            Imt::new(Keccak::v256).with_layout(LengthPrefixed),
+        );
+        for key in [[5; 32], [2; 32], [9; 32]] {
+            let (old_root, old_size) = (prefixed.root, prefixed.size);
+            native.insert_node(key, [42; 32]);
+            let mutate = prefixed.insert_node(key, [42; 32]);
+
+            // Mutations only verify under the node hasher they were generated with.
+            let res =
+                mutate.verify_with_layout(Keccak::v256, scheme, LengthPrefixed, old_root, old_size);
+            assert_eq!(res.unwrap(), prefixed.root);
+            assert!(mutate.verify(Keccak::v256, old_root).is_err());
+        }
+        prefixed.validate_invariants().unwrap();
+        assert_eq!(prefixed.layout(), LengthPrefixed);
+
+        // The same nodes commit to different roots.
+        assert_ne!(native.root, prefixed.root);
+        assert_eq!(prefixed.recompute_root(), prefixed.root);
+        assert_eq!(prefixed.with_layout(LeafLayout::NATIVE).root, native.root);
+
+        let proof = native.prove_membership(&[9; 32]).unwrap();
+        proof.verify(Keccak::v256, native.root).unwrap();
+        let res = proof.verify_with_layout(Keccak::v256, scheme, LengthPrefixed, native.root);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTInclusion.node is not in the IMT"));
+    }
+
+    #[test]
+    #[cfg(feature = "schema-version")]
+    fn test_schema_versioned() {
+        use crate::circuits::node::SchemaVersioned;
+
+        let scheme = CommitmentScheme::default();
+        let (mut v1, mut v2) = (
+            Imt::new(Keccak::v256).with_layout(SchemaVersioned::new(1)),
+            Imt::new(Keccak::v256).with_layout(SchemaVersioned::new(2)),
+        );
+        for key in [[5; 32], [2; 32], [9; 32]] {
+            let (old_root, old_size) = (v1.root, v1.size);
+            v2.insert_node(key, [42; 32]);
+            let mutate = v1.insert_node(key, [42; 32]);
+
+            // Mutations do not verify under another schema version.
+            let res = mutate.verify_with_layout(
+                Keccak::v256,
+                scheme,
+                SchemaVersioned::new(1),
+                old_root,
+                old_size,
+            );
+            assert_eq!(res.unwrap(), v1.root);
+            let res = mutate.verify_with_layout(
+                Keccak::v256,
+                scheme,
+                SchemaVersioned::new(2),
+                old_root,
+                old_size,
+            );
+            assert!(res.is_err());
+        }
+
+        // The same nodes commit to different roots.
+        assert_ne!(v1.root, v2.root);
+        assert_eq!(v1.with_layout(SchemaVersioned::new(2)).root, v2.root);
+    }
+
+    #[test]
+    fn test_index_prefixed() {
+        let scheme = CommitmentScheme::default();
+        let layout = IndexPrefixed::<LeafLayout>::default();
+        let (mut native, mut bound) = (
+            Imt::new(Keccak::v256),
+            Imt::new(Keccak::v256).with_layout(layout),
+        );
+        for key in [[5; 32], [2; 32], [9; 32], [7; 32]] {
+            let (old_root, old_size) = (bound.root, bound.size);
+            native.insert_node(key, [42; 32]);
+            let mutate = bound.insert_node(key, [42; 32]);
+
+            let res = mutate.verify_with_layout(Keccak::v256, scheme, layout, old_root, old_size);
+            assert_eq!(res.unwrap(), bound.root);
+        }
+        bound.validate_invariants().unwrap();
+        assert_eq!(bound.recompute_root(), bound.root);
+
+        let proof = bound.prove_membership(&[9; 32]).unwrap();
+        proof
+            .verify_with_layout(Keccak::v256, scheme, layout, bound.root)
+            .unwrap();
+
+        // A node whose index was altered no longer hashes to the committed leaf, even along the
+        // sibling path of its original position.
+        let moved = IMTNode {
+            index: proof.node.index ^ 1,
+            ..proof.node
+        };
+        let siblings = &proof.node_siblings;
+        let index = proof.node.index;
+        assert!(verify_leaf_hash(
+            Keccak::v256,
+            &bound.root,
+            bound.size,
+            layout.hash_node(Keccak::v256(), &proof.node),
+            index,
+            siblings
+        ));
+        assert!(!verify_leaf_hash(
+            Keccak::v256,
+            &bound.root,
+            bound.size,
+            layout.hash_node(Keccak::v256(), &moved),
+            index,
+            siblings
+        ));
+
+        // Without the index in the preimage, only the sibling path tells both nodes apart.
+        #[cfg(not(feature = "bind-index"))]
+        {
+            let proof = native.prove_membership(&[9; 32]).unwrap();
+            let moved = IMTNode {
+                index: proof.node.index ^ 1,
+                ..proof.node
+            };
+            assert!(verify_leaf_hash(
+                Keccak::v256,
+                &native.root,
+                native.size,
+                moved.hash(Keccak::v256()),
+                proof.node.index,
+                &proof.node_siblings
+            ));
+        }
+
+        let mut forged = proof.clone();
+        forged.node.index ^= 1;
+        let res = forged.verify_with_layout(Keccak::v256, scheme, layout, bound.root);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTInclusion.node is not in the IMT"));
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let mut imt = Imt::new(Keccak::v256).with_max_depth(2);
+        for i in 1..=3 {
+            imt.try_insert_node([i; 32], [42; 32]).unwrap();
+        }
+        assert_eq!(imt.size, 4);
+
+        // The fifth node would need a third level.
+        let root = imt.root;
+        let err = imt.try_insert_node([4; 32], [42; 32]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ImtError>(),
+            Some(&ImtError::CapacityExceeded { max_depth: 2 })
+        );
+        assert_eq!(err.to_string(), "the IMT is full at its maximum depth of 2");
+        assert_eq!((imt.root, imt.size), (root, 4));
+        imt.validate_invariants().unwrap();
+
+        // Filling a vacant slot does not grow the IMT.
+        imt.remove_node(&[2; 32]);
+        imt.try_insert_node([4; 32], [42; 32]).unwrap();
+        assert_eq!(imt.depth(), 2);
+
+        let (ln_node, ln_siblings) = imt.low_nullifier_proof(&[5; 32]);
+        let res = imt.insert_with_ln([5; 32], [42; 32], ln_node, ln_siblings);
+        assert!(res.is_err());
+
+        // Nor can a merge grow it.
+        let mut other = Imt::new(Keccak::v256);
+        other.insert_node([9; 32], [42; 32]);
+        let res = imt.merge(other);
+        assert!(matches!(res, Err(e) if e.downcast_ref::<ImtError>().is_some()));
+    }
+
+    #[test]
+    fn test_clear() {
+        for (mut imt, new) in [
+            (Imt::new(Keccak::v256), Imt::new(Keccak::v256)),
+            (Imt::new_dense(Keccak::v256), Imt::new_dense(Keccak::v256)),
+            (
+                Imt::with_scheme(Keccak::v256, CommitmentScheme::SizeSeparate),
+                Imt::with_scheme(Keccak::v256, CommitmentScheme::SizeSeparate),
+            ),
+        ] {
+            for i in 1..=20 {
+                imt.insert_node([i; 32], [42; 32]);
+            }
+            imt.update_node([3; 32], [43; 32]);
+            imt.remove_node(&[5; 32]);
+
+            imt.clear();
+            assert_eq!(imt.root, new.root);
+            assert_eq!((imt.size, imt.depth), (1, 0));
+            assert_eq!(imt.node_count(), 1);
+            assert_eq!(imt.stats().hashes_per_level, [1]);
+            assert!(imt.last_key().is_none());
+            imt.validate_invariants().unwrap();
+
+            // The cleared IMT behaves as a new one.
+            let mut new = new;
+            for key in [[9; 32], [2; 32], [5; 32]] {
+                let mutate = imt.insert_node(key, [42; 32]);
+                let expected = new.insert_node(key, [42; 32]);
+                assert_eq!(
+                    bincode::serialize(&mutate).unwrap(),
+                    bincode::serialize(&expected).unwrap()
+                );
+            }
+            assert_eq!(imt.root, new.root);
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let shard = |keys: [u8; 4]| {
+            let mut imt = Imt::new(Keccak::v256);
+            for key in keys {
+                imt.insert_node([key; 32], [key; 32]);
+            }
+            imt
+        };
+
+        let merged = shard([3, 1, 4, 2]).merge(shard([8, 5, 7, 6])).unwrap();
+
+        let mut single = Imt::new(Keccak::v256);
+        for key in [3, 1, 4, 2, 8, 5, 7, 6] {
+            single.insert_node([key; 32], [key; 32]);
+        }
+        assert_eq!(merged.root, single.root);
+        assert_eq!((merged.size, merged.depth), (9, 4));
+        assert_eq!(merged.last_key(), Some([8; 32]));
+        merged.validate_invariants().unwrap();
+
+        // The merged IMT keeps working like any other one.
+        let mut merged = merged;
+        let old_root = merged.root;
+        let mutate = merged.insert_node([9; 32], [9; 32]);
+        assert_eq!(mutate.verify(Keccak::v256, old_root).unwrap(), merged.root);
+
+        // Vacant slots of the second IMT are left out.
+        let mut other = shard([8, 5, 7, 6]);
+        other.remove_node(&[5; 32]);
+        let merged = shard([3, 1, 4, 2]).merge(other).unwrap();
+        assert_eq!(merged.size, 8);
+        merged.validate_invariants().unwrap();
+
+        // Vacant slots of the first IMT are filled first, as insertions would.
+        let mut first = shard([3, 1, 4, 2]);
+        first.remove_node(&[1; 32]);
+        first.remove_node(&[4; 32]);
+        let mut single = first.clone();
+        let merged = first.merge(shard([8, 5, 7, 6])).unwrap();
+        for key in [8, 5, 7, 6] {
+            single.insert_node([key; 32], [key; 32]);
+        }
+        assert_eq!(merged.root, single.root);
+        assert_eq!(merged.size, 7);
+        merged.validate_invariants().unwrap();
+
+        // Merging an empty IMT on either side.
+        let empty = || Imt::new(Keccak::v256);
+        assert_eq!(
+            shard([1, 2, 3, 4]).merge(empty()).unwrap().root,
+            shard([1, 2, 3, 4]).root
+        );
+        assert_eq!(
+            empty().merge(shard([1, 2, 3, 4])).unwrap().root,
+            shard([1, 2, 3, 4]).root
+        );
+
+        // The history of the zero node of `self` is kept, the one of `other` is left out.
+        let versioned = |keys: [u8; 4]| {
+            let mut imt = shard(keys).versioned();
+            imt.update_node([0; 32], [keys[0]; 32]);
+            imt.update_node([0; 32], [0; 32]);
+            imt.update_node([keys[1]; 32], [0; 32]);
+            imt
+        };
+        let merged = versioned([3, 1, 4, 2])
+            .merge(versioned([8, 5, 7, 6]))
+            .unwrap();
+        assert_eq!(
+            merged.value_history(&[0; 32]),
+            Some(&[[0; 32], [3; 32]][..])
+        );
+        assert_eq!(merged.value_history(&[1; 32]), Some(&[[1; 32]][..]));
+        assert_eq!(merged.value_history(&[5; 32]), Some(&[[5; 32]][..]));
+        merged.validate_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_merge_overlapping() {
+        let shard = |keys: [u8; 2]| {
+            let mut imt = Imt::new(Keccak::v256);
+            for key in keys {
+                imt.insert_node([key; 32], [42; 32]);
+            }
+            imt
+        };
+
+        for (a, b) in [([1, 5], [3, 7]), ([1, 5], [5, 7]), ([5, 7], [1, 2])] {
+            let res = shard(a).merge(shard(b));
+            assert!(matches!(res, Err(e) if e.to_string() == "the key ranges of the IMTs overlap"));
+        }
+
+        let mut other = shard([3, 4]);
+        other.update_node([0; 32], [1; 32]);
+        let res = shard([1, 2]).merge(other);
+        assert!(
+            matches!(res, Err(e) if e.to_string() == "the zero node of the merged IMT holds a value")
+        );
+
+        let res = shard([1, 2]).merge(Imt::with_scheme(
+            Keccak::v256,
+            CommitmentScheme::SizeSeparate,
+        ));
+        assert!(
+            matches!(res, Err(e) if e.to_string() == "the IMTs do not commit to their nodes alike")
+        );
+    }
+
+    #[test]
+    fn test_dense_storage() {
+        let mut sparse = Imt::new(Keccak::v256);
+        let mut dense = Imt::new_dense(Keccak::v256);
+
+        // Insert and update keys in a scattered order, comparing both storages at each step.
+        for i in 1..=500_u32 {
+            let mut key = [0; 32];
+            key[..4].copy_from_slice(&i.wrapping_mul(2_654_435_761).to_be_bytes());
+
+            let sparse_insert = sparse.insert_node(key, [42; 32]);
+            let dense_insert = dense.insert_node(key, [42; 32]);
+            assert_eq!(dense.root, sparse.root);
+            assert_eq!(
+                bincode::serialize(&dense_insert).unwrap(),
+                bincode::serialize(&sparse_insert).unwrap()
+            );
+
+            if i % 3 == 0 {
+                sparse.update_node(key, [43; 32]);
+                dense.update_node(key, [43; 32]);
+                assert_eq!(dense.root, sparse.root);
+            }
+
+            assert_eq!(dense.siblings(&key), sparse.siblings(&key));
+        }
+
+        dense.validate_invariants().unwrap();
+
+        // Rebuilding keeps the dense storage consistent.
+        let root = dense.root;
+        dense.hashes.replace(HashMap::new());
+        dense.rebuild();
+        assert_eq!(dense.root, root);
+        assert!(matches!(dense.hashes, HashStorage::Dense(_)));
+
+        let key = sparse.low_nullifier(&[0xff; 32]).key;
+        assert_eq!(dense.siblings(&key), sparse.siblings(&key));
+        assert_eq!(
+            bincode::serialize(&dense.update_node(key, [44; 32])).unwrap(),
+            bincode::serialize(&sparse.update_node(key, [44; 32])).unwrap()
+        );
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_rebuild_async() {
+        let mut imt = Imt::new(Keccak::v256);
+        for i in 1..=1000_u32 {
+            let mut key = [0; 32];
+            key[..4].copy_from_slice(&i.to_be_bytes());
+            imt.insert_node(key, [42; 32]);
+        }
+
+        // The chunked rebuild must agree with the synchronous one.
+        let mut rebuilt = imt.clone();
+        rebuilt.hashes.replace(HashMap::new());
+        rebuilt.rebuild_async(64).await;
+        assert_eq!(rebuilt.root, imt.root);
+
+        let mut rebuilt = imt.clone();
+        rebuilt.rebuild();
+        assert_eq!(rebuilt.root, imt.root);
+    }
+
+    /// Keccak hashor pretending to output 512 bits hashes.
+    struct WideKeccak(Keccak);
+
+    impl Hashor for WideKeccak {
+        const OUTPUT_LEN: usize = 64;
+
+        fn finalize_reset(&mut self, output: &mut [u8]) {
+            self.0.finalize_reset(output);
+        }
+    }
+
+    impl Hasher for WideKeccak {
+        fn update(&mut self, input: &[u8]) {
+            self.0.update(input);
+        }
+
+        fn finalize(self, output: &mut [u8]) {
+            self.0.finalize(output);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "hasher output width does not match the hash width")]
+    fn test_new_rejects_hasher_width_mismatch() {
+        Imt::<_, [u8; 32], [u8; 32]>::new(|| WideKeccak(Keccak::v512()));
+    }
+
+    #[test]
+    fn test_old_value_and_new_root() {
+        for scheme in [CommitmentScheme::default(), CommitmentScheme::SizeSeparate] {
+            let mut imt = Imt::with_scheme(Keccak::v256, scheme);
+            assert_eq!(imt.get_value(&[1; 32]), None);
+
+            let insert = imt.insert_node([1; 32], [42; 32]);
+            assert_eq!(
+                insert.new_root_with_layout(Keccak::v256, scheme, imt.layout()),
+                imt.root
+            );
+
+            let old_value = imt.get_value(&[1; 32]).unwrap();
+            let IMTMutate::Update(update) = imt.update_node([1; 32], [43; 32]) else {
+                unreachable!()
+            };
+            assert_eq!(update.old_value(), old_value);
+            assert_eq!(update.new_value, [43; 32]);
+            assert_eq!(imt.get_value(&[1; 32]), Some([43; 32]));
+            assert_eq!(
+                update.new_root_with_layout(Keccak::v256, scheme, imt.layout()),
+                imt.root
+            );
+        }
+
+        // Filling a vacant slot does not grow the IMT.
+        let mut imt = Imt::new(Keccak::v256);
+        for i in 1..=3 {
+            imt.insert_node([i; 32], [42; 32]);
+        }
+        imt.remove_node(&[2; 32]);
+        let insert = imt.insert_node([4; 32], [42; 32]);
+        assert_eq!(insert.new_root(Keccak::v256), imt.root);
+    }
+
+    #[test]
+    fn test_insert_outcome() {
+        let mut imt = Imt::new(Keccak::v256);
+        for i in 1..=3 {
+            let old_root = imt.root;
+            let outcome = imt.insert([i; 32], [42; 32]);
+            assert_eq!(outcome.node.index, i as u64);
+            assert_eq!((outcome.node.key, outcome.node.value), ([i; 32], [42; 32]));
+            assert_eq!(outcome.new_root, imt.root);
+            assert_eq!(outcome.new_size, imt.size);
+            assert_eq!(
+                outcome.mutate.verify(Keccak::v256, old_root).unwrap(),
+                outcome.new_root
+            );
+        }
+
+        // Filling a vacant slot reuses its index and keeps the size.
+        imt.remove_node(&[2; 32]);
+        let outcome = imt.insert([4; 32], [42; 32]);
+        assert_eq!(outcome.node.index, 2);
+        assert_eq!(outcome.new_size, 4);
+        assert_eq!(outcome.new_root, imt.root);
+
+        let update = imt.update_node([4; 32], [43; 32]);
+        assert!(update.inserted_node().is_none());
+    }
+
+    #[test]
+    fn test_delete_node() {
+        let mut imt = Imt::new(Keccak::v256);
+        imt.insert_node([1; 32], [42; 32]);
+        imt.insert_node([2; 32], [42; 32]);
+
+        let old_root = imt.root;
+        let delete = imt.delete_node([1; 32]);
+        let delete_root = delete.verify(Keccak::v256, old_root).unwrap();
+        assert_eq!(delete_root, imt.root);
+
+        assert!(imt.is_deleted(&[1; 32]));
+        assert!(!imt.is_deleted(&[2; 32]));
+        assert!(!imt.is_deleted(&[3; 32]));
+        assert_eq!(imt.get_value(&[1; 32]), Some([0; 32]));
+        assert_eq!(imt.get_live_value(&[1; 32]), None);
+        assert_eq!(imt.get_live_value(&[2; 32]), Some([42; 32]));
+
+        // The deleted key is still in the IMT, only an update brings it back.
+        assert_eq!(imt.size, 3);
+        let update = imt.set([1; 32], [43; 32]);
+        assert!(matches!(update, IMTMutate::Update(_)));
+        assert_eq!(update.verify(Keccak::v256, delete_root).unwrap(), imt.root);
+        assert!(!imt.is_deleted(&[1; 32]));
+        assert_eq!(imt.get_live_value(&[1; 32]), Some([43; 32]));
+        imt.validate_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_update_node_if_changed() {
+        let mut imt = Imt::new(Keccak::v256);
+        imt.insert_node([1; 32], [42; 32]);
+
+        // Updating a node to its current value is skipped.
+        let old_root = imt.root;
+        assert!(imt.update_node_if_changed([1; 32], [42; 32]).is_none());
+        assert_eq!(imt.root, old_root);
+
+        let update = imt.update_node_if_changed([1; 32], [43; 32]).unwrap();
+        assert_eq!(update.verify(Keccak::v256, old_root).unwrap(), imt.root);
+        assert_eq!(imt.get_value(&[1; 32]), Some([43; 32]));
+    }
+
+    #[test]
+    fn test_zero_node_mutations() {
+        let mut imt = Imt::new(Keccak::v256);
+        for i in 1..=3 {
+            imt.insert_node([i; 32], [42; 32]);
+        }
+        imt.remove_node(&[3; 32]);
+
+        // Updating the zero node only changes its value.
+        let old_root = imt.root;
+        let update = imt.update_node([0; 32], [43; 32]);
+        assert_eq!(update.verify(Keccak::v256, old_root).unwrap(), imt.root);
+        assert_eq!(imt.get_value(&[0; 32]), Some([43; 32]));
+        assert_eq!(imt.node(&[0; 32]).unwrap().next_key, [1; 32]);
+        imt.validate_invariants().unwrap();
+
+        // The vacant slot holds the zero key too, but is not a node to update.
+        let vacant = IMTNode::vacant(3);
+        let forged: IMTMutate<[u8; 32], [u8; 32]> =
+            IMTMutate::update(imt.root, imt.size, vacant, imt.siblings_cached(3), [43; 32]);
+        let res = forged.verify(Keccak::v256, imt.root);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTMutate.node is vacant"));
+    }
+
+    #[test]
+    #[should_panic(expected = "the zero node can not be removed")]
+    fn test_remove_zero_node() {
+        let mut imt = Imt::<_, [u8; 32], [u8; 32]>::new(Keccak::v256);
+        imt.remove_node(&[0; 32]);
+    }
+
+    #[test]
+    #[should_panic(expected = "key conflict")]
+    fn test_insert_zero_key() {
+        let mut imt = Imt::new(Keccak::v256);
+        imt.insert_node([0; 32], [42; 32]);
+    }
+
+    #[test]
+    fn test_set() {
+        let mut imt = Imt::new(Keccak::v256);
+        imt.insert_node([1; 32], [42; 32]);
+
+        let old_root = imt.root;
+        let insert = imt.set([2; 32], [42; 32]);
+        assert!(matches!(insert, IMTMutate::Insert(_)));
+        let insert_root = insert.verify(Keccak::v256, old_root).unwrap();
+        assert_eq!(insert_root, imt.root);
+
+        let update = imt.set([2; 32], [43; 32]);
+        assert!(matches!(update, IMTMutate::Update(_)));
+        assert_eq!(update.verify(Keccak::v256, insert_root).unwrap(), imt.root);
+
+        // Same state as inserting then updating explicitly.
+        let mut expected = Imt::new(Keccak::v256);
+        expected.insert_node([1; 32], [42; 32]);
+        expected.insert_node([2; 32], [42; 32]);
+        expected.update_node([2; 32], [43; 32]);
+        assert_eq!(imt.root, expected.root);
+        assert_eq!((imt.size, imt.node_count()), (3, 3));
+        imt.validate_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_preview_update() {
+        for scheme in [CommitmentScheme::default(), CommitmentScheme::SizeSeparate] {
+            let mut imt = Imt::with_scheme(Keccak::v256, scheme);
+            for i in 1..=5 {
+                imt.insert_node([i; 32], [42; 32]);
+            }
+
+            let root = imt.root;
+            let (new_root, preview) = imt.preview_update(&[3; 32], [43; 32]).unwrap();
+            assert_eq!(imt.root, root);
+
+            let update = imt.update_node([3; 32], [43; 32]);
+            assert_eq!(new_root, imt.root);
+            assert_eq!(
+                bincode::serialize(&preview).unwrap(),
+                bincode::serialize(&update).unwrap()
+            );
+        }
+
+        let imt = Imt::<_, [u8; 32], [u8; 32]>::new(Keccak::v256);
+        let res = imt.preview_update(&[1; 32], [43; 32]);
+        assert!(matches!(res, Err(e) if e.to_string() == "node does not exist"));
+    }
+
+    #[test]
+    fn test_validate_invariants_corrupted() {
+        let mut imt = Imt::new(Keccak::v256);
+        for key in [[1; 32], [2; 32], [3; 32]] {
+            imt.insert_node(key, [42; 32]);
+        }
+
+        // Skip [2; 32] in the linked list.
+        let mut corrupted = imt.clone();
+        corrupted.node_mut(&[1; 32]).unwrap().next_key = [3; 32];
+        let res = corrupted.validate_invariants();
+        assert!(
+            matches!(res, Err(e) if e.to_string() == "next_key chain does not reach every node")
+        );
+
+        // Point to a key that is not in the tree.
+        let mut corrupted = imt.clone();
+        corrupted.node_mut(&[1; 32]).unwrap().next_key = [4; 32];
+        let res = corrupted.validate_invariants();
+        assert!(matches!(res, Err(e) if e.to_string()
+            == "node at index 1 has a next_key that is not in the IMT"));
+
+        // Break the ordering of the linked list.
+        let mut corrupted = imt.clone();
+        corrupted.node_mut(&[2; 32]).unwrap().next_key = [1; 32];
+        let res = corrupted.validate_invariants();
+        assert!(matches!(res, Err(e) if e.to_string()
+            == "node at index 2 is not sorted before its next_key"));
+
+        // Duplicate a node index.
+        let mut corrupted = imt.clone();
+        corrupted.node_mut(&[2; 32]).unwrap().index = 1;
+        let res = corrupted.validate_invariants();
+        assert!(matches!(res, Err(e) if e.to_string() == "node index 1 is duplicated"));
+
+        // Index a node at the wrong slot.
+        let mut corrupted = imt.clone();
+        corrupted.indices.insert([2; 32], 3);
+        let res = corrupted.validate_invariants();
+        assert!(matches!(res, Err(e) if e.to_string()
+            == "node at index 2 is not indexed by its key"));
+
+        // Change a node without refreshing the root.
+        let mut corrupted = imt.clone();
+        corrupted.node_mut(&[2; 32]).unwrap().value = [43; 32];
+        let res = corrupted.validate_invariants();
+        assert!(matches!(res, Err(e) if e.to_string() == "IMT root does not match its nodes"));
     }
 }
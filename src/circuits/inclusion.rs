@@ -0,0 +1,141 @@
+//! Proof that a node is in an IMT.
+
+use anyhow::{ensure, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::Hash;
+
+use super::{
+    node::{Hashor, IMTNode, Key, LeafLayout, NodeHasher, Value},
+    node_exists,
+    siblings::Siblings,
+    CommitmentScheme,
+};
+
+/// Proof that `node` is in the IMT commited to in `root`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct IMTInclusion<K: Key, V: Value, S = Vec<Option<Hash>>> {
+    pub root: Hash,
+    pub size: u64,
+    pub node: IMTNode<K, V>,
+    pub node_siblings: S,
+}
+
+impl<K: Key, V: Value, S: Siblings> IMTInclusion<K, V, S> {
+    /// Verifies that `self.node` is in the IMT commited to in `root`.
+    pub fn verify<H: Hashor>(&self, hasher_factory: fn() -> H, root: Hash) -> Result<()> {
+        self.verify_with_layout(
+            hasher_factory,
+            CommitmentScheme::default(),
+            LeafLayout::default(),
+            root,
+        )
+    }
+
+    /// Same as `verify` under the given commitment `scheme`, the leaves being hashed with the
+    /// given `layout`.
+    pub fn verify_with_layout<H: Hashor, N: NodeHasher<K, V>>(
+        &self,
+        hasher_factory: fn() -> H,
+        scheme: CommitmentScheme,
+        layout: N,
+        root: Hash,
+    ) -> Result<()> {
+        ensure!(root == self.root, "IMTInclusion.root is stale");
+
+        // Vacant slots hold no key to prove the membership of.
+        ensure!(!self.node.is_vacant(), "IMTInclusion.node is vacant");
+        ensure!(
+            self.node.index < self.size
+                && node_exists(
+                    hasher_factory,
+                    scheme,
+                    layout,
+                    &self.root,
+                    self.size,
+                    &self.node,
+                    &self.node_siblings
+                ),
+            "IMTInclusion.node is not in the IMT"
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use tiny_keccak::Keccak;
+
+    use super::*;
+    use crate::circuits::imt::Imt;
+
+    #[test]
+    fn test_inclusion_by_index() {
+        let mut imt = Imt::new(Keccak::v256);
+        for key in [[5; 32], [2; 32], [9; 32], [7; 32]] {
+            imt.insert_node(key, [42; 32]);
+        }
+
+        // Proofs generated by key and by index are identical.
+        for key in [[0; 32], [5; 32], [2; 32], [9; 32], [7; 32]] {
+            let by_key = imt.prove_membership(&key).unwrap();
+            let by_index = imt.prove_membership_by_index(by_key.node.index).unwrap();
+            assert_eq!(
+                bincode::serialize(&by_key).unwrap(),
+                bincode::serialize(&by_index).unwrap()
+            );
+            assert_eq!(
+                imt.siblings_by_index(by_key.node.index).unwrap(),
+                imt.siblings(&key)
+            );
+            by_index.verify(Keccak::v256, imt.root).unwrap();
+        }
+
+        // Indices never assigned.
+        let res = imt.prove_membership_by_index(imt.size);
+        assert!(matches!(res, Err(e) if e.to_string() == "index is out of the IMT"));
+        let res = imt.siblings_by_index(u64::MAX);
+        assert!(matches!(res, Err(e) if e.to_string() == "index is out of the IMT"));
+        let res = imt.prove_membership(&[3; 32]);
+        assert!(matches!(res, Err(e) if e.to_string() == "node does not exist"));
+
+        // Vacant slots hold no node, yet are part of the tree.
+        imt.remove_node(&[9; 32]);
+        let res = imt.prove_membership_by_index(3);
+        assert!(matches!(res, Err(e) if e.to_string() == "index is vacant"));
+        assert_eq!(imt.siblings_by_index(3).unwrap(), imt.siblings_cached(3));
+
+        let forged = IMTInclusion {
+            root: imt.root,
+            size: imt.size,
+            node: IMTNode::<[u8; 32], [u8; 32]>::vacant(3),
+            node_siblings: imt.siblings_cached(3),
+        };
+        let res = forged.verify(Keccak::v256, imt.root);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTInclusion.node is vacant"));
+    }
+
+    #[test]
+    fn test_inclusion_invalid() {
+        let mut imt = Imt::new(Keccak::v256);
+        for key in [[1; 32], [2; 32], [3; 32]] {
+            imt.insert_node(key, [42; 32]);
+        }
+        let proof = imt.prove_membership(&[2; 32]).unwrap();
+
+        let forged = IMTInclusion {
+            node: IMTNode {
+                value: [43; 32],
+                ..proof.node
+            },
+            ..proof.clone()
+        };
+        let res = forged.verify(Keccak::v256, imt.root);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTInclusion.node is not in the IMT"));
+
+        imt.insert_node([4; 32], [42; 32]);
+        let res = proof.verify(Keccak::v256, imt.root);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTInclusion.root is stale"));
+    }
+}
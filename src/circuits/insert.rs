@@ -1,69 +1,236 @@
-use anyhow::{ensure, Result};
+use std::fmt;
+
+use anyhow::{bail, ensure, Result};
 use serde::{Deserialize, Serialize};
 
 use crate::Hash;
 
+#[cfg(feature = "tracing")]
+use super::trace;
 use super::{
+    ensure_siblings_depth,
+    hex::{Hex, HexSiblings},
     imt_root,
-    node::{Hashor, IMTNode, Key, Value},
-    node_exists,
+    node::{HasherFactory, Hashor, IMTNode, Key, LeafLayout, NodeHasher, Value},
+    node_exists, roots_eq,
+    siblings::{CompactSiblings, FixedSiblings, Siblings},
+    CommitmentScheme,
 };
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct IMTInsert<K: Key, V: Value> {
+#[derive(Clone, Deserialize, Serialize)]
+pub struct IMTInsert<K: Key, V: Value, S = Vec<Option<Hash>>> {
     pub old_root: Hash,
     pub old_size: u64,
     pub ln_node: IMTNode<K, V>,
-    pub ln_siblings: Vec<Option<Hash>>,
+    pub ln_siblings: S,
 
     pub node: IMTNode<K, V>,
-    pub node_siblings: Vec<Option<Hash>>,
-    pub updated_ln_siblings: Vec<Option<Hash>>,
+    pub node_siblings: S,
+    pub updated_ln_siblings: S,
+}
+
+impl<K: Key, V: Value, S: Siblings> fmt::Debug for IMTInsert<K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IMTInsert")
+            .field("old_root", &Hex(&self.old_root))
+            .field("old_size", &self.old_size)
+            .field("ln_node", &self.ln_node)
+            .field("ln_siblings", &HexSiblings(&self.ln_siblings))
+            .field("node", &self.node)
+            .field("node_siblings", &HexSiblings(&self.node_siblings))
+            .field(
+                "updated_ln_siblings",
+                &HexSiblings(&self.updated_ln_siblings),
+            )
+            .finish()
+    }
 }
 
-impl<K: Key, V: Value> IMTInsert<K, V> {
+impl<K: Key, V: Value, S: Siblings> IMTInsert<K, V, S> {
+    /// Computes the root after the insertion from the inserted node and its siblings, without
+    /// verifying the insertion against its old root.
+    pub fn new_root<H: Hashor>(&self, hasher_factory: impl HasherFactory<H>) -> Hash {
+        self.new_root_with_layout(
+            hasher_factory,
+            CommitmentScheme::default(),
+            LeafLayout::default(),
+        )
+    }
+
+    /// Same as `new_root` under the given commitment `scheme`, the leaves being hashed with the
+    /// given `layout`.
+    pub fn new_root_with_layout<H: Hashor, N: NodeHasher<K, V>>(
+        &self,
+        hasher_factory: impl HasherFactory<H>,
+        scheme: CommitmentScheme,
+        layout: N,
+    ) -> Hash {
+        // Appending a node grows the IMT, filling a vacant slot does not.
+        let new_size = self.old_size + u64::from(self.node.index == self.old_size);
+
+        imt_root(
+            hasher_factory,
+            scheme,
+            layout,
+            new_size,
+            &self.node,
+            &self.node_siblings,
+        )
+    }
+
     /// Verifies the IMT insert and return the new updated root.
     ///
     /// Before performing the insertion, the state is checked to make sure it is coherent.
-    pub fn verify<H: Hashor>(&self, hasher_factory: fn() -> H, old_root: Hash) -> Result<Hash> {
+    pub fn verify<H: Hashor>(
+        &self,
+        hasher_factory: impl HasherFactory<H>,
+        old_root: Hash,
+    ) -> Result<Hash> {
+        self.verify_with_scheme(
+            hasher_factory,
+            CommitmentScheme::default(),
+            old_root,
+            self.old_size,
+        )
+    }
+
+    /// Verifies the IMT insert under the given commitment `scheme` and return the new updated root.
+    ///
+    /// The `old_size` is checked as a plain equality, which is what binds the size when it is
+    /// committed to separately from the root.
+    pub fn verify_with_scheme<H: Hashor>(
+        &self,
+        hasher_factory: impl HasherFactory<H>,
+        scheme: CommitmentScheme,
+        old_root: Hash,
+        old_size: u64,
+    ) -> Result<Hash> {
+        self.verify_with_layout(
+            hasher_factory,
+            scheme,
+            LeafLayout::default(),
+            old_root,
+            old_size,
+        )
+    }
+
+    /// Verifies the IMT insert under the given commitment `scheme`, the leaves being hashed with
+    /// the given `layout`, and return the new updated root.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "verify_insert",
+            level = "trace",
+            skip_all,
+            fields(
+                key = %Hex(self.node.key.as_ref()),
+                index = self.node.index,
+                old_root = %Hex(&old_root),
+                old_size = old_size,
+                siblings = self.node_siblings.levels().count(),
+            )
+        )
+    )]
+    pub fn verify_with_layout<H: Hashor, N: NodeHasher<K, V>>(
+        &self,
+        hasher_factory: impl HasherFactory<H>,
+        scheme: CommitmentScheme,
+        layout: N,
+        old_root: Hash,
+        old_size: u64,
+    ) -> Result<Hash> {
         // Make sure the IMTMutate old_root matches the expected old_root.
-        ensure!(old_root == self.old_root, "IMTMutate.old_root is stale");
+        #[cfg(feature = "tracing")]
+        trace::root_mismatch("IMTMutate.old_root is stale", &old_root, &self.old_root);
+        ensure!(
+            roots_eq(&old_root, &self.old_root),
+            "IMTMutate.old_root is stale"
+        );
+
+        // Make sure the IMTMutate old_size matches the expected old_size.
+        ensure!(old_size == self.old_size, "IMTMutate.old_size is stale");
+        ensure_siblings_depth(&self.ln_siblings, self.old_size)?;
 
         // Verify that the provided ln node is valid.
         ensure!(
-            self.is_valid_ln(hasher_factory),
+            self.is_valid_ln(hasher_factory, scheme, layout),
             "IMTMutate.ln_node is invalid"
         );
 
+        // Make sure the node takes over the ln node link, keeping the linked list sorted.
+        ensure!(
+            self.node.next_key == self.ln_node.next_key,
+            "IMTMutate.node.next_key is invalid"
+        );
+
+        // Make sure the node is appended right after the last node, growing the IMT. Filling a
+        // vacant slot is left out until removals are provable.
+        ensure!(
+            self.node.index == self.old_size,
+            "IMTMutate.node.index is invalid"
+        );
+
         // Compute the updated root from the node and the updated ln node.
         let updated_ln = IMTNode {
             next_key: self.node.key,
             ..self.ln_node
         };
 
-        let new_size: u64 = self.old_size + 1;
-        let root_from_node = imt_root(hasher_factory, new_size, &self.node, &self.node_siblings);
+        let Some(new_size) = self.old_size.checked_add(1) else {
+            bail!("IMTMutate.old_size is invalid");
+        };
+        ensure_siblings_depth(&self.node_siblings, new_size)?;
+        ensure_siblings_depth(&self.updated_ln_siblings, new_size)?;
+
+        let root_from_node = imt_root(
+            hasher_factory,
+            scheme,
+            layout,
+            new_size,
+            &self.node,
+            &self.node_siblings,
+        );
         let root_from_updated_ln = imt_root(
             hasher_factory,
+            scheme,
+            layout,
             new_size,
             &updated_ln,
             &self.updated_ln_siblings,
         );
 
         // Make sure both roots are equal.
+        #[cfg(feature = "tracing")]
+        trace::root_mismatch(
+            "IMTMutate.updated_ln_siblings is invalid",
+            &root_from_node,
+            &root_from_updated_ln,
+        );
         ensure!(
             root_from_node == root_from_updated_ln,
             "IMTMutate.updated_ln_siblings is invalid"
         );
 
+        #[cfg(feature = "tracing")]
+        tracing::trace!(new_root = %Hex(&root_from_node), "insert verified");
+
         Ok(root_from_node)
     }
 
     /// Returns `true` if `self.ln_node` is a valid ln node for `self.node`.
-    fn is_valid_ln<H: Hashor>(&self, hasher_factory: fn() -> H) -> bool {
+    fn is_valid_ln<H: Hashor, N: NodeHasher<K, V>>(
+        &self,
+        hasher_factory: impl HasherFactory<H>,
+        scheme: CommitmentScheme,
+        layout: N,
+    ) -> bool {
         self.ln_node.is_ln_of(&self.node.key)
+            && !self.ln_node.is_vacant()
+            && self.ln_node.index < self.old_size
             && node_exists(
                 hasher_factory,
+                scheme,
+                layout,
                 &self.old_root,
                 self.old_size,
                 &self.ln_node,
@@ -72,11 +239,77 @@ impl<K: Key, V: Value> IMTInsert<K, V> {
     }
 }
 
-#[cfg(test)]
+impl<K: Key, V: Value> From<IMTInsert<K, V>> for IMTInsert<K, V, CompactSiblings> {
+    fn from(insert: IMTInsert<K, V>) -> Self {
+        Self {
+            old_root: insert.old_root,
+            old_size: insert.old_size,
+            ln_node: insert.ln_node,
+            ln_siblings: insert.ln_siblings.into(),
+            node: insert.node,
+            node_siblings: insert.node_siblings.into(),
+            updated_ln_siblings: insert.updated_ln_siblings.into(),
+        }
+    }
+}
+
+impl<K: Key, V: Value> From<IMTInsert<K, V, CompactSiblings>> for IMTInsert<K, V> {
+    fn from(insert: IMTInsert<K, V, CompactSiblings>) -> Self {
+        Self {
+            old_root: insert.old_root,
+            old_size: insert.old_size,
+            ln_node: insert.ln_node,
+            ln_siblings: insert.ln_siblings.into(),
+            node: insert.node,
+            node_siblings: insert.node_siblings.into(),
+            updated_ln_siblings: insert.updated_ln_siblings.into(),
+        }
+    }
+}
+
+impl<K: Key, V: Value, const D: usize> TryFrom<IMTInsert<K, V>>
+    for IMTInsert<K, V, FixedSiblings<D>>
+{
+    type Error = anyhow::Error;
+
+    fn try_from(insert: IMTInsert<K, V>) -> Result<Self> {
+        Ok(Self {
+            old_root: insert.old_root,
+            old_size: insert.old_size,
+            ln_node: insert.ln_node,
+            ln_siblings: insert.ln_siblings.try_into()?,
+            node: insert.node,
+            node_siblings: insert.node_siblings.try_into()?,
+            updated_ln_siblings: insert.updated_ln_siblings.try_into()?,
+        })
+    }
+}
+
+impl<K: Key, V: Value, const D: usize> From<IMTInsert<K, V, FixedSiblings<D>>> for IMTInsert<K, V> {
+    fn from(insert: IMTInsert<K, V, FixedSiblings<D>>) -> Self {
+        Self {
+            old_root: insert.old_root,
+            old_size: insert.old_size,
+            ln_node: insert.ln_node,
+            ln_siblings: insert.ln_siblings.into(),
+            node: insert.node,
+            node_siblings: insert.node_siblings.into(),
+            updated_ln_siblings: insert.updated_ln_siblings.into(),
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
 mod tests {
     use tiny_keccak::Keccak;
 
-    use crate::circuits::{imt::Imt, mutate::IMTMutate, node::IMTNode};
+    use crate::circuits::{
+        imt::Imt,
+        imt_root,
+        mutate::IMTMutate,
+        node::{IMTNode, LeafLayout},
+        CommitmentScheme, ImtError,
+    };
 
     #[test]
     fn test_verify_invalid_old_root() {
@@ -149,6 +382,177 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_verify_invalid_next_key() {
+        // Instanciate an IMT with a few nodes.
+        let mut imt = Imt::new(Keccak::v256);
+        imt.insert_node([1; 32], [42; 32]);
+        imt.insert_node([5; 32], [42; 32]);
+        imt.insert_node([10; 32], [42; 32]);
+
+        // Link the node past the ln node old `next_key`, skipping keys of the IMT, or to a key
+        // that is not in the IMT.
+        if let IMTMutate::Insert(sut) = imt.insert_node([3; 32], [42; 32]) {
+            for next_key in [[10; 32], [0; 32], [4; 32]] {
+                let mut sut = sut.clone();
+                sut.node.next_key = next_key;
+                let res = sut.verify(Keccak::v256, sut.old_root);
+                assert!(
+                    matches!(res, Err(e) if e.to_string() == "IMTMutate.node.next_key is invalid")
+                );
+            }
+        } else {
+            panic!("invalid result")
+        }
+    }
+
+    #[test]
+    fn test_verify_invalid_index() {
+        // Instanciate an IMT with a few nodes.
+        let mut imt = Imt::new(Keccak::v256);
+        imt.insert_node([1; 32], [42; 32]);
+        imt.insert_node([2; 32], [42; 32]);
+        imt.insert_node([3; 32], [42; 32]);
+
+        // Flip an `ln_node.index` bit above the IMT depth.
+        if let IMTMutate::Insert(mut sut) = imt.insert_node([4; 32], [42; 32]) {
+            sut.ln_node.index |= 1 << 40;
+            let res = sut.verify(Keccak::v256, sut.old_root);
+            assert!(matches!(res, Err(e) if e.to_string() == "IMTMutate.ln_node is invalid"));
+        } else {
+            panic!("invalid result")
+        }
+
+        // Flip a `node.index` bit above the IMT depth.
+        if let IMTMutate::Insert(mut sut) = imt.insert_node([5; 32], [42; 32]) {
+            sut.node.index |= 1 << 40;
+            let res = sut.verify(Keccak::v256, sut.old_root);
+            assert!(matches!(res, Err(e) if e.to_string() == "IMTMutate.node.index is invalid"));
+        } else {
+            panic!("invalid result")
+        }
+    }
+
+    #[test]
+    fn test_verify_siblings_exceed_depth() {
+        // Instanciate an IMT with a few nodes.
+        let mut imt = Imt::new(Keccak::v256);
+        imt.insert_node([1; 32], [42; 32]);
+        imt.insert_node([2; 32], [42; 32]);
+        imt.insert_node([3; 32], [42; 32]);
+
+        let IMTMutate::Insert(insert) = imt.insert_node([4; 32], [42; 32]) else {
+            panic!("invalid result")
+        };
+
+        // Append one sibling above the IMT depth to each sibling path. The IMT grows from 4 to
+        // 5 nodes, and from a depth of 2 to 3.
+        for (sut, len, depth) in [
+            {
+                let mut sut = insert.clone();
+                sut.ln_siblings.push(Some([0; 32]));
+                (sut, 3, 2)
+            },
+            {
+                let mut sut = insert.clone();
+                sut.node_siblings.push(Some([0; 32]));
+                (sut, 4, 3)
+            },
+            {
+                let mut sut = insert.clone();
+                sut.updated_ln_siblings.push(Some([0; 32]));
+                (sut, 4, 3)
+            },
+        ] {
+            let res = sut.verify(Keccak::v256, sut.old_root);
+            let err = res.unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<ImtError>(),
+                Some(&ImtError::SiblingsExceedDepth { len, depth })
+            );
+        }
+    }
+
+    #[test]
+    fn test_verify_vacant_slot() {
+        // Instanciate an IMT with a vacant slot.
+        let mut imt = Imt::new(Keccak::v256);
+        for i in 1..=4 {
+            imt.insert_node([i; 32], [42; 32]);
+        }
+        imt.remove_node(&[2; 32]);
+
+        // Filling the vacant slot is rejected until removals are provable.
+        let old_root = imt.root;
+        let IMTMutate::Insert(sut) = imt.insert_node([10; 32], [42; 32]) else {
+            panic!("invalid result")
+        };
+        assert_eq!(sut.node.index, 2);
+        assert_eq!(sut.new_root(Keccak::v256), imt.root);
+        let res = sut.verify(Keccak::v256, old_root);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTMutate.node.index is invalid"));
+
+        // So is filling an occupied slot.
+        let mut forged = sut.clone();
+        forged.node.index = 3;
+        let res = forged.verify(Keccak::v256, old_root);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTMutate.node.index is invalid"));
+
+        // A vacant node can not be used as ln node.
+        imt.remove_node(&[3; 32]);
+        let node = IMTNode {
+            index: 3,
+            key: [20; 32],
+            value: [42; 32],
+            next_key: [0; 32],
+        };
+        let sut = IMTMutate::insert(
+            imt.root,
+            imt.size,
+            IMTNode::vacant(3),
+            imt.siblings_cached(3),
+            node,
+            vec![],
+            vec![],
+        );
+        let res = sut.verify(Keccak::v256, imt.root);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTMutate.ln_node is invalid"));
+    }
+
+    #[test]
+    fn test_verify_size_overflow() {
+        // Craft a coherent proof inserting into a tree claiming the maximum size.
+        let scheme = CommitmentScheme::default();
+        let ln_node = IMTNode::default();
+        let ln_siblings = vec![Some([0; 32]); 64];
+        let old_root = imt_root(
+            Keccak::v256,
+            scheme,
+            LeafLayout::default(),
+            u64::MAX,
+            &ln_node,
+            &ln_siblings,
+        );
+        let node = IMTNode {
+            index: u64::MAX,
+            key: [1; 32],
+            value: [42; 32],
+            next_key: [0; 32],
+        };
+
+        let sut = IMTMutate::insert(
+            old_root,
+            u64::MAX,
+            ln_node,
+            ln_siblings,
+            node,
+            vec![],
+            vec![],
+        );
+        let res = sut.verify(Keccak::v256, old_root);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTMutate.old_size is invalid"));
+    }
+
     #[test]
     fn test_verify_invalid_updated_ln_siblings() {
         // Instanciate an IMT with a few nodes.
@@ -0,0 +1,382 @@
+//! Ready-made key and value types.
+//!
+//! The IMT orders keys by comparing their bytes, which only matches the numeric order of
+//! integers encoded in big endian. The types below always store their bytes in big endian, so
+//! that their byte order and their numeric order agree.
+
+use std::{fmt, mem::size_of};
+
+use anyhow::{bail, ensure};
+use serde::{Deserialize, Serialize};
+
+/// 20 bytes account address.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+pub struct Address(pub [u8; 20]);
+
+impl AsRef<[u8]> for Address {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<[u8; 20]> for Address {
+    fn from(bytes: [u8; 20]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl fmt::Debug for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Address(0x")?;
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// 20 bytes account address left padded with zeros to 32 bytes, as done by `abi.encode` and
+/// `bytes32(uint256(uint160(address)))` in Solidity.
+///
+/// The padding keeps the order of the addresses, and leaves the zero address as the zero key.
+/// With the `alloy` feature, it is serialized as a checksummed hex address in human readable
+/// formats.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(not(feature = "alloy"), derive(Deserialize, Serialize))]
+pub struct AddressKey([u8; 32]);
+
+impl AddressKey {
+    /// Returns the 20 bytes address.
+    pub fn address(&self) -> [u8; 20] {
+        let mut address = [0; 20];
+        address.copy_from_slice(&self.0[12..]);
+        address
+    }
+}
+
+impl AsRef<[u8]> for AddressKey {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<[u8; 20]> for AddressKey {
+    fn from(address: [u8; 20]) -> Self {
+        let mut bytes = [0; 32];
+        bytes[12..].copy_from_slice(&address);
+        Self(bytes)
+    }
+}
+
+impl From<Address> for AddressKey {
+    fn from(address: Address) -> Self {
+        Self::from(address.0)
+    }
+}
+
+impl From<AddressKey> for Address {
+    fn from(key: AddressKey) -> Self {
+        Self(key.address())
+    }
+}
+
+/// Accepts either the 20 bytes of an address or its 32 bytes padded form.
+impl TryFrom<&[u8]> for AddressKey {
+    type Error = anyhow::Error;
+
+    fn try_from(bytes: &[u8]) -> anyhow::Result<Self> {
+        match bytes.len() {
+            20 => Ok(Self::from(<[u8; 20]>::try_from(bytes)?)),
+            32 => {
+                ensure!(
+                    bytes[..12].iter().all(|byte| *byte == 0),
+                    "bytes are not a padded address"
+                );
+                Ok(Self(bytes.try_into()?))
+            }
+            _ => bail!("bytes are not an address"),
+        }
+    }
+}
+
+impl fmt::Debug for AddressKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AddressKey(0x")?;
+        for byte in self.address() {
+            write!(f, "{byte:02x}")?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// 256 bits unsigned integer, stored in big endian.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+pub struct U256([u8; 32]);
+
+impl U256 {
+    /// Creates a `U256` from its big endian bytes.
+    pub fn from_be_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    /// Creates a `U256` from its little endian bytes.
+    pub fn from_le_bytes(mut bytes: [u8; 32]) -> Self {
+        bytes.reverse();
+        Self(bytes)
+    }
+
+    /// Returns the big endian bytes of the integer.
+    pub fn to_be_bytes(&self) -> [u8; 32] {
+        self.0
+    }
+
+    /// Returns the little endian bytes of the integer.
+    pub fn to_le_bytes(&self) -> [u8; 32] {
+        let mut bytes = self.0;
+        bytes.reverse();
+        bytes
+    }
+}
+
+impl AsRef<[u8]> for U256 {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<u64> for U256 {
+    fn from(value: u64) -> Self {
+        let mut bytes = [0; 32];
+        bytes[24..].copy_from_slice(&value.to_be_bytes());
+        Self(bytes)
+    }
+}
+
+impl From<u128> for U256 {
+    fn from(value: u128) -> Self {
+        let mut bytes = [0; 32];
+        bytes[16..].copy_from_slice(&value.to_be_bytes());
+        Self(bytes)
+    }
+}
+
+impl fmt::Debug for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "U256(0x")?;
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Defines an unsigned integer key stored in big endian on the width of `$int`.
+macro_rules! int_key {
+    ($(#[$doc:meta])* $name:ident, $value:ident, $int:ty) => {
+        $(#[$doc])*
+        #[derive(
+            Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize,
+        )]
+        pub struct $name([u8; size_of::<$int>()]);
+
+        #[doc = concat!("`", stringify!($name), "` used as a value.")]
+        pub type $value = $name;
+
+        impl $name {
+            /// Returns the integer.
+            pub fn get(&self) -> $int {
+                <$int>::from_be_bytes(self.0)
+            }
+        }
+
+        impl AsRef<[u8]> for $name {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        impl From<$int> for $name {
+            fn from(value: $int) -> Self {
+                Self(value.to_be_bytes())
+            }
+        }
+
+        impl From<$name> for $int {
+            fn from(key: $name) -> Self {
+                key.get()
+            }
+        }
+
+        impl From<$name> for U256 {
+            fn from(key: $name) -> Self {
+                Self::from(key.get())
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}({})", stringify!($name), self.get())
+            }
+        }
+    };
+}
+
+int_key!(
+    /// 64 bits unsigned integer, e.g. an account id, stored in big endian on 8 bytes.
+    ///
+    /// Keys are thus ordered numerically. Converting to a `U256` pads the integer to 32 bytes,
+    /// ordered the same way.
+    U64Key,
+    U64Value,
+    u64
+);
+
+int_key!(
+    /// 128 bits unsigned integer, stored in big endian on 16 bytes.
+    ///
+    /// Keys are thus ordered numerically. Converting to a `U256` pads the integer to 32 bytes,
+    /// ordered the same way.
+    U128Key,
+    U128Value,
+    u128
+);
+
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use tiny_keccak::Keccak;
+
+    use super::*;
+    use crate::circuits::imt::Imt;
+
+    #[test]
+    fn test_u256_keys_are_ordered_numerically() {
+        let mut imt = Imt::new(Keccak::v256);
+
+        // Keys whose little endian bytes would not sort numerically.
+        for key in [256_u64, 1, 65_536, 2, 257] {
+            let old_root = imt.root;
+            let mutate = imt.insert_node(U256::from(key), U256::from(42_u64));
+            assert_eq!(mutate.verify(Keccak::v256, old_root).unwrap(), imt.root);
+        }
+        imt.validate_invariants().unwrap();
+
+        for (key, ln_key) in [(3_u64, 2_u64), (258, 257), (300, 257), (1_000_000, 65_536)] {
+            let ln = imt.low_nullifier(&U256::from(key));
+            assert_eq!(ln.key, U256::from(ln_key));
+        }
+
+        // Little endian bytes are converted to the same numeric key.
+        let mut le = [0; 32];
+        le[..8].copy_from_slice(&300_u64.to_le_bytes());
+        let key = U256::from_le_bytes(le);
+        assert_eq!(key, U256::from(300_u64));
+        assert_eq!(key.to_le_bytes(), le);
+        assert_eq!(imt.low_nullifier(&key).key, U256::from(257_u64));
+    }
+
+    #[test]
+    fn test_int_keys_are_ordered_numerically() {
+        let mut imt = Imt::new(Keccak::v256);
+        for key in [255_u64, 1, 65_536, 257, u64::MAX] {
+            imt.insert_node(U64Key::from(key), U64Value::from(key));
+        }
+        imt.validate_invariants().unwrap();
+
+        // Low nullifiers of absent keys across byte boundaries.
+        for (key, ln_key) in [
+            (2_u64, 1_u64),
+            (256, 255),
+            (258, 257),
+            (65_535, 257),
+            (65_537, 65_536),
+        ] {
+            let ln = imt.low_nullifier(&U64Key::from(key));
+            assert_eq!(ln.key.get(), ln_key);
+            assert!(ln.is_ln_of(&U64Key::from(key)));
+        }
+        assert!(!imt
+            .low_nullifier(&U64Key::from(300))
+            .is_ln_of(&U64Key::from(255)));
+        assert_eq!(imt.last_key().map(u64::from), Some(u64::MAX));
+
+        let mut imt = Imt::new(Keccak::v256);
+        for key in [1_u128 << 64, 255, 256, u64::MAX as u128] {
+            imt.insert_node(U128Key::from(key), U128Value::from(0));
+        }
+        let ln = imt.low_nullifier(&U128Key::from(u64::MAX as u128 + 2));
+        assert_eq!(u128::from(ln.key), 1 << 64);
+        assert_eq!(imt.low_nullifier(&U128Key::from(300)).key.get(), 256);
+
+        // Padded to 32 bytes, the keys keep their order.
+        assert_eq!(U256::from(U64Key::from(256)), U256::from(256_u64));
+        assert!(U256::from(U128Key::from(255)) < U256::from(U128Key::from(256)));
+        assert_eq!(format!("{:?}", U64Key::from(42)), "U64Key(42)");
+    }
+
+    #[test]
+    fn test_padded_address_keys() {
+        let a = [0xd8, 0xda, 0x6b, 0xf2, 0x69, 0x64, 0xaf, 0x9d, 0x7e, 0xed];
+        let a: [u8; 20] = [
+            a,
+            [0x9e, 0x03, 0xe5, 0x34, 0x15, 0xd3, 0x7a, 0xa9, 0x60, 0x45],
+        ]
+        .concat()
+        .try_into()
+        .unwrap();
+        let b = [0x5a, 0xae, 0xb6, 0x05, 0x3f, 0x3e, 0x94, 0xc9, 0xb9, 0xa0];
+        let b: [u8; 20] = [
+            b,
+            [0x9f, 0x33, 0x66, 0x94, 0x35, 0xe7, 0xef, 0x1b, 0xea, 0xed],
+        ]
+        .concat()
+        .try_into()
+        .unwrap();
+
+        let key = AddressKey::from(a);
+        assert_eq!(key.as_ref()[..12], [0; 12]);
+        assert_eq!(key.address(), a);
+        assert_eq!(AddressKey::try_from(&a[..]).unwrap(), key);
+        assert_eq!(AddressKey::try_from(key.as_ref()).unwrap(), key);
+        assert_eq!(Address::from(key), Address(a));
+        assert!(AddressKey::from(b) < key);
+
+        let res = AddressKey::try_from(&[1; 32][..]);
+        assert!(matches!(res, Err(e) if e.to_string() == "bytes are not a padded address"));
+        let res = AddressKey::try_from(&a[1..]);
+        assert!(matches!(res, Err(e) if e.to_string() == "bytes are not an address"));
+
+        let mut imt = Imt::new(Keccak::v256);
+        imt.insert_node(AddressKey::from(a), U256::from(1_u64));
+        imt.insert_node(AddressKey::from(b), U256::from(2_u64));
+        imt.validate_invariants().unwrap();
+        assert_eq!(imt.first_key(), Some(AddressKey::from(b)));
+
+        // Root of the same IMT built in Solidity, the leaves hashing
+        // `abi.encode(bytes32(uint256(uint160(key))), value, bytes32(uint256(uint160(nextKey))))`.
+        #[cfg(not(any(
+            feature = "bind-index",
+            feature = "balanced-hashing",
+            feature = "level-tagging"
+        )))]
+        assert_eq!(
+            format!("{:?}", crate::circuits::root::Root::new(imt.root)),
+            "Root(0x067ef980ecb4ccef6cb67ec871eaf0322606129516864f368897d642fd98e976)"
+        );
+    }
+
+    #[test]
+    fn test_address_keys() {
+        let mut imt = Imt::new(Keccak::v256);
+        for i in [3, 1, 2] {
+            imt.insert_node(Address([i; 20]), U256::from(i as u64));
+        }
+        imt.validate_invariants().unwrap();
+
+        // An absent key between two inserted ones.
+        let mut key = [1; 20];
+        key[19] = 2;
+        let ln = imt.low_nullifier(&Address(key));
+        assert_eq!(ln.key, Address([1; 20]));
+        assert_eq!(ln.next_key, Address([2; 20]));
+    }
+}
@@ -0,0 +1,417 @@
+//! Merkle Mountain Range of the IMT leaves, for append-only use.
+//!
+//! Appending a leaf to an MMR only hashes the mountains it completes, and the inclusion proof of
+//! a leaf only climbs its own mountain. The IMT is still maintained next to it to prove the non
+//! membership of keys, both roots being bound together by `combined_root`.
+
+use anyhow::{ensure, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::Hash;
+
+use super::{climb_level, fold_size, hash_children, hash_level, node::Hashor, SizeEncoding};
+#[cfg(not(feature = "verifier-only"))]
+use super::{imt::Imt, mutate::IMTMutate, node::IMTNode, node::Key, node::Value};
+
+/// Append-only Merkle Mountain Range.
+#[derive(Debug, Clone)]
+pub struct Mmr<H: Hashor> {
+    hasher_factory: fn() -> H,
+    /// Hashes of the complete subtrees of each height, from the leaves up, in append order.
+    levels: Vec<Vec<Hash>>,
+}
+
+impl<H: Hashor> Mmr<H> {
+    /// Insanciate a new empty MMR.
+    pub fn new(hasher_factory: fn() -> H) -> Self {
+        Self {
+            hasher_factory,
+            levels: vec![],
+        }
+    }
+
+    /// Returns the number of leaves appended to the MMR.
+    pub fn leaf_count(&self) -> u64 {
+        self.levels.first().map_or(0, |leaves| leaves.len() as _)
+    }
+
+    /// Appends `leaf_hash` to the MMR, merging the mountains it completes, and returns its leaf
+    /// index.
+    pub fn append(&mut self, leaf_hash: Hash) -> u64 {
+        let index = self.leaf_count();
+
+        let mut hash = leaf_hash;
+        for height in 0.. {
+            if self.levels.len() == height {
+                self.levels.push(vec![]);
+            }
+
+            let level = &mut self.levels[height];
+            level.push(hash);
+            if !level.len().is_multiple_of(2) {
+                break;
+            }
+
+            let len = level.len();
+            hash = hash_level(
+                self.hasher_factory,
+                height as u8,
+                Some(&level[len - 2]),
+                Some(&level[len - 1]),
+            );
+        }
+
+        index
+    }
+
+    /// Returns the peaks of the MMR, from the highest mountain to the lowest.
+    pub fn peaks(&self) -> Vec<Hash> {
+        let leaf_count = self.leaf_count();
+
+        (0..self.levels.len())
+            .rev()
+            .filter(|height| (leaf_count >> height) & 1 == 1)
+            .map(|height| *self.levels[height].last().expect("failed to get peak"))
+            .collect()
+    }
+
+    /// Returns the MMR root, bagging its peaks along with its leaf count.
+    pub fn root(&self) -> Hash {
+        bag_peaks(self.hasher_factory, &self.peaks(), self.leaf_count())
+    }
+
+    /// Returns the inclusion proof of the leaf at `leaf_index`, or `None` if there is no such
+    /// leaf.
+    pub fn prove(&self, leaf_index: u64) -> Option<MmrProof> {
+        if leaf_index >= self.leaf_count() {
+            return None;
+        }
+
+        // Climb until the subtree holding the leaf is a peak, i.e. has no sibling yet.
+        let mut siblings = vec![];
+        let mut index = leaf_index;
+        for level in &self.levels {
+            let Some(sibling) = level.get((index ^ 1) as usize) else {
+                break;
+            };
+            siblings.push(*sibling);
+            index /= 2;
+        }
+
+        Some(MmrProof {
+            leaf_count: self.leaf_count(),
+            leaf_index,
+            siblings,
+            peaks: self.peaks(),
+        })
+    }
+}
+
+/// Proof that a leaf is in the MMR.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MmrProof {
+    pub leaf_count: u64,
+    pub leaf_index: u64,
+    /// Siblings of the leaf, up to the peak of its mountain.
+    pub siblings: Vec<Hash>,
+    /// Peaks of the MMR, from the highest mountain to the lowest.
+    pub peaks: Vec<Hash>,
+}
+
+impl MmrProof {
+    /// Verifies that `leaf_hash` is the leaf at `self.leaf_index` of the MMR commited to in
+    /// `root`.
+    pub fn verify<H: Hashor>(
+        &self,
+        hasher_factory: fn() -> H,
+        root: Hash,
+        leaf_hash: Hash,
+    ) -> Result<()> {
+        ensure!(
+            self.leaf_index < self.leaf_count,
+            "MmrProof.leaf_index is invalid"
+        );
+        ensure!(
+            self.peaks.len() == self.leaf_count.count_ones() as usize,
+            "MmrProof.peaks is invalid"
+        );
+
+        // Find the mountain holding the leaf, mountains being laid out from the highest one.
+        let (mut start, mut position) = (0, 0);
+        let mut height = 0;
+        for h in (0..u64::BITS).rev() {
+            if (self.leaf_count >> h) & 1 == 0 {
+                continue;
+            }
+            if self.leaf_index < start + (1 << h) {
+                height = h;
+                break;
+            }
+            start += 1 << h;
+            position += 1;
+        }
+        ensure!(
+            self.siblings.len() == height as usize,
+            "MmrProof.siblings is invalid"
+        );
+
+        let mut hash = leaf_hash;
+        let mut index = self.leaf_index;
+        for (level, sibling) in self.siblings.iter().enumerate() {
+            hash = climb_level(hasher_factory, level as u8, &hash, index, Some(sibling));
+            index /= 2;
+        }
+
+        ensure!(
+            self.peaks[position] == hash
+                && bag_peaks(hasher_factory, &self.peaks, self.leaf_count) == root,
+            "MmrProof.leaf is not in the MMR"
+        );
+
+        Ok(())
+    }
+}
+
+/// Bags the `peaks` from the lowest mountain to the highest one, and folds the `leaf_count` in
+/// to obtain the MMR root.
+fn bag_peaks<H: Hashor>(hasher_factory: fn() -> H, peaks: &[Hash], leaf_count: u64) -> Hash {
+    let mut peaks = peaks.iter().rev();
+
+    let mut hash = peaks.next().copied().unwrap_or_default();
+    for peak in peaks {
+        hash = hash_children(hasher_factory, Some(peak), Some(&hash));
+    }
+
+    fold_size(
+        hasher_factory,
+        SizeEncoding::U64BigEndian,
+        &hash,
+        leaf_count,
+    )
+}
+
+/// Binds the root of an IMT and the root of the MMR of its leaves into a single commitment.
+pub fn combined_root<H: Hashor>(
+    hasher_factory: fn() -> H,
+    imt_root: &Hash,
+    mmr_root: &Hash,
+) -> Hash {
+    hash_children(hasher_factory, Some(imt_root), Some(mmr_root))
+}
+
+/// IMT for append-only use, its leaves also being appended to an MMR.
+///
+/// The leaf appended for a node is the node with a zero `next_key`, which is the only field
+/// later insertions change. Updating or removing nodes is thus not supported.
+#[cfg(not(feature = "verifier-only"))]
+#[derive(Debug, Clone)]
+pub struct MmrImt<H: Hashor, K: Key, V: Value> {
+    imt: Imt<H, K, V>,
+    mmr: Mmr<H>,
+}
+
+#[cfg(not(feature = "verifier-only"))]
+impl<H: Hashor, K: Key, V: Value> MmrImt<H, K, V> {
+    /// Insanciate a new MMR backed IMT holding only the zero node.
+    pub fn new(hasher_factory: fn() -> H) -> Self {
+        Self::try_from(Imt::new(hasher_factory)).expect("failed to create the MMR")
+    }
+
+    /// Inserts a new leaf in the IMT and appends it to the MMR.
+    ///
+    /// Returns the IMT insertion to use for zkVM verification.
+    pub fn insert_node(&mut self, key: K, value: V) -> IMTMutate<K, V> {
+        let outcome = self.imt.insert(key, value);
+        let leaf_hash = self.leaf_hash(&outcome.node);
+        let index = self.mmr.append(leaf_hash);
+        debug_assert_eq!(index, outcome.node.index);
+
+        outcome.mutate
+    }
+
+    /// Returns the commitment to both the IMT and the MMR of its leaves.
+    pub fn root(&self) -> Hash {
+        combined_root(self.mmr.hasher_factory, &self.imt.root, &self.mmr.root())
+    }
+
+    /// Returns the IMT, e.g. to prove the non membership of a key.
+    pub fn imt(&self) -> &Imt<H, K, V> {
+        &self.imt
+    }
+
+    /// Returns the MMR of the IMT leaves.
+    pub fn mmr(&self) -> &Mmr<H> {
+        &self.mmr
+    }
+
+    /// Returns the MMR leaf of the given `key` along with its inclusion proof, or `None` if the
+    /// key is not in the IMT.
+    ///
+    /// The proof is verified against the MMR root with the hash of the returned leaf.
+    pub fn mmr_proof(&self, key: &K) -> Option<(IMTNode<K, V>, MmrProof)> {
+        let leaf = mmr_leaf(self.imt.node(key)?);
+        let proof = self
+            .mmr
+            .prove(leaf.index)
+            .expect("failed to prove the leaf");
+
+        Some((leaf, proof))
+    }
+
+    /// Returns the IMT, dropping the MMR.
+    pub fn into_imt(self) -> Imt<H, K, V> {
+        self.imt
+    }
+
+    /// Hashes the MMR leaf of the given `node`.
+    fn leaf_hash(&self, node: &IMTNode<K, V>) -> Hash {
+        mmr_leaf(node).hash_with_layout((self.mmr.hasher_factory)(), self.imt.layout())
+    }
+}
+
+#[cfg(not(feature = "verifier-only"))]
+impl<H: Hashor, K: Key, V: Value> TryFrom<Imt<H, K, V>> for MmrImt<H, K, V> {
+    type Error = anyhow::Error;
+
+    /// Appends the leaves of `imt` to a new MMR, in index order.
+    ///
+    /// Fails if the IMT has vacant slots, as they would be filled instead of appended.
+    fn try_from(imt: Imt<H, K, V>) -> Result<Self> {
+        ensure!(
+            imt.nodes().iter().all(|node| !node.is_vacant()),
+            "the IMT has vacant slots"
+        );
+
+        let mut mmr_imt = Self {
+            mmr: Mmr::new(imt.hasher_factory()),
+            imt,
+        };
+        for node in mmr_imt.imt.nodes() {
+            let leaf_hash = mmr_imt.leaf_hash(node);
+            mmr_imt.mmr.append(leaf_hash);
+        }
+
+        Ok(mmr_imt)
+    }
+}
+
+/// Returns the MMR leaf of `node`, i.e. the node with a zero `next_key`.
+#[cfg(not(feature = "verifier-only"))]
+fn mmr_leaf<K: Key, V: Value>(node: &IMTNode<K, V>) -> IMTNode<K, V> {
+    IMTNode {
+        next_key: K::default(),
+        ..*node
+    }
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use tiny_keccak::Keccak;
+
+    use super::*;
+
+    #[test]
+    fn test_mmr_inclusion_proofs() {
+        let mut imt = MmrImt::new(Keccak::v256);
+        for i in 1..=20 {
+            let old_root = imt.imt().root;
+            let mutate = imt.insert_node([i; 32], [42; 32]);
+            assert_eq!(
+                mutate.verify(Keccak::v256, old_root).unwrap(),
+                imt.imt().root
+            );
+        }
+        assert_eq!(imt.mmr().leaf_count(), imt.imt().size);
+
+        // 21 leaves form mountains of 16, 4 and 1 leaves.
+        assert_eq!(imt.mmr().peaks().len(), 3);
+
+        let root = imt.mmr().root();
+        for i in 0..=20 {
+            let (leaf, proof) = imt.mmr_proof(&[i; 32]).unwrap();
+            let leaf_hash = leaf.hash(Keccak::v256());
+            proof.verify(Keccak::v256, root, leaf_hash).unwrap();
+
+            // A tampered leaf.
+            let leaf_hash = IMTNode {
+                value: [43; 32],
+                ..leaf
+            }
+            .hash(Keccak::v256());
+            let res = proof.verify(Keccak::v256, root, leaf_hash);
+            assert!(matches!(res, Err(e) if e.to_string() == "MmrProof.leaf is not in the MMR"));
+        }
+        assert!(imt.mmr_proof(&[21; 32]).is_none());
+
+        // Proofs only hold for the root they were generated at.
+        let (leaf, proof) = imt.mmr_proof(&[20; 32]).unwrap();
+        imt.insert_node([21; 32], [42; 32]);
+        let res = proof.verify(Keccak::v256, imt.mmr().root(), leaf.hash(Keccak::v256()));
+        assert!(res.is_err());
+
+        assert_eq!(
+            imt.root(),
+            combined_root(Keccak::v256, &imt.imt().root, &imt.mmr().root())
+        );
+    }
+
+    #[test]
+    fn test_mmr_root() {
+        let leaves = [[1; 32], [2; 32], [3; 32]];
+        let mut mmr = Mmr::new(Keccak::v256);
+        for leaf in leaves {
+            mmr.append(leaf);
+        }
+
+        // The peaks are bagged from the lowest mountain to the highest one.
+        let peak = hash_level(Keccak::v256, 0, Some(&leaves[0]), Some(&leaves[1]));
+        assert_eq!(mmr.peaks(), vec![peak, leaves[2]]);
+        let bagged = hash_children(Keccak::v256, Some(&peak), Some(&leaves[2]));
+        assert_eq!(
+            mmr.root(),
+            fold_size(Keccak::v256, SizeEncoding::U64BigEndian, &bagged, 3)
+        );
+
+        // Forged proof shapes.
+        let proof = mmr.prove(2).unwrap();
+        assert!(proof.siblings.is_empty());
+        proof.verify(Keccak::v256, mmr.root(), leaves[2]).unwrap();
+
+        let mut forged = proof.clone();
+        forged.siblings.push(leaves[1]);
+        let res = forged.verify(Keccak::v256, mmr.root(), leaves[2]);
+        assert!(matches!(res, Err(e) if e.to_string() == "MmrProof.siblings is invalid"));
+
+        let forged = MmrProof {
+            leaf_index: 3,
+            ..proof.clone()
+        };
+        let res = forged.verify(Keccak::v256, mmr.root(), leaves[2]);
+        assert!(matches!(res, Err(e) if e.to_string() == "MmrProof.leaf_index is invalid"));
+
+        let mut forged = proof;
+        forged.peaks.pop();
+        let res = forged.verify(Keccak::v256, mmr.root(), leaves[2]);
+        assert!(matches!(res, Err(e) if e.to_string() == "MmrProof.peaks is invalid"));
+    }
+
+    #[test]
+    fn test_mmr_imt_conversion() {
+        let mut imt = Imt::new(Keccak::v256);
+        let mut mmr_imt = MmrImt::new(Keccak::v256);
+        for i in [5, 2, 9, 7] {
+            imt.insert_node([i; 32], [42; 32]);
+            mmr_imt.insert_node([i; 32], [42; 32]);
+        }
+
+        // Converting an IMT appends its leaves in index order.
+        let converted = MmrImt::try_from(imt.clone()).unwrap();
+        assert_eq!(converted.root(), mmr_imt.root());
+        assert_eq!(mmr_imt.into_imt().root, imt.root);
+
+        // Vacant slots would be filled instead of appended.
+        imt.remove_node(&[9; 32]);
+        let res = MmrImt::try_from(imt);
+        assert!(matches!(res, Err(e) if e.to_string() == "the IMT has vacant slots"));
+    }
+}
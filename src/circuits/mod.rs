@@ -1,64 +1,1198 @@
-use node::{Hashor, IMTNode, Key, Value};
+use std::{cmp::Ordering, fmt};
+
+use anyhow::{ensure, Result};
+use node::{HasherFactory, Hashor, IMTNode, Key, LeafLayout, NodeHasher, Value};
+use serde::{Deserialize, Serialize};
+use siblings::Siblings;
 
 use crate::Hash;
 
+mod hex;
 mod insert;
+#[cfg(all(feature = "oplog", not(feature = "verifier-only")))]
+mod oplog;
+#[cfg(not(feature = "verifier-only"))]
+mod storage;
+#[cfg(feature = "stream")]
+mod stream;
+#[cfg(feature = "tracing")]
+mod trace;
 mod update;
 
+pub mod adjacency;
+#[cfg(feature = "alloy")]
+pub mod alloy;
+#[cfg(all(feature = "proptest", not(feature = "verifier-only")))]
+pub mod arbitrary;
+#[cfg(feature = "ark")]
+pub mod ark;
+pub mod boundary;
+pub mod bundle;
+#[cfg(all(feature = "concurrent", not(feature = "verifier-only")))]
+pub mod concurrent;
+#[cfg(feature = "eth-compat")]
+pub mod eth;
+pub mod exclusion;
+#[cfg(not(feature = "verifier-only"))]
 pub mod imt;
+pub mod inclusion;
+pub mod keys;
+#[cfg(feature = "mmr")]
+pub mod mmr;
+pub mod multiproof;
 pub mod mutate;
 pub mod node;
+#[cfg(not(feature = "verifier-only"))]
+pub mod observer;
+#[cfg(not(feature = "verifier-only"))]
+pub mod paged;
+#[cfg(all(feature = "async", not(feature = "verifier-only")))]
+pub mod persist;
+pub mod range;
+pub mod root;
+#[cfg(feature = "ruint")]
+pub mod ruint;
+#[cfg(feature = "sha2")]
+pub mod sha256;
+pub mod siblings;
+#[cfg(all(feature = "test-support", not(feature = "verifier-only")))]
+pub mod test_support;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod zkvm;
+
+/// Error of an IMT proof verification, for the failures worth telling apart from a mere hash
+/// mismatch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImtError {
+    /// A sibling path holds more levels than the depth of the IMT, which no valid proof does.
+    SiblingsExceedDepth { len: usize, depth: u8 },
+    /// A sibling path holds fewer levels than the depth of the IMT, which would let an inner
+    /// node hash be presented as a leaf.
+    SiblingsBelowDepth { len: usize, depth: u8 },
+    /// An insertion would grow the IMT beyond the maximum depth it was capped at.
+    CapacityExceeded { max_depth: u8 },
+}
+
+impl fmt::Display for ImtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImtError::SiblingsExceedDepth { len, depth } => {
+                write!(
+                    f,
+                    "siblings hold {len} levels, exceeding the IMT depth of {depth}"
+                )
+            }
+            ImtError::SiblingsBelowDepth { len, depth } => {
+                write!(
+                    f,
+                    "siblings hold {len} levels, short of the IMT depth of {depth}"
+                )
+            }
+            ImtError::CapacityExceeded { max_depth } => {
+                write!(f, "the IMT is full at its maximum depth of {max_depth}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ImtError {}
+
+/// Encoding of the IMT size when it is folded into the root.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum SizeEncoding {
+    /// 8 big endian bytes.
+    #[default]
+    U64BigEndian,
+    /// 8 little endian bytes.
+    U64LittleEndian,
+    /// 32 big endian bytes, as the `uint256(size)` committed to by Solidity verifiers.
+    U256BigEndian,
+}
+
+impl SizeEncoding {
+    /// Absorbs the encoded IMT `size` into `hasher`.
+    fn absorb<H: Hashor>(&self, hasher: &mut H, size: u64) {
+        match self {
+            SizeEncoding::U64BigEndian => hasher.update(&size.to_be_bytes()),
+            SizeEncoding::U64LittleEndian => hasher.update(&size.to_le_bytes()),
+            SizeEncoding::U256BigEndian => {
+                hasher.update(&[0; 24]);
+                hasher.update(&size.to_be_bytes());
+            }
+        }
+    }
+}
+
+/// How the IMT size is committed to alongside the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum CommitmentScheme {
+    /// The size is folded into the root as `hash(top || size)`, the size being encoded with the
+    /// given `SizeEncoding`.
+    SizeInRoot(SizeEncoding),
+    /// The root is the top level hash and the size is committed to separately.
+    SizeSeparate,
+}
+
+impl Default for CommitmentScheme {
+    fn default() -> Self {
+        CommitmentScheme::SizeInRoot(SizeEncoding::U64BigEndian)
+    }
+}
+
+impl CommitmentScheme {
+    /// Computes the IMT root from the top level `hash` and the IMT `size`.
+    fn root<H: Hashor>(
+        &self,
+        hasher_factory: impl HasherFactory<H>,
+        hash: &Hash,
+        size: u64,
+    ) -> Hash {
+        match self {
+            CommitmentScheme::SizeInRoot(encoding) => {
+                fold_size(hasher_factory, *encoding, hash, size)
+            }
+            CommitmentScheme::SizeSeparate => *hash,
+        }
+    }
+}
 
 /// Computes the IMT root.
-fn imt_root<H: Hashor, K: Key, V: Value>(
-    hasher_factory: fn() -> H,
+fn imt_root<H: Hashor, K: Key, V: Value, N: NodeHasher<K, V>, S: Siblings + ?Sized>(
+    hasher_factory: impl HasherFactory<H>,
+    scheme: CommitmentScheme,
+    layout: N,
     size: u64,
     node: &IMTNode<K, V>,
-    siblings: &Vec<Option<Hash>>,
+    siblings: &S,
 ) -> Hash {
-    let mut hash = node.hash(hasher_factory());
+    let leaf_hash = node.hash_with(hasher_factory, layout);
+    let hash = climb(hasher_factory, leaf_hash, node.index, siblings);
+    scheme.root(hasher_factory, &hash, size)
+}
 
-    let mut index = node.index;
-    for sibling in siblings {
-        let node_hash = Some(hash);
+/// Computes the root of the IMT of `size` nodes holding `node`, from its `siblings` borrowed as
+/// a slice, under the default commitment scheme and leaf layout.
+///
+/// Nothing is copied nor allocated, so that a zkVM guest can verify directly over its input
+/// buffer, e.g. a memory mapped region.
+pub fn imt_root_borrowed<H: Hashor, K: Key, V: Value>(
+    hasher_factory: impl HasherFactory<H>,
+    size: u64,
+    node: &IMTNode<K, V>,
+    siblings: &[Option<Hash>],
+) -> Hash {
+    imt_root(
+        hasher_factory,
+        CommitmentScheme::default(),
+        LeafLayout::default(),
+        size,
+        node,
+        siblings,
+    )
+}
 
-        let (left, right) = if index % 2 == 0 {
-            (&node_hash, sibling)
-        } else {
-            (sibling, &node_hash)
-        };
+/// Change of the leaf at `index` from `old_leaf_hash` to `new_leaf_hash`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LeafChange {
+    pub index: u64,
+    pub old_leaf_hash: Hash,
+    pub new_leaf_hash: Hash,
+}
 
-        let mut hasher = hasher_factory();
-        match (left, right) {
-            (None, None) => unreachable!(),
-            (None, Some(right)) => hasher.update(right),
-            (Some(left), None) => hasher.update(left),
-            (Some(left), Some(right)) => {
-                hasher.update(left);
-                hasher.update(right);
-            }
-        };
+/// Computes the root of an IMT of `size` nodes after the given leaf `change`.
+///
+/// The `siblings` of the leaf are first used to make sure `old_leaf_hash` is committed to in
+/// `old_root`, the new root is then obtained by climbing them once more with `new_leaf_hash`.
+/// Both roots commit to the `size` under the given commitment `scheme`.
+///
+/// The `index` must be in the IMT and the `siblings` must span its whole depth, so that no inner
+/// node hash can be changed as if it were a leaf.
+pub fn root_after_leaf_change<H: Hashor, S: Siblings + ?Sized>(
+    hasher_factory: impl HasherFactory<H>,
+    scheme: CommitmentScheme,
+    old_root: &Hash,
+    change: LeafChange,
+    siblings: &S,
+    size: u64,
+) -> Result<Hash> {
+    ensure!(change.index < size, "LeafChange.index is invalid");
+    ensure_siblings_depth(siblings, size)?;
 
-        hasher.finalize(&mut hash);
+    let hash = climb(hasher_factory, change.old_leaf_hash, change.index, siblings);
+    ensure!(
+        *old_root == scheme.root(hasher_factory, &hash, size),
+        "old_leaf_hash is not in the IMT"
+    );
 
-        index /= 2;
+    let hash = climb(hasher_factory, change.new_leaf_hash, change.index, siblings);
+    Ok(scheme.root(hasher_factory, &hash, size))
+}
+
+/// Returns `true` if folding `size` into the top level `subtree_root`, as done by the default
+/// commitment scheme, gives `expected_root`.
+///
+/// Allows to check a size claimed alongside a top level hash transmitted separately from the
+/// root.
+pub fn root_matches_size<H: Hashor>(
+    hasher_factory: impl HasherFactory<H>,
+    subtree_root: &Hash,
+    size: u64,
+    expected_root: &Hash,
+) -> bool {
+    let root = CommitmentScheme::default().root(hasher_factory, subtree_root, size);
+    root == *expected_root
+}
+
+/// Returns `true` if the leaf of hash `leaf_hash` is at `index` in the IMT of `size` nodes
+/// commited to in `root`, under the default commitment scheme.
+///
+/// Unlike `IMTInclusion`, the node itself is not needed, so that its key and value can stay
+/// private while its leaf hash is public.
+pub fn verify_leaf_hash<H: Hashor, S: Siblings + ?Sized>(
+    hasher_factory: impl HasherFactory<H>,
+    root: &Hash,
+    size: u64,
+    leaf_hash: Hash,
+    index: u64,
+    siblings: &S,
+) -> bool {
+    // A path short of the IMT depth would accept an inner node hash as a leaf hash.
+    if index >= size || depth_mismatch(siblings.levels().count(), size).is_some() {
+        return false;
     }
 
-    let mut hasher = hasher_factory();
-    hasher.update(&hash);
-    hasher.update(&size.to_be_bytes());
-    hasher.finalize(&mut hash);
+    let hash = climb(hasher_factory, leaf_hash, index, siblings);
+    let computed = CommitmentScheme::default().root(hasher_factory, &hash, size);
+
+    roots_eq(root, &computed)
+}
+
+/// Climbs the `siblings` from the leaf at `index` and returns the resulting top level hash.
+///
+/// Empty `siblings` (a size 1 IMT has a depth of 0) leave the leaf hash as the top level hash.
+fn climb<H: Hashor, S: Siblings + ?Sized>(
+    hasher_factory: impl HasherFactory<H>,
+    leaf_hash: Hash,
+    mut index: u64,
+    siblings: &S,
+) -> Hash {
+    let mut hash = leaf_hash;
+
+    for (level, sibling) in siblings.levels().enumerate() {
+        hash = climb_level(hasher_factory, level as u8, &hash, index, sibling);
+        index /= 2;
+    }
 
     hash
 }
 
-/// Returns `true` if the given `node` is part of the tree commited to in `root`.
-fn node_exists<H: Hashor, K: Key, V: Value>(
-    hasher_factory: fn() -> H,
+/// Hashes the node `hash` at `index` in the given `level` with its `sibling` into their parent.
+fn climb_level<H: Hashor>(
+    hasher_factory: impl HasherFactory<H>,
+    level: u8,
+    hash: &Hash,
+    index: u64,
+    sibling: Option<&Hash>,
+) -> Hash {
+    let (left, right) = if index.is_multiple_of(2) {
+        (Some(hash), sibling)
+    } else {
+        (sibling, Some(hash))
+    };
+
+    hash_level(hasher_factory, level, left, right)
+}
+
+/// Hashes the `left` and `right` children in the given `level` of a tree, the leaves being in
+/// level 0, into their parent.
+///
+/// With the `level-tagging` feature, the `level` byte is absorbed before the children: the
+/// parent is `hash(level || left || right)`, so that no hash can be presented at another level.
+#[cfg_attr(not(feature = "level-tagging"), allow(unused_variables))]
+fn hash_level<H: Hashor>(
+    hasher_factory: impl HasherFactory<H>,
+    level: u8,
+    left: Option<&Hash>,
+    right: Option<&Hash>,
+) -> Hash {
+    let empty = lone_child_empty(hasher_factory, left, right);
+    hasher_factory.hash(|hasher| {
+        #[cfg(feature = "level-tagging")]
+        hasher.update(&[level]);
+
+        absorb_children(hasher, left, right, empty.as_ref());
+    })
+}
+
+/// Hashes the `left` and `right` children into their parent, a lone child being hashed alone.
+///
+/// With the `balanced-hashing` feature, a missing child is replaced by `empty_hash` instead, so
+/// that every parent absorbs exactly two hashes.
+///
+/// The children are not tagged with their level, see `hash_level` for the tree levels.
+#[cfg(any(feature = "mmr", all(test, not(feature = "verifier-only"))))]
+fn hash_children<H: Hashor>(
+    hasher_factory: impl HasherFactory<H>,
+    left: Option<&Hash>,
+    right: Option<&Hash>,
+) -> Hash {
+    let empty = lone_child_empty(hasher_factory, left, right);
+    hasher_factory.hash(|hasher| absorb_children(hasher, left, right, empty.as_ref()))
+}
+
+/// Absorbs the `left` and `right` children into `hasher`, `empty` standing for a missing one.
+fn absorb_children<H: Hashor>(
+    hasher: &mut H,
+    left: Option<&Hash>,
+    right: Option<&Hash>,
+    empty: Option<&Hash>,
+) {
+    match (left.or(empty), right.or(empty)) {
+        (None, None) => unreachable!(),
+        (Some(left), Some(right)) => {
+            hasher.update(left);
+            hasher.update(right);
+        }
+        (None, Some(child)) | (Some(child), None) => hasher.update(child),
+    }
+}
+
+/// Returns the hash standing for the missing child of a lone child, if any.
+///
+/// Computed before the parent hash starts, so that it does not need a hasher of its own.
+#[cfg(feature = "balanced-hashing")]
+fn lone_child_empty<H: Hashor>(
+    hasher_factory: impl HasherFactory<H>,
+    left: Option<&Hash>,
+    right: Option<&Hash>,
+) -> Option<Hash> {
+    (left.is_none() || right.is_none()).then(|| empty_hash(hasher_factory))
+}
+
+/// Without the `balanced-hashing` feature, a lone child is hashed alone.
+#[cfg(not(feature = "balanced-hashing"))]
+fn lone_child_empty<H: Hashor>(
+    _hasher_factory: impl HasherFactory<H>,
+    _left: Option<&Hash>,
+    _right: Option<&Hash>,
+) -> Option<Hash> {
+    None
+}
+
+/// Domain separation tag of the hash standing for a missing child.
+#[cfg(feature = "balanced-hashing")]
+const EMPTY_TAG: &[u8] = b"imt.empty";
+
+/// Returns the hash standing for a missing child, which no pair of children hashes to.
+#[cfg(feature = "balanced-hashing")]
+fn empty_hash<H: Hashor>(hasher_factory: impl HasherFactory<H>) -> Hash {
+    hasher_factory.hash(|hasher| hasher.update(EMPTY_TAG))
+}
+
+/// Folds the IMT `size`, encoded with the given `encoding`, into the top level `hash` to obtain
+/// the IMT root.
+fn fold_size<H: Hashor>(
+    hasher_factory: impl HasherFactory<H>,
+    encoding: SizeEncoding,
+    hash: &Hash,
+    size: u64,
+) -> Hash {
+    hasher_factory.hash(|hasher| {
+        hasher.update(hash);
+        encoding.absorb(hasher, size);
+    })
+}
+
+/// Returns the depth an IMT needs to be able to store `size` nodes.
+fn depth_for_size(size: u64) -> u8 {
+    let depth = (u64::BITS - size.leading_zeros() - 1) as u8;
+    if size == (1_u64 << depth) {
+        depth
+    } else {
+        depth + 1
+    }
+}
+
+/// Makes sure the `siblings` hold exactly as many levels as the depth of an IMT of `size` nodes.
+fn ensure_siblings_depth<S: Siblings + ?Sized>(siblings: &S, size: u64) -> Result<()> {
+    match depth_mismatch(siblings.levels().count(), size) {
+        Some(err) => Err(err.into()),
+        None => Ok(()),
+    }
+}
+
+/// Returns the error of a sibling path of `len` levels in an IMT of `size` nodes, if its length
+/// is not the IMT depth.
+fn depth_mismatch(len: usize, size: u64) -> Option<ImtError> {
+    let depth = depth_for_size(size.max(1));
+    match len.cmp(&(depth as usize)) {
+        Ordering::Greater => Some(ImtError::SiblingsExceedDepth { len, depth }),
+        Ordering::Less => Some(ImtError::SiblingsBelowDepth { len, depth }),
+        Ordering::Equal => None,
+    }
+}
+
+/// Returns the number of hashes in the given `level` of an IMT of `size` nodes.
+fn level_width(size: u64, level: u8) -> u64 {
+    ((size - 1) >> level) + 1
+}
+
+/// Returns `true` if the given `node` is part of the tree commited to in `root`, its `siblings`
+/// spanning the whole depth of the IMT.
+fn node_exists<H: Hashor, K: Key, V: Value, N: NodeHasher<K, V>, S: Siblings + ?Sized>(
+    hasher_factory: impl HasherFactory<H>,
+    scheme: CommitmentScheme,
+    layout: N,
     root: &Hash,
     size: u64,
     node: &IMTNode<K, V>,
-    siblings: &Vec<Option<Hash>>,
+    siblings: &S,
 ) -> bool {
-    *root == imt_root(hasher_factory, size, node, siblings)
+    let computed = imt_root(hasher_factory, scheme, layout, size, node, siblings);
+
+    #[cfg(feature = "tracing")]
+    trace::root_mismatch("node is not in the IMT", root, &computed);
+
+    node.index < size
+        && depth_mismatch(siblings.levels().count(), size).is_none()
+        && roots_eq(root, &computed)
+}
+
+/// Returns `true` if both roots are equal, comparing them in constant time with the
+/// `constant-time` feature.
+fn roots_eq(a: &Hash, b: &Hash) -> bool {
+    #[cfg(feature = "constant-time")]
+    {
+        use subtle::ConstantTimeEq;
+        a.ct_eq(b).into()
+    }
+    #[cfg(not(feature = "constant-time"))]
+    {
+        a == b
+    }
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use tiny_keccak::Keccak;
+
+    use super::*;
+    use crate::circuits::{
+        imt::Imt,
+        mutate::{CompactIMTMutate, IMTMutate, IMTMutateFixed},
+        siblings::{CompactSiblings, FixedSiblings},
+    };
+
+    /// Compiles only if `T` can be sent and shared across threads.
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_send_sync() {
+        type K = [u8; 32];
+
+        assert_send_sync::<Imt<Keccak, K, K>>();
+        assert_send_sync::<Imt<Keccak, K, K, node::LengthPrefixed>>();
+        assert_send_sync::<IMTMutate<K, K>>();
+        assert_send_sync::<CompactIMTMutate<K, K>>();
+        assert_send_sync::<IMTMutateFixed<K, K, 32>>();
+        assert_send_sync::<inclusion::IMTInclusion<K, K>>();
+        assert_send_sync::<exclusion::IMTBatchExclusion<K, K>>();
+        assert_send_sync::<adjacency::IMTAdjacency<K, K>>();
+        assert_send_sync::<multiproof::IMTMultiProof<K, K>>();
+        assert_send_sync::<range::IMTRangeProof<K, K>>();
+        assert_send_sync::<bundle::ProofBundle<K, K>>();
+        assert_send_sync::<root::Root>();
+        assert_send_sync::<ImtError>();
+        assert_send_sync::<anyhow::Error>();
+
+        #[cfg(feature = "concurrent")]
+        assert_send_sync::<concurrent::ConcurrentImt<Keccak, K, K>>();
+    }
+
+    #[test]
+    fn test_root_after_leaf_change() {
+        // Instanciate an IMT with a few nodes.
+        let mut imt = Imt::new(Keccak::v256);
+        imt.insert_node([1; 32], [42; 32]);
+        imt.insert_node([2; 32], [42; 32]);
+        imt.insert_node([3; 32], [42; 32]);
+
+        let old_root = imt.root;
+        let node = imt.low_nullifier(&[4; 32]);
+        let siblings = imt.siblings(&node.key);
+
+        // Change the node value and compare against a full recompute of the root.
+        let new_node = IMTNode {
+            value: [43; 32],
+            ..node
+        };
+        let new_root = root_after_leaf_change(
+            Keccak::v256,
+            CommitmentScheme::default(),
+            &old_root,
+            LeafChange {
+                index: node.index,
+                old_leaf_hash: node.hash(Keccak::v256()),
+                new_leaf_hash: new_node.hash(Keccak::v256()),
+            },
+            &siblings,
+            imt.size,
+        )
+        .unwrap();
+        assert_eq!(
+            new_root,
+            imt_root(
+                Keccak::v256,
+                CommitmentScheme::default(),
+                LeafLayout::default(),
+                imt.size,
+                &new_node,
+                &siblings
+            )
+        );
+
+        // The IMT must agree once the update is applied.
+        imt.update_node(node.key, new_node.value);
+        assert_eq!(new_root, imt.root);
+    }
+
+    #[test]
+    fn test_imt_root_borrowed() {
+        let mut imt = Imt::new(Keccak::v256);
+        for i in 1..20 {
+            imt.insert_node([i; 32], [42; 32]);
+        }
+
+        // Lay every sibling path out in a single flat buffer, as a guest would receive them.
+        let nodes: Vec<_> = (0..20).map(|i| *imt.node(&[i; 32]).unwrap()).collect();
+        let buffer: Vec<_> = nodes
+            .iter()
+            .flat_map(|node| imt.siblings(&node.key))
+            .collect();
+
+        let depth = imt.depth as usize;
+        for (node, siblings) in nodes.iter().zip(buffer.chunks(depth)) {
+            let root = imt_root_borrowed(Keccak::v256, imt.size, node, siblings);
+            assert_eq!(root, imt.root);
+            assert_eq!(
+                root,
+                imt_root(
+                    Keccak::v256,
+                    CommitmentScheme::default(),
+                    LeafLayout::default(),
+                    imt.size,
+                    node,
+                    &imt.siblings(&node.key)
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_root_matches_size() {
+        let mut imt = Imt::new(Keccak::v256);
+        for i in 1..=5 {
+            imt.insert_node([i; 32], [42; 32]);
+        }
+        let top = imt.cached_hashes().get(imt.depth, 0).unwrap();
+
+        assert!(root_matches_size(Keccak::v256, &top, imt.size, &imt.root));
+        assert!(!root_matches_size(
+            Keccak::v256,
+            &top,
+            imt.size + 1,
+            &imt.root
+        ));
+        assert!(!root_matches_size(
+            Keccak::v256,
+            &top,
+            imt.size - 1,
+            &imt.root
+        ));
+        assert!(!root_matches_size(
+            Keccak::v256,
+            &imt.root,
+            imt.size,
+            &imt.root
+        ));
+    }
+
+    #[test]
+    fn test_roots_eq() {
+        // Same outcome whether the comparison is constant time or not.
+        let root = [7; 32];
+        assert!(roots_eq(&root, &root));
+        for i in [0, 15, 31] {
+            let mut other = root;
+            other[i] ^= 1;
+            assert!(!roots_eq(&root, &other));
+        }
+
+        // Stale roots are still rejected.
+        let mut imt = Imt::new(Keccak::v256);
+        imt.insert_node([1; 32], [42; 32]);
+        let old_root = imt.root;
+        let update = imt.update_node([1; 32], [43; 32]);
+        assert_eq!(update.verify(Keccak::v256, old_root).unwrap(), imt.root);
+        let res = update.verify(Keccak::v256, imt.root);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTMutate.old_root is stale"));
+    }
+
+    #[test]
+    fn test_root_after_leaf_change_invalid_old_leaf() {
+        // Instanciate an IMT with a few nodes.
+        let mut imt = Imt::new(Keccak::v256);
+        imt.insert_node([1; 32], [42; 32]);
+        imt.insert_node([2; 32], [42; 32]);
+
+        let node = imt.low_nullifier(&[3; 32]);
+        let siblings = imt.siblings(&node.key);
+
+        // Use an old leaf hash that is not the one committed in the root.
+        let res = root_after_leaf_change(
+            Keccak::v256,
+            CommitmentScheme::default(),
+            &imt.root,
+            LeafChange {
+                index: node.index,
+                old_leaf_hash: [0xff; 32],
+                new_leaf_hash: node.hash(Keccak::v256()),
+            },
+            &siblings,
+            imt.size,
+        );
+        assert!(matches!(res, Err(e) if e.to_string() == "old_leaf_hash is not in the IMT"));
+
+        // An index out of the IMT.
+        let change = LeafChange {
+            index: imt.size,
+            old_leaf_hash: [0xff; 32],
+            new_leaf_hash: node.hash(Keccak::v256()),
+        };
+        let res = root_after_leaf_change(
+            Keccak::v256,
+            CommitmentScheme::default(),
+            &imt.root,
+            change,
+            &siblings,
+            imt.size,
+        );
+        assert!(matches!(res, Err(e) if e.to_string() == "LeafChange.index is invalid"));
+
+        // The hash of the parent of leaves 2 and 3 of a size 4 IMT, changed as if it were a leaf
+        // at index 1 of the level above the leaves.
+        imt.insert_node([3; 32], [42; 32]);
+        let leaf = |index| imt.node_by_index(index).unwrap().hash(Keccak::v256());
+        let parent = |left, right| hash_level(Keccak::v256, 0, Some(&left), Some(&right));
+        let change = LeafChange {
+            index: 1,
+            old_leaf_hash: parent(leaf(2), leaf(3)),
+            new_leaf_hash: [0; 32],
+        };
+        let res = root_after_leaf_change(
+            Keccak::v256,
+            CommitmentScheme::default(),
+            &imt.root,
+            change,
+            &[Some(parent(leaf(0), leaf(1)))][..],
+            imt.size,
+        );
+        let err = res.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ImtError>(),
+            Some(&ImtError::SiblingsBelowDepth { len: 1, depth: 2 })
+        );
+    }
+
+    #[test]
+    fn test_commitment_schemes() {
+        for scheme in [
+            CommitmentScheme::SizeInRoot(SizeEncoding::U64BigEndian),
+            CommitmentScheme::SizeInRoot(SizeEncoding::U64LittleEndian),
+            CommitmentScheme::SizeInRoot(SizeEncoding::U256BigEndian),
+            CommitmentScheme::SizeSeparate,
+        ] {
+            let mut imt = Imt::with_scheme(Keccak::v256, scheme);
+
+            // Insert and update a few nodes and verify each mutation under the IMT scheme.
+            for i in 1..=10 {
+                let (old_root, old_size) = (imt.root, imt.size);
+                let mutate = imt.insert_node([i; 32], [42; 32]);
+                let root = mutate
+                    .verify_with_scheme(Keccak::v256, scheme, old_root, old_size)
+                    .unwrap();
+                assert_eq!(root, imt.root);
+
+                let (old_root, size) = (imt.root, imt.size);
+                let mutate = imt.update_node([i; 32], [43; 32]);
+                let root = mutate
+                    .verify_with_scheme(Keccak::v256, scheme, old_root, size)
+                    .unwrap();
+                assert_eq!(root, imt.root);
+            }
+        }
+    }
+
+    #[test]
+    fn test_little_endian_size() {
+        let little = CommitmentScheme::SizeInRoot(SizeEncoding::U64LittleEndian);
+        let mut le_imt = Imt::with_scheme(Keccak::v256, little);
+        let mut be_imt = Imt::new(Keccak::v256);
+
+        for i in 1..=10 {
+            // The little endian IMT verifies against its own proofs.
+            let (old_root, old_size) = (le_imt.root, le_imt.size);
+            let mutate = le_imt.insert_node([i; 32], [42; 32]);
+            let root = mutate
+                .verify_with_scheme(Keccak::v256, little, old_root, old_size)
+                .unwrap();
+            assert_eq!(root, le_imt.root);
+
+            // But not against the default big endian encoding.
+            assert!(mutate.verify(Keccak::v256, old_root).is_err());
+
+            be_imt.insert_node([i; 32], [42; 32]);
+            assert_ne!(le_imt.root, be_imt.root);
+        }
+
+        // Both IMTs only differ by the size encoding.
+        let node = le_imt.low_nullifier(&[11; 32]);
+        let siblings = le_imt.siblings(&node.key);
+        let top = climb(
+            Keccak::v256,
+            node.hash(Keccak::v256()),
+            node.index,
+            &siblings,
+        );
+        assert_eq!(
+            fold_size(
+                Keccak::v256,
+                SizeEncoding::U64LittleEndian,
+                &top,
+                le_imt.size
+            ),
+            le_imt.root
+        );
+        assert_eq!(
+            fold_size(Keccak::v256, SizeEncoding::U64BigEndian, &top, be_imt.size),
+            be_imt.root
+        );
+
+        // Incremental root updates use the same encoding.
+        let new_node = IMTNode {
+            value: [43; 32],
+            ..node
+        };
+        let new_root = root_after_leaf_change(
+            Keccak::v256,
+            CommitmentScheme::SizeInRoot(SizeEncoding::U64LittleEndian),
+            &le_imt.root,
+            LeafChange {
+                index: node.index,
+                old_leaf_hash: node.hash(Keccak::v256()),
+                new_leaf_hash: new_node.hash(Keccak::v256()),
+            },
+            &siblings,
+            le_imt.size,
+        )
+        .unwrap();
+        le_imt.update_node(node.key, new_node.value);
+        assert_eq!(new_root, le_imt.root);
+    }
+
+    #[test]
+    fn test_size_encoding_vectors() {
+        let fold = |encoding, hash: &Hash, size| {
+            hex::Hex(&fold_size(Keccak::v256, encoding, hash, size)).to_string()
+        };
+
+        // keccak([0x11; 32] || encode(0x0102030405060708)).
+        for (encoding, expected) in [
+            (
+                SizeEncoding::U64BigEndian,
+                "0x0d4e3a5300bf1cb8dc9419cb6030807babf0309aff97c2b0a3f81174006324aa",
+            ),
+            (
+                SizeEncoding::U64LittleEndian,
+                "0xe715944f7062578f611ebf41dbd6b72e6b070ab451e8ef250f628062fbc60366",
+            ),
+            (
+                SizeEncoding::U256BigEndian,
+                "0x767ba0604789e2f63f958fba416157381b1f7a385c2a73f8d2006db42db9c1bb",
+            ),
+        ] {
+            assert_eq!(fold(encoding, &[0x11; 32], 0x0102030405060708), expected);
+        }
+
+        // Root of the empty IMT, holding only the zero node: keccak(keccak([0; 96]) || encode(1)).
+        #[cfg(not(feature = "bind-index"))]
+        for (encoding, expected) in [
+            (
+                SizeEncoding::U64BigEndian,
+                "0xdd8c15c9791e3b56d7bf484214486d9dde59067d3ff02dd55f0336614b04e7c1",
+            ),
+            (
+                SizeEncoding::U64LittleEndian,
+                "0xbd1acfe9adda186604706f1792145cafdc559f8ea5848693be0263334e9bb6b5",
+            ),
+            (
+                SizeEncoding::U256BigEndian,
+                "0xbc80430e4bf8c0bcb9dd9f217cf97bb632de5aaade03d77069baed60c69cc4c3",
+            ),
+        ] {
+            let imt = Imt::<_, [u8; 32], [u8; 32]>::with_scheme(
+                Keccak::v256,
+                CommitmentScheme::SizeInRoot(encoding),
+            );
+            assert_eq!(hex::Hex(&imt.root).to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_commitment_scheme_size_separate() {
+        let mut imt = Imt::with_scheme(Keccak::v256, CommitmentScheme::SizeSeparate);
+        imt.insert_node([1; 32], [42; 32]);
+        imt.insert_node([2; 32], [42; 32]);
+
+        // The root is the bare top level hash, without the size folded in.
+        let node = imt.low_nullifier(&[3; 32]);
+        let siblings = imt.siblings(&node.key);
+        let top = climb(
+            Keccak::v256,
+            node.hash(Keccak::v256()),
+            node.index,
+            &siblings,
+        );
+        assert_eq!(imt.root, top);
+
+        // Incremental root updates leave the size out too.
+        let new_node = IMTNode {
+            value: [43; 32],
+            ..node
+        };
+        let new_root = root_after_leaf_change(
+            Keccak::v256,
+            CommitmentScheme::SizeSeparate,
+            &imt.root,
+            LeafChange {
+                index: node.index,
+                old_leaf_hash: node.hash(Keccak::v256()),
+                new_leaf_hash: new_node.hash(Keccak::v256()),
+            },
+            &siblings,
+            imt.size,
+        )
+        .unwrap();
+        imt.update_node(node.key, new_node.value);
+        assert_eq!(new_root, imt.root);
+
+        // A stale size is rejected even though the root matches.
+        let (old_root, old_size) = (imt.root, imt.size);
+        let mutate = imt.insert_node([3; 32], [42; 32]);
+        let res = mutate.verify_with_scheme(
+            Keccak::v256,
+            CommitmentScheme::SizeSeparate,
+            old_root,
+            old_size + 1,
+        );
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTMutate.old_size is stale"));
+
+        let (old_root, size) = (imt.root, imt.size);
+        let mutate = imt.update_node([3; 32], [43; 32]);
+        let res = mutate.verify_with_scheme(
+            Keccak::v256,
+            CommitmentScheme::SizeSeparate,
+            old_root,
+            size - 1,
+        );
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTMutate.size is stale"));
+
+        // Verifying under the wrong scheme fails.
+        let (old_root, size) = (imt.root, imt.size);
+        let mutate = imt.update_node([3; 32], [44; 32]);
+        assert!(matches!(mutate, IMTMutate::Update(_)));
+        assert!(mutate.verify(Keccak::v256, old_root).is_err());
+        assert!(mutate
+            .verify_with_scheme(Keccak::v256, CommitmentScheme::SizeSeparate, old_root, size)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_size_one_tree() {
+        let imt = Imt::<_, [u8; 32], [u8; 32]>::new(Keccak::v256);
+        assert_eq!((imt.size, imt.depth), (1, 0));
+
+        // The zero node has no siblings and the root is its hash with the size folded in.
+        let zero_node = imt.low_nullifier(&[1; 32]);
+        let siblings = imt.siblings(&zero_node.key);
+        assert!(siblings.is_empty());
+
+        let leaf_hash = zero_node.hash(Keccak::v256());
+        assert_eq!(
+            fold_size(Keccak::v256, SizeEncoding::U64BigEndian, &leaf_hash, 1),
+            imt.root
+        );
+
+        // Inclusion of the zero node, whatever the siblings representation.
+        let scheme = CommitmentScheme::default();
+        assert!(node_exists(
+            Keccak::v256,
+            scheme,
+            LeafLayout::default(),
+            &imt.root,
+            1,
+            &zero_node,
+            &siblings
+        ));
+        assert!(node_exists(
+            Keccak::v256,
+            scheme,
+            LeafLayout::default(),
+            &imt.root,
+            1,
+            &zero_node,
+            &CompactSiblings::from(siblings.clone())
+        ));
+        assert!(node_exists(
+            Keccak::v256,
+            scheme,
+            LeafLayout::default(),
+            &imt.root,
+            1,
+            &zero_node,
+            &FixedSiblings::<0>::try_from(siblings.clone()).unwrap()
+        ));
+        assert!(node_exists(
+            Keccak::v256,
+            CommitmentScheme::SizeSeparate,
+            LeafLayout::default(),
+            &leaf_hash,
+            1,
+            &zero_node,
+            &siblings
+        ));
+
+        // The zero node is the low nullifier of any key, proving its non membership.
+        assert!(zero_node.is_ln_of(&[1; 32]));
+        assert!(zero_node.is_ln_of(&[0xff; 32]));
+
+        // The empty siblings are not enough to prove a node at another index.
+        let moved = IMTNode {
+            index: 1,
+            ..zero_node
+        };
+        assert!(!node_exists(
+            Keccak::v256,
+            scheme,
+            LeafLayout::default(),
+            &imt.root,
+            2,
+            &moved,
+            &siblings
+        ));
+
+        // Changing the zero node leaf only refolds the size.
+        let new_leaf = IMTNode {
+            value: [1; 32],
+            ..zero_node
+        }
+        .hash(Keccak::v256());
+        let root = root_after_leaf_change(
+            Keccak::v256,
+            CommitmentScheme::default(),
+            &imt.root,
+            LeafChange {
+                index: 0,
+                old_leaf_hash: leaf_hash,
+                new_leaf_hash: new_leaf,
+            },
+            &siblings,
+            1,
+        )
+        .unwrap();
+        assert_eq!(
+            root,
+            fold_size(Keccak::v256, SizeEncoding::U64BigEndian, &new_leaf, 1)
+        );
+    }
+
+    #[test]
+    fn test_size_one_tree_mutations() {
+        // Update the zero node of a size 1 tree.
+        let mut imt = Imt::new(Keccak::v256);
+        let old_root = imt.root;
+        let update = imt.update_node([0; 32], [1; 32]);
+        if let IMTMutate::Update(update) = &update {
+            assert!(update.node_siblings.is_empty());
+        }
+        assert_eq!(update.verify(Keccak::v256, old_root).unwrap(), imt.root);
+
+        let compact = CompactIMTMutate::from(update.clone());
+        assert_eq!(compact.verify(Keccak::v256, old_root).unwrap(), imt.root);
+        let fixed = IMTMutateFixed::<_, _, 0>::try_from(update).unwrap();
+        assert_eq!(fixed.verify(Keccak::v256, old_root).unwrap(), imt.root);
+
+        // Insert in a size 1 tree, the ln being the zero node with no siblings.
+        let old_root = imt.root;
+        let insert = imt.insert_node([1; 32], [42; 32]);
+        if let IMTMutate::Insert(insert) = &insert {
+            assert!(insert.ln_siblings.is_empty());
+            assert_eq!(insert.node_siblings.len(), 1);
+        }
+        assert_eq!(insert.verify(Keccak::v256, old_root).unwrap(), imt.root);
+        let compact = CompactIMTMutate::from(insert);
+        assert_eq!(compact.verify(Keccak::v256, old_root).unwrap(), imt.root);
+    }
+
+    #[test]
+    fn test_hash_children_vectors() {
+        let hash = |left: Option<&Hash>, right: Option<&Hash>| {
+            hex::Hex(&hash_children(Keccak::v256, left, right)).to_string()
+        };
+
+        // Pairs of children hash alike in both modes.
+        assert_eq!(
+            hash(Some(&[1; 32]), Some(&[2; 32])),
+            "0x346d8c96a2454213fcc0daff3c96ad0398148181b9fa6488f7ae2c0af5b20aa0"
+        );
+
+        // A lone child is hashed alone: keccak(child).
+        #[cfg(not(feature = "balanced-hashing"))]
+        for (left, right) in [(Some(&[1; 32]), None), (None, Some(&[1; 32]))] {
+            assert_eq!(
+                hash(left, right),
+                "0xcebc8882fecbec7fb80d2cf4b312bec018884c2d66667c67a90508214bd8bafc"
+            );
+        }
+
+        // A lone child is paired with keccak("imt.empty") on its side, so that both sides differ.
+        #[cfg(feature = "balanced-hashing")]
+        {
+            assert_eq!(
+                hex::Hex(&empty_hash(Keccak::v256)).to_string(),
+                "0xd3ed8a8735afa83d39a53574f80193147a796ef5829a76e7a5acbd345a1cb278"
+            );
+            assert_eq!(
+                hash(Some(&[1; 32]), None),
+                "0x706e549f4cc140e693f86a47856dbf52cd02069fa3e588983e8930a767b9ed64"
+            );
+            assert_eq!(
+                hash(None, Some(&[1; 32])),
+                "0x1c5f4b0b4a938f6a382fad898c332e773009a2ed7e94f206b62a8180bcb9eed8"
+            );
+        }
+    }
+
+    #[test]
+    fn test_verify_leaf_hash() {
+        let mut imt = Imt::new(Keccak::v256);
+        for key in [[5; 32], [2; 32], [9; 32], [7; 32]] {
+            imt.insert_node(key, [42; 32]);
+        }
+
+        let node = imt.low_nullifier(&[8; 32]);
+        let leaf_hash = node.hash(Keccak::v256());
+        let siblings = imt.siblings(&node.key);
+        assert!(verify_leaf_hash(
+            Keccak::v256,
+            &imt.root,
+            imt.size,
+            leaf_hash,
+            node.index,
+            &siblings
+        ));
+
+        // Another position, another hash or another size.
+        assert!(!verify_leaf_hash(
+            Keccak::v256,
+            &imt.root,
+            imt.size,
+            leaf_hash,
+            node.index ^ 1,
+            &siblings
+        ));
+        assert!(!verify_leaf_hash(
+            Keccak::v256,
+            &imt.root,
+            imt.size,
+            [0; 32],
+            node.index,
+            &siblings
+        ));
+        assert!(!verify_leaf_hash(
+            Keccak::v256,
+            &imt.root,
+            imt.size + 1,
+            leaf_hash,
+            node.index,
+            &siblings
+        ));
+
+        // The hash of the parent of leaves 2 and 3 of a size 4 IMT, at index 1 of the level
+        // above the leaves, climbs to the root but is not a leaf.
+        let mut imt = Imt::new(Keccak::v256);
+        for i in 1..4 {
+            imt.insert_node([i; 32], [42; 32]);
+        }
+        let leaf = |index| imt.node_by_index(index).unwrap().hash(Keccak::v256());
+        let parent = |left, right| hash_level(Keccak::v256, 0, Some(&left), Some(&right));
+        let (inner, forged) = (parent(leaf(2), leaf(3)), [Some(parent(leaf(0), leaf(1)))]);
+
+        // Without level tagging, which tells levels apart, the forged path climbs to the root.
+        #[cfg(not(feature = "level-tagging"))]
+        {
+            let top = climb(Keccak::v256, inner, 1, &forged[..]);
+            let root = CommitmentScheme::default().root(Keccak::v256, &top, imt.size);
+            assert_eq!(root, imt.root);
+        }
+        assert!(!verify_leaf_hash(
+            Keccak::v256,
+            &imt.root,
+            imt.size,
+            inner,
+            1,
+            &forged[..]
+        ));
+    }
+
+    #[test]
+    fn test_hash_level_vectors() {
+        let hash = |level| {
+            hex::Hex(&hash_level(
+                Keccak::v256,
+                level,
+                Some(&[1; 32]),
+                Some(&[2; 32]),
+            ))
+            .to_string()
+        };
+
+        // Without tags, every level hashes alike.
+        #[cfg(not(feature = "level-tagging"))]
+        for level in [0, 5] {
+            assert_eq!(
+                hash(level),
+                hex::Hex(&hash_children(Keccak::v256, Some(&[1; 32]), Some(&[2; 32]))).to_string()
+            );
+        }
+
+        // With tags, the level byte comes first: keccak(level || left || right).
+        #[cfg(feature = "level-tagging")]
+        {
+            assert_eq!(
+                hash(0),
+                "0xcc00fb1023d284c63b89ba218c121e83d59206130ec78854fc0eb77e6b89e76b"
+            );
+            assert_eq!(
+                hash(5),
+                "0x010d8755453150692e5c7dd426811ef2c6f92d5fd8c3ef73b72297cbb64bdd72"
+            );
+            #[cfg(not(feature = "balanced-hashing"))]
+            assert_eq!(
+                hex::Hex(&hash_level(Keccak::v256, 5, Some(&[1; 32]), None)).to_string(),
+                "0x7c32a0a3edd0018ecab65faabecfda6cf8bfe3c4b12163f9c3998ab71b2f9970"
+            );
+        }
+    }
 }
@@ -0,0 +1,249 @@
+//! Membership proof of many nodes at once, the hashes their sibling paths have in common being
+//! sent only once.
+
+use anyhow::{ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::Hash;
+
+use super::{
+    depth_for_size, hash_level, level_width,
+    node::{Hashor, IMTNode, Key, LeafLayout, NodeHasher, Value},
+    CommitmentScheme,
+};
+
+/// Proof that each of `nodes` is in the IMT.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct IMTMultiProof<K: Key, V: Value> {
+    /// Proven nodes, sorted by increasing index.
+    pub nodes: Vec<IMTNode<K, V>>,
+    /// Hashes of the subtrees the nodes do not cover, level by level from the leaves and by
+    /// increasing index within a level.
+    pub decommitments: Vec<Hash>,
+}
+
+impl<K: Key, V: Value> IMTMultiProof<K, V> {
+    /// Verifies that `self.nodes` are all in the IMT of `size` nodes commited to in
+    /// `expected_root`.
+    pub fn verify<H: Hashor>(
+        &self,
+        hasher_factory: fn() -> H,
+        expected_root: Hash,
+        size: u64,
+    ) -> Result<()> {
+        self.verify_with_layout(
+            hasher_factory,
+            CommitmentScheme::default(),
+            LeafLayout::default(),
+            expected_root,
+            size,
+        )
+    }
+
+    /// Same as `verify` under the given commitment `scheme`, the leaves being hashed with the
+    /// given `layout`.
+    pub fn verify_with_layout<H: Hashor, N: NodeHasher<K, V>>(
+        &self,
+        hasher_factory: fn() -> H,
+        scheme: CommitmentScheme,
+        layout: N,
+        expected_root: Hash,
+        size: u64,
+    ) -> Result<()> {
+        ensure!(!self.nodes.is_empty(), "IMTMultiProof.nodes is empty");
+
+        // Nodes must be sorted to consume the decommitments in order, and vacant slots hold no
+        // key to prove the membership of.
+        ensure!(
+            self.nodes
+                .iter()
+                .all(|node| node.index < size && !node.is_vacant())
+                && self.nodes.windows(2).all(|w| w[0].index < w[1].index),
+            "IMTMultiProof.nodes is invalid"
+        );
+
+        let mut decommitments = self.decommitments.iter();
+        let mut hashes: Vec<_> = self
+            .nodes
+            .iter()
+            .map(|node| (node.index, node.hash_with_layout(hasher_factory(), layout)))
+            .collect();
+
+        for level in 0..depth_for_size(size) {
+            let width = level_width(size, level);
+
+            let mut parents = Vec::with_capacity(hashes.len());
+            let mut i = 0;
+            while i < hashes.len() {
+                let (index, hash) = hashes[i];
+
+                let parent = if index.is_multiple_of(2) {
+                    if hashes
+                        .get(i + 1)
+                        .is_some_and(|(next, _)| *next == index + 1)
+                    {
+                        i += 1;
+                        hash_level(hasher_factory, level, Some(&hash), Some(&hashes[i].1))
+                    } else if index + 1 < width {
+                        let sibling = decommitments
+                            .next()
+                            .context("IMTMultiProof.decommitments is invalid")?;
+                        hash_level(hasher_factory, level, Some(&hash), Some(sibling))
+                    } else {
+                        hash_level(hasher_factory, level, Some(&hash), None)
+                    }
+                } else {
+                    // A known left sibling would have been paired with the node already.
+                    let sibling = decommitments
+                        .next()
+                        .context("IMTMultiProof.decommitments is invalid")?;
+                    hash_level(hasher_factory, level, Some(sibling), Some(&hash))
+                };
+
+                parents.push((index / 2, parent));
+                i += 1;
+            }
+
+            hashes = parents;
+        }
+
+        ensure!(
+            decommitments.next().is_none(),
+            "IMTMultiProof.decommitments is invalid"
+        );
+        ensure!(
+            scheme.root(hasher_factory, &hashes[0].1, size) == expected_root,
+            "IMTMultiProof.nodes are not in the IMT"
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use tiny_keccak::Keccak;
+
+    use super::*;
+    use crate::circuits::imt::Imt;
+
+    #[test]
+    fn test_multiproof() {
+        let mut imt = Imt::new(Keccak::v256);
+        for i in 1..=20 {
+            imt.insert_node([i; 32], [42; 32]);
+        }
+
+        // Sparse keys, siblings of one another, and duplicated keys.
+        for keys in [
+            vec![[3; 32], [11; 32], [17; 32]],
+            vec![[2; 32], [3; 32], [4; 32], [5; 32]],
+            vec![[20; 32], [7; 32], [20; 32], [7; 32]],
+            (0..=20).map(|i| [i; 32]).collect(),
+        ] {
+            let proof = imt.prove_membership_many(&keys);
+            assert!(proof.nodes.windows(2).all(|w| w[0].index < w[1].index));
+            proof.verify(Keccak::v256, imt.root, imt.size).unwrap();
+
+            // Proofs only hold for the size they were generated at.
+            let res = proof.verify(Keccak::v256, imt.root, imt.size + 1);
+            assert!(res.is_err());
+        }
+
+        // Proving every node requires no decommitment.
+        let proof = imt.prove_membership_many(&(0..=20).map(|i| [i; 32]).collect::<Vec<_>>());
+        assert!(proof.decommitments.is_empty());
+    }
+
+    #[test]
+    fn test_multiproof_single_key() {
+        let mut imt = Imt::new(Keccak::v256);
+        for i in 1..=20 {
+            imt.insert_node([i; 32], [42; 32]);
+        }
+
+        // A single key degenerates into its regular sibling path.
+        for i in 0..=20 {
+            let proof = imt.prove_membership_many(&[[i; 32]]);
+            let siblings: Vec<_> = imt.siblings(&[i; 32]).into_iter().flatten().collect();
+            assert_eq!(proof.decommitments, siblings);
+            proof.verify(Keccak::v256, imt.root, imt.size).unwrap();
+        }
+
+        // So does the size 1 IMT, holding only the zero node.
+        let imt = Imt::<_, [u8; 32], [u8; 32]>::new(Keccak::v256);
+        let proof = imt.prove_membership_many(&[[0; 32]]);
+        assert!(proof.decommitments.is_empty());
+        proof.verify(Keccak::v256, imt.root, imt.size).unwrap();
+    }
+
+    #[test]
+    fn test_multiproof_is_smaller() {
+        let mut imt = Imt::new(Keccak::v256);
+        for i in 1..=200 {
+            imt.insert_node([i; 32], [42; 32]);
+        }
+
+        let keys: Vec<_> = (50..100).map(|i| [i; 32]).collect();
+        let proof = imt.prove_membership_many(&keys);
+        proof.verify(Keccak::v256, imt.root, imt.size).unwrap();
+
+        let multi_len = bincode::serialize(&proof).unwrap().len();
+        let single_len: usize = keys
+            .iter()
+            .map(|key| {
+                let node = imt.prove_membership_many(&[*key]).nodes[0];
+                bincode::serialize(&(node, imt.siblings(key)))
+                    .unwrap()
+                    .len()
+            })
+            .sum();
+        assert!(multi_len * 3 < single_len);
+    }
+
+    #[test]
+    fn test_multiproof_tampered() {
+        let mut imt = Imt::new(Keccak::v256);
+        for i in 1..=20 {
+            imt.insert_node([i; 32], [42; 32]);
+        }
+        let proof = imt.prove_membership_many(&[[3; 32], [11; 32], [17; 32]]);
+
+        // A tampered leaf.
+        let mut forged = proof.clone();
+        forged.nodes[1].value = [43; 32];
+        let res = forged.verify(Keccak::v256, imt.root, imt.size);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTMultiProof.nodes are not in the IMT"));
+
+        // A tampered decommitment.
+        let mut forged = proof.clone();
+        forged.decommitments[0] = [0; 32];
+        let res = forged.verify(Keccak::v256, imt.root, imt.size);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTMultiProof.nodes are not in the IMT"));
+
+        // Missing and extra decommitments.
+        let mut forged = proof.clone();
+        forged.decommitments.pop();
+        let res = forged.verify(Keccak::v256, imt.root, imt.size);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTMultiProof.decommitments is invalid"));
+
+        let mut forged = proof.clone();
+        forged.decommitments.push([0; 32]);
+        let res = forged.verify(Keccak::v256, imt.root, imt.size);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTMultiProof.decommitments is invalid"));
+
+        // Unsorted nodes.
+        let mut forged = proof.clone();
+        forged.nodes.swap(0, 1);
+        let res = forged.verify(Keccak::v256, imt.root, imt.size);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTMultiProof.nodes is invalid"));
+
+        // No node at all.
+        let forged = IMTMultiProof::<[u8; 32], [u8; 32]> {
+            nodes: vec![],
+            decommitments: vec![],
+        };
+        let res = forged.verify(Keccak::v256, imt.root, imt.size);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTMultiProof.nodes is empty"));
+    }
+}
@@ -1,31 +1,186 @@
-use anyhow::Result;
+use std::{cell::Cell, fmt};
+
+use anyhow::{ensure, Result};
 use serde::{Deserialize, Serialize};
 
 use crate::Hash;
 
 use super::{
     insert::IMTInsert,
-    node::{Hashor, IMTNode, Key, Value},
+    node::{HasherFactory, Hashor, IMTNode, Key, LeafLayout, NodeHasher, ResetHasher, Value},
+    root::{NewRoot, OldRoot, Root},
+    siblings::{CompactSiblings, FixedSiblings, Siblings},
     update::IMTUpdate,
+    CommitmentScheme,
 };
 
-#[derive(Debug, Deserialize, Serialize)]
-pub enum IMTMutate<K: Key, V: Value> {
-    Insert(IMTInsert<K, V>),
-    Update(IMTUpdate<K, V>),
+/// Kind of an IMT mutation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MutationKind {
+    Insert,
+    Update,
+}
+
+/// IMT mutation proven against the IMT root before the mutation, implemented by `IMTInsert`,
+/// `IMTUpdate` and `IMTMutate` so that helpers can be written once for all of them.
+pub trait Mutation<K: Key, V: Value> {
+    /// Verifies the mutation against `old_root` and returns the new updated root.
+    fn verify<H: Hashor>(
+        &self,
+        hasher_factory: impl HasherFactory<H>,
+        old_root: Hash,
+    ) -> Result<Hash>;
+
+    /// Returns the IMT root the mutation applies to.
+    fn old_root(&self) -> Hash;
+
+    /// Returns the kind of the mutation.
+    fn kind(&self) -> MutationKind;
+
+    /// Returns the inserted or updated key.
+    fn key(&self) -> K;
+}
+
+impl<K: Key, V: Value, S: Siblings> Mutation<K, V> for IMTInsert<K, V, S> {
+    fn verify<H: Hashor>(
+        &self,
+        hasher_factory: impl HasherFactory<H>,
+        old_root: Hash,
+    ) -> Result<Hash> {
+        IMTInsert::verify(self, hasher_factory, old_root)
+    }
+
+    fn old_root(&self) -> Hash {
+        self.old_root
+    }
+
+    fn kind(&self) -> MutationKind {
+        MutationKind::Insert
+    }
+
+    fn key(&self) -> K {
+        self.node.key
+    }
+}
+
+impl<K: Key, V: Value, S: Siblings> Mutation<K, V> for IMTUpdate<K, V, S> {
+    fn verify<H: Hashor>(
+        &self,
+        hasher_factory: impl HasherFactory<H>,
+        old_root: Hash,
+    ) -> Result<Hash> {
+        IMTUpdate::verify(self, hasher_factory, old_root)
+    }
+
+    fn old_root(&self) -> Hash {
+        self.old_root
+    }
+
+    fn kind(&self) -> MutationKind {
+        MutationKind::Update
+    }
+
+    fn key(&self) -> K {
+        self.node.key
+    }
+}
+
+impl<K: Key, V: Value, S: Siblings> Mutation<K, V> for IMTMutate<K, V, S> {
+    fn verify<H: Hashor>(
+        &self,
+        hasher_factory: impl HasherFactory<H>,
+        old_root: Hash,
+    ) -> Result<Hash> {
+        IMTMutate::verify(self, hasher_factory, old_root)
+    }
+
+    fn old_root(&self) -> Hash {
+        IMTMutate::old_root(self)
+    }
+
+    fn kind(&self) -> MutationKind {
+        match self {
+            IMTMutate::Insert(_) => MutationKind::Insert,
+            IMTMutate::Update(_) => MutationKind::Update,
+        }
+    }
+
+    fn key(&self) -> K {
+        match self {
+            IMTMutate::Insert(insert) => insert.node.key,
+            IMTMutate::Update(update) => update.node.key,
+        }
+    }
 }
 
-impl<K: Key, V: Value> IMTMutate<K, V> {
+/// Verifies each of the `mutations` against its own old root and returns their new roots.
+///
+/// The mutations are not chained: making sure each one applies to the root left by the previous
+/// one is up to the caller.
+///
+/// A single hasher is created for the whole batch, reset after each hash.
+pub fn verify_many<K: Key, V: Value, M: Mutation<K, V>, H: Hashor>(
+    mutations: &[M],
+    hasher_factory: impl Fn() -> H + Copy,
+) -> Vec<Result<Hash>> {
+    let hasher = Cell::new(None);
+    let hasher_factory = ResetHasher::new(&hasher, hasher_factory);
+
+    mutations
+        .iter()
+        .map(|mutate| mutate.verify(hasher_factory, mutate.old_root()))
+        .collect()
+}
+
+/// Same as `verify_many`, cloning the given `hasher` for every hash instead of calling a hasher
+/// factory.
+pub fn verify_many_with<K: Key, V: Value, M: Mutation<K, V>, H: Hashor + Clone>(
+    mutations: &[M],
+    hasher: H,
+) -> Vec<Result<Hash>> {
+    verify_many(mutations, || hasher.clone())
+}
+
+/// IMT mutation using `CompactSiblings` for its sibling paths.
+pub type CompactIMTMutate<K, V> = IMTMutate<K, V, CompactSiblings>;
+
+/// IMT mutation using `FixedSiblings` for its sibling paths, free of any heap allocation.
+pub type IMTMutateFixed<K, V, const D: usize> = IMTMutate<K, V, FixedSiblings<D>>;
+
+/// Mutation of an IMT, proven against the IMT root before the mutation.
+///
+/// Serialized mutations are reproducible: applying the same operations to two IMTs yields
+/// mutations serializing to the same bytes, whatever the iteration order of the hash maps used
+/// to build them, e.g. for content addressed caching of proofs.
+///
+/// Mutations, as the other proofs of the crate, are `Send` and `Sync` whenever their keys,
+/// values and sibling paths are.
+#[derive(Clone, Deserialize, Serialize)]
+pub enum IMTMutate<K: Key, V: Value, S = Vec<Option<Hash>>> {
+    Insert(IMTInsert<K, V, S>),
+    Update(IMTUpdate<K, V, S>),
+}
+
+impl<K: Key, V: Value, S: Siblings> fmt::Debug for IMTMutate<K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IMTMutate::Insert(insert) => f.debug_tuple("Insert").field(insert).finish(),
+            IMTMutate::Update(update) => f.debug_tuple("Update").field(update).finish(),
+        }
+    }
+}
+
+impl<K: Key, V: Value, S: Siblings> IMTMutate<K, V, S> {
     /// Create a new IMTMutate for insertion.
     pub fn insert(
         old_root: Hash,
         old_size: u64,
         ln_node: IMTNode<K, V>,
-        ln_siblings: Vec<Option<Hash>>,
+        ln_siblings: S,
 
         node: IMTNode<K, V>,
-        node_siblings: Vec<Option<Hash>>,
-        updated_ln_siblings: Vec<Option<Hash>>,
+        node_siblings: S,
+        updated_ln_siblings: S,
     ) -> Self {
         Self::Insert(IMTInsert {
             old_root,
@@ -43,7 +198,7 @@ impl<K: Key, V: Value> IMTMutate<K, V> {
         old_root: Hash,
         size: u64,
         node: IMTNode<K, V>,
-        node_siblings: Vec<Option<Hash>>,
+        node_siblings: S,
         new_value: V,
     ) -> Self {
         Self::Update(IMTUpdate {
@@ -51,18 +206,651 @@ impl<K: Key, V: Value> IMTMutate<K, V> {
             size,
             node,
             node_siblings,
+            old_value: node.value,
             new_value,
         })
     }
 
+    /// Returns the IMT root the mutation applies to.
+    pub fn old_root(&self) -> Hash {
+        match self {
+            IMTMutate::Insert(insert) => insert.old_root,
+            IMTMutate::Update(update) => update.old_root,
+        }
+    }
+
+    /// Returns the IMT root the mutation applies to, along with the IMT size it was computed at.
+    pub fn typed_old_root(&self) -> Root {
+        Root::with_size(self.old_root(), self.old_size())
+    }
+
+    /// Returns the IMT size before the mutation.
+    pub fn old_size(&self) -> u64 {
+        match self {
+            IMTMutate::Insert(insert) => insert.old_size,
+            IMTMutate::Update(update) => update.size,
+        }
+    }
+
+    /// Returns the inserted node, holding its assigned index, if the mutation is an insertion.
+    pub fn inserted_node(&self) -> Option<&IMTNode<K, V>> {
+        match self {
+            IMTMutate::Insert(insert) => Some(&insert.node),
+            IMTMutate::Update(_) => None,
+        }
+    }
+
+    /// Returns the `(index, node)` pairs written by applying the mutation: the inserted node and
+    /// the updated ln node for an insertion, the updated node for an update.
+    pub fn node_delta(&self) -> Vec<(u64, IMTNode<K, V>)> {
+        match self {
+            IMTMutate::Insert(insert) => {
+                let updated_ln = IMTNode {
+                    next_key: insert.node.key,
+                    ..insert.ln_node
+                };
+                vec![
+                    (insert.node.index, insert.node),
+                    (updated_ln.index, updated_ln),
+                ]
+            }
+            IMTMutate::Update(update) => {
+                let updated_node = IMTNode {
+                    value: update.new_value,
+                    ..update.node
+                };
+                vec![(updated_node.index, updated_node)]
+            }
+        }
+    }
+
+    /// Computes the root after the mutation from the mutated node and its siblings, without
+    /// verifying the mutation against its old root.
+    pub fn new_root<H: Hashor>(&self, hasher_factory: impl HasherFactory<H>) -> Hash {
+        match self {
+            IMTMutate::Insert(insert) => insert.new_root(hasher_factory),
+            IMTMutate::Update(update) => update.new_root(hasher_factory),
+        }
+    }
+
+    /// Same as `new_root` under the given commitment `scheme`, the leaves being hashed with the
+    /// given `layout`.
+    pub fn new_root_with_layout<H: Hashor, N: NodeHasher<K, V>>(
+        &self,
+        hasher_factory: impl HasherFactory<H>,
+        scheme: CommitmentScheme,
+        layout: N,
+    ) -> Hash {
+        match self {
+            IMTMutate::Insert(insert) => {
+                insert.new_root_with_layout(hasher_factory, scheme, layout)
+            }
+            IMTMutate::Update(update) => {
+                update.new_root_with_layout(hasher_factory, scheme, layout)
+            }
+        }
+    }
+
     /// Verifies the IMT mutation and return the new updated root.
     ///
     /// Before performing the mutation, the state is checked to make sure it is coherent.
     /// In case of any inconsistency, `None` is returned.
-    pub fn verify<H: Hashor>(&self, hasher_factory: fn() -> H, old_root: Hash) -> Result<Hash> {
+    ///
+    /// Verification never allocates on the heap on success: nodes are rebuilt on the stack and
+    /// each hash is computed into a stack `[u8; 32]`, making it cheap to run in a zkVM guest.
+    ///
+    /// The `old_root` is either a bare `Hash`, an `OldRoot` or a `Root`, whose size, if known,
+    /// must match the IMT size before the mutation. A `NewRoot` does not convert into a `Root`
+    /// and is rejected at compile time.
+    ///
+    /// Bare hashes stay accepted, and returned, as roots mostly reach verifiers untyped, e.g.
+    /// from JSON proofs, zkVM inputs or on-chain state. Chaining mutations is better done with
+    /// `verify_typed`.
+    pub fn verify<H: Hashor>(
+        &self,
+        hasher_factory: impl HasherFactory<H>,
+        old_root: impl Into<Root>,
+    ) -> Result<Hash> {
+        let old_root = old_root.into();
+        ensure!(
+            old_root.size().is_none_or(|size| size == self.old_size()),
+            "IMTMutate.size is stale"
+        );
+
         match &self {
-            IMTMutate::Insert(insert) => insert.verify(hasher_factory, old_root),
-            IMTMutate::Update(update) => update.verify(hasher_factory, old_root),
+            IMTMutate::Insert(insert) => insert.verify(hasher_factory, old_root.into()),
+            IMTMutate::Update(update) => update.verify(hasher_factory, old_root.into()),
         }
     }
+
+    /// Same as `verify`, the roots being typed so that passing the new root of a mutation where
+    /// its old root is expected does not compile.
+    pub fn verify_typed<H: Hashor>(
+        &self,
+        hasher_factory: impl HasherFactory<H>,
+        old_root: OldRoot,
+    ) -> Result<NewRoot> {
+        self.verify(hasher_factory, old_root).map(NewRoot)
+    }
+
+    /// Same as `verify`, cloning the given `hasher` for every hash instead of calling a hasher
+    /// factory.
+    pub fn verify_with<H: Hashor + Clone>(
+        &self,
+        hasher: H,
+        old_root: impl Into<Root>,
+    ) -> Result<Hash> {
+        self.verify(|| hasher.clone(), old_root)
+    }
+
+    /// Verifies the IMT mutation under the given commitment `scheme` and return the new updated
+    /// root.
+    ///
+    /// The IMT `size` before the mutation is checked as a plain equality against `old_size`.
+    pub fn verify_with_scheme<H: Hashor>(
+        &self,
+        hasher_factory: impl HasherFactory<H>,
+        scheme: CommitmentScheme,
+        old_root: Hash,
+        old_size: u64,
+    ) -> Result<Hash> {
+        self.verify_with_layout(
+            hasher_factory,
+            scheme,
+            LeafLayout::default(),
+            old_root,
+            old_size,
+        )
+    }
+
+    /// Verifies the IMT mutation under the given commitment `scheme`, the leaves being hashed
+    /// with the given `layout`, and return the new updated root.
+    pub fn verify_with_layout<H: Hashor, N: NodeHasher<K, V>>(
+        &self,
+        hasher_factory: impl HasherFactory<H>,
+        scheme: CommitmentScheme,
+        layout: N,
+        old_root: Hash,
+        old_size: u64,
+    ) -> Result<Hash> {
+        match &self {
+            IMTMutate::Insert(insert) => {
+                insert.verify_with_layout(hasher_factory, scheme, layout, old_root, old_size)
+            }
+            IMTMutate::Update(update) => {
+                update.verify_with_layout(hasher_factory, scheme, layout, old_root, old_size)
+            }
+        }
+    }
+}
+
+impl<K: Key, V: Value> From<IMTMutate<K, V>> for CompactIMTMutate<K, V> {
+    fn from(mutate: IMTMutate<K, V>) -> Self {
+        match mutate {
+            IMTMutate::Insert(insert) => IMTMutate::Insert(insert.into()),
+            IMTMutate::Update(update) => IMTMutate::Update(update.into()),
+        }
+    }
+}
+
+impl<K: Key, V: Value> From<CompactIMTMutate<K, V>> for IMTMutate<K, V> {
+    fn from(mutate: CompactIMTMutate<K, V>) -> Self {
+        match mutate {
+            IMTMutate::Insert(insert) => IMTMutate::Insert(insert.into()),
+            IMTMutate::Update(update) => IMTMutate::Update(update.into()),
+        }
+    }
+}
+
+impl<K: Key, V: Value, const D: usize> TryFrom<IMTMutate<K, V>> for IMTMutateFixed<K, V, D> {
+    type Error = anyhow::Error;
+
+    fn try_from(mutate: IMTMutate<K, V>) -> Result<Self> {
+        Ok(match mutate {
+            IMTMutate::Insert(insert) => IMTMutate::Insert(insert.try_into()?),
+            IMTMutate::Update(update) => IMTMutate::Update(update.try_into()?),
+        })
+    }
+}
+
+impl<K: Key, V: Value, const D: usize> From<IMTMutateFixed<K, V, D>> for IMTMutate<K, V> {
+    fn from(mutate: IMTMutateFixed<K, V, D>) -> Self {
+        match mutate {
+            IMTMutate::Insert(insert) => IMTMutate::Insert(insert.into()),
+            IMTMutate::Update(update) => IMTMutate::Update(update.into()),
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        cell::Cell,
+        collections::BTreeMap,
+        mem,
+    };
+
+    use tiny_keccak::{Hasher, Keccak};
+
+    use super::*;
+    use crate::circuits::imt::Imt;
+
+    /// Global allocator counting the allocations performed by the current thread.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    /// Returns the number of allocations performed while running `f`.
+    fn count_allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+        let start = ALLOCATIONS.with(Cell::get);
+        let res = f();
+        (res, ALLOCATIONS.with(Cell::get) - start)
+    }
+
+    #[test]
+    fn test_verify_does_not_allocate() {
+        // Instanciate an IMT with a few nodes.
+        let mut imt = Imt::new(Keccak::v256);
+        for i in 1..20 {
+            imt.insert_node([i; 32], [42; 32]);
+        }
+
+        let old_root = imt.root;
+        let insert = imt.insert_node([20; 32], [42; 32]);
+        let insert_root = imt.root;
+
+        let update = imt.update_node([10; 32], [43; 32]);
+        let update_root = imt.root;
+
+        // Verify the insert and the update using each sibling representation.
+        for (mutate, old_root, new_root) in [
+            (insert, old_root, insert_root),
+            (update, insert_root, update_root),
+        ] {
+            let compact = CompactIMTMutate::from(mutate.clone());
+            let fixed = IMTMutateFixed::<_, _, 8>::try_from(mutate.clone()).unwrap();
+
+            // Registering the `tracing` callsites allocates, do it before counting.
+            #[cfg(feature = "tracing")]
+            let _ = mutate.verify(Keccak::v256, old_root);
+
+            let (res, allocations) = count_allocations(|| mutate.verify(Keccak::v256, old_root));
+            assert_eq!(res.unwrap(), new_root);
+            assert_eq!(allocations, 0);
+
+            let (res, allocations) = count_allocations(|| compact.verify(Keccak::v256, old_root));
+            assert_eq!(res.unwrap(), new_root);
+            assert_eq!(allocations, 0);
+
+            let (res, allocations) = count_allocations(|| fixed.verify(Keccak::v256, old_root));
+            assert_eq!(res.unwrap(), new_root);
+            assert_eq!(allocations, 0);
+        }
+    }
+
+    #[test]
+    fn test_verify_many() {
+        let mut imt = Imt::new(Keccak::v256);
+        let mut mutations = vec![];
+        for i in 1..20 {
+            mutations.push(imt.insert_node([i; 32], [42; 32]));
+            mutations.push(imt.update_node([i / 2; 32], [i; 32]));
+        }
+
+        // A tampered mutation fails alone.
+        if let IMTMutate::Update(update) = &mut mutations[5] {
+            update.new_value = [0; 32];
+            update.old_value = [0; 32];
+        }
+
+        let expected: Vec<_> = mutations
+            .iter()
+            .map(|mutate| mutate.verify(Keccak::v256, mutate.old_root()))
+            .map(|res| res.map_err(|e| e.to_string()))
+            .collect();
+        assert!(expected[5].is_err());
+        assert_eq!(expected.iter().filter(|res| res.is_err()).count(), 1);
+
+        for results in [
+            verify_many(&mutations, Keccak::v256),
+            verify_many_with(&mutations, Keccak::v256()),
+        ] {
+            let results: Vec<_> = results
+                .into_iter()
+                .map(|res| res.map_err(|e| e.to_string()))
+                .collect();
+            assert_eq!(results, expected);
+        }
+
+        // The results are the only allocation of a successful batch.
+        mutations.remove(5);
+        #[cfg(feature = "tracing")]
+        let _ = verify_many(&mutations, Keccak::v256);
+        let (results, allocations) =
+            count_allocations(|| verify_many_with(&mutations, Keccak::v256()));
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(allocations, 1);
+    }
+
+    thread_local! {
+        static HASHERS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// Keccak256 hashor counting the instances created by the current thread.
+    struct CountedKeccak(Keccak);
+
+    impl CountedKeccak {
+        fn new() -> Self {
+            HASHERS.with(|count| count.set(count.get() + 1));
+            Self(Keccak::v256())
+        }
+    }
+
+    impl Hasher for CountedKeccak {
+        fn update(&mut self, input: &[u8]) {
+            self.0.update(input);
+        }
+
+        fn finalize(self, output: &mut [u8]) {
+            self.0.finalize(output);
+        }
+    }
+
+    impl Hashor for CountedKeccak {
+        const OUTPUT_LEN: usize = 32;
+
+        fn finalize_reset(&mut self, output: &mut [u8]) {
+            mem::replace(&mut self.0, Keccak::v256()).finalize(output);
+        }
+    }
+
+    #[test]
+    fn test_verify_many_reuses_hasher() {
+        let mut imt = Imt::new(Keccak::v256);
+        let mut mutations = vec![];
+        for i in 1..20 {
+            mutations.push(imt.insert_node([i; 32], [42; 32]));
+            mutations.push(imt.update_node([i / 2; 32], [i; 32]));
+        }
+
+        let expected: Vec<_> = verify_many(&mutations, Keccak::v256)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+
+        // A single hasher is created for the whole batch.
+        let start = HASHERS.with(Cell::get);
+        let roots: Vec<_> = verify_many(&mutations, CountedKeccak::new)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(roots, expected);
+        assert_eq!(HASHERS.with(Cell::get) - start, 1);
+
+        // Where a single verification creates one per hash.
+        let start = HASHERS.with(Cell::get);
+        mutations[0]
+            .verify(CountedKeccak::new, mutations[0].old_root())
+            .unwrap();
+        assert!(HASHERS.with(Cell::get) - start > 1);
+    }
+
+    #[test]
+    fn test_mutation_trait() {
+        /// Keys of the valid `mutations` of the given kind.
+        fn valid_keys<M: Mutation<[u8; 32], [u8; 32]>>(
+            mutations: &[M],
+            kind: MutationKind,
+        ) -> Vec<[u8; 32]> {
+            mutations
+                .iter()
+                .zip(verify_many(mutations, Keccak::v256))
+                .filter(|(mutate, res)| mutate.kind() == kind && res.is_ok())
+                .map(|(mutate, _)| mutate.key())
+                .collect()
+        }
+
+        let mut imt = Imt::new(Keccak::v256);
+        let (mut inserts, mut updates, mut mutations) = (vec![], vec![], vec![]);
+        for i in 1..5 {
+            let insert = imt.insert_node([i; 32], [42; 32]);
+            let update = imt.update_node([i; 32], [43; 32]);
+            mutations.extend([insert.clone(), update.clone()]);
+
+            let (IMTMutate::Insert(insert), IMTMutate::Update(update)) = (insert, update) else {
+                unreachable!()
+            };
+            assert_eq!(Mutation::old_root(&insert), insert.old_root);
+            inserts.push(insert);
+            updates.push(update);
+        }
+        updates[0].new_value = [0; 32];
+        updates[0].old_value = [0; 32];
+
+        let keys: Vec<_> = (1..5).map(|i| [i; 32]).collect();
+        assert_eq!(valid_keys(&inserts, MutationKind::Insert), keys);
+        assert_eq!(valid_keys(&updates, MutationKind::Update), keys[1..]);
+        assert!(valid_keys(&inserts, MutationKind::Update).is_empty());
+
+        // The enum reports the kind and key of its variant.
+        assert_eq!(valid_keys(&mutations, MutationKind::Insert), keys);
+        assert_eq!(valid_keys(&mutations, MutationKind::Update), keys);
+    }
+
+    #[test]
+    fn test_verify_typed_root() {
+        let mut imt = Imt::new(Keccak::v256);
+        imt.insert_node([1; 32], [42; 32]);
+
+        let old_root = imt.root();
+        let insert = imt.insert_node([2; 32], [42; 32]);
+        assert_eq!(insert.typed_old_root(), old_root);
+        assert_eq!(insert.verify(Keccak::v256, old_root).unwrap(), imt.root);
+
+        // Bare hashes still verify, without any size to check.
+        let hash = Hash::from(old_root);
+        assert_eq!(insert.verify(Keccak::v256, hash).unwrap(), imt.root);
+
+        // A root computed at another size is rejected.
+        let res = insert.verify(Keccak::v256, Root::with_size(hash, 3));
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTMutate.size is stale"));
+
+        let update = imt.update_node([1; 32], [43; 32]);
+        assert_eq!(update.old_size(), 3);
+        let res = update.verify_with(Keccak::v256(), Root::with_size(update.old_root(), 2));
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTMutate.size is stale"));
+    }
+
+    #[test]
+    fn test_node_delta() {
+        // Nodes of the IMT by index, serialized to be compared.
+        let nodes_of = |imt: &Imt<_, [u8; 32], [u8; 32]>| -> BTreeMap<u64, Vec<u8>> {
+            (0..imt.size)
+                .map(|index| {
+                    let node = imt.node_by_index(index).unwrap();
+                    (index, bincode::serialize(node).unwrap())
+                })
+                .collect()
+        };
+
+        let mut imt = Imt::new(Keccak::v256);
+        let mut nodes = nodes_of(&imt);
+        for (key, value) in [(5, 42), (2, 42), (9, 42), (7, 42), (2, 43)] {
+            let mutate = match imt.get_value(&[key; 32]) {
+                Some(_) => imt.update_node([key; 32], [value; 32]),
+                None => imt.insert_node([key; 32], [value; 32]),
+            };
+            for (index, node) in mutate.node_delta() {
+                assert_eq!(index, node.index);
+                nodes.insert(index, bincode::serialize(&node).unwrap());
+            }
+
+            // Applying the delta reproduces the IMT after the mutation.
+            assert_eq!(nodes, nodes_of(&imt));
+        }
+    }
+
+    #[test]
+    fn test_verify_typed() {
+        let mut imt = Imt::new(Keccak::v256);
+        let mut root = OldRoot::from(imt.root);
+
+        // Each new root becomes the old root of the next mutation.
+        for i in 1..=5 {
+            let insert = imt.insert_node([i; 32], [42; 32]);
+            let new_root: NewRoot = insert.verify_typed(Keccak::v256, root).unwrap();
+            assert_eq!(Hash::from(new_root), imt.root);
+            root = new_root.into_old();
+        }
+
+        // The old root also verifies as an untyped root.
+        let update = imt.update_node([1; 32], [43; 32]);
+        assert_eq!(update.verify(Keccak::v256, root).unwrap(), imt.root);
+    }
+
+    #[test]
+    fn test_debug_hex() {
+        let node = IMTNode {
+            index: 1,
+            key: [0x01, 0x02],
+            value: [0xff, 0x00],
+            next_key: [0x00, 0x00],
+        };
+        let mutate = IMTMutate::update(
+            [0xab; 32],
+            2,
+            node,
+            vec![Some([0xcd; 32]), None],
+            [0x10, 0x20],
+        );
+
+        let (ab, cd) = ("ab".repeat(32), "cd".repeat(32));
+        assert_eq!(
+            format!("{mutate:?}"),
+            format!(
+                "Update(IMTUpdate {{ old_root: 0x{ab}, size: 2, node: IMTNode {{ index: 1, \
+                 key: 0x0102, value: 0xff00, next_key: 0x0000 }}, node_siblings: [0x{cd}, None], \
+                 old_value: 0xff00, new_value: 0x1020 }})"
+            )
+        );
+
+        let mut imt = Imt::new(Keccak::v256);
+        imt.insert_node([1; 32], [42; 32]);
+        let debug = format!("{imt:?}");
+        assert!(debug.starts_with(&format!("Imt {{ root: {}, size: 2, depth: 1", imt.root())));
+        assert!(debug.contains(&format!("key: 0x{}", "01".repeat(32))));
+    }
+
+    #[test]
+    fn test_serialization_is_reproducible() {
+        // Every `Imt` seeds its hash maps differently.
+        let (mut imt, mut other) = (Imt::new(Keccak::v256), Imt::new(Keccak::v256));
+        let mut mutations = vec![];
+        for i in 1..=50_u8 {
+            let key = [i.wrapping_mul(97); 32];
+            mutations.push((
+                imt.insert_node(key, [i; 32]),
+                other.insert_node(key, [i; 32]),
+            ));
+            if i % 7 == 0 {
+                imt.remove_node(&key);
+                other.remove_node(&key);
+            } else if i % 3 == 0 {
+                mutations.push((
+                    imt.update_node(key, [0; 32]),
+                    other.update_node(key, [0; 32]),
+                ));
+            }
+        }
+        // Rebuilding the cached hashes iterates over hash maps as well.
+        other.rebuild();
+        let key = [97; 32];
+        mutations.push((
+            imt.update_node(key, [1; 32]),
+            other.update_node(key, [1; 32]),
+        ));
+
+        for (mutate, other) in mutations {
+            let bytes = bincode::serialize(&mutate).unwrap();
+            assert_eq!(bincode::serialize(&mutate).unwrap(), bytes);
+            assert_eq!(bincode::serialize(&other).unwrap(), bytes);
+        }
+
+        let keys: Vec<_> = (1..=50_u8)
+            .map(|i| [i.wrapping_mul(97); 32])
+            .filter(|key| imt.node(key).is_some())
+            .collect();
+        assert_eq!(
+            bincode::serialize(&imt.prove_membership_many(&keys)).unwrap(),
+            bincode::serialize(&other.prove_membership_many(&keys)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_verify_with() {
+        let mut imt = Imt::new(Keccak::v256);
+        let mut old_root = imt.root;
+        for i in 1..20 {
+            let mutate = imt.insert_node([i; 32], [42; 32]);
+            assert_eq!(
+                mutate.verify_with(Keccak::v256(), old_root).unwrap(),
+                imt.root
+            );
+            old_root = imt.root;
+        }
+
+        let update = imt.update_node([10; 32], [43; 32]);
+        assert_eq!(
+            update.verify_with(Keccak::v256(), old_root).unwrap(),
+            update.verify(Keccak::v256, old_root).unwrap()
+        );
+
+        // Failures are identical as well.
+        let res = update.verify_with(Keccak::v256(), imt.root);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTMutate.old_root is stale"));
+
+        // Cloning the hasher does not allocate.
+        let hasher = Keccak::v256();
+        let (res, allocations) = count_allocations(|| update.verify_with(hasher, old_root));
+        assert_eq!(res.unwrap(), update.verify(Keccak::v256, old_root).unwrap());
+        assert_eq!(allocations, 0);
+    }
+
+    #[test]
+    fn test_update_node_allocates_witness_only() {
+        let mut imt = Imt::new_dense(Keccak::v256);
+        for i in 1..20 {
+            imt.insert_node([i; 32], [42; 32]);
+        }
+
+        // Registering the `tracing` callsites allocates, do it before counting.
+        #[cfg(feature = "tracing")]
+        imt.update_node([10; 32], [42; 32]);
+
+        // Refreshing the tree collects the siblings inline, only the returned witness allocates.
+        let (update, allocations) = count_allocations(|| imt.update_node([10; 32], [43; 32]));
+        assert_eq!(allocations, 1);
+
+        let IMTMutate::Update(update) = update else {
+            unreachable!()
+        };
+        assert_eq!(update.node_siblings.len(), imt.depth as usize);
+    }
 }
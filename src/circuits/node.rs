@@ -1,15 +1,225 @@
-use std::fmt::Debug;
+use std::{
+    cell::Cell,
+    fmt,
+    mem::{self, size_of},
+};
 
+use anyhow::{ensure, Result};
 use serde::{Deserialize, Serialize};
-use tiny_keccak::Hasher;
+use tiny_keccak::{Hasher, Keccak};
 
 use crate::Hash;
 
-pub trait Hashor = Hasher;
+use super::hex::Hex;
+
+/// Hasher usable by the IMT.
+pub trait Hashor: Hasher {
+    /// Number of bytes output by the hasher, which must match the size of a `Hash`.
+    const OUTPUT_LEN: usize;
+
+    /// Finalizes the hash into `output` and resets the hasher to its initial state, so that a
+    /// single instance can be reused across hashes, e.g. keeping its buffers.
+    fn finalize_reset(&mut self, output: &mut [u8]);
+}
+
+/// Source of the hashers computing the IMT hashes, implemented by every `Fn() -> H` hasher
+/// factory.
+pub trait HasherFactory<H: Hashor>: Copy {
+    /// Returns the hash of the bytes `absorb` feeds to a new hasher.
+    fn hash(self, absorb: impl FnOnce(&mut H)) -> Hash;
+}
+
+impl<H: Hashor, F: Fn() -> H + Copy> HasherFactory<H> for F {
+    fn hash(self, absorb: impl FnOnce(&mut H)) -> Hash {
+        let mut hash = [0; 32];
+
+        let mut hasher = self();
+        absorb(&mut hasher);
+        hasher.finalize(&mut hash);
+
+        hash
+    }
+}
+
+/// Hasher factory reusing a single hasher, reset after each hash.
+pub(crate) struct ResetHasher<'a, H, F> {
+    hasher: &'a Cell<Option<H>>,
+    factory: F,
+}
+
+impl<H, F: Copy> Clone for ResetHasher<'_, H, F> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<H, F: Copy> Copy for ResetHasher<'_, H, F> {}
+
+impl<'a, H: Hashor, F: Fn() -> H + Copy> ResetHasher<'a, H, F> {
+    /// Creates a hasher factory reusing the hasher held in `hasher`, created by `factory` on
+    /// first use.
+    pub(crate) fn new(hasher: &'a Cell<Option<H>>, factory: F) -> Self {
+        Self { hasher, factory }
+    }
+}
+
+impl<H: Hashor, F: Fn() -> H + Copy> HasherFactory<H> for ResetHasher<'_, H, F> {
+    fn hash(self, absorb: impl FnOnce(&mut H)) -> Hash {
+        let mut hash = [0; 32];
+
+        // A hash computed while absorbing another one gets a hasher of its own.
+        let mut hasher = self.hasher.take().unwrap_or_else(self.factory);
+        absorb(&mut hasher);
+        hasher.finalize_reset(&mut hash);
+        self.hasher.set(Some(hasher));
+
+        hash
+    }
+}
+
+/// `Keccak` is expected to be instanciated with `Keccak::v256`, its other variants sharing the
+/// same type.
+impl Hashor for Keccak {
+    const OUTPUT_LEN: usize = 32;
+
+    fn finalize_reset(&mut self, output: &mut [u8]) {
+        mem::replace(self, Keccak::v256()).finalize(output);
+    }
+}
+
+const _: () = assert!(<Keccak as Hashor>::OUTPUT_LEN == size_of::<Hash>());
+
 pub trait Key = Default + Clone + Copy + Eq + std::hash::Hash + AsRef<[u8]>;
 pub trait Value = Default + Clone + Copy + AsRef<[u8]>;
 
-#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+/// Field of a node absorbed into its leaf hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeafField {
+    Key,
+    Value,
+    NextKey,
+}
+
+/// Layout of the bytes absorbed into a leaf hash: a constant `prefix` followed by the node
+/// `fields` in order.
+///
+/// Selecting the layout of an existing deployment allows to compute roots matching it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LeafLayout {
+    pub prefix: &'static [u8],
+    pub fields: [LeafField; 3],
+}
+
+impl LeafLayout {
+    /// Layout of the crate: `key || value || next_key`, without any prefix.
+    pub const NATIVE: Self = Self {
+        prefix: &[],
+        fields: [LeafField::Key, LeafField::Value, LeafField::NextKey],
+    };
+
+    /// Semaphore style layout, the linked list fields first: `key || next_key || value`,
+    /// without any prefix.
+    pub const SEMAPHORE: Self = Self {
+        prefix: &[],
+        fields: [LeafField::Key, LeafField::NextKey, LeafField::Value],
+    };
+}
+
+impl Default for LeafLayout {
+    fn default() -> Self {
+        Self::NATIVE
+    }
+}
+
+/// Serializes nodes into the preimage of their leaf hash, e.g. to interoperate with the leaves
+/// of another ecosystem.
+///
+/// `Imt` and the proof verifiers are generic over it, `LeafLayout` being the default.
+pub trait NodeHasher<K: Key, V: Value>: fmt::Debug + Clone + Copy + PartialEq {
+    /// Absorbs the preimage of the leaf hash of `node` into `hasher`.
+    fn absorb_node<H: Hashor>(&self, hasher: &mut H, node: &IMTNode<K, V>);
+
+    /// Computes the leaf hash of `node`.
+    ///
+    /// The `bind-index` feature absorbs the index after the preimage, whatever the node hasher.
+    fn hash_node<H: Hashor>(&self, hasher: H, node: &IMTNode<K, V>) -> Hash {
+        node.hash_inner(hasher, *self, None)
+    }
+}
+
+impl<K: Key, V: Value> NodeHasher<K, V> for LeafLayout {
+    fn absorb_node<H: Hashor>(&self, hasher: &mut H, node: &IMTNode<K, V>) {
+        hasher.update(self.prefix);
+        for field in self.fields {
+            match field {
+                LeafField::Key => hasher.update(node.key.as_ref()),
+                LeafField::Value => hasher.update(node.value.as_ref()),
+                LeafField::NextKey => hasher.update(node.next_key.as_ref()),
+            }
+        }
+    }
+}
+
+/// Node hasher absorbing `key || value || next_key`, each field being preceded by its length on
+/// 4 big endian bytes, so that keys and values of different widths can not be confused.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LengthPrefixed;
+
+impl<K: Key, V: Value> NodeHasher<K, V> for LengthPrefixed {
+    fn absorb_node<H: Hashor>(&self, hasher: &mut H, node: &IMTNode<K, V>) {
+        for field in [
+            node.key.as_ref(),
+            node.value.as_ref(),
+            node.next_key.as_ref(),
+        ] {
+            hasher.update(&(field.len() as u32).to_be_bytes());
+            hasher.update(field);
+        }
+    }
+}
+
+/// Node hasher absorbing `index.to_be_bytes()` before the preimage of the wrapped node hasher,
+/// binding each leaf to its position whatever the `bind-index` feature.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IndexPrefixed<N = LeafLayout>(pub N);
+
+impl<K: Key, V: Value, N: NodeHasher<K, V>> NodeHasher<K, V> for IndexPrefixed<N> {
+    fn absorb_node<H: Hashor>(&self, hasher: &mut H, node: &IMTNode<K, V>) {
+        hasher.update(&node.index.to_be_bytes());
+        self.0.absorb_node(hasher, node);
+    }
+}
+
+/// Node hasher absorbing `version.to_be_bytes()` before the preimage of the wrapped node hasher,
+/// so that the leaves of a tree whose leaf semantics evolved can not be confused across schema
+/// versions: the same nodes commit to different roots under different versions.
+#[cfg(feature = "schema-version")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SchemaVersioned<N = LeafLayout> {
+    pub version: u16,
+    pub inner: N,
+}
+
+#[cfg(feature = "schema-version")]
+impl SchemaVersioned {
+    /// Versions the native layout of the crate with `version`.
+    pub fn new(version: u16) -> Self {
+        Self {
+            version,
+            inner: LeafLayout::NATIVE,
+        }
+    }
+}
+
+#[cfg(feature = "schema-version")]
+impl<K: Key, V: Value, N: NodeHasher<K, V>> NodeHasher<K, V> for SchemaVersioned<N> {
+    fn absorb_node<H: Hashor>(&self, hasher: &mut H, node: &IMTNode<K, V>) {
+        hasher.update(&self.version.to_be_bytes());
+        self.inner.absorb_node(hasher, node);
+    }
+}
+
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
 pub struct IMTNode<K: Key, V: Value> {
     pub index: u64,
     pub key: K,
@@ -17,18 +227,117 @@ pub struct IMTNode<K: Key, V: Value> {
     pub next_key: K,
 }
 
+impl<K: Key, V: Value> fmt::Debug for IMTNode<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IMTNode")
+            .field("index", &self.index)
+            .field("key", &Hex(self.key.as_ref()))
+            .field("value", &Hex(self.value.as_ref()))
+            .field("next_key", &Hex(self.next_key.as_ref()))
+            .finish()
+    }
+}
+
 impl<K: Key, V: Value> IMTNode<K, V> {
-    pub fn hash<H: Hashor>(&self, mut hasher: H) -> Hash {
-        let mut h = [0u8; 32];
-        // NOTE: index is intentionnaly not hashed.
-        hasher.update(self.key.as_ref());
-        hasher.update(self.value.as_ref());
-        hasher.update(self.next_key.as_ref());
+    /// Creates a new node, making sure its `key` is before its `next_key`, unless the node is the
+    /// last one and links to the zero key.
+    ///
+    /// Meant for witnesses built by hand, the IMT itself only creates consistent nodes.
+    pub fn new(index: u64, key: K, value: V, next_key: K) -> Result<Self> {
+        ensure!(
+            key.as_ref() < next_key.as_ref() || *next_key.as_ref() == *K::default().as_ref(),
+            "IMTNode.next_key is invalid"
+        );
+
+        Ok(Self {
+            index,
+            key,
+            value,
+            next_key,
+        })
+    }
+
+    /// Computes the leaf hash of the node.
+    ///
+    /// By default the index is not hashed: the leaf position is only bound by the siblings path,
+    /// so two nodes with identical key, value and next_key hash identically wherever they sit.
+    /// The `bind-index` feature also absorbs `index.to_be_bytes()`, binding each leaf to its
+    /// position so that a leaf can not be presented at another index. This changes every hash
+    /// and thus every root.
+    pub fn hash<H: Hashor>(&self, hasher: H) -> Hash {
+        self.hash_with_layout(hasher, LeafLayout::NATIVE)
+    }
 
+    /// Computes the leaf hash of the node, absorbing its fields as set by the given `layout`.
+    ///
+    /// The `bind-index` feature absorbs the index after the fields, whatever the layout.
+    pub fn hash_with_layout<H: Hashor, N: NodeHasher<K, V>>(&self, hasher: H, layout: N) -> Hash {
+        self.hash_inner(hasher, layout, None)
+    }
+
+    /// Computes the leaf hash of a node whose value was updated `version` times, absorbing
+    /// `version.to_be_bytes()` right after the fields set by the given `layout`.
+    pub fn hash_versioned<H: Hashor, N: NodeHasher<K, V>>(
+        &self,
+        hasher: H,
+        layout: N,
+        version: u64,
+    ) -> Hash {
+        self.hash_inner(hasher, layout, Some(version))
+    }
+
+    /// Same as `hash_with_layout`, hashing with a hasher of the given `hasher_factory`.
+    pub(crate) fn hash_with<H: Hashor, N: NodeHasher<K, V>>(
+        &self,
+        hasher_factory: impl HasherFactory<H>,
+        layout: N,
+    ) -> Hash {
+        hasher_factory.hash(|hasher| self.absorb_inner(hasher, layout, None))
+    }
+
+    fn hash_inner<H: Hashor, N: NodeHasher<K, V>>(
+        &self,
+        mut hasher: H,
+        layout: N,
+        version: Option<u64>,
+    ) -> Hash {
+        let mut h = [0u8; 32];
+        self.absorb_inner(&mut hasher, layout, version);
         hasher.finalize(&mut h);
         h
     }
 
+    fn absorb_inner<H: Hashor, N: NodeHasher<K, V>>(
+        &self,
+        hasher: &mut H,
+        layout: N,
+        version: Option<u64>,
+    ) {
+        layout.absorb_node(hasher, self);
+        if let Some(version) = version {
+            hasher.update(&version.to_be_bytes());
+        }
+        #[cfg(feature = "bind-index")]
+        hasher.update(&self.index.to_be_bytes());
+    }
+
+    /// Returns the node filling the vacant slot at `index`, left behind by a removed node.
+    ///
+    /// Vacant nodes hold the zero key, value and next key: as the zero node is always at index
+    /// 0, a node with the zero key at any other index can only be a vacant one.
+    pub fn vacant(index: u64) -> Self {
+        Self {
+            index,
+            ..Default::default()
+        }
+    }
+
+    /// Returns `true` if the node fills a vacant slot, i.e. holds the zero key at a non zero
+    /// index.
+    pub fn is_vacant(&self) -> bool {
+        self.index != 0 && *self.key.as_ref() == *K::default().as_ref()
+    }
+
     pub fn is_ln_of(&self, node_key: &K) -> bool {
         self.key.as_ref() < node_key.as_ref()
             && ((self.next_key.as_ref() > node_key.as_ref())
@@ -39,7 +348,6 @@ impl<K: Key, V: Value> IMTNode<K, V> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tiny_keccak::Keccak;
 
     #[test]
     fn test_hash() {
@@ -61,12 +369,168 @@ mod tests {
         hasher.update(&key);
         hasher.update(&value);
         hasher.update(&next_key);
+        #[cfg(feature = "bind-index")]
+        hasher.update(&0_u64.to_be_bytes());
         let mut expected_hash = [0u8; 32];
         hasher.finalize(&mut expected_hash);
 
         assert_eq!(hash, expected_hash, "hashes do not match");
     }
 
+    #[test]
+    #[cfg(feature = "bind-index")]
+    fn test_hash_binds_index() {
+        let node = IMTNode {
+            index: 1,
+            key: [1; 32],
+            value: [2; 32],
+            next_key: [3; 32],
+        };
+        let moved = IMTNode { index: 2, ..node };
+
+        assert_ne!(
+            node.hash(Keccak::v256()),
+            moved.hash(Keccak::v256()),
+            "hashes should differ"
+        );
+    }
+
+    #[test]
+    fn test_hash_with_layout() {
+        let node = IMTNode {
+            index: 0,
+            key: [1; 32],
+            value: [2; 32],
+            next_key: [3; 32],
+        };
+
+        assert_eq!(
+            node.hash_with_layout(Keccak::v256(), LeafLayout::NATIVE),
+            node.hash(Keccak::v256())
+        );
+
+        // Prefix then fields in the layout order.
+        let layout = LeafLayout {
+            prefix: &[0xff],
+            fields: [LeafField::Value, LeafField::NextKey, LeafField::Key],
+        };
+        let mut hasher = Keccak::v256();
+        hasher.update(&[0xff]);
+        hasher.update(&[2; 32]);
+        hasher.update(&[3; 32]);
+        hasher.update(&[1; 32]);
+        #[cfg(feature = "bind-index")]
+        hasher.update(&0_u64.to_be_bytes());
+        let mut expected_hash = [0u8; 32];
+        hasher.finalize(&mut expected_hash);
+
+        assert_eq!(node.hash_with_layout(Keccak::v256(), layout), expected_hash);
+    }
+
+    #[test]
+    fn test_length_prefixed() {
+        let node = IMTNode {
+            index: 0,
+            key: [1; 20],
+            value: [2; 32],
+            next_key: [3; 20],
+        };
+
+        let mut hasher = Keccak::v256();
+        for field in [&[1; 20][..], &[2; 32], &[3; 20]] {
+            hasher.update(&(field.len() as u32).to_be_bytes());
+            hasher.update(field);
+        }
+        #[cfg(feature = "bind-index")]
+        hasher.update(&0_u64.to_be_bytes());
+        let mut expected_hash = [0u8; 32];
+        hasher.finalize(&mut expected_hash);
+
+        assert_eq!(
+            LengthPrefixed.hash_node(Keccak::v256(), &node),
+            expected_hash
+        );
+        assert_eq!(
+            LeafLayout::NATIVE.hash_node(Keccak::v256(), &node),
+            node.hash(Keccak::v256())
+        );
+    }
+
+    #[test]
+    fn test_index_prefixed() {
+        let node = IMTNode {
+            index: 3,
+            key: [1; 32],
+            value: [2; 32],
+            next_key: [3; 32],
+        };
+
+        let mut hasher = Keccak::v256();
+        hasher.update(&3_u64.to_be_bytes());
+        hasher.update(&[1; 32]);
+        hasher.update(&[2; 32]);
+        hasher.update(&[3; 32]);
+        #[cfg(feature = "bind-index")]
+        hasher.update(&3_u64.to_be_bytes());
+        let mut expected_hash = [0u8; 32];
+        hasher.finalize(&mut expected_hash);
+
+        let layout = IndexPrefixed::<LeafLayout>::default();
+        assert_eq!(layout.hash_node(Keccak::v256(), &node), expected_hash);
+
+        // The same fields at another index hash differently.
+        let moved = IMTNode { index: 4, ..node };
+        assert_ne!(
+            layout.hash_node(Keccak::v256(), &moved),
+            layout.hash_node(Keccak::v256(), &node)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "schema-version")]
+    fn test_schema_versioned() {
+        let node = IMTNode {
+            index: 0,
+            key: [1; 32],
+            value: [2; 32],
+            next_key: [3; 32],
+        };
+
+        let mut hasher = Keccak::v256();
+        hasher.update(&[0, 1]);
+        hasher.update(&[1; 32]);
+        hasher.update(&[2; 32]);
+        hasher.update(&[3; 32]);
+        #[cfg(feature = "bind-index")]
+        hasher.update(&0_u64.to_be_bytes());
+        let mut expected_hash = [0u8; 32];
+        hasher.finalize(&mut expected_hash);
+
+        let v1 = SchemaVersioned::new(1).hash_node(Keccak::v256(), &node);
+        assert_eq!(v1, expected_hash);
+
+        // Identical nodes hash differently under different schema versions.
+        assert_ne!(v1, SchemaVersioned::new(2).hash_node(Keccak::v256(), &node));
+        assert_ne!(v1, node.hash(Keccak::v256()));
+    }
+
+    #[test]
+    fn test_new() {
+        // Keys are linked in increasing order, the last node linking to the zero key.
+        for (key, next_key) in [(1, 2), (0, 5), (7, 0), (0, 0)] {
+            let node = IMTNode::new(3, [key; 32], [42; 32], [next_key; 32]).unwrap();
+            assert_eq!(node.index, 3);
+            assert_eq!(node.key, [key; 32]);
+            assert_eq!(node.value, [42; 32]);
+            assert_eq!(node.next_key, [next_key; 32]);
+        }
+
+        for (key, next_key) in [(2, 1), (5, 5)] {
+            let res = IMTNode::new(3, [key; 32], [42; 32], [next_key; 32]);
+            assert!(matches!(res, Err(e) if e.to_string() == "IMTNode.next_key is invalid"));
+        }
+    }
+
     #[test]
     fn test_is_ln_of() {
         let mut ln_node = IMTNode {
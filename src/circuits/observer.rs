@@ -0,0 +1,212 @@
+//! Callbacks notified of the mutations applied to an IMT, e.g. to mirror it into a database.
+
+use std::fmt;
+
+use crate::Hash;
+
+use super::node::{IMTNode, Key, Value};
+
+/// Observer of the mutations applied to an `Imt`, set with `Imt::set_observer`.
+///
+/// Each callback is invoked once per mutation, after the IMT is consistent again, and only gets
+/// shared references to its state. Callbacks do nothing by default.
+pub trait ImtObserver<K: Key, V: Value>: Send + Sync {
+    /// Called after `node` was inserted, the IMT root being then `new_root`.
+    ///
+    /// A merge calls it for each node it appends, with the root after the merge.
+    fn on_insert(&mut self, node: &IMTNode<K, V>, new_root: &Hash) {
+        let _ = (node, new_root);
+    }
+
+    /// Called after `old` was updated to `new_value`, the IMT root being then `new_root`.
+    fn on_update(&mut self, old: &IMTNode<K, V>, new_value: &V, new_root: &Hash) {
+        let _ = (old, new_value, new_root);
+    }
+
+    /// Called after any mutation changing the IMT root from `old` to `new`, removals, merges and
+    /// clears included, right after the `on_insert` or `on_update` calls of the mutation if any.
+    fn on_root_changed(&mut self, old: Hash, new: Hash) {
+        let _ = (old, new);
+    }
+}
+
+/// Observer of an IMT, if any.
+///
+/// A clone of an IMT does not share its observer, which would otherwise be notified of the
+/// mutations of both.
+pub(crate) struct Observer<K: Key, V: Value>(Option<Box<dyn ImtObserver<K, V>>>);
+
+impl<K: Key, V: Value> Observer<K, V> {
+    pub(crate) fn new(observer: Box<dyn ImtObserver<K, V>>) -> Self {
+        Self(Some(observer))
+    }
+
+    /// Notifies the observer, if any, of the insertion of `node`.
+    pub(crate) fn inserted(&mut self, node: &IMTNode<K, V>, old_root: Hash, new_root: Hash) {
+        if let Some(observer) = &mut self.0 {
+            observer.on_insert(node, &new_root);
+            observer.on_root_changed(old_root, new_root);
+        }
+    }
+
+    /// Notifies the observer, if any, of the update of `old` to `new_value`.
+    pub(crate) fn updated(
+        &mut self,
+        old: &IMTNode<K, V>,
+        new_value: &V,
+        old_root: Hash,
+        new_root: Hash,
+    ) {
+        if let Some(observer) = &mut self.0 {
+            observer.on_update(old, new_value, &new_root);
+            observer.on_root_changed(old_root, new_root);
+        }
+    }
+
+    /// Notifies the observer, if any, of the insertion of the `nodes` added by a merge.
+    pub(crate) fn merged<'a>(
+        &mut self,
+        nodes: impl IntoIterator<Item = &'a IMTNode<K, V>>,
+        old_root: Hash,
+        new_root: Hash,
+    ) where
+        K: 'a,
+        V: 'a,
+    {
+        if let Some(observer) = &mut self.0 {
+            for node in nodes {
+                observer.on_insert(node, &new_root);
+            }
+            observer.on_root_changed(old_root, new_root);
+        }
+    }
+
+    /// Notifies the observer, if any, of a root change not coming from a single insertion or
+    /// update.
+    pub(crate) fn root_changed(&mut self, old_root: Hash, new_root: Hash) {
+        if let Some(observer) = &mut self.0 {
+            observer.on_root_changed(old_root, new_root);
+        }
+    }
+}
+
+impl<K: Key, V: Value> Default for Observer<K, V> {
+    fn default() -> Self {
+        Self(None)
+    }
+}
+
+impl<K: Key, V: Value> Clone for Observer<K, V> {
+    fn clone(&self) -> Self {
+        Self(None)
+    }
+}
+
+impl<K: Key, V: Value> fmt::Debug for Observer<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Observer")
+            .field("enabled", &self.0.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tiny_keccak::Keccak;
+
+    use super::*;
+    use crate::circuits::{imt::Imt, mutate::IMTMutate};
+
+    type Node = IMTNode<[u8; 32], [u8; 32]>;
+
+    /// Notification received by a `Recorder`, nodes being kept as their debug string.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    enum Event {
+        Insert(String, Hash),
+        Update(String, [u8; 32], Hash),
+        RootChanged(Hash, Hash),
+    }
+
+    /// Observer recording its notifications into a log shared with the test.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<Event>>>);
+
+    impl Recorder {
+        fn take(&self) -> Vec<Event> {
+            std::mem::take(&mut *self.0.lock().unwrap())
+        }
+    }
+
+    impl ImtObserver<[u8; 32], [u8; 32]> for Recorder {
+        fn on_insert(&mut self, node: &Node, new_root: &Hash) {
+            let event = Event::Insert(format!("{node:?}"), *new_root);
+            self.0.lock().unwrap().push(event);
+        }
+
+        fn on_update(&mut self, old: &Node, new_value: &[u8; 32], new_root: &Hash) {
+            let event = Event::Update(format!("{old:?}"), *new_value, *new_root);
+            self.0.lock().unwrap().push(event);
+        }
+
+        fn on_root_changed(&mut self, old: Hash, new: Hash) {
+            self.0.lock().unwrap().push(Event::RootChanged(old, new));
+        }
+    }
+
+    /// Events expected from the given `mutate`, the IMT root being then `new_root`.
+    fn expected(mutate: &IMTMutate<[u8; 32], [u8; 32]>, new_root: Hash) -> Vec<Event> {
+        let event = match mutate {
+            IMTMutate::Insert(insert) => Event::Insert(format!("{:?}", insert.node), new_root),
+            IMTMutate::Update(update) => {
+                Event::Update(format!("{:?}", update.node), update.new_value, new_root)
+            }
+        };
+
+        vec![event, Event::RootChanged(mutate.old_root(), new_root)]
+    }
+
+    #[test]
+    fn test_observer() {
+        let recorder = Recorder::default();
+        let mut imt = Imt::new(Keccak::v256);
+        imt.set_observer(Box::new(recorder.clone()));
+
+        // Each mutation is notified once, as returned by the IMT.
+        for i in 1..10 {
+            let insert = imt.insert_node([i; 32], [42; 32]);
+            assert_eq!(recorder.take(), expected(&insert, imt.root));
+
+            let update = imt.set([i / 2; 32], [i; 32]);
+            assert_eq!(recorder.take(), expected(&update, imt.root));
+        }
+        assert!(imt.update_node_if_changed([1; 32], [42; 32]).is_some());
+        recorder.take();
+        assert!(imt.update_node_if_changed([1; 32], [42; 32]).is_none());
+        assert!(recorder.take().is_empty());
+
+        // Removals only change the root.
+        let old_root = imt.root;
+        imt.remove_node(&[3; 32]);
+        assert_eq!(recorder.take(), [Event::RootChanged(old_root, imt.root)]);
+
+        // Clones do not notify the observer.
+        imt.clone().insert_node([20; 32], [42; 32]);
+        assert!(recorder.take().is_empty());
+
+        // Merges notify each appended node, then the root change.
+        let mut other = Imt::new(Keccak::v256);
+        other.insert_node([30; 32], [42; 32]);
+        other.insert_node([31; 32], [42; 32]);
+        let old_root = imt.root;
+        let imt = imt.merge(other).unwrap();
+        let events = recorder.take();
+        assert_eq!(events.len(), 3);
+        for (event, key) in events.iter().zip([[30; 32], [31; 32]]) {
+            let node = imt.prove_membership(&key).unwrap().node;
+            assert_eq!(*event, Event::Insert(format!("{node:?}"), imt.root));
+        }
+        assert_eq!(events[2], Event::RootChanged(old_root, imt.root));
+    }
+}
@@ -0,0 +1,247 @@
+//! Operation log of the mutations applied to an IMT, to reconstruct it after a crash.
+
+use std::{
+    fmt,
+    io::{BufRead, BufReader, Read, Write},
+};
+
+use anyhow::{anyhow, ensure, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{
+    imt::Imt,
+    mutate::IMTMutate,
+    node::{Hashor, Key, Value},
+};
+
+/// Appends the entry it is given to a sink, if any.
+type Append<K, V> = Box<dyn FnMut(Entry<&IMTMutate<K, V>>) -> Result<()> + Send + Sync>;
+
+/// Entry of an oplog.
+#[derive(Debug, Deserialize, Serialize)]
+enum Entry<M> {
+    /// Mutation applied to the IMT.
+    Mutate(M),
+    /// Clearing of the IMT back to the zero node alone, see `Imt::clear`.
+    Clear,
+}
+
+/// Sink receiving the bincode serialized entries of an IMT, one after the other: the mutations
+/// applied to it and its clearings.
+///
+/// A clone of an IMT does not share its log, as both would then append diverging mutations to
+/// the same sink.
+pub(crate) struct OpLog<K: Key, V: Value>(Option<Append<K, V>>);
+
+impl<K: Key + Serialize + 'static, V: Value + Serialize + 'static> OpLog<K, V> {
+    /// Creates a log appending the entries to `sink`, flushing it after each one.
+    pub(crate) fn new<W: Write + Send + Sync + 'static>(mut sink: W) -> Self {
+        Self(Some(Box::new(move |entry| {
+            bincode::serialize_into(&mut sink, &entry)?;
+            sink.flush()?;
+            Ok(())
+        })))
+    }
+}
+
+impl<K: Key, V: Value> OpLog<K, V> {
+    /// Returns whether the log appends to a sink.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Appends `mutate` to the log, if any.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the mutation can not be written to the sink.
+    pub(crate) fn append(&mut self, mutate: &IMTMutate<K, V>) {
+        self.append_entry(Entry::Mutate(mutate));
+    }
+
+    /// Appends the clearing of the IMT to the log, if any.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the clearing can not be written to the sink.
+    pub(crate) fn clear(&mut self) {
+        self.append_entry(Entry::Clear);
+    }
+
+    fn append_entry(&mut self, entry: Entry<&IMTMutate<K, V>>) {
+        if let Some(append) = &mut self.0 {
+            append(entry).expect("failed to append to the oplog");
+        }
+    }
+}
+
+impl<K: Key, V: Value> Default for OpLog<K, V> {
+    fn default() -> Self {
+        Self(None)
+    }
+}
+
+impl<K: Key, V: Value> Clone for OpLog<K, V> {
+    fn clone(&self) -> Self {
+        Self(None)
+    }
+}
+
+impl<K: Key, V: Value> fmt::Debug for OpLog<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpLog")
+            .field("enabled", &self.is_enabled())
+            .finish()
+    }
+}
+
+impl<H: Hashor, K: Key + DeserializeOwned, V: Value + DeserializeOwned> Imt<H, K, V> {
+    /// Rebuilds an IMT by verifying and applying, in order, each mutation of the log read from
+    /// `reader`, as written by an IMT created with `with_oplog`, clearing it where the logged IMT
+    /// was cleared.
+    ///
+    /// Fails on the first mutation that does not verify against the state rebuilt so far,
+    /// reporting its index in the log.
+    pub fn replay<R: Read>(hasher_factory: fn() -> H, reader: R) -> Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let mut imt = Self::new(hasher_factory);
+
+        let mut index = 0;
+        while !reader.fill_buf()?.is_empty() {
+            let entry: Entry<IMTMutate<K, V>> = bincode::deserialize_from(&mut reader)
+                .map_err(|e| anyhow!("oplog mutation {index} can not be read: {e}"))?;
+            let mutate = match entry {
+                Entry::Mutate(mutate) => mutate,
+                Entry::Clear => {
+                    imt.clear();
+                    index += 1;
+                    continue;
+                }
+            };
+
+            let new_root = mutate
+                .verify_with_scheme(hasher_factory, imt.scheme(), imt.root, imt.size)
+                .map_err(|e| anyhow!("oplog mutation {index} is invalid: {e}"))?;
+
+            match mutate {
+                IMTMutate::Insert(insert) => {
+                    imt.insert_node(insert.node.key, insert.node.value);
+                }
+                IMTMutate::Update(update) => {
+                    imt.update_node(update.node.key, update.new_value);
+                }
+            }
+            ensure!(
+                imt.root == new_root,
+                "oplog mutation {index} does not apply to the IMT"
+            );
+
+            index += 1;
+        }
+
+        Ok(imt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tiny_keccak::Keccak;
+
+    use super::*;
+
+    /// Sink shared with the test, to read back what was written to it.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_replay() {
+        let log = SharedBuffer::default();
+        let mut imt = Imt::new(Keccak::v256).with_oplog(log.clone());
+
+        let mut mutations = vec![];
+        for i in 1..20 {
+            mutations.push(imt.insert_node([i; 32], [42; 32]));
+        }
+        mutations.push(imt.update_node([7; 32], [43; 32]));
+        mutations.push(imt.set([20; 32], [44; 32]));
+        mutations.push(imt.set([20; 32], [45; 32]));
+
+        // Clones do not append to the log.
+        imt.clone().insert_node([21; 32], [42; 32]);
+
+        let bytes = log.0.lock().unwrap().clone();
+        let replayed = Imt::<_, [u8; 32], [u8; 32]>::replay(Keccak::v256, &bytes[..]).unwrap();
+        assert_eq!(replayed.root, imt.root);
+        assert_eq!(replayed.size, imt.size);
+        replayed.validate_invariants().unwrap();
+
+        // An empty log replays into an empty IMT.
+        let empty = Imt::<_, [u8; 32], [u8; 32]>::replay(Keccak::v256, &[][..]).unwrap();
+        assert_eq!(
+            empty.root,
+            Imt::<_, [u8; 32], [u8; 32]>::new(Keccak::v256).root
+        );
+
+        // A missing mutation aborts the replay at the next one.
+        let mut bytes = bincode::serialize(&Entry::Mutate(&mutations[0])).unwrap();
+        bytes.extend(bincode::serialize(&Entry::Mutate(&mutations[2])).unwrap());
+        let res = Imt::<_, [u8; 32], [u8; 32]>::replay(Keccak::v256, &bytes[..]);
+        assert!(matches!(res, Err(e) if e.to_string()
+            == "oplog mutation 1 is invalid: IMTMutate.old_root is stale"));
+
+        // So does a truncated mutation.
+        let bytes = bincode::serialize(&Entry::Mutate(&mutations[0])).unwrap();
+        let res = Imt::<_, [u8; 32], [u8; 32]>::replay(Keccak::v256, &bytes[..bytes.len() - 1]);
+        assert!(
+            matches!(res, Err(e) if e.to_string().starts_with("oplog mutation 0 can not be read"))
+        );
+    }
+
+    #[test]
+    fn test_merge_logged() {
+        let mut imt = Imt::new(Keccak::v256).with_oplog(SharedBuffer::default());
+        imt.insert_node([1; 32], [42; 32]);
+
+        let mut other = Imt::new(Keccak::v256);
+        other.insert_node([2; 32], [42; 32]);
+
+        let res = imt.merge(other);
+        assert!(
+            matches!(res, Err(e) if e.to_string() == "merged nodes can not be written to the oplog")
+        );
+    }
+
+    #[test]
+    fn test_replay_clear() {
+        let log = SharedBuffer::default();
+        let mut imt = Imt::new(Keccak::v256).with_oplog(log.clone());
+        for i in 1..10 {
+            imt.insert_node([i; 32], [42; 32]);
+        }
+
+        // The clearing is logged, the mutations after it replay from the zero node alone.
+        imt.clear();
+        for i in 5..8 {
+            imt.insert_node([i; 32], [43; 32]);
+        }
+        imt.update_node([6; 32], [44; 32]);
+
+        let bytes = log.0.lock().unwrap().clone();
+        let replayed = Imt::<_, [u8; 32], [u8; 32]>::replay(Keccak::v256, &bytes[..]).unwrap();
+        assert_eq!(replayed.root, imt.root);
+        assert_eq!(replayed.size, imt.size);
+        replayed.validate_invariants().unwrap();
+    }
+}
@@ -0,0 +1,462 @@
+//! Level hashes storage spilling to disk, for IMTs too large to keep their hashes in memory.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
+    mem::size_of,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::{ensure, Result};
+
+use crate::Hash;
+
+/// Number of copies of paged storages made by the current process, to name their directories.
+static COPIES: AtomicU64 = AtomicU64::new(0);
+
+/// Configuration of the paged level hashes storage.
+#[derive(Debug, Clone)]
+pub struct PagedConfig {
+    /// Directory holding one file per level.
+    pub dir: PathBuf,
+    /// Maximum number of bytes of hashes kept in memory.
+    pub ram_budget: usize,
+    /// Number of hashes per page.
+    pub page_size: usize,
+}
+
+impl PagedConfig {
+    /// Default number of hashes per page, i.e. 4 KiB pages.
+    pub const DEFAULT_PAGE_SIZE: usize = 128;
+
+    /// Creates a new configuration storing the level files in `dir` and keeping at most
+    /// `ram_budget` bytes of hashes in memory.
+    pub fn new(dir: impl Into<PathBuf>, ram_budget: usize) -> Self {
+        Self {
+            dir: dir.into(),
+            ram_budget,
+            page_size: Self::DEFAULT_PAGE_SIZE,
+        }
+    }
+
+    /// Sets the number of hashes per page.
+    pub fn with_page_size(self, page_size: usize) -> Self {
+        Self { page_size, ..self }
+    }
+
+    /// Returns the number of bytes of a page.
+    fn page_bytes(&self) -> usize {
+        self.page_size * size_of::<Hash>()
+    }
+
+    /// Returns the position of `index` in its page.
+    fn offset(&self, index: u64) -> usize {
+        (index % self.page_size as u64) as _
+    }
+}
+
+/// Level hashes stored in one file per level and read through an LRU cache of pages.
+///
+/// Like the dense storage, every level is filled from its start without any hole. The top
+/// levels, touched by every operation, stay in the cache while the lower and larger levels are
+/// mostly read from and written to disk.
+#[derive(Debug)]
+pub(crate) struct PagedHashes {
+    config: PagedConfig,
+    lens: Vec<u64>,
+    pages: Mutex<Pages>,
+}
+
+/// Pages cached in memory.
+#[derive(Debug, Default)]
+struct Pages {
+    files: Vec<File>,
+    cache: HashMap<(u8, u64), Page>,
+    lru: BTreeMap<u64, (u8, u64)>,
+    tick: u64,
+}
+
+/// Page of hashes of a level.
+#[derive(Debug)]
+struct Page {
+    hashes: Vec<Hash>,
+    dirty: bool,
+    tick: u64,
+}
+
+impl PagedHashes {
+    /// Creates an empty paged storage, creating its directory if needed.
+    pub fn create(config: PagedConfig) -> Result<Self> {
+        ensure!(config.page_size > 0, "page_size must not be zero");
+        ensure!(
+            config.ram_budget >= config.page_bytes(),
+            "ram_budget must hold at least one page"
+        );
+        fs::create_dir_all(&config.dir)?;
+
+        Ok(Self {
+            config,
+            lens: Vec::new(),
+            pages: Default::default(),
+        })
+    }
+
+    /// Returns the hash at `index` in the given `level`, if any.
+    pub fn get(&self, level: u8, index: u64) -> Option<Hash> {
+        if index >= *self.lens.get(level as usize)? {
+            return None;
+        }
+
+        let mut pages = self.pages.lock().expect("paged hashes lock is poisoned");
+        let page = pages.page(&self.config, level, index);
+        Some(page.hashes[self.config.offset(index)])
+    }
+
+    /// Sets the hash at `index` in the given `level`, which is at most one past its last hash.
+    pub fn set(&mut self, level: u8, index: u64, hash: Hash) {
+        if self.lens.len() <= level as usize {
+            self.lens.resize(level as usize + 1, 0);
+        }
+
+        let len = &mut self.lens[level as usize];
+        assert!(index <= *len, "paged hashes can not hold holes");
+        *len = (*len).max(index + 1);
+
+        let pages = self.pages.get_mut().expect("paged hashes lock is poisoned");
+        let page = pages.page(&self.config, level, index);
+        page.hashes[self.config.offset(index)] = hash;
+        page.dirty = true;
+    }
+
+    /// Drops the hashes past `width(level)` in each level up to `depth` and every level above,
+    /// returning the number of dropped hashes.
+    pub fn prune(&mut self, depth: u8, width: impl Fn(u8) -> u64) -> usize {
+        let levels = (depth as usize + 1).min(self.lens.len());
+        let mut pruned: u64 = self.lens.drain(levels..).sum();
+        for (level, len) in self.lens.iter_mut().enumerate() {
+            let kept = (*len).min(width(level as u8));
+            pruned += *len - kept;
+            *len = kept;
+        }
+        let pruned = pruned as usize;
+
+        if pruned > 0 {
+            self.truncate_files();
+        }
+        pruned
+    }
+
+    /// Returns the number of hashes stored in each level.
+    pub fn level_lens(&self) -> Vec<u64> {
+        self.lens.clone()
+    }
+
+    /// Returns an estimate of the heap memory used by the cached pages, in bytes.
+    pub fn heap_size(&self) -> usize {
+        let pages = self.pages.lock().expect("paged hashes lock is poisoned");
+        pages.cache.len() * (self.config.page_bytes() + size_of::<((u8, u64), Page)>() + 1)
+            + pages.lru.len() * size_of::<(u64, (u8, u64))>()
+    }
+
+    /// Replaces all the hashes with the given `levels`.
+    pub fn replace(&mut self, levels: HashMap<u8, HashMap<u64, Hash>>) {
+        self.lens.clear();
+        self.truncate_files();
+
+        let mut levels: Vec<_> = levels.into_iter().collect();
+        levels.sort_unstable_by_key(|(level, _)| *level);
+        for (level, level_hashes) in levels {
+            let mut level_hashes: Vec<_> = level_hashes.into_iter().collect();
+            level_hashes.sort_unstable_by_key(|(index, _)| *index);
+            for (index, hash) in level_hashes {
+                self.set(level, index, hash);
+            }
+        }
+    }
+
+    /// Writes the dirty cached pages to disk.
+    pub fn flush(&self) {
+        let mut pages = self.pages.lock().expect("paged hashes lock is poisoned");
+        let Pages { files, cache, .. } = &mut *pages;
+        for (&(level, page), cached) in cache.iter_mut().filter(|(_, page)| page.dirty) {
+            write_page(
+                &self.config,
+                &mut files[level as usize],
+                page,
+                &cached.hashes,
+            );
+            cached.dirty = false;
+        }
+    }
+
+    /// Drops the cached pages and truncates the level files to the current level lengths.
+    fn truncate_files(&mut self) {
+        let pages = self.pages.get_mut().expect("paged hashes lock is poisoned");
+        pages.flush_and_clear(&self.config);
+
+        for (level, file) in pages.files.iter().enumerate() {
+            let len = self.lens.get(level).copied().unwrap_or_default();
+            file.set_len(len * size_of::<Hash>() as u64)
+                .expect("failed to truncate hashes file");
+        }
+    }
+}
+
+impl Pages {
+    /// Returns the cached page holding `index` in the given `level`, reading it from disk (and
+    /// evicting the least recently used pages) if needed.
+    fn page(&mut self, config: &PagedConfig, level: u8, index: u64) -> &mut Page {
+        let key = (level, index / config.page_size as u64);
+        self.tick += 1;
+        let tick = self.tick;
+
+        if let Some(cached) = self.cache.get(&key) {
+            self.lru.remove(&cached.tick);
+        } else {
+            let capacity = config.ram_budget / config.page_bytes();
+            while self.cache.len() >= capacity {
+                let (_, evicted) = self.lru.pop_first().expect("cached pages are in the lru");
+                let page = self.cache.remove(&evicted).expect("lru pages are cached");
+                if page.dirty {
+                    let file = &mut self.files[evicted.0 as usize];
+                    write_page(config, file, evicted.1, &page.hashes);
+                }
+            }
+
+            let hashes = read_page(config, self.file(&config.dir, level), key.1);
+            self.cache.insert(
+                key,
+                Page {
+                    hashes,
+                    dirty: false,
+                    tick,
+                },
+            );
+        }
+
+        self.lru.insert(tick, key);
+        let page = self.cache.get_mut(&key).expect("page is cached");
+        page.tick = tick;
+        page
+    }
+
+    /// Returns the file of the given `level`, creating it (and the ones below) if needed.
+    fn file(&mut self, dir: &Path, level: u8) -> &mut File {
+        while self.files.len() <= level as usize {
+            let path = level_path(dir, self.files.len() as _);
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)
+                .expect("failed to create hashes file");
+            self.files.push(file);
+        }
+
+        &mut self.files[level as usize]
+    }
+
+    /// Writes the dirty pages to disk and drops every cached page.
+    fn flush_and_clear(&mut self, config: &PagedConfig) {
+        for ((level, page), cached) in self.cache.drain() {
+            if cached.dirty {
+                write_page(
+                    config,
+                    &mut self.files[level as usize],
+                    page,
+                    &cached.hashes,
+                );
+            }
+        }
+        self.lru.clear();
+    }
+}
+
+impl Clone for PagedHashes {
+    /// Copies the level files in a new directory next to the original one.
+    fn clone(&self) -> Self {
+        self.flush();
+
+        let copy = COPIES.fetch_add(1, Ordering::Relaxed);
+        let mut dir = self.config.dir.clone().into_os_string();
+        dir.push(format!("-copy-{}-{copy}", std::process::id()));
+        let config = PagedConfig {
+            dir: dir.into(),
+            ..self.config.clone()
+        };
+
+        let mut clone = Self::create(config).expect("failed to create hashes directory");
+        clone.lens = self.lens.clone();
+
+        let levels = self
+            .pages
+            .lock()
+            .expect("paged hashes lock is poisoned")
+            .files
+            .len();
+        let clone_pages = clone
+            .pages
+            .get_mut()
+            .expect("paged hashes lock is poisoned");
+        for level in 0..levels as u8 {
+            clone_pages.file(&clone.config.dir, level);
+            fs::copy(
+                level_path(&self.config.dir, level),
+                level_path(&clone.config.dir, level),
+            )
+            .expect("failed to copy hashes file");
+        }
+
+        clone
+    }
+}
+
+impl Drop for PagedHashes {
+    /// Removes the level files, which are only meaningful to this storage, and the directory if
+    /// it is left empty.
+    fn drop(&mut self) {
+        let Ok(pages) = self.pages.get_mut() else {
+            return;
+        };
+        for level in 0..pages.files.len() {
+            let _ = fs::remove_file(level_path(&self.config.dir, level as _));
+        }
+        let _ = fs::remove_dir(&self.config.dir);
+    }
+}
+
+/// Reads the given `page` from a level `file`, the hashes past its end being zeroed.
+fn read_page(config: &PagedConfig, file: &mut File, page: u64) -> Vec<Hash> {
+    let mut bytes = vec![0; config.page_bytes()];
+    file.seek(SeekFrom::Start(page * bytes.len() as u64))
+        .expect("failed to seek hashes page");
+
+    let mut read = 0;
+    while read < bytes.len() {
+        match file.read(&mut bytes[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => panic!("failed to read hashes page: {e}"),
+        }
+    }
+
+    bytes
+        .chunks_exact(size_of::<Hash>())
+        .map(|hash| hash.try_into().expect("chunk is a hash"))
+        .collect()
+}
+
+/// Writes the `hashes` of the given `page` to a level `file`.
+fn write_page(config: &PagedConfig, file: &mut File, page: u64, hashes: &[Hash]) {
+    file.seek(SeekFrom::Start(page * config.page_bytes() as u64))
+        .expect("failed to seek hashes page");
+    file.write_all(hashes.as_flattened())
+        .expect("failed to write hashes page");
+}
+
+/// Returns the path of the file holding the given `level` hashes.
+fn level_path(dir: &Path, level: u8) -> PathBuf {
+    dir.join(format!("level-{level}.bin"))
+}
+
+#[cfg(test)]
+mod tests {
+    use tiny_keccak::Keccak;
+
+    use super::*;
+    use crate::circuits::{imt::Imt, keys::U256};
+
+    /// Returns a configuration using tiny pages in a directory unique to the given `test`.
+    fn config(test: &str) -> PagedConfig {
+        let dir = std::env::temp_dir().join(format!("imt-{test}-{}", std::process::id()));
+        PagedConfig::new(dir, 4 * 2 * size_of::<Hash>()).with_page_size(2)
+    }
+
+    #[test]
+    fn test_create_invalid() {
+        let res = PagedHashes::create(config("create-invalid").with_page_size(0));
+        assert!(matches!(res, Err(e) if e.to_string() == "page_size must not be zero"));
+
+        let res = PagedHashes::create(config("create-invalid").with_page_size(16));
+        assert!(matches!(res, Err(e) if e.to_string() == "ram_budget must hold at least one page"));
+    }
+
+    #[test]
+    fn test_matches_in_memory() {
+        let mut paged = Imt::new_paged(Keccak::v256, config("matches-in-memory")).unwrap();
+        let mut imt = Imt::new(Keccak::v256);
+
+        for i in 1..=200_u64 {
+            // Distinct non zero keys, inserted out of order.
+            let key = U256::from(i * 7919 % 1009);
+
+            let old_root = paged.root;
+            let insert = paged.insert_node(key, key);
+            imt.insert_node(key, key);
+            assert_eq!(paged.root, imt.root);
+            assert_eq!(insert.verify(Keccak::v256, old_root).unwrap(), paged.root);
+
+            if i % 3 == 0 {
+                let old_root = paged.root;
+                let update = paged.update_node(key, Default::default());
+                imt.update_node(key, Default::default());
+                assert_eq!(update.verify(Keccak::v256, old_root).unwrap(), paged.root);
+            }
+        }
+
+        // Every sibling path is read through the tiny page cache.
+        for index in 0..paged.size {
+            assert_eq!(paged.siblings_cached(index), imt.siblings_cached(index));
+        }
+        assert_eq!(paged.stats().hashes_per_level, imt.stats().hashes_per_level);
+        paged.validate_invariants().unwrap();
+
+        // A copy lives on its own files.
+        let mut copy = paged.clone();
+        paged.rebuild();
+        assert_eq!(paged.root, imt.root);
+        copy.update_node(Default::default(), U256::from(1_u64));
+        assert_ne!(copy.root, imt.root);
+        copy.validate_invariants().unwrap();
+
+        // Writing to the copy leaves the hashes of the original untouched.
+        assert_eq!(paged.root, imt.root);
+        for index in 0..paged.size {
+            assert_eq!(paged.siblings_cached(index), imt.siblings_cached(index));
+        }
+        paged.validate_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_clear() {
+        let config = config("clear");
+        let dir = config.dir.clone();
+        let mut paged = Imt::new_paged(Keccak::v256, config).unwrap();
+        for i in 1..=50_u64 {
+            paged.insert_node(U256::from(i), U256::from(i));
+        }
+
+        // The hashes written to disk are dropped along with the nodes.
+        paged.clear();
+        assert_eq!(paged.root, Imt::<_, U256, U256>::new(Keccak::v256).root);
+        for level in 1..=6 {
+            let len = fs::metadata(level_path(&dir, level)).unwrap().len();
+            assert_eq!(len, 0);
+        }
+        paged.validate_invariants().unwrap();
+
+        let mut imt = Imt::new(Keccak::v256);
+        for i in [7_u64, 3, 9] {
+            paged.insert_node(U256::from(i), U256::from(i));
+            imt.insert_node(U256::from(i), U256::from(i));
+            assert_eq!(paged.root, imt.root);
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! `Imt` persisting its nodes through an asynchronous store, e.g. a database client.
+
+use std::future::Future;
+
+use anyhow::Result;
+
+use super::{
+    imt::Imt,
+    inclusion::IMTInclusion,
+    mutate::IMTMutate,
+    node::{Hashor, IMTNode, Key, Value},
+};
+
+/// Asynchronous store of the nodes of an IMT, by index.
+pub trait AsyncImtStore<K: Key, V: Value> {
+    /// Writes the `(index, node)` pairs of a single mutation at once.
+    fn write_nodes(
+        &mut self,
+        nodes: &[(u64, IMTNode<K, V>)],
+    ) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// `Imt` held in memory, each mutation writing the nodes it changes to a `store` in a single
+/// call.
+///
+/// Mutations are persisted before being applied in memory: if the store fails, the IMT is left
+/// untouched and the mutation can be retried. The store is expected to already hold the nodes
+/// of the IMT it is created with.
+///
+/// Proofs are generated from memory, identical to the ones of the underlying `Imt`.
+#[derive(Debug)]
+pub struct AsyncImt<H: Hashor, K: Key, V: Value, S> {
+    imt: Imt<H, K, V>,
+    store: S,
+}
+
+impl<H: Hashor, K: Key, V: Value, S: AsyncImtStore<K, V>> AsyncImt<H, K, V, S> {
+    /// Persists the mutations of `imt` through `store`.
+    pub fn new(imt: Imt<H, K, V>, store: S) -> Self {
+        Self { imt, store }
+    }
+
+    /// Returns the IMT held in memory.
+    pub fn imt(&self) -> &Imt<H, K, V> {
+        &self.imt
+    }
+
+    /// Returns the store.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Returns the IMT and the store.
+    pub fn into_parts(self) -> (Imt<H, K, V>, S) {
+        (self.imt, self.store)
+    }
+
+    /// Same as `Imt::try_insert_node`, the inserted node and the updated ln node being written
+    /// to the store first. Also fails, instead of panicking, if the key already exists.
+    pub async fn insert_node(&mut self, key: K, value: V) -> Result<IMTMutate<K, V>> {
+        let nodes = self.imt.insert_delta(&key, value)?;
+        self.store.write_nodes(&nodes).await?;
+
+        self.imt.try_insert_node(key, value)
+    }
+
+    /// Same as `Imt::update_node`, the updated node being written to the store first. Fails,
+    /// instead of panicking, if the node does not exist.
+    pub async fn update_node(&mut self, key: K, value: V) -> Result<IMTMutate<K, V>> {
+        let (_, update) = self.imt.preview_update(&key, value)?;
+        self.store.write_nodes(&update.node_delta()).await?;
+
+        Ok(self.imt.update_node(key, value))
+    }
+
+    /// See `Imt::prove_membership`.
+    pub fn prove_membership(&self, key: &K) -> Result<IMTInclusion<K, V>> {
+        self.imt.prove_membership(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use anyhow::bail;
+    use tiny_keccak::Keccak;
+
+    use super::*;
+
+    /// In memory store yielding to the executor before each write, and failing the writes whose
+    /// number is in `failures`.
+    #[derive(Default)]
+    struct MockStore {
+        nodes: BTreeMap<u64, Vec<u8>>,
+        writes: u64,
+        failures: Vec<u64>,
+    }
+
+    impl AsyncImtStore<[u8; 32], [u8; 32]> for MockStore {
+        async fn write_nodes(
+            &mut self,
+            nodes: &[(u64, IMTNode<[u8; 32], [u8; 32]>)],
+        ) -> Result<()> {
+            for _ in 0..3 {
+                tokio::task::yield_now().await;
+            }
+
+            self.writes += 1;
+            if self.failures.contains(&self.writes) {
+                bail!("write {} failed", self.writes);
+            }
+            for (index, node) in nodes {
+                self.nodes.insert(*index, bincode::serialize(node).unwrap());
+            }
+            Ok(())
+        }
+    }
+
+    /// Returns the nodes of `imt` serialized by index, as written to a `MockStore`.
+    fn nodes_of(imt: &Imt<Keccak, [u8; 32], [u8; 32]>) -> BTreeMap<u64, Vec<u8>> {
+        (0..imt.size)
+            .map(|index| {
+                let node = imt.node_by_index(index).unwrap();
+                (index, bincode::serialize(node).unwrap())
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_async_imt() {
+        let imt = Imt::new(Keccak::v256);
+        let store = MockStore {
+            nodes: nodes_of(&imt),
+            ..Default::default()
+        };
+        let mut async_imt = AsyncImt::new(imt, store);
+        let mut sync_imt = Imt::new(Keccak::v256);
+
+        // Mutations and proofs are identical to the synchronous ones.
+        for i in 1..=10 {
+            let insert = async_imt.insert_node([i; 32], [42; 32]).await.unwrap();
+            assert_eq!(
+                bincode::serialize(&insert).unwrap(),
+                bincode::serialize(&sync_imt.insert_node([i; 32], [42; 32])).unwrap()
+            );
+
+            let update = async_imt.update_node([i / 2; 32], [i; 32]).await.unwrap();
+            assert_eq!(
+                bincode::serialize(&update).unwrap(),
+                bincode::serialize(&sync_imt.update_node([i / 2; 32], [i; 32])).unwrap()
+            );
+        }
+        assert_eq!(async_imt.imt().root, sync_imt.root);
+        assert_eq!(
+            bincode::serialize(&async_imt.prove_membership(&[5; 32]).unwrap()).unwrap(),
+            bincode::serialize(&sync_imt.prove_membership(&[5; 32]).unwrap()).unwrap()
+        );
+        assert_eq!(async_imt.store().nodes, nodes_of(async_imt.imt()));
+
+        // Invalid mutations fail before reaching the store.
+        let writes = async_imt.store().writes;
+        assert!(async_imt.insert_node([1; 32], [42; 32]).await.is_err());
+        assert!(async_imt.update_node([11; 32], [42; 32]).await.is_err());
+        assert_eq!(async_imt.store().writes, writes);
+    }
+
+    #[tokio::test]
+    async fn test_async_imt_failed_write() {
+        let imt = Imt::new(Keccak::v256);
+        let store = MockStore {
+            nodes: nodes_of(&imt),
+            failures: vec![3, 4],
+            ..Default::default()
+        };
+        let mut async_imt = AsyncImt::new(imt, store);
+        async_imt.insert_node([1; 32], [42; 32]).await.unwrap();
+        async_imt.insert_node([3; 32], [42; 32]).await.unwrap();
+
+        // Failed writes leave both the IMT and the store untouched.
+        let (root, nodes) = (async_imt.imt().root, async_imt.store().nodes.clone());
+        let res = async_imt.insert_node([2; 32], [42; 32]).await;
+        assert!(matches!(res, Err(e) if e.to_string() == "write 3 failed"));
+        let res = async_imt.update_node([1; 32], [43; 32]).await;
+        assert!(matches!(res, Err(e) if e.to_string() == "write 4 failed"));
+
+        assert_eq!(async_imt.imt().root, root);
+        assert_eq!(async_imt.store().nodes, nodes);
+        async_imt.imt().validate_invariants().unwrap();
+
+        // The mutations can then be retried.
+        let insert = async_imt.insert_node([2; 32], [42; 32]).await.unwrap();
+        assert_eq!(
+            insert.verify(Keccak::v256, root).unwrap(),
+            async_imt.imt().root
+        );
+        async_imt.update_node([1; 32], [43; 32]).await.unwrap();
+
+        let (imt, store) = async_imt.into_parts();
+        assert_eq!(store.nodes, nodes_of(&imt));
+    }
+}
@@ -0,0 +1,216 @@
+//! Proof of the exact set of keys of an IMT lying in an interval, e.g. for audits.
+
+use anyhow::{ensure, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::Hash;
+
+use super::{
+    multiproof::IMTMultiProof,
+    node::{Hashor, IMTNode, Key, LeafLayout, NodeHasher, Value},
+    CommitmentScheme,
+};
+
+/// Proof that the nodes of `proof` are exactly the nodes whose key lies in `[start, end]`, along
+/// with the node preceding them.
+///
+/// The nodes are chained by their `next_key`, from the predecessor of `start` (the zero node if
+/// there is none) to the last node in the range, whose `next_key` is after `end` or zero.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct IMTRangeProof<K: Key, V: Value> {
+    pub start: K,
+    pub end: K,
+    pub proof: IMTMultiProof<K, V>,
+}
+
+impl<K: Key, V: Value> IMTRangeProof<K, V> {
+    /// Returns the nodes whose key lies in the range, in increasing key order.
+    pub fn nodes(&self) -> Vec<IMTNode<K, V>> {
+        self.chain().into_iter().skip(1).copied().collect()
+    }
+
+    /// Verifies that the proven nodes are exactly the nodes whose key lies in the range, in the
+    /// IMT of `size` nodes commited to in `root`.
+    pub fn verify<H: Hashor>(
+        &self,
+        hasher_factory: fn() -> H,
+        root: Hash,
+        size: u64,
+    ) -> Result<()> {
+        self.verify_with_layout(
+            hasher_factory,
+            CommitmentScheme::default(),
+            LeafLayout::default(),
+            root,
+            size,
+        )
+    }
+
+    /// Same as `verify` under the given commitment `scheme`, the leaves being hashed with the
+    /// given `layout`.
+    pub fn verify_with_layout<H: Hashor, N: NodeHasher<K, V>>(
+        &self,
+        hasher_factory: fn() -> H,
+        scheme: CommitmentScheme,
+        layout: N,
+        root: Hash,
+        size: u64,
+    ) -> Result<()> {
+        ensure!(
+            self.start.as_ref() <= self.end.as_ref(),
+            "IMTRangeProof.end is invalid"
+        );
+
+        self.proof
+            .verify_with_layout(hasher_factory, scheme, layout, root, size)?;
+
+        let chain = self.chain();
+
+        // The first node precedes the range, the zero node preceding any key.
+        let first = chain[0];
+        ensure!(
+            first.key.as_ref() < self.start.as_ref()
+                || *first.key.as_ref() == *K::default().as_ref(),
+            "IMTRangeProof.nodes does not start before the range"
+        );
+
+        // Any key between two chained nodes would be missing from the proof.
+        for window in chain.windows(2) {
+            ensure!(
+                window[0].next_key == window[1].key,
+                "IMTRangeProof.nodes is not a chain"
+            );
+            ensure!(
+                self.start.as_ref() <= window[1].key.as_ref()
+                    && window[1].key.as_ref() <= self.end.as_ref(),
+                "IMTRangeProof.nodes is out of the range"
+            );
+        }
+
+        let last = chain[chain.len() - 1];
+        ensure!(
+            last.next_key.as_ref() > self.end.as_ref()
+                || *last.next_key.as_ref() == *K::default().as_ref(),
+            "IMTRangeProof.nodes is incomplete"
+        );
+
+        Ok(())
+    }
+
+    /// Returns the proven nodes in increasing key order.
+    fn chain(&self) -> Vec<&IMTNode<K, V>> {
+        let mut chain: Vec<_> = self.proof.nodes.iter().collect();
+        chain.sort_unstable_by(|a, b| a.key.as_ref().cmp(b.key.as_ref()));
+        chain
+    }
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use tiny_keccak::Keccak;
+
+    use super::*;
+    use crate::circuits::imt::Imt;
+
+    /// Returns an IMT holding the even keys from 2 to 20.
+    fn imt() -> Imt<Keccak, [u8; 32], [u8; 32]> {
+        let mut imt = Imt::new(Keccak::v256);
+        for i in [12, 4, 20, 2, 8, 16, 6, 18, 10, 14] {
+            imt.insert_node([i; 32], [i; 32]);
+        }
+        imt
+    }
+
+    /// Returns the keys of the nodes in the range of `proof`.
+    fn keys(proof: &IMTRangeProof<[u8; 32], [u8; 32]>) -> Vec<u8> {
+        proof.nodes().iter().map(|node| node.key[0]).collect()
+    }
+
+    #[test]
+    fn test_range_proof() {
+        let imt = imt();
+
+        for (start, end, expected) in [
+            (5, 11, vec![6, 8, 10]),
+            (4, 10, vec![4, 6, 8, 10]),
+            (0, 4, vec![2, 4]),
+            (20, 20, vec![20]),
+            // Spanning the largest key, the last node links to the zero node.
+            (15, 255, vec![16, 18, 20]),
+            (0, 255, (1..=10).map(|i| i * 2).collect()),
+        ] {
+            let proof = imt.prove_range(&[start; 32], &[end; 32]);
+            proof.verify(Keccak::v256, imt.root, imt.size).unwrap();
+            assert_eq!(keys(&proof), expected);
+        }
+    }
+
+    #[test]
+    fn test_range_proof_empty() {
+        let imt = imt();
+
+        // Ranges within a gap, before the smallest key and after the largest one.
+        for (start, end) in [(5, 5), (1, 1), (21, 255)] {
+            let proof = imt.prove_range(&[start; 32], &[end; 32]);
+            proof.verify(Keccak::v256, imt.root, imt.size).unwrap();
+            assert!(proof.nodes().is_empty());
+        }
+
+        // Any range of an empty IMT.
+        let empty = Imt::<_, [u8; 32], [u8; 32]>::new(Keccak::v256);
+        let proof = empty.prove_range(&[0; 32], &[255; 32]);
+        proof.verify(Keccak::v256, empty.root, empty.size).unwrap();
+        assert!(proof.nodes().is_empty());
+    }
+
+    #[test]
+    fn test_range_proof_omitted_node() {
+        let imt = imt();
+        let proof = imt.prove_range(&[5; 32], &[11; 32]);
+
+        // Omit a node in the middle of the range, its first node and its last node.
+        for (key, err) in [
+            (8, "IMTRangeProof.nodes is not a chain"),
+            (6, "IMTRangeProof.nodes is not a chain"),
+            (10, "IMTRangeProof.nodes is incomplete"),
+        ] {
+            let forged = IMTRangeProof {
+                proof: imt.prove_membership_many(
+                    &keys(&proof)
+                        .into_iter()
+                        .filter(|k| *k != key)
+                        .map(|k| [k; 32])
+                        .chain([[4; 32]])
+                        .collect::<Vec<_>>(),
+                ),
+                ..proof.clone()
+            };
+            let res = forged.verify(Keccak::v256, imt.root, imt.size);
+            assert!(matches!(res, Err(e) if e.to_string() == err));
+        }
+
+        // A range proof claiming a wider range.
+        let forged = IMTRangeProof {
+            end: [13; 32],
+            ..proof.clone()
+        };
+        let res = forged.verify(Keccak::v256, imt.root, imt.size);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTRangeProof.nodes is incomplete"));
+
+        // The predecessor can not be in the range.
+        let forged = IMTRangeProof {
+            start: [4; 32],
+            ..proof.clone()
+        };
+        let res = forged.verify(Keccak::v256, imt.root, imt.size);
+        assert!(matches!(res, Err(e) if e.to_string()
+            == "IMTRangeProof.nodes does not start before the range"));
+
+        let forged = IMTRangeProof {
+            start: [12; 32],
+            ..proof
+        };
+        let res = forged.verify(Keccak::v256, imt.root, imt.size);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTRangeProof.end is invalid"));
+    }
+}
@@ -0,0 +1,246 @@
+//! Typed IMT root, not to be mistaken for a node hash or a level hash.
+
+use std::fmt;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::Hash;
+
+use super::hex::{parse_hash, Hex};
+
+/// Root commitment of an IMT, optionally along with the IMT `size` it was computed at.
+///
+/// Displayed as `0x` prefixed hex, and serialized as such in human readable formats.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Root {
+    hash: Hash,
+    size: Option<u64>,
+}
+
+impl Root {
+    /// Wraps the given root `hash`, computed at an unknown size.
+    pub fn new(hash: Hash) -> Self {
+        Self { hash, size: None }
+    }
+
+    /// Wraps the given root `hash`, computed at the given IMT `size`.
+    pub fn with_size(hash: Hash, size: u64) -> Self {
+        Self {
+            hash,
+            size: Some(size),
+        }
+    }
+
+    /// Returns the root hash.
+    pub fn as_hash(&self) -> &Hash {
+        &self.hash
+    }
+
+    /// Returns the IMT size the root was computed at, if known.
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+}
+
+impl From<Hash> for Root {
+    fn from(hash: Hash) -> Self {
+        Self::new(hash)
+    }
+}
+
+impl From<Root> for Hash {
+    fn from(root: Root) -> Self {
+        root.hash
+    }
+}
+
+impl AsRef<[u8]> for Root {
+    fn as_ref(&self) -> &[u8] {
+        &self.hash
+    }
+}
+
+impl fmt::Display for Root {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&Hex(&self.hash), f)
+    }
+}
+
+impl fmt::Debug for Root {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.size {
+            Some(size) => write!(f, "Root({self}, size: {size})"),
+            None => write!(f, "Root({self})"),
+        }
+    }
+}
+
+/// Root of an IMT before a mutation, as expected by its verifier.
+///
+/// Converts into a `Root` so that `IMTMutate::verify` accepts it, unlike a `NewRoot`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OldRoot(pub Hash);
+
+/// Root of an IMT after a mutation, as returned by its verifier.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NewRoot(pub Hash);
+
+impl NewRoot {
+    /// Returns the root as the old root of the next mutation.
+    pub fn into_old(self) -> OldRoot {
+        OldRoot(self.0)
+    }
+}
+
+impl From<Hash> for OldRoot {
+    fn from(hash: Hash) -> Self {
+        Self(hash)
+    }
+}
+
+impl From<Hash> for NewRoot {
+    fn from(hash: Hash) -> Self {
+        Self(hash)
+    }
+}
+
+impl From<OldRoot> for Hash {
+    fn from(root: OldRoot) -> Self {
+        root.0
+    }
+}
+
+impl From<NewRoot> for Hash {
+    fn from(root: NewRoot) -> Self {
+        root.0
+    }
+}
+
+impl From<OldRoot> for Root {
+    fn from(root: OldRoot) -> Self {
+        Self::new(root.0)
+    }
+}
+
+impl fmt::Debug for OldRoot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "OldRoot({})", Hex(&self.0))
+    }
+}
+
+impl fmt::Debug for NewRoot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NewRoot({})", Hex(&self.0))
+    }
+}
+
+/// Serialized form of a `Root`, the hash being a hex string in human readable formats.
+#[derive(Deserialize, Serialize)]
+struct RootRepr<T> {
+    hash: T,
+    size: Option<u64>,
+}
+
+impl Serialize for Root {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            RootRepr {
+                hash: self.to_string(),
+                size: self.size,
+            }
+            .serialize(serializer)
+        } else {
+            RootRepr {
+                hash: self.hash,
+                size: self.size,
+            }
+            .serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Root {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if !deserializer.is_human_readable() {
+            let RootRepr { hash, size } = RootRepr::<Hash>::deserialize(deserializer)?;
+            return Ok(Self { hash, size });
+        }
+
+        let RootRepr { hash, size } = RootRepr::<String>::deserialize(deserializer)?;
+        let Some(bytes) = parse_hash(&hash) else {
+            return Err(de::Error::invalid_value(
+                de::Unexpected::Str(&hash),
+                &"0x prefixed hash",
+            ));
+        };
+
+        Ok(Self { hash: bytes, size })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let mut hash = [0; 32];
+        hash[0] = 0xab;
+        hash[31] = 0x01;
+
+        let root = Root::new(hash);
+        let hex = format!("0xab{}01", "00".repeat(30));
+        assert_eq!(root.to_string(), hex);
+        assert_eq!(format!("{root:?}"), format!("Root({hex})"));
+        assert_eq!(
+            format!("{:?}", Root::with_size(hash, 5)),
+            format!("Root({hex}, size: 5)")
+        );
+
+        assert_eq!(Hash::from(root), hash);
+        assert_eq!(Root::from(hash), root);
+    }
+
+    #[test]
+    fn test_old_new_roots() {
+        let old = OldRoot::from([0xab; 32]);
+        let new = NewRoot::from([0xcd; 32]);
+        assert_eq!(Hash::from(old), [0xab; 32]);
+        assert_eq!(Hash::from(new), [0xcd; 32]);
+        assert_eq!(Root::from(old), Root::new([0xab; 32]));
+
+        // The new root of a mutation is the old root of the next one.
+        assert_eq!(new.into_old(), OldRoot([0xcd; 32]));
+        assert_eq!(
+            format!("{old:?}"),
+            format!("OldRoot(0x{})", "ab".repeat(32))
+        );
+        assert_eq!(
+            format!("{new:?}"),
+            format!("NewRoot(0x{})", "cd".repeat(32))
+        );
+    }
+
+    #[test]
+    fn test_serde() {
+        let root = Root::with_size([0xcd; 32], 7);
+
+        let json = serde_json::to_value(root).unwrap();
+        assert_eq!(json["hash"], format!("0x{}", "cd".repeat(32)));
+        assert_eq!(json["size"], 7);
+        assert_eq!(serde_json::from_value::<Root>(json).unwrap(), root);
+
+        let bytes = bincode::serialize(&root).unwrap();
+        assert_eq!(bincode::deserialize::<Root>(&bytes).unwrap(), root);
+
+        for hash in [
+            "cd".repeat(32),
+            format!("0x{}", "cd".repeat(31)),
+            format!("0x{}", "zz".repeat(32)),
+            format!("0x+f{}", "cd".repeat(31)),
+        ] {
+            let json = serde_json::json!({ "hash": hash, "size": null });
+            assert!(serde_json::from_value::<Root>(json).is_err());
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! Support of the `ruint` integers.
+//!
+//! `ruint::aliases::U256` stores its limbs in little endian, so its bytes do not sort as the
+//! integer does. `U256Key` holds the big endian bytes instead.
+
+use std::fmt;
+
+use ruint::aliases::U256;
+use serde::{Deserialize, Serialize};
+
+/// `U256` stored in big endian, so that keys are ordered numerically.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+pub struct U256Key([u8; 32]);
+
+/// `U256Key` used as a value.
+pub type U256Value = U256Key;
+
+impl U256Key {
+    /// Returns the integer.
+    pub fn get(&self) -> U256 {
+        U256::from_be_bytes(self.0)
+    }
+}
+
+impl AsRef<[u8]> for U256Key {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<U256> for U256Key {
+    fn from(value: U256) -> Self {
+        Self(value.to_be_bytes())
+    }
+}
+
+impl From<U256Key> for U256 {
+    fn from(key: U256Key) -> Self {
+        key.get()
+    }
+}
+
+impl fmt::Debug for U256Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "U256Key({})", self.get())
+    }
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use tiny_keccak::Keccak;
+
+    use super::*;
+    use crate::circuits::{imt::Imt, node::IMTNode};
+
+    /// Returns a node of key `key` linking to `next_key`.
+    fn node(key: U256, next_key: U256) -> IMTNode<U256Key, U256Value> {
+        IMTNode {
+            index: 1,
+            key: key.into(),
+            value: U256Key::default(),
+            next_key: next_key.into(),
+        }
+    }
+
+    #[test]
+    fn test_u256_keys_are_ordered_numerically() {
+        let mut imt = Imt::new(Keccak::v256);
+
+        // Keys whose little endian limbs would not sort numerically.
+        for key in [256_u64, 1, 1 << 32, u64::MAX, 2] {
+            let old_root = imt.root;
+            let mutate = imt.insert_node(
+                U256Key::from(U256::from(key)),
+                U256Key::from(U256::from(key)),
+            );
+            assert_eq!(mutate.verify(Keccak::v256, old_root).unwrap(), imt.root);
+        }
+        imt.insert_node(U256::MAX.into(), U256Key::default());
+        imt.validate_invariants().unwrap();
+
+        for (key, ln_key) in [
+            (U256::from(3), U256::from(2)),
+            (U256::from(257), U256::from(256)),
+            (U256::from(1_u64 << 33), U256::from(1_u64 << 32)),
+            (U256::from(1) << 64, U256::from(u64::MAX)),
+        ] {
+            assert_eq!(imt.low_nullifier(&key.into()).key.get(), ln_key);
+        }
+        assert_eq!(imt.last_key().map(|key| key.get()), Some(U256::MAX));
+
+        assert_eq!(U256Key::default().get(), U256::ZERO);
+        assert_eq!(
+            format!("{:?}", U256Key::from(U256::from(42))),
+            "U256Key(42)"
+        );
+    }
+
+    #[test]
+    fn test_is_ln_of_at_the_boundaries() {
+        let max = U256::MAX;
+        let half = U256::from(1_u64) << 255_usize;
+
+        assert!(node(half - U256::from(1), U256::ZERO).is_ln_of(&half.into()));
+        assert!(node(half, U256::ZERO).is_ln_of(&max.into()));
+        assert!(node(U256::ZERO, max).is_ln_of(&half.into()));
+        assert!(!node(half, max).is_ln_of(&max.into()));
+        assert!(!node(half, max).is_ln_of(&(half - U256::from(1)).into()));
+    }
+
+    #[cfg(feature = "proptest")]
+    proptest::proptest! {
+        #[test]
+        fn prop_is_ln_of_matches_numeric_order(
+            key: [u64; 4],
+            node_key: [u64; 4],
+            next_key: [u64; 4],
+        ) {
+            let (key, node_key, next_key) = (
+                U256::from_limbs(key),
+                U256::from_limbs(node_key),
+                U256::from_limbs(next_key),
+            );
+
+            proptest::prop_assert_eq!(
+                U256Key::from(key).cmp(&U256Key::from(node_key)),
+                key.cmp(&node_key)
+            );
+            proptest::prop_assert_eq!(
+                node(node_key, next_key).is_ln_of(&key.into()),
+                node_key < key && (key < next_key || next_key == U256::ZERO)
+            );
+        }
+    }
+}
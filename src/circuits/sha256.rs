@@ -0,0 +1,123 @@
+//! SHA-256 hashor backed by the `sha2` crate.
+
+use sha2::Digest;
+use std::mem::size_of;
+
+use tiny_keccak::Hasher;
+
+use crate::{circuits::node::Hashor, Hash};
+
+/// SHA-256 hashor, usable as an IMT hasher factory with `Sha256::new`.
+#[derive(Debug, Clone, Default)]
+pub struct Sha256(sha2::Sha256);
+
+impl Sha256 {
+    /// Creates a new SHA-256 hashor.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Hashor for Sha256 {
+    const OUTPUT_LEN: usize = 32;
+
+    fn finalize_reset(&mut self, output: &mut [u8]) {
+        output.copy_from_slice(&self.0.finalize_reset()[..output.len()]);
+    }
+}
+
+const _: () = assert!(<Sha256 as Hashor>::OUTPUT_LEN == size_of::<Hash>());
+
+impl Hasher for Sha256 {
+    fn update(&mut self, input: &[u8]) {
+        self.0.update(input);
+    }
+
+    fn finalize(self, output: &mut [u8]) {
+        output.copy_from_slice(&self.0.finalize()[..output.len()]);
+    }
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use super::*;
+    use crate::circuits::{imt::Imt, mutate::verify_many, node::LeafLayout, CommitmentScheme};
+
+    #[test]
+    fn test_matches_sha2() {
+        let mut hash = [0; 32];
+        let mut hashor = Sha256::new();
+        hashor.update(b"imt");
+        hashor.update(b"-rs");
+        hashor.finalize(&mut hash);
+
+        assert_eq!(hash, <[u8; 32]>::from(sha2::Sha256::digest(b"imt-rs")));
+
+        // A reset hashor starts over.
+        let mut hashor = Sha256::new();
+        hashor.update(b"imt");
+        hashor.finalize_reset(&mut hash);
+        hashor.update(b"imt-rs");
+        hashor.finalize_reset(&mut hash);
+        assert_eq!(hash, <[u8; 32]>::from(sha2::Sha256::digest(b"imt-rs")));
+    }
+
+    #[test]
+    fn test_verify() {
+        let mut imt = Imt::new(Sha256::new);
+        for i in 1..20 {
+            let old_root = imt.root;
+            let mutate = imt.insert_node([i; 32], [42; 32]);
+            assert_eq!(mutate.verify(Sha256::new, old_root).unwrap(), imt.root);
+        }
+    }
+
+    #[test]
+    fn test_verify_many() {
+        let mut imt = Imt::new(Sha256::new);
+        let mut mutations = vec![];
+        for i in 1..20 {
+            mutations.push(imt.insert_node([i; 32], [42; 32]));
+            mutations.push(imt.update_node([i / 2; 32], [i; 32]));
+        }
+
+        // The single reset hashor of the batch computes the same roots as new ones.
+        for (mutate, res) in mutations.iter().zip(verify_many(&mutations, Sha256::new)) {
+            let root = mutate.verify(Sha256::new, mutate.old_root()).unwrap();
+            assert_eq!(res.unwrap(), root);
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "bind-index"))]
+    fn test_semaphore_layout_vector() {
+        // Root of the tree holding ([1; 32]; [10; 32]) then ([2; 32]; [11; 32]), each leaf hashing
+        // `key || next_key || value` and the size being folded in big endian, computed with an
+        // independent SHA-256 implementation.
+        const EXPECTED_ROOT: Hash = [
+            0x03, 0xa3, 0x60, 0x27, 0x03, 0x47, 0x69, 0x36, 0xc4, 0xea, 0xb9, 0xad, 0xc7, 0x00,
+            0xb0, 0x7c, 0xe9, 0x1a, 0x7e, 0xd5, 0xe1, 0x99, 0xdc, 0x04, 0x92, 0xa7, 0x24, 0x6d,
+            0x31, 0x82, 0x2f, 0x04,
+        ];
+
+        let scheme = CommitmentScheme::default();
+        let layout = LeafLayout::SEMAPHORE;
+        let mut imt = Imt::new(Sha256::new).with_layout(layout);
+        for (key, value) in [([1; 32], [10; 32]), ([2; 32], [11; 32])] {
+            let old_root = imt.root;
+            let old_size = imt.size;
+            let mutate = imt.insert_node(key, value);
+
+            let res = mutate.verify_with_layout(Sha256::new, scheme, layout, old_root, old_size);
+            assert_eq!(res.unwrap(), imt.root);
+            assert!(mutate.verify(Sha256::new, old_root).is_err());
+        }
+        imt.validate_invariants().unwrap();
+
+        assert_eq!(imt.root, EXPECTED_ROOT);
+        assert_ne!(
+            Imt::<_, [u8; 32], [u8; 32]>::new(Sha256::new).root,
+            imt.root
+        );
+    }
+}
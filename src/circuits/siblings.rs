@@ -0,0 +1,459 @@
+use std::fmt;
+
+use anyhow::{ensure, Result};
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    ser::SerializeTuple,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_big_array::BigArray;
+use smallvec::SmallVec;
+
+use crate::Hash;
+
+/// Maximum number of levels a sibling path can span (one per bit of a `u64` index).
+pub(crate) const MAX_LEVELS: usize = u64::BITS as usize;
+
+/// Sibling path collected internally by the IMT, kept inline for up to 32 levels.
+pub type SiblingsVec = SmallVec<[Option<Hash>; 32]>;
+
+/// A sibling path, ordered from the leaf level up to the root.
+pub trait Siblings {
+    /// Returns an iterator over the siblings, from the leaf level up to the root.
+    fn levels(&self) -> impl Iterator<Item = Option<&Hash>>;
+}
+
+impl Siblings for [Option<Hash>] {
+    fn levels(&self) -> impl Iterator<Item = Option<&Hash>> {
+        self.iter().map(Option::as_ref)
+    }
+}
+
+impl Siblings for Vec<Option<Hash>> {
+    fn levels(&self) -> impl Iterator<Item = Option<&Hash>> {
+        self.as_slice().levels()
+    }
+}
+
+impl Siblings for SiblingsVec {
+    fn levels(&self) -> impl Iterator<Item = Option<&Hash>> {
+        self.as_slice().levels()
+    }
+}
+
+/// Compact representation of a sibling path.
+///
+/// The presence of each level is stored in a bitmap (bit `i` set means level `i` has a sibling)
+/// and only the present hashes are kept, densely packed in level order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactSiblings {
+    len: u8,
+    bitmap: u64,
+    hashes: Vec<Hash>,
+}
+
+impl CompactSiblings {
+    /// Returns the number of levels in the path.
+    pub fn len(&self) -> usize {
+        self.len as _
+    }
+
+    /// Returns `true` if the path has no level.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Siblings for CompactSiblings {
+    fn levels(&self) -> impl Iterator<Item = Option<&Hash>> {
+        let mut hashes = self.hashes.iter();
+        (0..self.len).map(move |level| {
+            if self.bitmap & (1 << level) != 0 {
+                hashes.next()
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl From<&[Option<Hash>]> for CompactSiblings {
+    fn from(siblings: &[Option<Hash>]) -> Self {
+        assert!(siblings.len() <= MAX_LEVELS, "too many siblings");
+
+        let mut bitmap = 0;
+        let mut hashes = Vec::new();
+        for (level, sibling) in siblings.iter().enumerate() {
+            if let Some(hash) = sibling {
+                bitmap |= 1 << level;
+                hashes.push(*hash);
+            }
+        }
+
+        Self {
+            len: siblings.len() as _,
+            bitmap,
+            hashes,
+        }
+    }
+}
+
+impl From<Vec<Option<Hash>>> for CompactSiblings {
+    fn from(siblings: Vec<Option<Hash>>) -> Self {
+        Self::from(siblings.as_slice())
+    }
+}
+
+impl From<CompactSiblings> for Vec<Option<Hash>> {
+    fn from(siblings: CompactSiblings) -> Self {
+        siblings.levels().map(|sibling| sibling.cloned()).collect()
+    }
+}
+
+/// Fixed capacity representation of a sibling path, holding up to `D` levels without any heap
+/// allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawFixedSiblings<D>")]
+pub struct FixedSiblings<const D: usize> {
+    len: u8,
+    #[serde(with = "BigArray")]
+    siblings: [Option<Hash>; D],
+}
+
+impl<const D: usize> FixedSiblings<D> {
+    /// Returns the number of levels in the path.
+    pub fn len(&self) -> usize {
+        self.len as _
+    }
+
+    /// Returns `true` if the path has no level.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<const D: usize> Default for FixedSiblings<D> {
+    fn default() -> Self {
+        Self {
+            len: 0,
+            siblings: [None; D],
+        }
+    }
+}
+
+impl<const D: usize> Siblings for FixedSiblings<D> {
+    fn levels(&self) -> impl Iterator<Item = Option<&Hash>> {
+        self.siblings[..self.len()].levels()
+    }
+}
+
+impl<const D: usize> TryFrom<&[Option<Hash>]> for FixedSiblings<D> {
+    type Error = anyhow::Error;
+
+    fn try_from(siblings: &[Option<Hash>]) -> Result<Self> {
+        ensure!(
+            siblings.len() <= D && siblings.len() <= MAX_LEVELS,
+            "siblings exceed FixedSiblings capacity"
+        );
+
+        let mut fixed = Self {
+            len: siblings.len() as _,
+            ..Default::default()
+        };
+        fixed.siblings[..siblings.len()].copy_from_slice(siblings);
+
+        Ok(fixed)
+    }
+}
+
+impl<const D: usize> TryFrom<Vec<Option<Hash>>> for FixedSiblings<D> {
+    type Error = anyhow::Error;
+
+    fn try_from(siblings: Vec<Option<Hash>>) -> Result<Self> {
+        Self::try_from(siblings.as_slice())
+    }
+}
+
+impl<const D: usize> From<FixedSiblings<D>> for Vec<Option<Hash>> {
+    fn from(siblings: FixedSiblings<D>) -> Self {
+        siblings.siblings[..siblings.len()].to_vec()
+    }
+}
+
+/// Unchecked wire form of `FixedSiblings`, validated before being accepted.
+#[derive(Deserialize)]
+struct RawFixedSiblings<const D: usize> {
+    len: u8,
+    #[serde(with = "BigArray")]
+    siblings: [Option<Hash>; D],
+}
+
+impl<const D: usize> TryFrom<RawFixedSiblings<D>> for FixedSiblings<D> {
+    type Error = &'static str;
+
+    fn try_from(raw: RawFixedSiblings<D>) -> Result<Self, Self::Error> {
+        let len = raw.len as usize;
+        if len > D {
+            return Err("FixedSiblings.len exceeds capacity");
+        }
+
+        if raw.siblings[len..].iter().any(Option::is_some) {
+            return Err("FixedSiblings.siblings has entries beyond len");
+        }
+
+        Ok(Self {
+            len: raw.len,
+            siblings: raw.siblings,
+        })
+    }
+}
+
+impl Serialize for CompactSiblings {
+    /// Serializes as a flat `(len, bitmap, hashes...)` tuple, the number of hashes being implied
+    /// by the bitmap.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(2 + self.hashes.len())?;
+        tuple.serialize_element(&self.len)?;
+        tuple.serialize_element(&self.bitmap)?;
+        for hash in &self.hashes {
+            tuple.serialize_element(hash)?;
+        }
+        tuple.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for CompactSiblings {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct CompactSiblingsVisitor;
+
+        impl<'de> Visitor<'de> for CompactSiblingsVisitor {
+            type Value = CompactSiblings;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a (len, bitmap, hashes...) tuple")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let len: u8 = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let bitmap: u64 = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+
+                if len as usize > MAX_LEVELS {
+                    return Err(de::Error::custom("CompactSiblings.len is too large"));
+                }
+
+                if (len as usize) < MAX_LEVELS && bitmap >> len != 0 {
+                    return Err(de::Error::custom(
+                        "CompactSiblings.bitmap has bits beyond len",
+                    ));
+                }
+
+                let count = bitmap.count_ones() as usize;
+                let mut hashes = Vec::with_capacity(count);
+                for i in 0..count {
+                    let hash = seq
+                        .next_element()?
+                        .ok_or_else(|| de::Error::invalid_length(2 + i, &self))?;
+                    hashes.push(hash);
+                }
+
+                Ok(CompactSiblings {
+                    len,
+                    bitmap,
+                    hashes,
+                })
+            }
+        }
+
+        deserializer.deserialize_tuple(2 + MAX_LEVELS, CompactSiblingsVisitor)
+    }
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use tiny_keccak::Keccak;
+
+    use super::*;
+    use crate::circuits::{
+        imt::Imt,
+        mutate::{CompactIMTMutate, IMTMutate, IMTMutateFixed},
+    };
+
+    /// Returns the siblings of the last inserted node of a 100 nodes tree, which are mostly `None`.
+    fn sparse_siblings() -> Vec<Option<Hash>> {
+        let mut imt = Imt::new(Keccak::v256);
+        (1..100).for_each(|i| {
+            imt.insert_node([i; 32], [42; 32]);
+        });
+
+        imt.siblings(&[99; 32])
+    }
+
+    #[test]
+    fn test_conversion_round_trip() {
+        let siblings = sparse_siblings();
+        assert!(siblings.iter().any(Option::is_none));
+        assert!(siblings.iter().any(Option::is_some));
+
+        let compact = CompactSiblings::from(siblings.clone());
+        assert_eq!(compact.len(), siblings.len());
+        assert!(compact.levels().eq(siblings.levels()));
+
+        let expanded: Vec<Option<Hash>> = compact.into();
+        assert_eq!(expanded, siblings);
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let compact = CompactSiblings::from(sparse_siblings());
+
+        let bytes = bincode::serialize(&compact).unwrap();
+        let decoded: CompactSiblings = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded, compact);
+
+        let json = serde_json::to_string(&compact).unwrap();
+        let decoded: CompactSiblings = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, compact);
+    }
+
+    #[test]
+    fn test_deserialize_invalid() {
+        let hash = format!("{:?}", [1u8; 32]);
+
+        // Bitmap announces two hashes but only one is provided.
+        let json = format!("[2,3,{hash}]");
+        let res = serde_json::from_str::<CompactSiblings>(&json);
+        assert!(res.is_err());
+
+        // Bitmap announces one hash but two are provided.
+        let json = format!("[2,1,{hash},{hash}]");
+        let res = serde_json::from_str::<CompactSiblings>(&json);
+        assert!(res.is_err());
+
+        // Bitmap has a bit set beyond the path length.
+        let json = format!("[1,2,{hash}]");
+        let res = serde_json::from_str::<CompactSiblings>(&json);
+        assert!(
+            matches!(res, Err(e) if e.to_string().starts_with("CompactSiblings.bitmap has bits beyond len"))
+        );
+
+        // Path is longer than what a `u64` index allows.
+        let res = serde_json::from_str::<CompactSiblings>("[65,0]");
+        assert!(
+            matches!(res, Err(e) if e.to_string().starts_with("CompactSiblings.len is too large"))
+        );
+    }
+
+    #[test]
+    fn test_compact_is_smaller() {
+        let siblings = sparse_siblings();
+        let compact = CompactSiblings::from(siblings.clone());
+
+        assert!(
+            bincode::serialize(&compact).unwrap().len()
+                < bincode::serialize(&siblings).unwrap().len()
+        );
+        assert!(
+            serde_json::to_string(&compact).unwrap().len()
+                < serde_json::to_string(&siblings).unwrap().len()
+        );
+    }
+
+    #[test]
+    fn test_verify_compact() {
+        let mut imt = Imt::new(Keccak::v256);
+        imt.insert_node([1; 32], [42; 32]);
+        imt.insert_node([2; 32], [42; 32]);
+
+        // Verifying the compact form of a mutation must give the same root as the expanded one.
+        let old_root = imt.root;
+        let insert = imt.insert_node([3; 32], [42; 32]);
+        let compact = CompactIMTMutate::from(insert);
+        assert_eq!(compact.verify(Keccak::v256, old_root).unwrap(), imt.root);
+
+        let old_root = imt.root;
+        let update = imt.update_node([2; 32], [43; 32]);
+        let compact = CompactIMTMutate::from(update);
+        assert_eq!(compact.verify(Keccak::v256, old_root).unwrap(), imt.root);
+
+        // Round trip the compact mutation through bincode and expand it back.
+        let bytes = bincode::serialize(&compact).unwrap();
+        let decoded: CompactIMTMutate<[u8; 32], [u8; 32]> = bincode::deserialize(&bytes).unwrap();
+        let expanded: IMTMutate<_, _> = decoded.into();
+        assert_eq!(expanded.verify(Keccak::v256, old_root).unwrap(), imt.root);
+    }
+
+    #[test]
+    fn test_fixed_conversion() {
+        let siblings = sparse_siblings();
+
+        // The path must fit in the fixed capacity.
+        let res = FixedSiblings::<4>::try_from(siblings.clone());
+        assert!(matches!(res, Err(e) if e.to_string() == "siblings exceed FixedSiblings capacity"));
+
+        let fixed = FixedSiblings::<32>::try_from(siblings.clone()).unwrap();
+        assert_eq!(fixed.len(), siblings.len());
+        assert!(fixed.levels().eq(siblings.levels()));
+
+        let expanded: Vec<Option<Hash>> = fixed.into();
+        assert_eq!(expanded, siblings);
+
+        // Round trip through bincode.
+        let bytes = bincode::serialize(&fixed).unwrap();
+        let decoded: FixedSiblings<32> = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded, fixed);
+    }
+
+    #[test]
+    fn test_fixed_deserialize_invalid() {
+        let mut fixed = FixedSiblings::<2>::try_from(vec![Some([1; 32])]).unwrap();
+
+        // Entries beyond `len` must be empty.
+        fixed.siblings[1] = Some([2; 32]);
+        let json = serde_json::to_string(&fixed).unwrap();
+        let res = serde_json::from_str::<FixedSiblings<2>>(&json);
+        assert!(
+            matches!(res, Err(e) if e.to_string().starts_with("FixedSiblings.siblings has entries beyond len"))
+        );
+
+        // `len` can not exceed the capacity.
+        fixed.len = 3;
+        let json = serde_json::to_string(&fixed).unwrap();
+        let res = serde_json::from_str::<FixedSiblings<2>>(&json);
+        assert!(
+            matches!(res, Err(e) if e.to_string().starts_with("FixedSiblings.len exceeds capacity"))
+        );
+    }
+
+    #[test]
+    fn test_verify_fixed() {
+        let mut imt = Imt::new(Keccak::v256);
+        for i in 1..20 {
+            imt.insert_node([i; 32], [42; 32]);
+        }
+
+        // Verifying the fixed form of a mutation must give the same root as the expanded one.
+        let old_root = imt.root;
+        let insert = imt.insert_node([20; 32], [42; 32]);
+        let fixed = IMTMutateFixed::<_, _, 8>::try_from(insert).unwrap();
+        assert_eq!(fixed.verify(Keccak::v256, old_root).unwrap(), imt.root);
+
+        let old_root = imt.root;
+        let update = imt.update_node([10; 32], [43; 32]);
+        let fixed = IMTMutateFixed::<_, _, 8>::try_from(update).unwrap();
+        assert_eq!(fixed.verify(Keccak::v256, old_root).unwrap(), imt.root);
+
+        // Round trip the fixed mutation through bincode and expand it back.
+        let bytes = bincode::serialize(&fixed).unwrap();
+        let decoded: IMTMutateFixed<[u8; 32], [u8; 32], 8> = bincode::deserialize(&bytes).unwrap();
+        let expanded: IMTMutate<_, _> = decoded.into();
+        assert_eq!(expanded.verify(Keccak::v256, old_root).unwrap(), imt.root);
+
+        // Proofs deeper than the fixed capacity are rejected.
+        let update = imt.update_node([10; 32], [44; 32]);
+        assert!(IMTMutateFixed::<_, _, 4>::try_from(update).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+use std::{collections::HashMap, mem::size_of};
+
+use crate::Hash;
+
+use super::paged::PagedHashes;
+
+/// Storage of the IMT level hashes.
+#[derive(Debug, Clone)]
+pub enum HashStorage {
+    /// Hashes are stored in a map per level, indexed by position.
+    Sparse(HashMap<u8, HashMap<u64, Hash>>),
+    /// Hashes are stored contiguously in a `Vec` per level, indexed by position.
+    ///
+    /// The IMT nodes are always stored at the contiguous `[0, size)` indices, so every level is
+    /// filled from its start without any hole.
+    Dense(Vec<Vec<Hash>>),
+    /// Hashes are stored contiguously in a file per level, read through a bounded cache of
+    /// pages.
+    Paged(PagedHashes),
+}
+
+impl HashStorage {
+    /// Returns the hash at `index` in the given `level`, if any.
+    pub fn get(&self, level: u8, index: u64) -> Option<Hash> {
+        match self {
+            HashStorage::Sparse(hashes) => hashes.get(&level)?.get(&index).copied(),
+            HashStorage::Dense(hashes) => hashes.get(level as usize)?.get(index as usize).copied(),
+            HashStorage::Paged(hashes) => hashes.get(level, index),
+        }
+    }
+
+    /// Sets the hash at `index` in the given `level`.
+    pub fn set(&mut self, level: u8, index: u64, hash: Hash) {
+        match self {
+            HashStorage::Sparse(hashes) => {
+                // Overwrite in place, as inserting may grow a full map even for a present index.
+                let level = hashes.entry(level).or_default();
+                match level.get_mut(&index) {
+                    Some(slot) => *slot = hash,
+                    None => {
+                        level.insert(index, hash);
+                    }
+                }
+            }
+            HashStorage::Dense(hashes) => {
+                let level = level as usize;
+                if hashes.len() <= level {
+                    hashes.resize_with(level + 1, Vec::new);
+                }
+
+                let level = &mut hashes[level];
+                let index = index as usize;
+                if index == level.len() {
+                    level.push(hash);
+                } else {
+                    level[index] = hash;
+                }
+            }
+            HashStorage::Paged(hashes) => hashes.set(level, index, hash),
+        }
+    }
+
+    /// Drops the hashes that are not part of an IMT of the given `depth` holding `size` leaves,
+    /// i.e. every hash above the top level or past the last hash of its level, and returns the
+    /// number of dropped hashes.
+    pub fn prune(&mut self, depth: u8, size: u64) -> usize {
+        // Number of hashes of each level, the last one covering the last leaf.
+        let width = |level: u8| ((size - 1) >> level) + 1;
+
+        match self {
+            HashStorage::Sparse(hashes) => {
+                let mut pruned = 0;
+                hashes.retain(|&level, level_hashes| {
+                    if level > depth {
+                        pruned += level_hashes.len();
+                        return false;
+                    }
+
+                    let len = level_hashes.len();
+                    level_hashes.retain(|&index, _| index < width(level));
+                    if level_hashes.len() < len {
+                        level_hashes.shrink_to_fit();
+                        pruned += len - level_hashes.len();
+                    }
+                    true
+                });
+                pruned
+            }
+            HashStorage::Dense(hashes) => {
+                let mut pruned: usize = hashes
+                    .drain((depth as usize + 1).min(hashes.len())..)
+                    .map(|level_hashes| level_hashes.len())
+                    .sum();
+                for (level, level_hashes) in hashes.iter_mut().enumerate() {
+                    let len = level_hashes.len();
+                    level_hashes.truncate(width(level as u8) as usize);
+                    if level_hashes.len() < len {
+                        level_hashes.shrink_to_fit();
+                        pruned += len - level_hashes.len();
+                    }
+                }
+                pruned
+            }
+            HashStorage::Paged(hashes) => hashes.prune(depth, width),
+        }
+    }
+
+    /// Returns the number of hashes stored in each level, from the leaves up to the top level.
+    pub fn level_lens(&self) -> Vec<u64> {
+        match self {
+            HashStorage::Sparse(hashes) => {
+                let levels = hashes.keys().max().map_or(0, |level| *level as usize + 1);
+                let mut lens = vec![0; levels];
+                for (level, level_hashes) in hashes {
+                    lens[*level as usize] = level_hashes.len() as _;
+                }
+                lens
+            }
+            HashStorage::Dense(hashes) => hashes.iter().map(|level| level.len() as _).collect(),
+            HashStorage::Paged(hashes) => hashes.level_lens(),
+        }
+    }
+
+    /// Returns an estimate of the heap memory used by the hashes, in bytes.
+    ///
+    /// Maps are accounted for one control byte per bucket on top of their entries, ignoring the
+    /// allocator overhead.
+    pub fn heap_size(&self) -> usize {
+        match self {
+            HashStorage::Sparse(hashes) => {
+                let levels = hashes.capacity() * (size_of::<(u8, HashMap<u64, Hash>)>() + 1);
+                let entries: usize = hashes
+                    .values()
+                    .map(|level| level.capacity() * (size_of::<(u64, Hash)>() + 1))
+                    .sum();
+                levels + entries
+            }
+            HashStorage::Dense(hashes) => {
+                let levels = hashes.capacity() * size_of::<Vec<Hash>>();
+                let entries: usize = hashes
+                    .iter()
+                    .map(|level| level.capacity() * size_of::<Hash>())
+                    .sum();
+                levels + entries
+            }
+            HashStorage::Paged(hashes) => hashes.heap_size(),
+        }
+    }
+
+    /// Replaces all the hashes with the given `levels`, keeping the current storage kind.
+    pub fn replace(&mut self, levels: HashMap<u8, HashMap<u64, Hash>>) {
+        match self {
+            HashStorage::Sparse(hashes) => *hashes = levels,
+            HashStorage::Dense(hashes) => {
+                hashes.clear();
+                hashes.resize_with(levels.len(), Vec::new);
+
+                for (level, level_hashes) in levels {
+                    let mut level_hashes: Vec<_> = level_hashes.into_iter().collect();
+                    level_hashes.sort_unstable_by_key(|(index, _)| *index);
+                    hashes[level as usize] =
+                        level_hashes.into_iter().map(|(_, hash)| hash).collect();
+                }
+            }
+            HashStorage::Paged(hashes) => hashes.replace(levels),
+        }
+    }
+}
@@ -0,0 +1,368 @@
+//! Streaming verification of bincode serialized IMT mutations.
+
+use std::io::Read;
+
+use anyhow::{bail, ensure, Result};
+use serde::de::DeserializeOwned;
+
+use crate::Hash;
+
+use super::{
+    climb_level, depth_mismatch, imt_root,
+    mutate::IMTMutate,
+    node::{Hashor, IMTNode, Key, LeafLayout, Value},
+    siblings::MAX_LEVELS,
+    CommitmentScheme,
+};
+
+impl<K: Key + DeserializeOwned, V: Value + DeserializeOwned> IMTMutate<K, V> {
+    /// Reads a bincode serialized IMT mutation from `reader`, verifies it and returns the new
+    /// updated root.
+    ///
+    /// The mutation is verified as it is read instead of being deserialized first: insert sibling
+    /// paths are hashed level by level and never held in memory, while the update sibling path is
+    /// kept on the stack until its new value is read.
+    pub fn verify_from_reader<R: Read, H: Hashor>(
+        mut reader: R,
+        hasher_factory: fn() -> H,
+        old_root: Hash,
+    ) -> Result<Hash> {
+        match read::<u32, _>(&mut reader)? {
+            0 => verify_insert::<K, V, _, _>(&mut reader, hasher_factory, old_root),
+            1 => verify_update::<K, V, _, _>(&mut reader, hasher_factory, old_root),
+            variant => bail!("IMTMutate variant {variant} is invalid"),
+        }
+    }
+}
+
+/// Streaming counterpart of `IMTInsert::verify`.
+fn verify_insert<K, V, R, H>(
+    reader: &mut R,
+    hasher_factory: fn() -> H,
+    old_root: Hash,
+) -> Result<Hash>
+where
+    K: Key + DeserializeOwned,
+    V: Value + DeserializeOwned,
+    R: Read,
+    H: Hashor,
+{
+    let scheme = CommitmentScheme::default();
+
+    // Make sure the IMTMutate old_root matches the expected old_root.
+    ensure!(
+        old_root == read::<Hash, _>(reader)?,
+        "IMTMutate.old_root is stale"
+    );
+    let old_size: u64 = read(reader)?;
+
+    // Check the ln node inclusion while reading its siblings.
+    let ln_node: IMTNode<K, V> = read(reader)?;
+    let ln_top = climb_reader(reader, hasher_factory, &ln_node, old_size, |_, _| ())?;
+    let ln_exists = !ln_node.is_vacant()
+        && ln_node.index < old_size
+        && scheme.root(hasher_factory, &ln_top, old_size) == old_root;
+
+    // Verify that the provided ln node is valid.
+    let node: IMTNode<K, V> = read(reader)?;
+    ensure!(
+        ln_node.is_ln_of(&node.key) && ln_exists,
+        "IMTMutate.ln_node is invalid"
+    );
+    ensure!(
+        node.next_key == ln_node.next_key,
+        "IMTMutate.node.next_key is invalid"
+    );
+
+    // Make sure the node is appended right after the last node, filling a vacant slot being left
+    // out until removals are provable.
+    ensure!(node.index == old_size, "IMTMutate.node.index is invalid");
+    let Some(new_size) = old_size.checked_add(1) else {
+        bail!("IMTMutate.old_size is invalid");
+    };
+    let node_top = climb_reader(reader, hasher_factory, &node, new_size, |_, _| ())?;
+
+    // Compute the updated root from the node and the updated ln node.
+    let updated_ln = IMTNode {
+        next_key: node.key,
+        ..ln_node
+    };
+    let updated_ln_top = climb_reader(reader, hasher_factory, &updated_ln, new_size, |_, _| ())?;
+
+    // Make sure both roots are equal.
+    ensure!(
+        node_top == updated_ln_top,
+        "IMTMutate.updated_ln_siblings is invalid"
+    );
+
+    Ok(scheme.root(hasher_factory, &node_top, new_size))
+}
+
+/// Streaming counterpart of `IMTUpdate::verify`.
+fn verify_update<K, V, R, H>(
+    reader: &mut R,
+    hasher_factory: fn() -> H,
+    old_root: Hash,
+) -> Result<Hash>
+where
+    K: Key + DeserializeOwned,
+    V: Value + DeserializeOwned,
+    R: Read,
+    H: Hashor,
+{
+    let scheme = CommitmentScheme::default();
+
+    // Make sure the IMTMutate old_root matches the expected old_root.
+    ensure!(
+        old_root == read::<Hash, _>(reader)?,
+        "IMTMutate.old_root is stale"
+    );
+    let size: u64 = read(reader)?;
+
+    // Verify that the node to update is already in the IMT, keeping its siblings.
+    let node: IMTNode<K, V> = read(reader)?;
+    ensure!(!node.is_vacant(), "IMTMutate.node is vacant");
+    let mut siblings = [None; MAX_LEVELS];
+    let mut len = 0;
+    let top = climb_reader(reader, hasher_factory, &node, size, |level, sibling| {
+        siblings[level] = sibling;
+        len = level + 1;
+    })?;
+    ensure!(
+        node.index < size && scheme.root(hasher_factory, &top, size) == old_root,
+        "IMTMutate.node is not in the IMT"
+    );
+
+    let old_value: V = read(reader)?;
+    ensure!(
+        node.value.as_ref() == old_value.as_ref(),
+        "IMTMutate.old_value is invalid"
+    );
+
+    // Compute the new root from the updated node.
+    let updated_node = IMTNode {
+        value: read(reader)?,
+        ..node
+    };
+
+    Ok(imt_root(
+        hasher_factory,
+        scheme,
+        LeafLayout::default(),
+        size,
+        &updated_node,
+        &siblings[..len],
+    ))
+}
+
+/// Reads a sibling path from `reader` and climbs it from `node`, returning the resulting top
+/// level hash. Each sibling is handed to `on_level` as it is read, along with its level.
+///
+/// The path must span the depth of an IMT of `size` nodes, as `IMTMutate::verify` requires.
+fn climb_reader<K, V, R, H>(
+    reader: &mut R,
+    hasher_factory: fn() -> H,
+    node: &IMTNode<K, V>,
+    size: u64,
+    mut on_level: impl FnMut(usize, Option<Hash>),
+) -> Result<Hash>
+where
+    K: Key,
+    V: Value,
+    R: Read,
+    H: Hashor,
+{
+    let len: u64 = read(reader)?;
+    let len = usize::try_from(len).unwrap_or(usize::MAX);
+    if let Some(err) = depth_mismatch(len, size) {
+        return Err(err.into());
+    }
+
+    let mut hash = node.hash(hasher_factory());
+    let mut index = node.index;
+    for level in 0..len {
+        let sibling: Option<Hash> = read(reader)?;
+        on_level(level, sibling);
+
+        hash = climb_level(hasher_factory, level as u8, &hash, index, sibling.as_ref());
+        index /= 2;
+    }
+
+    Ok(hash)
+}
+
+/// Reads the next bincode serialized value from `reader`.
+fn read<T: DeserializeOwned, R: Read>(reader: &mut R) -> Result<T> {
+    Ok(bincode::deserialize_from(reader)?)
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use std::io::Cursor;
+
+    use tiny_keccak::Keccak;
+
+    use super::*;
+    use crate::circuits::{imt::Imt, ImtError};
+
+    /// Verifies the serialized `mutate` both in memory and from a reader.
+    fn verify_both(
+        mutate: &IMTMutate<[u8; 32], [u8; 32]>,
+        old_root: Hash,
+    ) -> (Result<Hash>, Result<Hash>) {
+        let bytes = bincode::serialize(mutate).unwrap();
+        let streamed = IMTMutate::<[u8; 32], [u8; 32]>::verify_from_reader(
+            Cursor::new(bytes),
+            Keccak::v256,
+            old_root,
+        );
+
+        (mutate.verify(Keccak::v256, old_root), streamed)
+    }
+
+    #[test]
+    fn test_verify_from_reader() {
+        let mut imt = Imt::new(Keccak::v256);
+
+        for i in 1..=20 {
+            let old_root = imt.root;
+            let insert = imt.insert_node([i; 32], [42; 32]);
+            let (in_memory, streamed) = verify_both(&insert, old_root);
+            assert_eq!(streamed.unwrap(), in_memory.unwrap());
+
+            let old_root = imt.root;
+            let update = imt.update_node([i / 2; 32], [i; 32]);
+            let (in_memory, streamed) = verify_both(&update, old_root);
+            let new_root = in_memory.unwrap();
+            assert_eq!(streamed.unwrap(), new_root);
+            assert_eq!(imt.root, new_root);
+        }
+    }
+
+    #[test]
+    fn test_verify_from_reader_siblings_depth() {
+        let mut imt = Imt::new(Keccak::v256);
+        for i in 1..=4 {
+            imt.insert_node([i; 32], [42; 32]);
+        }
+        let update_root = imt.root;
+        let update = imt.update_node([2; 32], [43; 32]);
+        let insert_root = imt.root;
+        let insert = imt.insert_node([5; 32], [42; 32]);
+
+        // Both paths reject sibling paths not spanning the IMT depth of 3 with the same error.
+        for (len, err) in [
+            (4, ImtError::SiblingsExceedDepth { len: 4, depth: 3 }),
+            (2, ImtError::SiblingsBelowDepth { len: 2, depth: 3 }),
+        ] {
+            let mut update = update.clone();
+            if let IMTMutate::Update(update) = &mut update {
+                update.node_siblings.resize(len, Some([0; 32]));
+            }
+            let mut insert = insert.clone();
+            if let IMTMutate::Insert(insert) = &mut insert {
+                insert.ln_siblings.resize(len, Some([0; 32]));
+            }
+
+            for (mutate, old_root) in [(update, update_root), (insert, insert_root)] {
+                let (in_memory, streamed) = verify_both(&mutate, old_root);
+                assert_eq!(in_memory.unwrap_err().downcast_ref(), Some(&err));
+                assert_eq!(streamed.unwrap_err().downcast_ref(), Some(&err));
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify_from_reader_invalid() {
+        let mut imt = Imt::new(Keccak::v256);
+        imt.insert_node([1; 32], [42; 32]);
+        imt.insert_node([3; 32], [42; 32]);
+
+        let old_root = imt.root;
+        let insert = imt.insert_node([2; 32], [42; 32]);
+
+        // Both paths report the same error.
+        let (in_memory, streamed) = verify_both(&insert, [0xff; 32]);
+        assert_eq!(
+            streamed.unwrap_err().to_string(),
+            in_memory.unwrap_err().to_string()
+        );
+
+        let mut tampered = insert.clone();
+        if let IMTMutate::Insert(insert) = &mut tampered {
+            insert.updated_ln_siblings[0] = Some([0xff; 32]);
+        }
+        let (in_memory, streamed) = verify_both(&tampered, old_root);
+        assert_eq!(
+            streamed.unwrap_err().to_string(),
+            in_memory.unwrap_err().to_string()
+        );
+
+        let mut tampered = insert.clone();
+        if let IMTMutate::Insert(insert) = &mut tampered {
+            insert.node.next_key = [0; 32];
+        }
+        let (in_memory, streamed) = verify_both(&tampered, old_root);
+        assert_eq!(
+            streamed.unwrap_err().to_string(),
+            "IMTMutate.node.next_key is invalid"
+        );
+        assert_eq!(
+            in_memory.unwrap_err().to_string(),
+            "IMTMutate.node.next_key is invalid"
+        );
+
+        let old_root = imt.root;
+        let mut update = imt.update_node([1; 32], [43; 32]);
+        if let IMTMutate::Update(update) = &mut update {
+            update.node.value = [44; 32];
+        }
+        let (in_memory, streamed) = verify_both(&update, old_root);
+        assert_eq!(
+            streamed.unwrap_err().to_string(),
+            in_memory.unwrap_err().to_string()
+        );
+
+        if let IMTMutate::Update(update) = &mut update {
+            update.node.value = [42; 32];
+            update.old_value = [44; 32];
+        }
+        let (in_memory, streamed) = verify_both(&update, old_root);
+        assert_eq!(
+            streamed.unwrap_err().to_string(),
+            "IMTMutate.old_value is invalid"
+        );
+        assert_eq!(
+            in_memory.unwrap_err().to_string(),
+            "IMTMutate.old_value is invalid"
+        );
+
+        // Filling a vacant slot, or an occupied one, is rejected by both paths.
+        imt.remove_node(&[2; 32]);
+        let old_root = imt.root;
+        let mut recycled = imt.insert_node([4; 32], [42; 32]);
+        for index in [2, 1] {
+            if let IMTMutate::Insert(insert) = &mut recycled {
+                insert.node.index = index;
+            }
+            let (in_memory, streamed) = verify_both(&recycled, old_root);
+            assert_eq!(
+                streamed.unwrap_err().to_string(),
+                "IMTMutate.node.index is invalid"
+            );
+            assert_eq!(
+                in_memory.unwrap_err().to_string(),
+                "IMTMutate.node.index is invalid"
+            );
+        }
+
+        // A truncated stream is rejected.
+        let bytes = bincode::serialize(&insert).unwrap();
+        let res = IMTMutate::<[u8; 32], [u8; 32]>::verify_from_reader(
+            Cursor::new(&bytes[..bytes.len() - 1]),
+            Keccak::v256,
+            insert.old_root(),
+        );
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,301 @@
+//! Deterministic key generators, tree builders and a reference IMT shared by the benchmarks and
+//! property tests.
+
+use crate::Hash;
+
+use super::{
+    hash_level,
+    imt::Imt,
+    node::{Hashor, IMTNode, Key, Value},
+    CommitmentScheme,
+};
+
+/// Odd multiplier scattering sequential indices over the whole `u64` range.
+const SCATTER: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Returns the `i`-th deterministic key.
+///
+/// Keys are unique and non zero for every `i < u64::MAX` and are scattered, so that sequential
+/// indices are not inserted in increasing key order.
+pub fn key(i: u64) -> [u8; 32] {
+    let mut key = [0; 32];
+    key[..8].copy_from_slice(&(i + 1).wrapping_mul(SCATTER).to_be_bytes());
+    key
+}
+
+/// Returns the `i`-th deterministic value.
+pub fn value(i: u64) -> [u8; 32] {
+    let mut value = [0; 32];
+    value[24..].copy_from_slice(&i.to_be_bytes());
+    value
+}
+
+/// Builds an IMT holding the keys `key(0)..key(n)` with their `value`.
+///
+/// The nodes are loaded in bulk and each level is hashed once, so that large trees can be built
+/// without paying for `n` individual insertions.
+pub fn build_imt<H: Hashor>(hasher_factory: fn() -> H, n: u64) -> Imt<H, [u8; 32], [u8; 32]> {
+    Imt::from_entries(hasher_factory, (0..n).map(|i| (key(i), value(i))).collect())
+}
+
+/// Slow but straightforward IMT implementation, used as a reference for the `Imt`.
+///
+/// The nodes are kept in a list ordered by index, every query rebuilds the whole tree from them
+/// and the low nullifier is found by sorting the keys.
+#[derive(Debug, Clone)]
+pub struct ReferenceImt<H: Hashor, K: Key, V: Value> {
+    hasher_factory: fn() -> H,
+    nodes: Vec<IMTNode<K, V>>,
+}
+
+impl<H: Hashor, K: Key, V: Value> ReferenceImt<H, K, V> {
+    /// Instanciates a new reference IMT with the zero node.
+    pub fn new(hasher_factory: fn() -> H) -> Self {
+        Self {
+            hasher_factory,
+            nodes: vec![IMTNode::default()],
+        }
+    }
+
+    /// Returns the number of nodes.
+    pub fn size(&self) -> u64 {
+        self.nodes.len() as _
+    }
+
+    /// Returns the node with the given `key`, if any.
+    pub fn node(&self, key: &K) -> Option<&IMTNode<K, V>> {
+        self.nodes.iter().find(|node| node.key == *key)
+    }
+
+    /// Returns the node with the greatest key lower than `key`.
+    pub fn low_nullifier(&self, key: &K) -> IMTNode<K, V> {
+        let mut sorted = self.nodes.clone();
+        sorted.sort_by(|a, b| a.key.as_ref().cmp(b.key.as_ref()));
+
+        *sorted
+            .iter()
+            .rev()
+            .find(|node| node.key.as_ref() < key.as_ref())
+            .expect("the zero node is lower than any key")
+    }
+
+    /// Inserts a new (key; value), appending it after the last node.
+    pub fn insert_node(&mut self, key: K, value: V) {
+        assert!(self.node(&key).is_none(), "key conflict");
+
+        let ln = self.low_nullifier(&key);
+        self.nodes[ln.index as usize].next_key = key;
+        self.nodes.push(IMTNode {
+            index: self.size(),
+            key,
+            value,
+            next_key: ln.next_key,
+        });
+    }
+
+    /// Updates the given `key` to `value`.
+    pub fn update_node(&mut self, key: K, value: V) {
+        let index = self.node(&key).expect("node does not exist").index;
+        self.nodes[index as usize].value = value;
+    }
+
+    /// Returns the siblings of the node with the given `key`, from the leaf level up to the root.
+    pub fn siblings(&self, key: &K) -> Vec<Option<Hash>> {
+        let mut index = self.node(key).expect("node does not exist").index as usize;
+
+        let levels = self.levels();
+        let mut siblings = Vec::with_capacity(levels.len() - 1);
+        for level in &levels[..levels.len() - 1] {
+            siblings.push(level.get(index ^ 1).copied());
+            index /= 2;
+        }
+
+        siblings
+    }
+
+    /// Returns the IMT root, with the size folded in using the default commitment scheme.
+    pub fn root(&self) -> Hash {
+        let levels = self.levels();
+        let top = levels.last().expect("there is at least one level")[0];
+
+        CommitmentScheme::default().root(self.hasher_factory, &top, self.size())
+    }
+
+    /// Hashes every level of the tree, from the leaves up to the single top level hash.
+    fn levels(&self) -> Vec<Vec<Hash>> {
+        let mut level: Vec<Hash> = self
+            .nodes
+            .iter()
+            .map(|node| node.hash((self.hasher_factory)()))
+            .collect();
+
+        // The tree has as many levels as needed for its leaves to fit in a full binary tree.
+        let mut levels = Vec::new();
+        while level.len() > 1 {
+            let parents = level
+                .chunks(2)
+                .map(|children| {
+                    hash_level(
+                        self.hasher_factory,
+                        levels.len() as u8,
+                        children.first(),
+                        children.get(1),
+                    )
+                })
+                .collect();
+            levels.push(std::mem::replace(&mut level, parents));
+        }
+        levels.push(level);
+
+        levels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use tiny_keccak::Keccak;
+
+    use super::*;
+    use crate::circuits::mutate::IMTMutate;
+
+    /// SplitMix64 generator, to draw reproducible random operations.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next_u64(&mut self) -> u64 {
+            self.0 = self.0.wrapping_add(SCATTER);
+            let mut z = self.0;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next_u64() % n
+        }
+    }
+
+    #[test]
+    fn test_keys_are_unique() {
+        let keys: HashSet<_> = (0..10_000).map(key).collect();
+        assert_eq!(keys.len(), 10_000);
+        assert!(!keys.contains(&[0; 32]));
+    }
+
+    #[test]
+    fn test_build_imt_depth() {
+        for n in [0, 1, 2, 3, 4, 7, 8, 100, 255, 256] {
+            let imt = build_imt(Keccak::v256, n);
+
+            let mut inserted = Imt::new(Keccak::v256);
+            for i in 0..n {
+                inserted.insert_node(key(i), value(i));
+            }
+
+            assert_eq!(imt.depth(), inserted.depth());
+            assert_eq!(imt.expected_siblings_len(), imt.depth() as usize);
+            assert_eq!(imt.siblings(&[0; 32]).len(), imt.expected_siblings_len());
+        }
+    }
+
+    #[test]
+    fn test_build_imt() {
+        let imt = build_imt(Keccak::v256, 100);
+        imt.validate_invariants().unwrap();
+        assert_eq!(imt.size, 101);
+
+        // The bulk loaded IMT keeps working like any other one.
+        let mut imt = imt;
+        let old_root = imt.root;
+        let mutate = imt.insert_node(key(100), value(100));
+        assert_eq!(mutate.verify(Keccak::v256, old_root).unwrap(), imt.root);
+
+        let old_root = imt.root;
+        let mutate = imt.update_node(key(42), value(0));
+        assert_eq!(mutate.verify(Keccak::v256, old_root).unwrap(), imt.root);
+        imt.validate_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_differential() {
+        let mut rng = Rng(42);
+        let mut imt = Imt::new(Keccak::v256);
+        let mut reference = ReferenceImt::new(Keccak::v256);
+
+        let mut keys = Vec::new();
+        let mut log = Vec::new();
+        let check = |ok: bool, log: &[String], what: &str| {
+            assert!(ok, "{what} mismatch, operations:\n{}", log.join("\n"));
+        };
+
+        for step in 0..2_000 {
+            let old_root = imt.root;
+
+            let mutate = match rng.below(4) {
+                // Insert a new random key.
+                0 | 1 => {
+                    let key = key(rng.next_u64() >> 1);
+                    if keys.contains(&key) {
+                        continue;
+                    }
+                    keys.push(key);
+
+                    log.push(format!("{step}: insert {:02x?}", &key[..8]));
+                    reference.insert_node(key, value(step));
+                    imt.insert_node(key, value(step))
+                }
+                // Update an existing key, the zero node included.
+                2 => {
+                    let key = match rng.below(keys.len() as u64 + 1) {
+                        0 => [0; 32],
+                        i => keys[i as usize - 1],
+                    };
+
+                    log.push(format!("{step}: update {:02x?}", &key[..8]));
+                    reference.update_node(key, value(step));
+                    imt.update_node(key, value(step))
+                }
+                // Prove the (non) membership of a random key.
+                _ => {
+                    let key = key(rng.next_u64() >> 1);
+                    log.push(format!("{step}: prove {:02x?}", &key[..8]));
+
+                    let (ln, siblings) = imt.low_nullifier_proof(&key);
+                    let expected_ln = reference.low_nullifier(&key);
+                    check(
+                        ln.key == expected_ln.key && ln.index == expected_ln.index,
+                        &log,
+                        "low nullifier",
+                    );
+                    check(siblings == reference.siblings(&ln.key), &log, "ln siblings");
+                    continue;
+                }
+            };
+
+            // Compare the resulting states and verify the mutation.
+            check(imt.root == reference.root(), &log, "root");
+            check(imt.size == reference.size(), &log, "size");
+
+            let key = match &mutate {
+                IMTMutate::Insert(insert) => insert.node.key,
+                IMTMutate::Update(update) => update.node.key,
+            };
+            check(
+                imt.siblings(&key) == reference.siblings(&key),
+                &log,
+                "siblings",
+            );
+
+            let res = mutate.verify(Keccak::v256, old_root);
+            check(
+                res.is_ok_and(|root| root == reference.root()),
+                &log,
+                "verification",
+            );
+        }
+
+        imt.validate_invariants().unwrap();
+    }
+}
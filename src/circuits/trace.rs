@@ -0,0 +1,122 @@
+//! Helpers for the `tracing` instrumentation of the IMT operations and verifications.
+
+use crate::Hash;
+
+pub(crate) use super::hex::Hex;
+
+/// Emits a debug event with the `expected` and `computed` roots if they do not match.
+pub(crate) fn root_mismatch(reason: &str, expected: &Hash, computed: &Hash) {
+    if expected != computed {
+        tracing::debug!(
+            expected = %Hex(expected),
+            computed = %Hex(computed),
+            "{reason}"
+        );
+    }
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use std::{
+        fmt,
+        sync::{Arc, Mutex},
+    };
+
+    use tiny_keccak::Keccak;
+    use tracing::{
+        field::{Field, Visit},
+        span, Event, Subscriber,
+    };
+    use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+    use super::*;
+    use crate::circuits::{imt::Imt, mutate::IMTMutate};
+
+    /// Layer recording the name of the opened spans and the fields of the emitted events.
+    #[derive(Clone, Default)]
+    struct Capture {
+        spans: Arc<Mutex<Vec<String>>>,
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Capture {
+        fn on_new_span(&self, attrs: &span::Attributes<'_>, _: &span::Id, _: Context<'_, S>) {
+            self.spans
+                .lock()
+                .unwrap()
+                .push(attrs.metadata().name().into());
+        }
+
+        fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+            let mut fields = String::new();
+            event.record(&mut FieldsVisitor(&mut fields));
+            self.events.lock().unwrap().push(fields);
+        }
+    }
+
+    /// Formats the visited fields as `name=value` pairs.
+    struct FieldsVisitor<'a>(&'a mut String);
+
+    impl Visit for FieldsVisitor<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0.push_str(&format!("{}={:?} ", field.name(), value));
+        }
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(Hex(&[]).to_string(), "0x");
+        assert_eq!(Hex(&[0x00, 0x0a, 0xff]).to_string(), "0x000aff");
+    }
+
+    #[test]
+    fn test_spans_and_events() {
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry().with(capture.clone());
+
+        let (inserted_root, updated_root) = tracing::subscriber::with_default(subscriber, || {
+            let mut imt = Imt::new(Keccak::v256);
+            imt.insert_node([1; 32], [42; 32]);
+
+            let old_root = imt.root;
+            let insert = imt.insert_node([2; 32], [42; 32]);
+            insert.verify(Keccak::v256, old_root).unwrap();
+
+            let inserted_root = imt.root;
+            let mut update = imt.update_node([1; 32], [43; 32]);
+            update.verify(Keccak::v256, inserted_root).unwrap();
+            imt.siblings(&[1; 32]);
+
+            // A tampered mutation reports the expected and computed roots.
+            if let IMTMutate::Update(update) = &mut update {
+                update.node.value = [44; 32];
+            }
+            assert!(update.verify(Keccak::v256, inserted_root).is_err());
+
+            (inserted_root, imt.root)
+        });
+
+        let spans = capture.spans.lock().unwrap();
+        for name in [
+            "insert_node",
+            "update_node",
+            "refresh_tree",
+            "siblings",
+            "verify_insert",
+            "verify_update",
+        ] {
+            assert!(spans.iter().any(|span| span == name), "no {name} span");
+        }
+
+        let events = capture.events.lock().unwrap();
+        for root in [inserted_root, updated_root] {
+            let field = format!("new_root={} ", Hex(&root));
+            assert!(events.iter().any(|event| event.contains(&field)));
+        }
+        assert!(events.iter().any(|event| {
+            event.contains("message=node is not in the IMT")
+                && event.contains(&format!("expected={} ", Hex(&inserted_root)))
+                && event.contains("computed=0x")
+        }));
+    }
+}
@@ -1,42 +1,201 @@
+use std::fmt;
+
 use anyhow::{ensure, Result};
 use serde::{Deserialize, Serialize};
 
 use crate::Hash;
 
+#[cfg(feature = "tracing")]
+use super::trace;
 use super::{
+    ensure_siblings_depth,
+    hex::{Hex, HexSiblings},
     imt_root,
-    node::{Hashor, IMTNode, Key, Value},
-    node_exists,
+    node::{HasherFactory, Hashor, IMTNode, Key, LeafLayout, NodeHasher, Value},
+    node_exists, roots_eq,
+    siblings::{CompactSiblings, FixedSiblings, Siblings},
+    CommitmentScheme,
 };
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct IMTUpdate<K: Key, V: Value> {
+#[derive(Clone, Deserialize, Serialize)]
+pub struct IMTUpdate<K: Key, V: Value, S = Vec<Option<Hash>>> {
     pub old_root: Hash,
     pub size: u64,
     pub node: IMTNode<K, V>,
-    pub node_siblings: Vec<Option<Hash>>,
+    pub node_siblings: S,
+    /// Value of `node` before the update, kept for the transition to read on its own.
+    pub old_value: V,
     pub new_value: V,
 }
 
-impl<K: Key, V: Value> IMTUpdate<K, V> {
+impl<K: Key, V: Value, S: Siblings> fmt::Debug for IMTUpdate<K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IMTUpdate")
+            .field("old_root", &Hex(&self.old_root))
+            .field("size", &self.size)
+            .field("node", &self.node)
+            .field("node_siblings", &HexSiblings(&self.node_siblings))
+            .field("old_value", &Hex(self.old_value.as_ref()))
+            .field("new_value", &Hex(self.new_value.as_ref()))
+            .finish()
+    }
+}
+
+impl<K: Key, V: Value, S: Siblings> IMTUpdate<K, V, S> {
+    /// Returns the value of the node before the update, `new_value` being its value after it.
+    pub fn old_value(&self) -> V {
+        self.old_value
+    }
+
+    /// Returns `true` if the update leaves the node value unchanged.
+    pub fn is_noop(&self) -> bool {
+        self.old_value.as_ref() == self.new_value.as_ref()
+    }
+
+    /// Computes the root after the update from the updated node and its siblings, without
+    /// verifying the update against its old root.
+    pub fn new_root<H: Hashor>(&self, hasher_factory: impl HasherFactory<H>) -> Hash {
+        self.new_root_with_layout(
+            hasher_factory,
+            CommitmentScheme::default(),
+            LeafLayout::default(),
+        )
+    }
+
+    /// Same as `new_root` under the given commitment `scheme`, the leaves being hashed with the
+    /// given `layout`.
+    pub fn new_root_with_layout<H: Hashor, N: NodeHasher<K, V>>(
+        &self,
+        hasher_factory: impl HasherFactory<H>,
+        scheme: CommitmentScheme,
+        layout: N,
+    ) -> Hash {
+        let updated_node = IMTNode {
+            value: self.new_value,
+            ..self.node
+        };
+
+        imt_root(
+            hasher_factory,
+            scheme,
+            layout,
+            self.size,
+            &updated_node,
+            &self.node_siblings,
+        )
+    }
+
     /// Verifies the IMT update and return the new updated root.
     ///
     /// Before performing the update, the state is checked to make sure it is coherent.
-    pub fn verify<H: Hashor>(&self, hasher_factory: fn() -> H, old_root: Hash) -> Result<Hash> {
+    ///
+    /// No-op updates are valid, the new root being the old one: see `verify_changed` to reject
+    /// them.
+    pub fn verify<H: Hashor>(
+        &self,
+        hasher_factory: impl HasherFactory<H>,
+        old_root: Hash,
+    ) -> Result<Hash> {
+        self.verify_with_scheme(
+            hasher_factory,
+            CommitmentScheme::default(),
+            old_root,
+            self.size,
+        )
+    }
+
+    /// Same as `verify`, rejecting no-op updates for verifiers not willing to spend a proof on
+    /// them.
+    pub fn verify_changed<H: Hashor>(
+        &self,
+        hasher_factory: impl HasherFactory<H>,
+        old_root: Hash,
+    ) -> Result<Hash> {
+        let new_root = self.verify(hasher_factory, old_root)?;
+        ensure!(!self.is_noop(), "IMTMutate.new_value is unchanged");
+
+        Ok(new_root)
+    }
+
+    /// Verifies the IMT update under the given commitment `scheme` and return the new updated root.
+    ///
+    /// The `size` is checked as a plain equality, which is what binds the size when it is
+    /// committed to separately from the root.
+    pub fn verify_with_scheme<H: Hashor>(
+        &self,
+        hasher_factory: impl HasherFactory<H>,
+        scheme: CommitmentScheme,
+        old_root: Hash,
+        size: u64,
+    ) -> Result<Hash> {
+        self.verify_with_layout(
+            hasher_factory,
+            scheme,
+            LeafLayout::default(),
+            old_root,
+            size,
+        )
+    }
+
+    /// Verifies the IMT update under the given commitment `scheme`, the leaves being hashed with
+    /// the given `layout`, and return the new updated root.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "verify_update",
+            level = "trace",
+            skip_all,
+            fields(
+                key = %Hex(self.node.key.as_ref()),
+                index = self.node.index,
+                old_root = %Hex(&old_root),
+                size = size,
+                siblings = self.node_siblings.levels().count(),
+            )
+        )
+    )]
+    pub fn verify_with_layout<H: Hashor, N: NodeHasher<K, V>>(
+        &self,
+        hasher_factory: impl HasherFactory<H>,
+        scheme: CommitmentScheme,
+        layout: N,
+        old_root: Hash,
+        size: u64,
+    ) -> Result<Hash> {
         // Make sure the IMTMutate old_root matches the expected old_root.
-        ensure!(old_root == self.old_root, "IMTMutate.old_root is stale");
+        #[cfg(feature = "tracing")]
+        trace::root_mismatch("IMTMutate.old_root is stale", &old_root, &self.old_root);
+        ensure!(
+            roots_eq(&old_root, &self.old_root),
+            "IMTMutate.old_root is stale"
+        );
+
+        // Make sure the IMTMutate size matches the expected size.
+        ensure!(size == self.size, "IMTMutate.size is stale");
+        ensure_siblings_depth(&self.node_siblings, self.size)?;
+
+        // Vacant slots hold the zero key but are not nodes, only the zero node can be updated
+        // under the zero key.
+        ensure!(!self.node.is_vacant(), "IMTMutate.node is vacant");
 
         // Verify that the node to update is already in the IMT.
         ensure!(
-            node_exists(
-                hasher_factory,
-                &self.old_root,
-                self.size,
-                &self.node,
-                &self.node_siblings
-            ),
+            self.node.index < self.size
+                && node_exists(
+                    hasher_factory,
+                    scheme,
+                    layout,
+                    &self.old_root,
+                    self.size,
+                    &self.node,
+                    &self.node_siblings
+                ),
             "IMTMutate.node is not in the IMT"
         );
+        ensure!(
+            self.node.value.as_ref() == self.old_value.as_ref(),
+            "IMTMutate.old_value is invalid"
+        );
 
         // Compute the new root from the updated node.
         let updated_node = IMTNode {
@@ -44,20 +203,83 @@ impl<K: Key, V: Value> IMTUpdate<K, V> {
             ..self.node
         };
 
-        Ok(imt_root(
+        let new_root = imt_root(
             hasher_factory,
+            scheme,
+            layout,
             self.size,
             &updated_node,
             &self.node_siblings,
-        ))
+        );
+
+        #[cfg(feature = "tracing")]
+        tracing::trace!(new_root = %Hex(&new_root), "update verified");
+
+        Ok(new_root)
+    }
+}
+
+impl<K: Key, V: Value> From<IMTUpdate<K, V>> for IMTUpdate<K, V, CompactSiblings> {
+    fn from(update: IMTUpdate<K, V>) -> Self {
+        Self {
+            old_root: update.old_root,
+            size: update.size,
+            node: update.node,
+            node_siblings: update.node_siblings.into(),
+            old_value: update.old_value,
+            new_value: update.new_value,
+        }
+    }
+}
+
+impl<K: Key, V: Value> From<IMTUpdate<K, V, CompactSiblings>> for IMTUpdate<K, V> {
+    fn from(update: IMTUpdate<K, V, CompactSiblings>) -> Self {
+        Self {
+            old_root: update.old_root,
+            size: update.size,
+            node: update.node,
+            node_siblings: update.node_siblings.into(),
+            old_value: update.old_value,
+            new_value: update.new_value,
+        }
     }
 }
 
-#[cfg(test)]
+impl<K: Key, V: Value, const D: usize> TryFrom<IMTUpdate<K, V>>
+    for IMTUpdate<K, V, FixedSiblings<D>>
+{
+    type Error = anyhow::Error;
+
+    fn try_from(update: IMTUpdate<K, V>) -> Result<Self> {
+        Ok(Self {
+            old_root: update.old_root,
+            size: update.size,
+            node: update.node,
+            node_siblings: update.node_siblings.try_into()?,
+            old_value: update.old_value,
+            new_value: update.new_value,
+        })
+    }
+}
+
+impl<K: Key, V: Value, const D: usize> From<IMTUpdate<K, V, FixedSiblings<D>>> for IMTUpdate<K, V> {
+    fn from(update: IMTUpdate<K, V, FixedSiblings<D>>) -> Self {
+        Self {
+            old_root: update.old_root,
+            size: update.size,
+            node: update.node,
+            node_siblings: update.node_siblings.into(),
+            old_value: update.old_value,
+            new_value: update.new_value,
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
 mod tests {
     use tiny_keccak::Keccak;
 
-    use crate::circuits::{imt::Imt, mutate::IMTMutate};
+    use crate::circuits::{imt::Imt, mutate::IMTMutate, ImtError};
 
     #[test]
     fn test_verify_invalid_old_root() {
@@ -92,6 +314,101 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_verify_invalid_old_value() {
+        let mut imt = Imt::new(Keccak::v256);
+        imt.insert_node([1; 32], [42; 32]);
+        imt.insert_node([2; 32], [42; 32]);
+
+        if let IMTMutate::Update(mut sut) = imt.update_node([2; 32], [43; 32]) {
+            assert_eq!(sut.old_value(), [42; 32]);
+            sut.verify(Keccak::v256, sut.old_root).unwrap();
+
+            sut.old_value = [41; 32];
+            let res = sut.verify(Keccak::v256, sut.old_root);
+            assert!(matches!(res, Err(e) if e.to_string() == "IMTMutate.old_value is invalid"));
+        } else {
+            panic!("invalid result")
+        }
+    }
+
+    #[test]
+    fn test_verify_noop() {
+        let mut imt = Imt::new(Keccak::v256);
+        imt.insert_node([1; 32], [42; 32]);
+        imt.insert_node([2; 32], [42; 32]);
+
+        // No-op updates verify, leaving the root unchanged.
+        let old_root = imt.root;
+        if let IMTMutate::Update(sut) = imt.update_node([2; 32], [42; 32]) {
+            assert!(sut.is_noop());
+            assert_eq!(sut.verify(Keccak::v256, old_root).unwrap(), old_root);
+
+            // Unless the verifier rejects them.
+            let res = sut.verify_changed(Keccak::v256, old_root);
+            assert!(matches!(res, Err(e) if e.to_string() == "IMTMutate.new_value is unchanged"));
+        } else {
+            panic!("invalid result")
+        }
+
+        if let IMTMutate::Update(sut) = imt.update_node([2; 32], [43; 32]) {
+            assert!(!sut.is_noop());
+            let new_root = sut.verify_changed(Keccak::v256, old_root).unwrap();
+            assert_eq!(new_root, imt.root);
+        } else {
+            panic!("invalid result")
+        }
+    }
+
+    #[test]
+    fn test_verify_siblings_exceed_depth() {
+        // Instanciate an IMT with a few nodes.
+        let mut imt = Imt::new(Keccak::v256);
+        imt.insert_node([1; 32], [42; 32]);
+        imt.insert_node([2; 32], [42; 32]);
+        imt.insert_node([3; 32], [42; 32]);
+
+        // Append one sibling above the IMT depth.
+        if let IMTMutate::Update(mut sut) = imt.update_node([2; 32], [43; 32]) {
+            sut.node_siblings.push(Some([0; 32]));
+            let res = sut.verify(Keccak::v256, sut.old_root);
+            let err = res.unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<ImtError>(),
+                Some(&ImtError::SiblingsExceedDepth { len: 3, depth: 2 })
+            );
+
+            // Stopping short of the IMT depth is rejected as well.
+            sut.node_siblings.truncate(1);
+            let res = sut.verify(Keccak::v256, sut.old_root);
+            let err = res.unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<ImtError>(),
+                Some(&ImtError::SiblingsBelowDepth { len: 1, depth: 2 })
+            );
+        } else {
+            panic!("invalid result")
+        }
+    }
+
+    #[test]
+    fn test_verify_invalid_index() {
+        // Instanciate an IMT with a few nodes.
+        let mut imt = Imt::new(Keccak::v256);
+        imt.insert_node([1; 32], [42; 32]);
+        imt.insert_node([2; 32], [42; 32]);
+        imt.insert_node([3; 32], [42; 32]);
+
+        // Flip a `node.index` bit above the IMT depth.
+        if let IMTMutate::Update(mut sut) = imt.update_node([2; 32], [43; 32]) {
+            sut.node.index |= 1 << 40;
+            let res = sut.verify(Keccak::v256, sut.old_root);
+            assert!(matches!(res, Err(e) if e.to_string() == "IMTMutate.node is not in the IMT"));
+        } else {
+            panic!("invalid result")
+        }
+    }
+
     #[test]
     fn test_verify() {
         // Instanciate an IMT with a few nodes.
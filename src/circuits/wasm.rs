@@ -0,0 +1,143 @@
+//! `wasm-bindgen` bindings verifying JSON serialized proofs, e.g. in a browser that does not
+//! trust the server generating them.
+//!
+//! The proofs are the JSON serialization of `IMTMutate` and `IMTInclusion`, for IMTs of 32 bytes
+//! keys and values hashed with Keccak256 under the default commitment scheme and leaf layout.
+//! Roots are exchanged as `0x` prefixed hex strings.
+//!
+//! Verification neither draws randomness nor depends on the platform, so that the module builds
+//! for `wasm32-unknown-unknown`, along with the `verifier-only` feature to leave the prover out.
+//! Run its tests with `wasm-pack test --node -- --features wasm`.
+
+use anyhow::{Context, Result};
+use tiny_keccak::Keccak;
+use wasm_bindgen::prelude::*;
+
+use crate::Hash;
+
+use super::{
+    hex::{parse_hash, Hex},
+    inclusion::IMTInclusion,
+    mutate::IMTMutate,
+};
+
+/// Verifies the JSON serialized `IMTMutate` in `proof_json` against `old_root_hex` and returns
+/// the new root as hex.
+#[wasm_bindgen]
+pub fn verify_mutation(proof_json: &str, old_root_hex: &str) -> Result<String, JsError> {
+    mutation_new_root(proof_json, old_root_hex).map_err(js_error)
+}
+
+/// Verifies that the node of the JSON serialized `IMTInclusion` in `proof_json` is in the IMT
+/// commited to in `root_hex`.
+#[wasm_bindgen]
+pub fn verify_inclusion(proof_json: &str, root_hex: &str) -> Result<(), JsError> {
+    check_inclusion(proof_json, root_hex).map_err(js_error)
+}
+
+fn mutation_new_root(proof_json: &str, old_root_hex: &str) -> Result<String> {
+    let mutate: IMTMutate<Hash, Hash> =
+        serde_json::from_str(proof_json).context("proof is not a JSON IMTMutate")?;
+    let old_root = parse_root(old_root_hex)?;
+
+    let new_root = mutate.verify(Keccak::v256, old_root)?;
+    Ok(Hex(&new_root).to_string())
+}
+
+fn check_inclusion(proof_json: &str, root_hex: &str) -> Result<()> {
+    let inclusion: IMTInclusion<Hash, Hash> =
+        serde_json::from_str(proof_json).context("proof is not a JSON IMTInclusion")?;
+    let root = parse_root(root_hex)?;
+
+    inclusion.verify(Keccak::v256, root)
+}
+
+/// Parses a `0x` prefixed hex root.
+fn parse_root(hex: &str) -> Result<Hash> {
+    parse_hash(hex).context("root is not a 0x prefixed hash")
+}
+
+/// Converts `err` into a JS `Error`, its message listing the whole chain of causes.
+fn js_error(err: anyhow::Error) -> JsError {
+    JsError::new(&format!("{err:#}"))
+}
+
+/// Native tests, the exported functions only erroring on `wasm32` targets.
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use super::*;
+    use crate::circuits::imt::Imt;
+
+    #[test]
+    fn test_verify_mutation() {
+        let mut imt = Imt::new(Keccak::v256);
+        for i in 1..=10 {
+            let old_root = Hex(&imt.root).to_string();
+            let proof = serde_json::to_string(&imt.insert_node([i; 32], [42; 32])).unwrap();
+            let new_root = mutation_new_root(&proof, &old_root).unwrap();
+            assert_eq!(new_root, Hex(&imt.root).to_string());
+        }
+
+        let old_root = Hex(&imt.root).to_string();
+        let proof = serde_json::to_string(&imt.update_node([3; 32], [43; 32])).unwrap();
+        let new_root = mutation_new_root(&proof, &old_root).unwrap();
+        assert_eq!(new_root, Hex(&imt.root).to_string());
+
+        // The proof is checked against the given root.
+        let res = mutation_new_root(&proof, &new_root);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTMutate.old_root is stale"));
+
+        // Malformed inputs.
+        let res = mutation_new_root(&proof, &old_root[2..]);
+        assert!(matches!(res, Err(e) if e.to_string() == "root is not a 0x prefixed hash"));
+
+        let res = mutation_new_root("{}", &old_root);
+        assert!(matches!(res, Err(e) if e.to_string() == "proof is not a JSON IMTMutate"));
+    }
+
+    #[test]
+    fn test_verify_inclusion() {
+        let mut imt = Imt::new(Keccak::v256);
+        for i in 1..=10 {
+            imt.insert_node([i; 32], [42; 32]);
+        }
+        let root = Hex(&imt.root).to_string();
+
+        let proof = serde_json::to_string(&imt.prove_membership(&[3; 32]).unwrap()).unwrap();
+        check_inclusion(&proof, &root).unwrap();
+
+        imt.update_node([3; 32], [43; 32]);
+        let res = check_inclusion(&proof, &Hex(&imt.root).to_string());
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTInclusion.root is stale"));
+
+        let res = check_inclusion(&proof, "0x");
+        assert!(matches!(res, Err(e) if e.to_string() == "root is not a 0x prefixed hash"));
+    }
+}
+
+/// Tests of the exported functions, run with `wasm-pack`.
+#[cfg(all(test, target_arch = "wasm32", not(feature = "verifier-only")))]
+mod wasm_tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+    use crate::circuits::imt::Imt;
+
+    #[wasm_bindgen_test]
+    fn test_verify_proofs() {
+        let mut imt = Imt::new(Keccak::v256);
+        imt.insert_node([1; 32], [42; 32]);
+
+        // Proofs serialized by the prover as a server would.
+        let old_root = Hex(&imt.root).to_string();
+        let mutation = serde_json::to_string(&imt.insert_node([2; 32], [42; 32])).unwrap();
+        let inclusion = serde_json::to_string(&imt.prove_membership(&[1; 32]).unwrap()).unwrap();
+
+        let new_root = verify_mutation(&mutation, &old_root).unwrap();
+        assert_eq!(new_root, Hex(&imt.root).to_string());
+        verify_inclusion(&inclusion, &new_root).unwrap();
+
+        assert!(verify_mutation(&mutation, &new_root).is_err());
+        assert!(verify_inclusion(&inclusion, &old_root).is_err());
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::{ensure, Result};
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(feature = "verifier-only"))]
+use crate::circuits::imt::Imt;
+use crate::{
+    circuits::{
+        mutate::IMTMutate,
+        node::{Hashor, Key, Value},
+    },
+    Hash,
+};
+
+/// Input of a guest program verifying a batch of IMT mutations, along with the root the batch
+/// is expected to result in.
+///
+/// The guest reads the `(old_root, mutations)` tuple returned by `guest_input`, the
+/// `expected_new_root` stays on the host to check the guest journal against.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ProofInput<K: Key, V: Value> {
+    pub old_root: Hash,
+    pub mutations: Vec<IMTMutate<K, V>>,
+    pub expected_new_root: Hash,
+}
+
+impl<K: Key, V: Value> ProofInput<K, V> {
+    /// Returns the `(old_root, mutations)` tuple to write to the guest input.
+    pub fn guest_input(&self) -> (&Hash, &[IMTMutate<K, V>]) {
+        (&self.old_root, &self.mutations)
+    }
+
+    /// Replays the batch on the host and makes sure it results in `expected_new_root`.
+    pub fn verify_locally<H: Hashor>(&self, hasher_factory: fn() -> H) -> Result<()> {
+        let mut root = self.old_root;
+        for mutate in &self.mutations {
+            root = mutate.verify(hasher_factory, root)?;
+        }
+
+        ensure!(
+            root == self.expected_new_root,
+            "ProofInput.expected_new_root does not match the batch"
+        );
+
+        Ok(())
+    }
+}
+
+/// Wraps an `Imt` and records every mutation applied to it, to be handed to the prover in
+/// batches.
+#[cfg(not(feature = "verifier-only"))]
+#[derive(Debug, Clone)]
+pub struct BatchBuilder<H: Hashor, K: Key, V: Value> {
+    imt: Imt<H, K, V>,
+    old_root: Hash,
+    mutations: Vec<IMTMutate<K, V>>,
+}
+
+#[cfg(not(feature = "verifier-only"))]
+impl<H: Hashor, K: Key, V: Value> BatchBuilder<H, K, V> {
+    /// Creates a new batch builder, the first batch starting from the current `imt` root.
+    pub fn new(imt: Imt<H, K, V>) -> Self {
+        Self {
+            old_root: imt.root,
+            imt,
+            mutations: Default::default(),
+        }
+    }
+
+    /// Returns the wrapped IMT.
+    pub fn imt(&self) -> &Imt<H, K, V> {
+        &self.imt
+    }
+
+    /// Returns the mutations recorded since the last `take_batch`.
+    pub fn pending(&self) -> &[IMTMutate<K, V>] {
+        &self.mutations
+    }
+
+    /// Inserts a new (key; value) in the IMT and records the resulting mutation.
+    pub fn insert_node(&mut self, key: K, value: V) -> &IMTMutate<K, V> {
+        let mutate = self.imt.insert_node(key, value);
+        self.record(mutate)
+    }
+
+    /// Updates the given `key` to `value` in the IMT and records the resulting mutation.
+    pub fn update_node(&mut self, key: K, value: V) -> &IMTMutate<K, V> {
+        let mutate = self.imt.update_node(key, value);
+        self.record(mutate)
+    }
+
+    /// Returns the mutations recorded since the last call as a `ProofInput`, the next batch
+    /// starting from the current IMT root.
+    pub fn take_batch(&mut self) -> ProofInput<K, V> {
+        let old_root = std::mem::replace(&mut self.old_root, self.imt.root);
+
+        ProofInput {
+            old_root,
+            mutations: std::mem::take(&mut self.mutations),
+            expected_new_root: self.imt.root,
+        }
+    }
+
+    /// Returns the wrapped IMT, discarding the pending mutations.
+    pub fn into_inner(self) -> Imt<H, K, V> {
+        self.imt
+    }
+
+    /// Records the given `mutate` and returns a reference to it.
+    fn record(&mut self, mutate: IMTMutate<K, V>) -> &IMTMutate<K, V> {
+        self.mutations.push(mutate);
+        self.mutations.last().expect("mutation was just recorded")
+    }
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use tiny_keccak::Keccak;
+
+    use super::*;
+    use crate::circuits::zkvm::Journal;
+
+    #[test]
+    fn test_batches_chain() {
+        let mut builder = BatchBuilder::new(Imt::new(Keccak::v256));
+        let genesis_root = builder.imt().root;
+
+        // First batch interleaving inserts and updates.
+        builder.insert_node([1; 32], [42; 32]);
+        builder.insert_node([3; 32], [42; 32]);
+        builder.update_node([1; 32], [43; 32]);
+        builder.insert_node([2; 32], [42; 32]);
+        let first = builder.take_batch();
+        assert!(builder.pending().is_empty());
+
+        // Second batch, mutating nodes of the first one.
+        builder.update_node([3; 32], [43; 32]);
+        builder.insert_node([4; 32], [42; 32]);
+        builder.update_node([2; 32], [43; 32]);
+        let second = builder.take_batch();
+
+        assert_eq!(first.old_root, genesis_root);
+        assert_eq!(first.mutations.len(), 4);
+        assert_eq!(first.expected_new_root, second.old_root);
+        assert_eq!(second.mutations.len(), 3);
+        assert_eq!(second.expected_new_root, builder.imt().root);
+
+        first.verify_locally(Keccak::v256).unwrap();
+        second.verify_locally(Keccak::v256).unwrap();
+
+        // The guest input results in the expected journal.
+        let (old_root, mutations) = second.guest_input();
+        let journal = Journal::from_batch(Keccak::v256, *old_root, mutations).unwrap();
+        assert_eq!(journal.new_root, second.expected_new_root);
+
+        // An empty batch leaves the root untouched.
+        let empty = builder.take_batch();
+        assert_eq!(empty.old_root, empty.expected_new_root);
+        empty.verify_locally(Keccak::v256).unwrap();
+    }
+
+    #[test]
+    fn test_verify_locally_invalid() {
+        let mut builder = BatchBuilder::new(Imt::new(Keccak::v256));
+        builder.insert_node([1; 32], [42; 32]);
+        builder.insert_node([2; 32], [42; 32]);
+
+        let mut batch = builder.take_batch();
+        batch.expected_new_root = [0xff; 32];
+        let res = batch.verify_locally(Keccak::v256);
+        assert!(matches!(res, Err(e) if e.to_string()
+            == "ProofInput.expected_new_root does not match the batch"));
+
+        // Dropping a mutation breaks the chain of roots.
+        builder.insert_node([3; 32], [42; 32]);
+        builder.insert_node([4; 32], [42; 32]);
+        let mut batch = builder.take_batch();
+        batch.mutations.remove(0);
+        let res = batch.verify_locally(Keccak::v256);
+        assert!(matches!(res, Err(e) if e.to_string() == "IMTMutate.old_root is stale"));
+    }
+}
@@ -0,0 +1,302 @@
+use anyhow::{ensure, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    circuits::{
+        insert::IMTInsert,
+        mutate::IMTMutate,
+        node::{Hashor, IMTNode, Key, Value},
+        siblings::Siblings,
+        update::IMTUpdate,
+    },
+    Hash,
+};
+
+use super::GuestError;
+
+/// Public output committed after verifying a batch of IMT mutations.
+///
+/// Its canonical byte encoding is the Solidity ABI encoding of
+/// `(bytes32 old_root, bytes32 new_root, uint64 mutation_count, bytes32 mutations_digest)`, so
+/// on-chain verifiers can parse it with `abi.decode`.
+///
+/// The `mutations_digest` is a running hash over the mutations of the batch, binding the exact
+/// batch that was verified: starting from `[0; 32]`, each mutation updates it to
+/// `hash(mutations_digest || encoding(mutation))` where the mutation encoding is made of:
+/// - Insert: `0x00 || old_root || old_size || ln_node || ln_siblings || node || node_siblings ||
+///   updated_ln_siblings`
+/// - Update: `0x01 || old_root || size || node || node_siblings || new_value`, the `old_value`
+///   being the value of `node`
+///
+/// with sizes as 8 bytes big endian integers, nodes as `index (8 bytes big endian) || key ||
+/// value || next_key` and siblings as their number of levels on 1 byte followed, for each level,
+/// by `0x00` for an empty sibling or `0x01 || hash`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Journal {
+    pub old_root: Hash,
+    pub new_root: Hash,
+    pub mutation_count: u64,
+    pub mutations_digest: Hash,
+}
+
+impl Journal {
+    /// Length of the canonical byte encoding.
+    pub const LEN: usize = 128;
+
+    /// Verifies the batch of `mutations` from `old_root` and returns the resulting journal.
+    pub fn from_batch<H: Hashor, K: Key, V: Value, S: Siblings>(
+        hasher_factory: fn() -> H,
+        old_root: Hash,
+        mutations: &[IMTMutate<K, V, S>],
+    ) -> Result<Self, GuestError> {
+        let mut new_root = old_root;
+        for (index, mutate) in mutations.iter().enumerate() {
+            new_root = mutate
+                .verify(hasher_factory, new_root)
+                .map_err(|source| GuestError::Mutation { index, source })?;
+        }
+
+        Ok(Self {
+            old_root,
+            new_root,
+            mutation_count: mutations.len() as _,
+            mutations_digest: Self::mutations_digest(hasher_factory, mutations),
+        })
+    }
+
+    /// Computes the running hash over the given `mutations`.
+    pub fn mutations_digest<H: Hashor, K: Key, V: Value, S: Siblings>(
+        hasher_factory: fn() -> H,
+        mutations: &[IMTMutate<K, V, S>],
+    ) -> Hash {
+        let mut digest = [0; 32];
+
+        for mutate in mutations {
+            let mut hasher = hasher_factory();
+            hasher.update(&digest);
+            absorb_mutation(&mut hasher, mutate);
+            hasher.finalize(&mut digest);
+        }
+
+        digest
+    }
+
+    /// Returns the canonical byte encoding of the journal.
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        bytes[..32].copy_from_slice(&self.old_root);
+        bytes[32..64].copy_from_slice(&self.new_root);
+        bytes[88..96].copy_from_slice(&self.mutation_count.to_be_bytes());
+        bytes[96..].copy_from_slice(&self.mutations_digest);
+        bytes
+    }
+
+    /// Decodes a journal from its canonical byte encoding.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        ensure!(
+            bytes.len() == Self::LEN,
+            "Journal encoding has an invalid length"
+        );
+        ensure!(
+            bytes[64..88].iter().all(|byte| *byte == 0),
+            "Journal.mutation_count is not a uint64"
+        );
+
+        Ok(Self {
+            old_root: bytes[..32].try_into()?,
+            new_root: bytes[32..64].try_into()?,
+            mutation_count: u64::from_be_bytes(bytes[88..96].try_into()?),
+            mutations_digest: bytes[96..].try_into()?,
+        })
+    }
+
+    /// Returns the hash of the canonical byte encoding of the journal.
+    pub fn digest<H: Hashor>(&self, hasher_factory: fn() -> H) -> Hash {
+        let mut digest = [0; 32];
+
+        let mut hasher = hasher_factory();
+        hasher.update(&self.to_bytes());
+        hasher.finalize(&mut digest);
+
+        digest
+    }
+}
+
+/// Absorbs the canonical encoding of `mutate` into `hasher`.
+fn absorb_mutation<H: Hashor, K: Key, V: Value, S: Siblings>(
+    hasher: &mut H,
+    mutate: &IMTMutate<K, V, S>,
+) {
+    match mutate {
+        IMTMutate::Insert(IMTInsert {
+            old_root,
+            old_size,
+            ln_node,
+            ln_siblings,
+            node,
+            node_siblings,
+            updated_ln_siblings,
+        }) => {
+            hasher.update(&[0]);
+            hasher.update(old_root);
+            hasher.update(&old_size.to_be_bytes());
+            absorb_node(hasher, ln_node);
+            absorb_siblings(hasher, ln_siblings);
+            absorb_node(hasher, node);
+            absorb_siblings(hasher, node_siblings);
+            absorb_siblings(hasher, updated_ln_siblings);
+        }
+        IMTMutate::Update(IMTUpdate {
+            old_root,
+            size,
+            node,
+            node_siblings,
+            new_value,
+            ..
+        }) => {
+            hasher.update(&[1]);
+            hasher.update(old_root);
+            hasher.update(&size.to_be_bytes());
+            absorb_node(hasher, node);
+            absorb_siblings(hasher, node_siblings);
+            hasher.update(new_value.as_ref());
+        }
+    }
+}
+
+/// Absorbs the canonical encoding of `node` into `hasher`.
+fn absorb_node<H: Hashor, K: Key, V: Value>(hasher: &mut H, node: &IMTNode<K, V>) {
+    hasher.update(&node.index.to_be_bytes());
+    hasher.update(node.key.as_ref());
+    hasher.update(node.value.as_ref());
+    hasher.update(node.next_key.as_ref());
+}
+
+/// Absorbs the canonical encoding of `siblings` into `hasher`.
+fn absorb_siblings<H: Hashor, S: Siblings>(hasher: &mut H, siblings: &S) {
+    hasher.update(&[siblings.levels().count() as u8]);
+    for sibling in siblings.levels() {
+        match sibling {
+            Some(hash) => {
+                hasher.update(&[1]);
+                hasher.update(hash);
+            }
+            None => hasher.update(&[0]),
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use tiny_keccak::Keccak;
+
+    use super::*;
+    use crate::circuits::{imt::Imt, mutate::CompactIMTMutate};
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    #[test]
+    fn test_encoding() {
+        let journal = Journal {
+            old_root: [0x11; 32],
+            new_root: [0x22; 32],
+            mutation_count: 0x0102030405060708,
+            mutations_digest: [0x33; 32],
+        };
+
+        let bytes = journal.to_bytes();
+        assert_eq!(
+            hex(&bytes),
+            "1111111111111111111111111111111111111111111111111111111111111111\
+             2222222222222222222222222222222222222222222222222222222222222222\
+             0000000000000000000000000000000000000000000000000102030405060708\
+             3333333333333333333333333333333333333333333333333333333333333333"
+        );
+        assert_eq!(Journal::from_bytes(&bytes).unwrap(), journal);
+
+        assert_eq!(
+            hex(&journal.digest(Keccak::v256)),
+            "ae4e677241b58d1d50e2e7e723e8e5a90f6adb02dbdb2469cad2b319e3761af6"
+        );
+    }
+
+    #[test]
+    fn test_decoding_invalid() {
+        let bytes = Journal::default().to_bytes();
+
+        let res = Journal::from_bytes(&bytes[1..]);
+        assert!(matches!(res, Err(e) if e.to_string() == "Journal encoding has an invalid length"));
+
+        let mut invalid = bytes;
+        invalid[70] = 1;
+        let res = Journal::from_bytes(&invalid);
+        assert!(matches!(res, Err(e) if e.to_string() == "Journal.mutation_count is not a uint64"));
+    }
+
+    #[test]
+    fn test_mutations_digest() {
+        let node = IMTNode {
+            index: 1,
+            key: [1; 32],
+            value: [2; 32],
+            next_key: [0; 32],
+        };
+        let mutations: Vec<IMTMutate<_, _>> = vec![
+            IMTMutate::insert(
+                [0xaa; 32],
+                1,
+                IMTNode::default(),
+                vec![],
+                node,
+                vec![Some([0xbb; 32])],
+                vec![Some([0xcc; 32])],
+            ),
+            IMTMutate::update([0xdd; 32], 2, node, vec![None, Some([0xee; 32])], [3; 32]),
+        ];
+
+        assert_eq!(
+            Journal::mutations_digest(Keccak::v256, &mutations[..0]),
+            [0; 32]
+        );
+        assert_eq!(
+            hex(&Journal::mutations_digest(Keccak::v256, &mutations)),
+            "bfefe3f6bff07bbbd29078b30a59c6794882942642e1a1150996ea7cc5650f17"
+        );
+
+        // The digest does not depend on the siblings representation.
+        let compact: Vec<CompactIMTMutate<_, _>> =
+            mutations.iter().cloned().map(Into::into).collect();
+        assert_eq!(
+            Journal::mutations_digest(Keccak::v256, &compact),
+            Journal::mutations_digest(Keccak::v256, &mutations)
+        );
+    }
+
+    #[test]
+    fn test_from_batch() {
+        let mut imt = Imt::new(Keccak::v256);
+        let old_root = imt.root;
+        let mutations = vec![
+            imt.insert_node([1; 32], [42; 32]),
+            imt.insert_node([2; 32], [42; 32]),
+            imt.update_node([1; 32], [43; 32]),
+        ];
+
+        let journal = Journal::from_batch(Keccak::v256, old_root, &mutations).unwrap();
+        assert_eq!(
+            journal,
+            Journal {
+                old_root,
+                new_root: imt.root,
+                mutation_count: 3,
+                mutations_digest: Journal::mutations_digest(Keccak::v256, &mutations),
+            }
+        );
+
+        // Reordering the batch is rejected.
+        let res = Journal::from_batch(Keccak::v256, old_root, &mutations[1..]);
+        assert!(matches!(res, Err(GuestError::Mutation { index: 0, .. })));
+    }
+}
@@ -0,0 +1,224 @@
+//! zkVM specific helpers, used when verifying IMT mutations inside a guest program.
+
+use std::fmt;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::Hash;
+
+use super::{
+    mutate::IMTMutate,
+    node::{Hashor, Key, Value},
+};
+
+mod batch;
+mod journal;
+#[cfg(feature = "risc0")]
+pub mod risc0;
+#[cfg(feature = "sp1")]
+pub mod sp1;
+
+#[cfg(not(feature = "verifier-only"))]
+pub use batch::BatchBuilder;
+pub use batch::ProofInput;
+pub use journal::Journal;
+
+/// Input of a guest program, typically backed by the zkVM stdin.
+pub trait Read {
+    /// Reads and deserializes the next value.
+    fn read<T: DeserializeOwned>(&mut self) -> anyhow::Result<T>;
+}
+
+/// Public output of a guest program, typically backed by the zkVM journal.
+pub trait CommitSink {
+    /// Serializes and commits the given `value`.
+    fn commit<T: Serialize>(&mut self, value: &T) -> anyhow::Result<()>;
+
+    /// Commits the given raw `bytes`.
+    fn commit_slice(&mut self, bytes: &[u8]) -> anyhow::Result<()>;
+}
+
+/// Error returned by `run_guest`.
+#[derive(Debug)]
+pub enum GuestError {
+    /// The guest input could not be read.
+    Input(anyhow::Error),
+    /// The mutation at `index` in the batch failed to verify.
+    Mutation { index: usize, source: anyhow::Error },
+    /// The guest output could not be committed.
+    Commit(anyhow::Error),
+}
+
+impl fmt::Display for GuestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GuestError::Input(e) => write!(f, "failed to read the guest input: {e}"),
+            GuestError::Mutation { index, source } => {
+                write!(f, "mutation {index} is invalid: {source}")
+            }
+            GuestError::Commit(e) => write!(f, "failed to commit the guest output: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for GuestError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GuestError::Input(e) | GuestError::Commit(e) => Some(e.as_ref()),
+            GuestError::Mutation { source, .. } => Some(source.as_ref()),
+        }
+    }
+}
+
+/// Runs the standard guest program verifying a batch of IMT mutations.
+///
+/// Reads an `(old_root, mutations)` tuple from `input`, verifies each mutation in order starting
+/// from `old_root` and commits the canonical encoding of the resulting `Journal` to `output`.
+pub fn run_guest<H, K, V, R, W>(
+    hasher_factory: fn() -> H,
+    input: &mut R,
+    output: &mut W,
+) -> Result<(), GuestError>
+where
+    H: Hashor,
+    K: Key + DeserializeOwned,
+    V: Value + DeserializeOwned,
+    R: Read,
+    W: CommitSink,
+{
+    let (old_root, mutations): (Hash, Vec<IMTMutate<K, V>>) =
+        input.read().map_err(GuestError::Input)?;
+
+    let journal = Journal::from_batch(hasher_factory, old_root, &mutations)?;
+
+    output
+        .commit_slice(&journal.to_bytes())
+        .map_err(GuestError::Commit)
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use std::io::Cursor;
+
+    use tiny_keccak::Keccak;
+
+    use super::*;
+    use crate::circuits::imt::Imt;
+
+    /// In memory guest input, reading bincode encoded values.
+    struct MemoryInput(Cursor<Vec<u8>>);
+
+    impl Read for MemoryInput {
+        fn read<T: DeserializeOwned>(&mut self) -> anyhow::Result<T> {
+            Ok(bincode::deserialize_from(&mut self.0)?)
+        }
+    }
+
+    /// In memory guest output, writing bincode encoded values.
+    #[derive(Default)]
+    struct MemorySink(Vec<u8>);
+
+    impl CommitSink for MemorySink {
+        fn commit<T: Serialize>(&mut self, value: &T) -> anyhow::Result<()> {
+            Ok(bincode::serialize_into(&mut self.0, value)?)
+        }
+
+        fn commit_slice(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+            self.0.extend_from_slice(bytes);
+            Ok(())
+        }
+    }
+
+    fn input(old_root: Hash, mutations: &[IMTMutate<[u8; 32], [u8; 32]>]) -> MemoryInput {
+        MemoryInput(Cursor::new(
+            bincode::serialize(&(old_root, mutations)).unwrap(),
+        ))
+    }
+
+    #[test]
+    fn test_run_guest() {
+        let mut imt = Imt::new(Keccak::v256);
+        imt.insert_node([1; 32], [42; 32]);
+
+        // Generate a batch mixing inserts and updates.
+        let old_root = imt.root;
+        let mutations = vec![
+            imt.insert_node([3; 32], [42; 32]),
+            imt.insert_node([2; 32], [42; 32]),
+            imt.update_node([1; 32], [43; 32]),
+            imt.insert_node([4; 32], [42; 32]),
+        ];
+
+        let mut output = MemorySink::default();
+        run_guest::<_, [u8; 32], [u8; 32], _, _>(
+            Keccak::v256,
+            &mut input(old_root, &mutations),
+            &mut output,
+        )
+        .unwrap();
+
+        let journal = Journal::from_bytes(&output.0).unwrap();
+        assert_eq!(
+            journal,
+            Journal::from_batch(Keccak::v256, old_root, &mutations).unwrap()
+        );
+        assert_eq!(journal.new_root, imt.root);
+        assert_eq!(journal.mutation_count, 4);
+    }
+
+    #[test]
+    fn test_run_guest_empty_batch() {
+        let imt = Imt::<_, [u8; 32], [u8; 32]>::new(Keccak::v256);
+
+        let mut output = MemorySink::default();
+        run_guest::<_, [u8; 32], [u8; 32], _, _>(
+            Keccak::v256,
+            &mut input(imt.root, &[]),
+            &mut output,
+        )
+        .unwrap();
+
+        let journal = Journal::from_bytes(&output.0).unwrap();
+        assert_eq!(
+            journal,
+            Journal {
+                old_root: imt.root,
+                new_root: imt.root,
+                mutation_count: 0,
+                mutations_digest: [0; 32],
+            }
+        );
+    }
+
+    #[test]
+    fn test_run_guest_errors() {
+        let mut imt = Imt::new(Keccak::v256);
+        let old_root = imt.root;
+        let mut mutations = vec![
+            imt.insert_node([1; 32], [42; 32]),
+            imt.insert_node([2; 32], [42; 32]),
+        ];
+
+        // Skipping the first mutation makes the second one stale.
+        mutations.remove(0);
+        let mut output = MemorySink::default();
+        let res = run_guest::<_, [u8; 32], [u8; 32], _, _>(
+            Keccak::v256,
+            &mut input(old_root, &mutations),
+            &mut output,
+        );
+        assert!(matches!(
+            res,
+            Err(GuestError::Mutation { index: 0, source }) if source.to_string() == "IMTMutate.old_root is stale"
+        ));
+        assert!(output.0.is_empty());
+
+        // Truncated input.
+        let res = run_guest::<_, [u8; 32], [u8; 32], _, _>(
+            Keccak::v256,
+            &mut MemoryInput(Cursor::new(vec![0; 16])),
+            &mut output,
+        );
+        assert!(matches!(res, Err(GuestError::Input(_))));
+    }
+}
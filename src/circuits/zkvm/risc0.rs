@@ -0,0 +1,115 @@
+//! SHA-256 hashor backed by the RISC Zero SHA-256 accelerator.
+//!
+//! Hashing goes through `risc0_zkvm::sha::Impl`, which uses the accelerator circuit inside a
+//! RISC Zero guest and a software implementation on the host. Digests are identical to
+//! `sha2::Sha256`, so proofs generated on the host with the `sha2` feature verify in the guest.
+//!
+//! The input is buffered and hashed in one go at finalize, which allocates: unlike the other
+//! hashors, verifying with `Risc0Sha256` is not free of heap allocations.
+//!
+//! See `examples/risc0-guest` for a guest program verifying an `IMTMutate`.
+
+use std::mem::size_of;
+
+use risc0_zkvm::sha::{Impl, Sha256};
+use tiny_keccak::Hasher;
+
+use crate::{circuits::node::Hashor, Hash};
+
+/// SHA-256 hashor buffering its input and hashing it through the RISC Zero accelerator.
+#[derive(Debug, Clone, Default)]
+pub struct Risc0Sha256 {
+    buffer: Vec<u8>,
+}
+
+impl Risc0Sha256 {
+    /// Creates a new SHA-256 hashor.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Hashor for Risc0Sha256 {
+    const OUTPUT_LEN: usize = 32;
+
+    /// Keeps the buffer, and its allocation, for the next hash.
+    fn finalize_reset(&mut self, output: &mut [u8]) {
+        let digest = Impl::hash_bytes(&self.buffer);
+        output.copy_from_slice(&digest.as_bytes()[..output.len()]);
+        self.buffer.clear();
+    }
+}
+
+const _: () = assert!(<Risc0Sha256 as Hashor>::OUTPUT_LEN == size_of::<Hash>());
+
+impl Hasher for Risc0Sha256 {
+    fn update(&mut self, input: &[u8]) {
+        self.buffer.extend_from_slice(input);
+    }
+
+    fn finalize(mut self, output: &mut [u8]) {
+        self.finalize_reset(output);
+    }
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use sha2::Digest;
+
+    use super::*;
+    use crate::circuits::imt::Imt;
+
+    #[test]
+    fn test_matches_sha2() {
+        let input: Vec<u8> = (0..500).map(|i| i as u8).collect();
+
+        // Cover inputs around the SHA-256 block boundaries.
+        for len in [0, 1, 32, 55, 56, 63, 64, 65, 96, 500] {
+            let mut hash = [0; 32];
+            let mut hashor = Risc0Sha256::new();
+            input[..len]
+                .chunks(7)
+                .for_each(|chunk| hashor.update(chunk));
+            hashor.finalize(&mut hash);
+
+            let expected: [u8; 32] = sha2::Sha256::digest(&input[..len]).into();
+            assert_eq!(hash, expected, "len {len}");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "sha2")]
+    fn test_verify_host_proofs() {
+        use crate::circuits::sha256::Sha256;
+
+        // Generate the proofs with the host sha2 and verify them with `Risc0Sha256`.
+        let mut imt = Imt::new(Sha256::new);
+        for i in 1..20 {
+            let old_root = imt.root;
+            let mutate = imt.insert_node([i; 32], [42; 32]);
+            assert_eq!(mutate.verify(Risc0Sha256::new, old_root).unwrap(), imt.root);
+
+            let old_root = imt.root;
+            let mutate = imt.update_node([i; 32], [43; 32]);
+            assert_eq!(mutate.verify(Risc0Sha256::new, old_root).unwrap(), imt.root);
+        }
+    }
+
+    #[test]
+    fn test_imt_root() {
+        // The IMT root computed with `Risc0Sha256` matches a manual sha2 computation.
+        let imt = Imt::<_, [u8; 32], [u8; 32]>::new(Risc0Sha256::new);
+
+        let leaf = sha2::Sha256::new().chain_update([0; 96]);
+        #[cfg(feature = "bind-index")]
+        let leaf = leaf.chain_update(0_u64.to_be_bytes());
+        let leaf: [u8; 32] = leaf.finalize().into();
+
+        let root: [u8; 32] = sha2::Sha256::new()
+            .chain_update(leaf)
+            .chain_update(1_u64.to_be_bytes())
+            .finalize()
+            .into();
+        assert_eq!(imt.root, root);
+    }
+}
@@ -0,0 +1,163 @@
+//! Keccak256 hashor backed by the SP1 keccak permutation precompile.
+//!
+//! Inside an SP1 guest (`target_os = "zkvm"`) every Keccak-f[1600] permutation goes through the
+//! `syscall_keccak_permute` precompile, which is far cheaper than hashing in plain RISC-V. On any
+//! other target the permutation falls back to `tiny_keccak::keccakf`, so hashes are
+//! byte-identical to `Keccak::v256` and host generated proofs verify in the guest.
+//!
+//! ```ignore
+//! // SP1 guest program verifying an IMT mutation generated on the host.
+//! #![no_main]
+//! sp1_zkvm::entrypoint!(main);
+//!
+//! use imt::circuits::{mutate::IMTMutate, zkvm::sp1::Sp1Keccak};
+//!
+//! pub fn main() {
+//!     let old_root = sp1_zkvm::io::read::<[u8; 32]>();
+//!     let mutate = sp1_zkvm::io::read::<IMTMutate<[u8; 32], [u8; 32]>>();
+//!
+//!     let new_root = mutate.verify(Sp1Keccak::v256, old_root).unwrap();
+//!     sp1_zkvm::io::commit(&new_root);
+//! }
+//! ```
+
+use std::mem::{self, size_of};
+
+use tiny_keccak::Hasher;
+
+use crate::{circuits::node::Hashor, Hash};
+
+/// Number of bytes absorbed per permutation for Keccak256.
+const RATE: usize = 136;
+
+#[cfg(target_os = "zkvm")]
+extern "C" {
+    /// Keccak-f[1600] permutation precompile, provided by `sp1-zkvm`.
+    fn syscall_keccak_permute(state: *mut [u64; 25]);
+}
+
+/// Applies the Keccak-f[1600] permutation to `state`.
+fn permute(state: &mut [u64; 25]) {
+    #[cfg(target_os = "zkvm")]
+    // SAFETY: `state` is a valid, exclusively borrowed Keccak state.
+    unsafe {
+        syscall_keccak_permute(state)
+    }
+
+    #[cfg(not(target_os = "zkvm"))]
+    tiny_keccak::keccakf(state)
+}
+
+/// Keccak256 sponge buffering its input one rate block at a time, permuting through the SP1
+/// precompile when running in the zkVM.
+#[derive(Debug, Clone)]
+pub struct Sp1Keccak {
+    state: [u64; 25],
+    buffer: [u8; RATE],
+    offset: usize,
+}
+
+impl Sp1Keccak {
+    /// Creates a new Keccak256 hashor.
+    pub fn v256() -> Self {
+        Self {
+            state: [0; 25],
+            buffer: [0; RATE],
+            offset: 0,
+        }
+    }
+
+    /// Xors the buffered block into the state and permutes it.
+    fn absorb_block(&mut self) {
+        for (lane, chunk) in self.state.iter_mut().zip(self.buffer.chunks_exact(8)) {
+            *lane ^= u64::from_le_bytes(chunk.try_into().expect("chunk is 8 bytes"));
+        }
+
+        permute(&mut self.state);
+        self.offset = 0;
+    }
+}
+
+impl Hashor for Sp1Keccak {
+    const OUTPUT_LEN: usize = 32;
+
+    fn finalize_reset(&mut self, output: &mut [u8]) {
+        mem::replace(self, Self::v256()).finalize(output);
+    }
+}
+
+const _: () = assert!(<Sp1Keccak as Hashor>::OUTPUT_LEN == size_of::<Hash>());
+
+impl Hasher for Sp1Keccak {
+    fn update(&mut self, mut input: &[u8]) {
+        while !input.is_empty() {
+            let len = input.len().min(RATE - self.offset);
+            self.buffer[self.offset..self.offset + len].copy_from_slice(&input[..len]);
+            self.offset += len;
+            input = &input[len..];
+
+            if self.offset == RATE {
+                self.absorb_block();
+            }
+        }
+    }
+
+    fn finalize(mut self, output: &mut [u8]) {
+        // Apply the Keccak `0x01 ... 0x80` padding.
+        self.buffer[self.offset..].fill(0);
+        self.buffer[self.offset] ^= 0x01;
+        self.buffer[RATE - 1] ^= 0x80;
+        self.absorb_block();
+
+        for (chunk, lane) in output.chunks_mut(8).zip(self.state) {
+            chunk.copy_from_slice(&lane.to_le_bytes()[..chunk.len()]);
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "verifier-only")))]
+mod tests {
+    use tiny_keccak::Keccak;
+
+    use super::*;
+    use crate::circuits::imt::Imt;
+
+    #[test]
+    fn test_matches_keccak() {
+        let input: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+
+        // Cover inputs around the rate boundaries, absorbed in one or several chunks.
+        for len in [0, 1, 32, 135, 136, 137, 271, 272, 273, 1000] {
+            for chunk_size in [1, 7, 32, 136, 1000] {
+                let mut expected = [0; 32];
+                let mut keccak = Keccak::v256();
+                keccak.update(&input[..len]);
+                keccak.finalize(&mut expected);
+
+                let mut hash = [0; 32];
+                let mut hashor = Sp1Keccak::v256();
+                input[..len]
+                    .chunks(chunk_size)
+                    .for_each(|chunk| hashor.update(chunk));
+                hashor.finalize(&mut hash);
+
+                assert_eq!(hash, expected, "len {len}, chunk size {chunk_size}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify_host_proofs() {
+        // Generate the proofs with the host keccak and verify them with `Sp1Keccak`.
+        let mut imt = Imt::new(Keccak::v256);
+        for i in 1..20 {
+            let old_root = imt.root;
+            let mutate = imt.insert_node([i; 32], [42; 32]);
+            assert_eq!(mutate.verify(Sp1Keccak::v256, old_root).unwrap(), imt.root);
+
+            let old_root = imt.root;
+            let mutate = imt.update_node([i; 32], [43; 32]);
+            assert_eq!(mutate.verify(Sp1Keccak::v256, old_root).unwrap(), imt.root);
+        }
+    }
+}